@@ -0,0 +1,117 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use domain::session::SessionId;
+use domain::user::{Role, User, UserId};
+use utoipa::Modify;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use uuid::Uuid;
+
+use super::AppState;
+use crate::web::jwt;
+
+/// Name of the security scheme of the protected operations in the OpenAPI document
+pub const BEARER_AUTH: &str = "bearer_auth";
+
+/// User on whose behalf a request is made, authenticated by a bearer JWT or the
+/// web interface's `token` cookie
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user_id: UserId,
+    pub role: Role,
+    /// Session the token was issued for
+    pub session_id: SessionId,
+}
+
+impl Caller {
+    #[must_use]
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Whether the caller may read and act on the account and orders of `user_id`
+    #[must_use]
+    pub fn can_act_for(&self, user_id: &UserId) -> bool {
+        self.user_id == *user_id || self.is_admin()
+    }
+
+    /// Caller a token was issued to, if it is valid, its session was not revoked and
+    /// the user still exists
+    pub async fn from_token(state: &AppState, token: &str) -> Option<Self> {
+        let (claims, user) = jwt::authenticate(state.broker(), token).await?;
+        Some(Caller {
+            user_id: user.id.unwrap_or_default(),
+            role: user.role,
+            session_id: Uuid::parse_str(&claims.sid).ok()?,
+        })
+    }
+}
+
+impl FromRequestParts<AppState> for Caller {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = jwt::extract_token_from_headers(&parts.headers).ok_or_else(unauthorized)?;
+        Caller::from_token(state, &token)
+            .await
+            .ok_or_else(unauthorized)
+    }
+}
+
+/// Caller with the admin role, requests of other users are refused with 403
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if Caller::from_request_parts(parts, state).await?.is_admin() {
+            Ok(Admin)
+        } else {
+            Err(StatusCode::FORBIDDEN.into_response())
+        }
+    }
+}
+
+/// User the token was issued to, if it is valid, its session was not revoked and the
+/// user still exists
+pub async fn authenticate(state: &AppState, token: Option<&str>) -> Option<User> {
+    jwt::authenticate(state.broker(), token?)
+        .await
+        .map(|(_, user)| user)
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+    )
+        .into_response()
+}
+
+/// Declares the bearer scheme referenced by the protected operations
+pub struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            BEARER_AUTH,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use domain::instrument::{Instrument, InstrumentError, InstrumentRepoExt};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use super::AppState;
+use super::auth::Admin;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .with_state(state)
+        .routes(routes!(list_instruments, create_instrument))
+        .routes(routes!(
+            get_instrument,
+            update_instrument,
+            delete_instrument
+        ))
+}
+
+fn error_response(error: &InstrumentError) -> Response {
+    match error {
+        InstrumentError::NotFound => StatusCode::NOT_FOUND.into_response(),
+        InstrumentError::AlreadyExists => {
+            (StatusCode::CONFLICT, "Instrument already exists").into_response()
+        }
+        InstrumentError::Invalid(_) => (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+        InstrumentError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// List instruments
+///
+/// List every instrument of the instrument master, whatever its status
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "Instruments found", body = Vec<Instrument>),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::INSTRUMENT_TAG
+)]
+async fn list_instruments(State(state): State<AppState>) -> impl IntoResponse {
+    match state
+        .broker()
+        .get_instrument_repo()
+        .await
+        .list_instruments()
+        .await
+    {
+        Ok(instruments) => Json(instruments).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Get instrument by symbol
+#[utoipa::path(
+    get,
+    path = "/{symbol}",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol")
+    ),
+    responses(
+        (status = 200, description = "Instrument found", body = Instrument),
+        (status = 404, description = "Instrument not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::INSTRUMENT_TAG
+)]
+async fn get_instrument(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+) -> impl IntoResponse {
+    let instrument_repo = state.broker().get_instrument_repo().await;
+    match instrument_repo.get_instrument(&symbol).await {
+        Ok(Some(instrument)) => Json(instrument).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// List a new instrument
+///
+/// The instrument can be traded as soon as it is created with the `Active` status.
+/// Only admins can change the instrument master.
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = Instrument,
+    responses(
+        (status = 201, description = "Instrument created successfully", body = Instrument),
+        (status = 400, description = "Invalid reference data"),
+        (status = 409, description = "An instrument with this symbol already exists"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::INSTRUMENT_TAG
+)]
+async fn create_instrument(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Json(instrument): Json<Instrument>,
+) -> impl IntoResponse {
+    let instrument_repo = state.broker().get_instrument_repo().await;
+    match instrument_repo.create_instrument(instrument.clone()).await {
+        Ok(()) => (StatusCode::CREATED, Json(instrument)).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Update instrument by symbol
+///
+/// Replace the reference data of an instrument, for instance to halt it.
+/// The symbol cannot be changed.
+#[utoipa::path(
+    put,
+    path = "/{symbol}",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol")
+    ),
+    request_body = Instrument,
+    responses(
+        (status = 200, description = "Instrument updated successfully", body = Instrument),
+        (status = 400, description = "Invalid reference data or symbol mismatch"),
+        (status = 404, description = "Instrument not found"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::INSTRUMENT_TAG
+)]
+async fn update_instrument(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(symbol): Path<String>,
+    Json(instrument): Json<Instrument>,
+) -> impl IntoResponse {
+    if instrument.symbol != symbol {
+        return (StatusCode::BAD_REQUEST, "Symbol cannot be changed").into_response();
+    }
+
+    let instrument_repo = state.broker().get_instrument_repo().await;
+    match instrument_repo.update_instrument(instrument.clone()).await {
+        Ok(()) => Json(instrument).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Delete instrument by symbol
+///
+/// Remove an instrument from the master. Prefer the `Delisted` status to keep
+/// the reference data of past orders.
+#[utoipa::path(
+    delete,
+    path = "/{symbol}",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol")
+    ),
+    responses(
+        (status = 204, description = "Instrument deleted"),
+        (status = 404, description = "Instrument not found"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::INSTRUMENT_TAG
+)]
+async fn delete_instrument(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(symbol): Path<String>,
+) -> impl IntoResponse {
+    let instrument_repo = state.broker().get_instrument_repo().await;
+    match instrument_repo.remove_instrument(&symbol).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(&e),
+    }
+}
@@ -0,0 +1,215 @@
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use domain::core::BrokerX;
+    use domain::instrument::{Instrument, InstrumentStatus, PriceBand, TradingCalendar};
+    use domain::order::{OrderSide, OrderType};
+    use domain::user::{Role, UserRepoExt};
+    use tower::ServiceExt; // for `oneshot`
+    use uuid::Uuid;
+
+    use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+
+    // Create an isolated broker with a funded admin, their authorization header and the
+    // instrument router
+    async fn create_test_setup() -> (Router, BrokerHandle, Uuid, String) {
+        let broker = BrokerX::new_for_testing().await;
+        let email = format!("instrument-{}@test.com", &Uuid::new_v4().to_string()[..8]);
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                email.clone(),
+                "password123".to_string(),
+                "Test".to_string(),
+                "User".to_string(),
+                10000.0,
+            )
+            .await
+            .expect("user creation failed");
+        user_repo.set_role(&user_id, Role::Admin).await.unwrap();
+        let authorization = bearer(&broker, user_id, &email).await;
+
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::instrument::router(handle.clone()).split_for_parts();
+        (
+            router.with_state(handle.clone()),
+            handle,
+            user_id,
+            authorization,
+        )
+    }
+
+    fn new_listing() -> Instrument {
+        Instrument {
+            symbol: "NVDA".to_string(),
+            name: "NVIDIA Corporation".to_string(),
+            currency: "USD".to_string(),
+            tick_size: 0.01,
+            lot_size: 10,
+            price_band: PriceBand {
+                min: 1.0,
+                max: 1000.0,
+            },
+            reference_price: 120.0,
+            status: InstrumentStatus::Active,
+            trading_calendar: TradingCalendar::always_open(),
+        }
+    }
+
+    async fn send(
+        app: Router,
+        authorization: &str,
+        method: Method,
+        uri: &str,
+        body: Option<&Instrument>,
+    ) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", authorization);
+        let request = match body {
+            Some(instrument) => request
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(instrument).unwrap())),
+            None => request.body(Body::empty()),
+        };
+        app.oneshot(request.unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_initial_instruments_are_listed() {
+        let (app, _, _, _) = create_test_setup().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let instruments: Vec<Instrument> = serde_json::from_slice(&body).unwrap();
+        let symbols: Vec<&str> = instruments.iter().map(|i| i.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["AAPL", "GOOGL", "MSFT", "TSLA"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_listing_is_tradable() {
+        let (app, handle, user_id, admin) = create_test_setup().await;
+        let buy = |quantity| {
+            handle.broker().create_order(
+                user_id,
+                "NVDA".to_string(),
+                quantity,
+                OrderSide::Buy,
+                OrderType::Market,
+            )
+        };
+
+        // Unknown before it is listed
+        assert!(buy(10).await.is_err());
+
+        let status = send(app.clone(), &admin, Method::POST, "/", Some(&new_listing())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let status = send(app.clone(), &admin, Method::POST, "/", Some(&new_listing())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        assert!(buy(10).await.is_ok());
+        // Lot size comes from the new listing
+        assert!(buy(15).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_halted_instrument_rejects_orders() {
+        let (app, handle, user_id, admin) = create_test_setup().await;
+        let status = send(app.clone(), &admin, Method::POST, "/", Some(&new_listing())).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let mut halted = new_listing();
+        halted.status = InstrumentStatus::Halted;
+        let status = send(app.clone(), &admin, Method::PUT, "/NVDA", Some(&halted)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let result = handle
+            .broker()
+            .create_order(
+                user_id,
+                "NVDA".to_string(),
+                10,
+                OrderSide::Buy,
+                OrderType::Market,
+            )
+            .await;
+        assert!(result.is_err());
+
+        // The symbol is the key and cannot be changed
+        let status = send(app, &admin, Method::PUT, "/AAPL", Some(&halted)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_and_missing_instruments() {
+        let (app, _, _, admin) = create_test_setup().await;
+
+        let mut invalid = new_listing();
+        invalid.tick_size = 0.0;
+        let status = send(app.clone(), &admin, Method::POST, "/", Some(&invalid)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let status = send(app.clone(), &admin, Method::GET, "/NVDA", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let status = send(app.clone(), &admin, Method::DELETE, "/TSLA", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let status = send(app, &admin, Method::DELETE, "/TSLA", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_only_admins_change_the_master() {
+        let (app, handle, _, _) = create_test_setup().await;
+        let email = format!("client-{}@test.com", &Uuid::new_v4().to_string()[..8]);
+        let client_id = handle
+            .broker()
+            .get_user_repo()
+            .await
+            .create_user(
+                email.clone(),
+                "password123".to_string(),
+                "Client".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        let client = bearer(handle.broker(), client_id, &email).await;
+
+        let status = send(
+            app.clone(),
+            &client,
+            Method::POST,
+            "/",
+            Some(&new_listing()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = send(app.clone(), &client, Method::DELETE, "/AAPL", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = send(app.clone(), "", Method::DELETE, "/AAPL", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Reference data is public
+        let status = send(app, "", Method::GET, "/AAPL", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -0,0 +1,175 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use domain::candles::{Candle, CandleInterval};
+use domain::order_book::OrderBook;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use super::AppState;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+const DEFAULT_BOOK_DEPTH: usize = 10;
+const MAX_BOOK_DEPTH: usize = 100;
+const DEFAULT_CANDLE_COUNT: i32 = 100;
+const MAX_CANDLE_COUNT: i32 = 1000;
+
+/// Current quote of an instrument
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuoteResponse {
+    pub symbol: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub last: f64,
+    /// Quantity traded on the day of the last trade
+    pub volume: u64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct BookQuery {
+    /// Maximum number of price levels per side
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct CandleQuery {
+    /// Length of each candle: `1m`, `5m`, `1h` or `1d`
+    #[serde(default = "default_interval")]
+    #[param(inline)]
+    pub interval: CandleInterval,
+    /// Start of the range, inclusive. Defaults to 100 candles before `to`.
+    pub from: Option<DateTime<Utc>>,
+    /// End of the range, exclusive. Defaults to now.
+    pub to: Option<DateTime<Utc>>,
+}
+
+fn default_interval() -> CandleInterval {
+    CandleInterval::OneMinute
+}
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .with_state(state)
+        .routes(routes!(get_quote))
+        .routes(routes!(get_book))
+        .routes(routes!(get_candles))
+}
+
+/// Get quote by symbol
+///
+/// Best bid and offer, last traded price and daily volume from the market data feed
+#[utoipa::path(
+    get,
+    path = "/{symbol}/quote",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol")
+    ),
+    responses(
+        (status = 200, description = "Quote found", body = QuoteResponse),
+        (status = 404, description = "Instrument not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::MARKET_TAG
+)]
+async fn get_quote(State(state): State<AppState>, Path(symbol): Path<String>) -> impl IntoResponse {
+    let broker = state.broker();
+    match broker.quote(&symbol).await {
+        Ok(Some(quote)) => Json(QuoteResponse {
+            volume: broker.market_data().volume(&symbol),
+            symbol: quote.symbol,
+            bid: quote.bid,
+            ask: quote.ask,
+            last: quote.last,
+            timestamp: quote.timestamp,
+        })
+        .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Get order book by symbol
+///
+/// Limit orders resting on the instrument, aggregated by price with the best prices first
+#[utoipa::path(
+    get,
+    path = "/{symbol}/book",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol"),
+        BookQuery
+    ),
+    responses(
+        (status = 200, description = "Order book found", body = OrderBook),
+        (status = 404, description = "Instrument not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::MARKET_TAG
+)]
+async fn get_book(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    Query(query): Query<BookQuery>,
+) -> impl IntoResponse {
+    let depth = query
+        .depth
+        .unwrap_or(DEFAULT_BOOK_DEPTH)
+        .clamp(1, MAX_BOOK_DEPTH);
+
+    match state.broker().order_book(&symbol, depth).await {
+        Ok(Some(book)) => Json(book).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Get candles by symbol
+///
+/// OHLCV candles of the instrument's trades, oldest first. At most 1000 candles are
+/// returned, the most recent ones of the range.
+#[utoipa::path(
+    get,
+    path = "/{symbol}/candles",
+    params(
+        ("symbol" = String, Path, description = "Instrument symbol"),
+        CandleQuery
+    ),
+    responses(
+        (status = 200, description = "Candles found", body = Vec<Candle>),
+        (status = 400, description = "Invalid interval or range"),
+        (status = 404, description = "Instrument not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::MARKET_TAG
+)]
+async fn get_candles(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    Query(query): Query<CandleQuery>,
+) -> impl IntoResponse {
+    let broker = state.broker();
+    let length = query.interval.duration();
+    let to = query.to.unwrap_or_else(|| broker.clock().now());
+    let from = query
+        .from
+        .unwrap_or(to - length * DEFAULT_CANDLE_COUNT)
+        .max(to - length * MAX_CANDLE_COUNT);
+    if from >= to {
+        return (StatusCode::BAD_REQUEST, "from must be before to").into_response();
+    }
+
+    match broker.candles(&symbol, query.interval, from, to).await {
+        Ok(Some(candles)) => Json(candles).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,190 @@
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use chrono::{DateTime, Utc};
+    use domain::candles::{Candle, CandleInterval};
+    use domain::clock::Clock;
+    use domain::core::BrokerX;
+    use domain::market_data::{MarketDataConfig, TradeTick};
+    use domain::order::{OrderSide, OrderType};
+    use domain::order_book::OrderBook;
+    use domain::user::{UserId, UserRepoExt};
+    use tower::ServiceExt; // for `oneshot`
+
+    use crate::api::market::QuoteResponse;
+    use crate::services::BrokerHandle;
+
+    // In-memory broker, so the book only holds the orders of the test
+    async fn create_test_setup() -> (Router, BrokerHandle, UserId) {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "market@test.com".to_string(),
+                "password123".to_string(),
+                "Market".to_string(),
+                "User".to_string(),
+                100_000.0,
+            )
+            .await
+            .expect("user creation failed");
+
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::market::router(handle.clone()).split_for_parts();
+        (router.with_state(handle.clone()), handle, user_id)
+    }
+
+    async fn get(app: Router, uri: &str) -> (StatusCode, Vec<u8>) {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quote_includes_traded_volume() {
+        let (app, handle, user_id) = create_test_setup().await;
+        let broker = handle.broker();
+        broker
+            .create_order(
+                user_id,
+                "AAPL".to_string(),
+                25,
+                OrderSide::Buy,
+                OrderType::Market,
+            )
+            .await
+            .unwrap();
+        broker.run_until_idle().await;
+
+        let (status, body) = get(app, "/AAPL/quote").await;
+        assert_eq!(status, StatusCode::OK);
+        let quote: QuoteResponse = serde_json::from_slice(&body).unwrap();
+        let expected = broker.market_data().last_quote("AAPL").unwrap();
+        assert_eq!(quote.symbol, "AAPL");
+        assert!((quote.bid - expected.bid).abs() < 1e-9);
+        assert!((quote.ask - expected.ask).abs() < 1e-9);
+        assert!(quote.bid < quote.ask);
+        assert_eq!(quote.volume, 25);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_book_aggregates_resting_limit_orders() {
+        let (app, handle, user_id) = create_test_setup().await;
+        let broker = handle.broker();
+        for (quantity, side, price) in [
+            (10, OrderSide::Buy, 140.0),
+            (5, OrderSide::Buy, 140.0),
+            (1, OrderSide::Buy, 139.5),
+            (3, OrderSide::Sell, 160.0),
+        ] {
+            broker
+                .create_order(
+                    user_id,
+                    "AAPL".to_string(),
+                    quantity,
+                    side,
+                    OrderType::Limit(price),
+                )
+                .await
+                .unwrap();
+        }
+        broker.run_until_idle().await;
+
+        let (status, body) = get(app.clone(), "/AAPL/book?depth=1").await;
+        assert_eq!(status, StatusCode::OK);
+        let book: OrderBook = serde_json::from_slice(&body).unwrap();
+        assert_eq!(book.bids.len(), 1);
+        assert!((book.bids[0].price - 140.0).abs() < 1e-9);
+        assert_eq!(book.bids[0].quantity, 15);
+        assert_eq!(book.bids[0].orders, 2);
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.asks[0].quantity, 3);
+
+        let (_, body) = get(app, "/AAPL/book").await;
+        let book: OrderBook = serde_json::from_slice(&body).unwrap();
+        assert_eq!(book.bids.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unknown_symbol_is_not_found() {
+        let (app, _, _) = create_test_setup().await;
+
+        let (status, _) = get(app.clone(), "/NOPE/quote").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get(app, "/NOPE/book").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_candles_are_served_for_a_range() {
+        let (app, handle, _) = create_test_setup().await;
+        let feed = handle.broker().market_data();
+        let at = |value: &str| value.parse::<DateTime<Utc>>().unwrap();
+        for (price, timestamp) in [
+            (150.0, "2024-01-02T14:30:10Z"),
+            (152.0, "2024-01-02T14:30:40Z"),
+            (151.0, "2024-01-02T14:31:10Z"),
+            (149.0, "2024-01-02T14:45:00Z"),
+        ] {
+            feed.publish_trade(TradeTick {
+                symbol: "AAPL".to_string(),
+                price,
+                quantity: 10,
+                timestamp: at(timestamp),
+            });
+        }
+
+        // Trades are aggregated in the background, the hourly candle holds them all
+        let hourly = "/AAPL/candles?interval=1h&from=2024-01-02T14:00:00Z&to=2024-01-02T15:00:00Z";
+        let mut hours: Vec<Candle> = Vec::new();
+        for _ in 0..50 {
+            let (status, body) = get(app.clone(), hourly).await;
+            assert_eq!(status, StatusCode::OK);
+            hours = serde_json::from_slice(&body).unwrap();
+            if hours.first().is_some_and(|candle| candle.trades == 4) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].low, hours[0].close), (149.0, 149.0));
+
+        let (_, body) = get(
+            app.clone(),
+            "/AAPL/candles?interval=1m&from=2024-01-02T14:30:00Z&to=2024-01-02T14:40:00Z",
+        )
+        .await;
+        let candles: Vec<Candle> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].interval, CandleInterval::OneMinute);
+        assert_eq!(candles[0].start, at("2024-01-02T14:30:00Z"));
+        assert_eq!((candles[0].open, candles[0].close), (150.0, 152.0));
+        assert_eq!(candles[0].volume, 20);
+
+        let (status, _) = get(app.clone(), "/AAPL/candles?interval=2m").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(
+            app,
+            "/AAPL/candles?from=2024-01-02T15:00:00Z&to=2024-01-02T14:00:00Z",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
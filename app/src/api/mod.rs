@@ -4,28 +4,61 @@ use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::services::BrokerHandle;
+use auth::SecurityAddon;
 
+pub mod auth;
+mod instrument;
+mod market;
 mod order;
+mod outbox;
+mod session;
+mod stream;
 mod user;
 
 const USER_TAG: &str = "user";
+const INSTRUMENT_TAG: &str = "instrument";
 const ORDER_TAG: &str = "order";
+const MARKET_TAG: &str = "market";
+const OUTBOX_TAG: &str = "outbox";
+const SESSION_TAG: &str = "session";
+const STREAM_TAG: &str = "stream";
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         health,
     ),
+    modifiers(&SecurityAddon),
     components(
         schemas(
+            domain::instrument::Instrument,
+            domain::candles::Candle,
+            domain::candles::CandleInterval,
+            domain::order_book::OrderBook,
+            domain::order_book::PriceLevel,
+            domain::user::Role,
+            market::QuoteResponse,
             order::CreateOrderRequest,
             order::UpdateOrderRequest,
-            user::UpdateUserRequest
+            outbox::OutboxEntryResponse,
+            session::RefreshRequest,
+            session::TokenResponse,
+            stream::ClientMessage,
+            stream::ServerMessage,
+            user::UpdateRoleRequest,
+            user::SessionResponse,
+            user::UpdateUserRequest,
+            user::UserResponse
         )
     ),
     tags(
         (name = USER_TAG, description = "User API endpoints"),
-        (name = ORDER_TAG, description = "Order API endpoints")
+        (name = INSTRUMENT_TAG, description = "Instrument reference data administration"),
+        (name = ORDER_TAG, description = "Order API endpoints"),
+        (name = MARKET_TAG, description = "Quotes and order book depth"),
+        (name = OUTBOX_TAG, description = "Event outbox inspection and replay"),
+        (name = SESSION_TAG, description = "Access token renewal"),
+        (name = STREAM_TAG, description = "Real-time account events and market data over WebSocket")
     )
 )]
 struct ApiDoc;
@@ -48,7 +81,12 @@ pub fn create_api(state: AppState) -> Router {
     let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(routes!(health))
         .nest("/api/user", user::router(state.clone()))
+        .nest("/api/instrument", instrument::router(state.clone()))
         .nest("/api/order", order::router(state.clone()))
+        .nest("/api/market", market::router(state.clone()))
+        .nest("/api/outbox", outbox::router(state.clone()))
+        .nest("/api/session", session::router(state.clone()))
+        .nest("/api/stream", stream::router(state.clone()))
         .split_for_parts();
 
     router
@@ -1,6 +1,6 @@
 use axum::{Json, extract::Path, extract::State, http::StatusCode, response::IntoResponse};
 use domain::Repository;
-use domain::order::{Order, OrderSide, OrderStatus, OrderType};
+use domain::order::{CancelError, Order, OrderSide, OrderStatus, OrderType};
 
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -9,6 +9,7 @@ use utoipa_axum::routes;
 use uuid::Uuid;
 
 use super::AppState;
+use super::auth::{Admin, Caller};
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct CreateOrderRequest {
@@ -34,24 +35,26 @@ pub fn router(state: AppState) -> OpenApiRouter<AppState> {
 
 /// Get all orders
 ///
-/// Get all orders in the system
+/// Get all orders in the system for admins, the caller's own orders for clients
 #[utoipa::path(
     get,
     path = "/api/order",
     responses(
         (status = 200,description = "Orders found",body = Vec<Order>),
+        (status = 401, description = "Missing or invalid token"),
         (status = 500,description = "Internal server error")
     ),
+    security(("bearer_auth" = [])),
     tag = super::ORDER_TAG
 )]
-async fn get_orders(State(state): State<AppState>) -> impl IntoResponse {
-    match state
-        .broker()
-        .get_order_repo()
-        .await
-        .find_all_by_field("client_id", "")
-        .await
-    {
+async fn get_orders(State(state): State<AppState>, caller: Caller) -> impl IntoResponse {
+    let broker = state.broker();
+    let orders = if caller.is_admin() {
+        broker.get_order_repo().await.all().await
+    } else {
+        broker.get_orders_for_user(&caller.user_id).await
+    };
+    match orders {
         Ok(orders) => Json(orders).into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
@@ -59,7 +62,7 @@ async fn get_orders(State(state): State<AppState>) -> impl IntoResponse {
 
 /// Get order by UUID
 ///
-/// Get a specific order by their UUID
+/// Get a specific order by their UUID. Clients can only get their own orders.
 #[utoipa::path(
     get,
     path = "/{order_id}",
@@ -69,13 +72,23 @@ async fn get_orders(State(state): State<AppState>) -> impl IntoResponse {
     responses(
         (status = 200, description = "Order found", body = Order),
         (status = 404, description = "Order not found"),
-        (status = 400, description = "Invalid UUID format")
+        (status = 400, description = "Invalid UUID format"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Order of another user")
     ),
+    security(("bearer_auth" = [])),
     tag = super::ORDER_TAG
 )]
-async fn get_order(State(state): State<AppState>, Path(order_id): Path<Uuid>) -> impl IntoResponse {
+async fn get_order(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(order_id): Path<Uuid>,
+) -> impl IntoResponse {
     let order_repo = state.broker().get_order_repo().await;
     match order_repo.get(&order_id).await {
+        Ok(Some(order)) if !caller.can_act_for(&order.client_id) => {
+            StatusCode::FORBIDDEN.into_response()
+        }
         Ok(Some(order)) => Json(order).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
@@ -84,7 +97,9 @@ async fn get_order(State(state): State<AppState>, Path(order_id): Path<Uuid>) ->
 
 /// Update order status by UUID
 ///
-/// Update an existing order's status (mainly for cancellation)
+/// Overwrite an existing order's status, bypassing the order lifecycle. The change is
+/// published like any status change, and orders set back to be processed are queued.
+/// Only admins can do so, clients cancel their orders with `DELETE`.
 #[utoipa::path(
     put,
     path = "/{order_id}",
@@ -96,29 +111,27 @@ async fn get_order(State(state): State<AppState>, Path(order_id): Path<Uuid>) ->
         (status = 200, description = "Order updated successfully", body = Order),
         (status = 404, description = "Order not found"),
         (status = 400, description = "Invalid request data"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
         (status = 500, description = "Internal server error")
     ),
+    security(("bearer_auth" = [])),
     tag = super::ORDER_TAG
 )]
 async fn put_order(
     State(state): State<AppState>,
+    _admin: Admin,
     Path(order_id): Path<Uuid>,
     Json(payload): Json<UpdateOrderRequest>,
 ) -> impl IntoResponse {
-    let order_repo = state.broker().get_order_repo().await;
+    let broker = state.broker();
+    let order = match payload.status {
+        Some(status) => broker.set_order_status(order_id, status).await,
+        None => broker.get_order_repo().await.get(&order_id).await,
+    };
 
-    match order_repo.get(&order_id).await {
-        Ok(Some(mut order)) => {
-            // Update order status if provided
-            if let Some(status) = payload.status {
-                order.status = status;
-            }
-
-            match order_repo.insert(order_id, order.clone()).await {
-                Ok(()) => Json(order).into_response(),
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            }
-        }
+    match order {
+        Ok(Some(order)) => Json(order).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
@@ -126,7 +139,8 @@ async fn put_order(
 
 /// Create a new order
 ///
-/// Create a new order. All fields are required.
+/// Create a new order. All fields are required. Clients can only place orders for
+/// themselves.
 #[utoipa::path(
     post,
     path = "/",
@@ -134,14 +148,22 @@ async fn put_order(
     responses(
         (status = 201, description = "Order created successfully", body = Order),
         (status = 400, description = "Invalid request data or pre-trade validation failed"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Order for another user"),
         (status = 500, description = "Internal server error")
     ),
+    security(("bearer_auth" = [])),
     tag = super::ORDER_TAG
 )]
 async fn post_order(
     State(state): State<AppState>,
+    caller: Caller,
     Json(payload): Json<CreateOrderRequest>,
 ) -> impl IntoResponse {
+    if !caller.can_act_for(&payload.client_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     match state
         .broker()
         .create_order(
@@ -172,7 +194,9 @@ async fn post_order(
 
 /// Cancel order by UUID
 ///
-/// Cancel (delete) a specific order by its UUID
+/// Request the cancellation of a specific order by its UUID.
+/// The order moves to `PendingCancel` and is cancelled ahead of new orders.
+/// Clients can only cancel their own orders.
 #[utoipa::path(
     delete,
     path = "/{order_id}",
@@ -180,44 +204,39 @@ async fn post_order(
         ("order_id" = Uuid, Path, description = "Order UUID")
     ),
     responses(
-        (status = 200, description = "Order cancelled successfully", body = Order),
+        (status = 200, description = "Order cancellation requested", body = Order),
         (status = 404, description = "Order not found"),
         (status = 400, description = "Order cannot be cancelled"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Order of another user"),
         (status = 500, description = "Internal server error")
     ),
+    security(("bearer_auth" = [])),
     tag = super::ORDER_TAG
 )]
 async fn delete_order(
     State(state): State<AppState>,
+    caller: Caller,
     Path(order_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let order_repo = state.broker().get_order_repo().await;
-
-    match order_repo.get(&order_id).await {
-        Ok(Some(mut order)) => {
-            // Check if order can be cancelled
-            match order.status {
-                OrderStatus::Filled { .. }
-                | OrderStatus::Cancelled
-                | OrderStatus::Expired { .. }
-                | OrderStatus::Rejected { .. } => {
-                    return (StatusCode::BAD_REQUEST, "Order cannot be cancelled").into_response();
-                }
-                _ => {
-                    // Update order status to cancelled
-                    order.status = OrderStatus::Cancelled;
+    match state.broker().get_order_repo().await.get(&order_id).await {
+        Ok(Some(order)) if !caller.can_act_for(&order.client_id) => {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        Ok(_) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
 
-                    match order_repo.insert(order_id, order.clone()).await {
-                        Ok(()) => Json(order).into_response(),
-                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                    }
-                }
-            }
+    match state.broker().cancel_order(order_id).await {
+        Ok(order) => Json(order).into_response(),
+        Err(CancelError::OrderNotFound) => StatusCode::NOT_FOUND.into_response(),
+        Err(CancelError::NotCancellable) => {
+            (StatusCode::BAD_REQUEST, "Order cannot be cancelled").into_response()
         }
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(CancelError::DbError(_)) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
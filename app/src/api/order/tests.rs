@@ -5,20 +5,26 @@ mod tests {
         body::Body,
         http::{Method, Request, StatusCode},
     };
+    use domain::Repository;
+    use domain::events::DomainEvent;
+    use domain::order::CancelError;
     use domain::order::{Order, OrderSide, OrderStatus, OrderType};
-    use domain::user::UserRepoExt;
+    use domain::replay::{MarketReplay, ReplayConfig, ReplayFormat};
+    use domain::user::{Role, UserRepoExt};
     use serde_json::json;
     use tower::ServiceExt; // for `oneshot`
     use uuid::Uuid;
 
     use crate::api::order::{CreateOrderRequest, UpdateOrderRequest};
     use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+    use crate::web::jwt;
 
-    // Create test setup that is isolated and consistent
-    async fn create_test_setup() -> (Router, Uuid, Uuid) {
+    // Create test setup that is isolated and consistent, with the authorization headers
+    // of the user and of an admin
+    async fn create_test_setup() -> (Router, Uuid, String, String) {
         // Use unique IDs for this test to avoid conflicts
         let test_user_id = Uuid::new_v4();
-        let test_order_id = Uuid::new_v4();
         let test_id_str = test_user_id.to_string();
         let test_email = format!("test-{}@test.com", &test_id_str[..8]);
 
@@ -48,9 +54,24 @@ mod tests {
             }
         };
 
+        let admin_email = format!("admin-{}@test.com", &test_id_str[..8]);
+        let admin_id = user_repo
+            .create_user(
+                admin_email.clone(),
+                "password123".to_string(),
+                "Admin".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .expect("admin creation failed");
+        user_repo.set_role(&admin_id, Role::Admin).await.unwrap();
+
+        let token = bearer(&broker, actual_user_id, &test_email).await;
+        let admin = bearer(&broker, admin_id, &admin_email).await;
         let handle = BrokerHandle::new(broker);
         let (router, _api) = crate::api::order::router(handle.clone()).split_for_parts();
-        (router.with_state(handle), actual_user_id, test_order_id)
+        (router.with_state(handle), actual_user_id, token, admin)
     }
 
     // Helper function to create a test order through the broker
@@ -73,13 +94,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_orders_empty() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri("/")
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -91,14 +113,14 @@ mod tests {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let orders: Vec<Order> = serde_json::from_slice(&body).unwrap();
-        // For now, this returns empty since we have a placeholder implementation
+        let orders: Vec<(Uuid, Order)> = serde_json::from_slice(&body).unwrap();
+        // Only the orders of the caller, who has not placed any
         assert!(orders.is_empty());
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_order_not_found() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
         let non_existent_id = Uuid::new_v4();
 
         let response = app
@@ -106,6 +128,7 @@ mod tests {
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{}", non_existent_id))
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -117,13 +140,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_order_invalid_uuid() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri("/invalid-uuid")
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -136,7 +160,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_post_order_create_success() {
-        let (app, user_id, _) = create_test_setup().await;
+        let (app, user_id, token, _) = create_test_setup().await;
 
         let create_request = CreateOrderRequest {
             client_id: user_id,
@@ -151,6 +175,7 @@ mod tests {
                 Request::builder()
                     .method(Method::POST)
                     .uri("/")
+                    .header("authorization", &token)
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&create_request).unwrap()))
                     .unwrap(),
@@ -175,7 +200,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_post_order_create_limit_order() {
-        let (app, user_id, _) = create_test_setup().await;
+        let (app, user_id, token, _) = create_test_setup().await;
 
         let create_request = CreateOrderRequest {
             client_id: user_id,
@@ -190,6 +215,7 @@ mod tests {
                 Request::builder()
                     .method(Method::POST)
                     .uri("/")
+                    .header("authorization", &token)
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&create_request).unwrap()))
                     .unwrap(),
@@ -214,7 +240,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_post_order_invalid_data() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         // Test with invalid JSON
         let invalid_json = r#"{"invalid": "json"#;
@@ -225,6 +251,7 @@ mod tests {
                 Request::builder()
                     .method(Method::POST)
                     .uri("/")
+                    .header("authorization", &token)
                     .header("content-type", "application/json")
                     .body(Body::from(invalid_json))
                     .unwrap(),
@@ -246,6 +273,7 @@ mod tests {
                 Request::builder()
                     .method(Method::POST)
                     .uri("/")
+                    .header("authorization", &token)
                     .header("content-type", "application/json")
                     .body(Body::from(incomplete_request.to_string()))
                     .unwrap(),
@@ -258,65 +286,83 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_put_order_update_status() {
-        let (app, user_id, _) = create_test_setup().await;
-
-        // First create an order via POST
-        let create_request = CreateOrderRequest {
-            client_id: user_id,
-            symbol: "GOOGL".to_string(),
-            quantity: 3,
-            order_side: OrderSide::Buy,
-            order_type: OrderType::Market,
-        };
-
-        let create_response = app
-            .clone()
-            .oneshot(
-                Request::builder()
-                    .method(Method::POST)
-                    .uri("/")
-                    .header("content-type", "application/json")
-                    .body(Body::from(serde_json::to_string(&create_request).unwrap()))
-                    .unwrap(),
+        // No workers, so the order stays queued until the admin changes it
+        let broker = domain::core::BrokerX::new_for_testing_with_thread_count(0).await;
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                "status@test.com".to_string(),
+                "password123".to_string(),
+                "Status".to_string(),
+                "User".to_string(),
+                10000.0,
             )
             .await
             .unwrap();
-
-        assert_eq!(create_response.status(), StatusCode::CREATED);
-
-        let body = axum::body::to_bytes(create_response.into_body(), usize::MAX)
+        let admin_id = user_repo
+            .create_user(
+                "status-admin@test.com".to_string(),
+                "password123".to_string(),
+                "Admin".to_string(),
+                "User".to_string(),
+                0.0,
+            )
             .await
             .unwrap();
-        let _created_order: Order = serde_json::from_slice(&body).unwrap();
-
-        // Extract the order ID - we'll need to simulate this since Order doesn't have an id field
-        // For this test, we'll use a mock order ID
-        let order_id = Uuid::new_v4();
+        user_repo.set_role(&admin_id, Role::Admin).await.unwrap();
+        let order_id = create_test_order(&broker, user_id).await.unwrap();
+        let since = broker.events().last_sequence();
 
-        // Now try to update the order status
+        let admin = bearer(&broker, admin_id, "status-admin@test.com").await;
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::order::router(handle.clone()).split_for_parts();
         let update_request = UpdateOrderRequest {
             status: Some(OrderStatus::Cancelled),
         };
-
-        let response = app
+        let response = router
+            .with_state(handle.clone())
             .oneshot(
                 Request::builder()
                     .method(Method::PUT)
-                    .uri(format!("/{}", order_id))
+                    .uri(format!("/{order_id}"))
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&update_request).unwrap()))
                     .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let order: Order = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(order.status, OrderStatus::Cancelled));
 
-        // This will return NOT_FOUND since we're using a mock order ID
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let broker = handle.broker();
+        let stored = broker
+            .get_order_repo()
+            .await
+            .get(&order_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(stored.status, OrderStatus::Cancelled));
+        // Published like any status change
+        let events = broker.events().subscribe_after(since).events;
+        assert!(events.iter().any(|envelope| matches!(
+            &envelope.event,
+            DomainEvent::OrderStatusChanged {
+                order_id: id,
+                status: OrderStatus::Cancelled,
+                ..
+            } if *id == order_id
+        )));
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_put_order_not_found() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
         let non_existent_id = Uuid::new_v4();
 
         let update_request = UpdateOrderRequest {
@@ -328,6 +374,7 @@ mod tests {
                 Request::builder()
                     .method(Method::PUT)
                     .uri(format!("/{}", non_existent_id))
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&update_request).unwrap()))
                     .unwrap(),
@@ -340,7 +387,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_delete_order_not_found() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
         let non_existent_id = Uuid::new_v4();
 
         let response = app
@@ -348,6 +395,7 @@ mod tests {
                 Request::builder()
                     .method(Method::DELETE)
                     .uri(format!("/{}", non_existent_id))
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -359,13 +407,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_delete_order_invalid_uuid() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::DELETE)
                     .uri("/invalid-uuid")
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -378,7 +427,7 @@ mod tests {
     // Test error handling for various UUID formats
     #[tokio::test(flavor = "multi_thread")]
     async fn test_error_handling() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         // Test various UUID formats
         let test_cases = vec![
@@ -402,6 +451,7 @@ mod tests {
                     Request::builder()
                         .method(Method::GET)
                         .uri(uri)
+                        .header("authorization", &token)
                         .body(Body::empty())
                         .unwrap(),
                 )
@@ -420,7 +470,7 @@ mod tests {
     // Test pre-trade validation failures
     #[tokio::test(flavor = "multi_thread")]
     async fn test_post_order_insufficient_balance() {
-        let (app, _, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
 
         // Create a user with zero balance
         let broker = domain::core::BrokerX::new_for_testing().await;
@@ -436,6 +486,7 @@ mod tests {
             .await
             .unwrap_or_else(|_| Uuid::new_v4());
 
+        // Placed by an admin, clients can only place orders for themselves
         let create_request = CreateOrderRequest {
             client_id: poor_user_id,
             symbol: "AAPL".to_string(),
@@ -449,6 +500,7 @@ mod tests {
                 Request::builder()
                     .method(Method::POST)
                     .uri("/")
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(serde_json::to_string(&create_request).unwrap()))
                     .unwrap(),
@@ -466,6 +518,344 @@ mod tests {
         assert!(error_msg.contains("Order creation error"));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stale_orders_expire() {
+        // No workers, so the order stays queued until it expires
+        let broker = domain::core::BrokerX::new_for_testing_with_thread_count(0).await;
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "expiry@test.com".to_string(),
+                "password123".to_string(),
+                "Expiry".to_string(),
+                "User".to_string(),
+                10000.0,
+            )
+            .await
+            .unwrap();
+        let order_id = create_test_order(&broker, user_id).await.unwrap();
+
+        let recent = broker
+            .expire_orders_older_than(chrono::TimeDelta::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(recent, 0);
+        let expired = broker
+            .expire_orders_older_than(chrono::TimeDelta::zero())
+            .await
+            .unwrap();
+        assert_eq!(expired, 1);
+
+        let token = bearer(&broker, user_id, "expiry@test.com").await;
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::order::router(handle.clone()).split_for_parts();
+        let response = router
+            .with_state(handle)
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(format!("/{order_id}"))
+                    .header("authorization", &token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let order: Order = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(order.status, OrderStatus::Expired { .. }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orders_fill_against_market_data() {
+        let broker = domain::core::BrokerX::new_for_testing().await;
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                "market-data@test.com".to_string(),
+                "password123".to_string(),
+                "Market".to_string(),
+                "Data".to_string(),
+                10000.0,
+            )
+            .await
+            .unwrap();
+        broker.start_order_processing().await;
+
+        let market_id = create_test_order(&broker, user_id).await.unwrap();
+        // Below the ask, so it waits for the market to come down
+        let limit_id = broker
+            .create_order(
+                user_id,
+                "AAPL".to_string(),
+                10,
+                OrderSide::Buy,
+                OrderType::Limit(100.0),
+            )
+            .await
+            .unwrap();
+
+        let order_repo = broker.get_order_repo().await;
+        let mut filled = false;
+        for _ in 0..100 {
+            let order = order_repo.get(&market_id).await.unwrap().unwrap();
+            if matches!(order.status, OrderStatus::Filled { .. }) {
+                filled = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(filled, "market order was not filled");
+
+        // Without a ticker the quote does not move, so the fill is at the current ask
+        let ask = broker.market_data().last_quote("AAPL").unwrap().ask;
+        let user = user_repo.get(&user_id).await.unwrap().unwrap();
+        let holding = &user.holdings["AAPL"];
+        assert_eq!(holding.quantity, 10);
+        assert!((holding.average_cost - ask).abs() < 1e-9);
+        assert!((user.balance - (10000.0 - 10.0 * ask)).abs() < 1e-6);
+
+        let limit = order_repo.get(&limit_id).await.unwrap().unwrap();
+        assert!(matches!(
+            limit.status,
+            OrderStatus::Queued | OrderStatus::Pending
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancel_racing_a_fill_on_another_instance() {
+        // Two instances over the same tables, processing orders when told to
+        let broker = domain::core::BrokerX::new_for_testing_with_thread_count(0).await;
+        let replica = broker.new_for_testing_sharing_tables(0).await;
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                "race@test.com".to_string(),
+                "password123".to_string(),
+                "Race".to_string(),
+                "User".to_string(),
+                1_000_000.0,
+            )
+            .await
+            .unwrap();
+        broker.start_order_processing().await;
+        replica.start_order_processing().await;
+
+        let order_repo = broker.get_order_repo().await;
+        let mut filled = 0;
+        for _ in 0..20 {
+            let order_id = create_test_order(&broker, user_id).await.unwrap();
+            let (cancelled, _) =
+                tokio::join!(broker.cancel_order(order_id), replica.run_until_idle());
+            while broker.run_until_idle().await + replica.run_until_idle().await > 0 {}
+
+            // Whichever wins, the other sees its outcome rather than overwriting it
+            let order = order_repo.get(&order_id).await.unwrap().unwrap();
+            match (cancelled, order.status) {
+                (Ok(_), OrderStatus::Cancelled) => {}
+                (Err(CancelError::NotCancellable), OrderStatus::Filled { .. }) => filled += 1,
+                (cancelled, status) => panic!("cancel {cancelled:?} left the order {status:?}"),
+            }
+        }
+
+        // Only filled orders moved funds and shares
+        let ask = broker.market_data().last_quote("AAPL").unwrap().ask;
+        let user = user_repo.get(&user_id).await.unwrap().unwrap();
+        let held = user
+            .holdings
+            .get("AAPL")
+            .map_or(0, |holding| holding.quantity);
+        assert_eq!(held, 10 * filled);
+        assert!((user.balance - (1_000_000.0 - (10 * filled) as f64 * ask)).abs() < 1e-6);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replayed_prices_trigger_stop_orders() {
+        let broker = domain::core::BrokerX::new_for_testing().await;
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "replay@test.com".to_string(),
+                "password123".to_string(),
+                "Replay".to_string(),
+                "User".to_string(),
+                10000.0,
+            )
+            .await
+            .unwrap();
+        broker.start_order_processing().await;
+
+        // Buy once AAPL trades at 160 or above
+        let stop_id = broker
+            .create_order(
+                user_id,
+                "AAPL".to_string(),
+                10,
+                OrderSide::Buy,
+                OrderType::Stop(160.0),
+            )
+            .await
+            .unwrap();
+        let order_repo = broker.get_order_repo().await;
+        let status = |order_id| {
+            let order_repo = order_repo.clone();
+            async move { order_repo.get(&order_id).await.unwrap().unwrap().status }
+        };
+
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        assert!(!matches!(status(stop_id).await, OrderStatus::Filled { .. }));
+
+        let data = "timestamp,symbol,price\n2024-03-01T14:30:00Z,AAPL,159.0\n2024-03-01T14:30:01Z,AAPL,161.0\n";
+        let replay = MarketReplay::from_reader(
+            data.as_bytes(),
+            ReplayFormat::Csv,
+            &ReplayConfig {
+                speed: f64::INFINITY,
+                ..ReplayConfig::new("ticks.csv")
+            },
+        )
+        .unwrap();
+        replay
+            .run(
+                broker.market_data().clone(),
+                broker.get_instrument_repo().await,
+            )
+            .await;
+
+        let mut filled = false;
+        for _ in 0..100 {
+            if matches!(status(stop_id).await, OrderStatus::Filled { .. }) {
+                filled = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(filled, "stop order was not triggered");
+
+        // Filled at the ask of the replayed price
+        let user = broker
+            .get_user_repo()
+            .await
+            .get(&user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((user.holdings["AAPL"].average_cost - 161.01).abs() < 1e-9);
+    }
+
+    async fn send(
+        app: Router,
+        method: Method,
+        uri: &str,
+        authorization: Option<&str>,
+    ) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_orders_require_a_valid_token() {
+        let (app, _, _, _) = create_test_setup().await;
+
+        let status = send(app.clone(), Method::GET, "/", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let status = send(app.clone(), Method::GET, "/", Some("Bearer not-a-jwt")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // Signed for a user that does not exist
+        let stranger = format!(
+            "Bearer {}",
+            jwt::create_jwt(Uuid::new_v4(), "stranger@test.com".into(), Uuid::new_v4()).unwrap()
+        );
+        let status = send(app, Method::GET, "/", Some(&stranger)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clients_only_act_on_their_own_orders() {
+        let broker = domain::core::BrokerX::new_for_testing_with_thread_count(0).await;
+        let user_repo = broker.get_user_repo().await;
+        let mut users = Vec::new();
+        for name in ["alice", "bob"] {
+            let email = format!("{name}-{}@test.com", &Uuid::new_v4().to_string()[..8]);
+            let user_id = user_repo
+                .create_user(
+                    email.clone(),
+                    "password123".to_string(),
+                    name.to_string(),
+                    "User".to_string(),
+                    10000.0,
+                )
+                .await
+                .unwrap();
+            users.push((user_id, bearer(&broker, user_id, &email).await));
+        }
+        let [(alice_id, alice), (bob_id, bob)] = users.try_into().unwrap();
+        let order_id = create_test_order(&broker, alice_id).await.unwrap();
+
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::order::router(handle.clone()).split_for_parts();
+        let app = router.with_state(handle.clone());
+        let uri = format!("/{order_id}");
+
+        let status = send(app.clone(), Method::GET, &uri, Some(&alice)).await;
+        assert_eq!(status, StatusCode::OK);
+        let status = send(app.clone(), Method::GET, &uri, Some(&bob)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = send(app.clone(), Method::DELETE, &uri, Some(&bob)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // Only admins overwrite statuses
+        let status = send(app.clone(), Method::PUT, &uri, Some(&alice)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Bob cannot place orders on Alice's account
+        let create_request = CreateOrderRequest {
+            client_id: alice_id,
+            symbol: "AAPL".to_string(),
+            quantity: 10,
+            order_side: OrderSide::Buy,
+            order_type: OrderType::Market,
+        };
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/")
+                    .header("authorization", &bob)
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&create_request).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Admins act on every account
+        handle
+            .broker()
+            .get_user_repo()
+            .await
+            .set_role(&bob_id, Role::Admin)
+            .await
+            .unwrap();
+        let status = send(app.clone(), Method::GET, &uri, Some(&bob)).await;
+        assert_eq!(status, StatusCode::OK);
+        let status = send(app, Method::DELETE, &uri, Some(&bob)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
     // Test JSON serialization/deserialization
     #[tokio::test(flavor = "multi_thread")]
     async fn test_request_dto_serialization() {
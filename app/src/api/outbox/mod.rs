@@ -0,0 +1,191 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use domain::events::{OutboxFilter, OutboxRecord};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+use uuid::Uuid;
+
+use super::AppState;
+use super::auth::Admin;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+const DEFAULT_LIST_LIMIT: i64 = 100;
+
+/// Outbox entry as seen by operators
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct OutboxEntryResponse {
+    #[schema(value_type = String, format = Uuid)]
+    pub id: String,
+    pub sequence: i64,
+    pub topic: String,
+    pub payload: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub dispatched_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub attempts: i32,
+    pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+    pub last_error: Option<String>,
+}
+
+impl From<OutboxRecord> for OutboxEntryResponse {
+    fn from(record: OutboxRecord) -> Self {
+        Self {
+            id: record.id,
+            sequence: record.sequence,
+            topic: record.topic,
+            payload: record.payload,
+            created_at: record.created_at,
+            dispatched_at: record.dispatched_at,
+            attempts: record.attempts,
+            next_attempt_at: record.next_attempt_at,
+            last_error: record.last_error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatusFilter {
+    #[default]
+    All,
+    Pending,
+    Dispatched,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ListOutboxQuery {
+    /// Only return entries with this delivery status
+    #[serde(default)]
+    pub status: OutboxStatusFilter,
+    /// Maximum number of entries to return, newest first
+    pub limit: Option<i64>,
+}
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .with_state(state)
+        .routes(routes!(list_outbox))
+        .routes(routes!(get_outbox_entry))
+        .routes(routes!(replay_outbox_entry))
+}
+
+/// List outbox entries
+///
+/// List events recorded in the outbox, newest first
+#[utoipa::path(
+    get,
+    path = "/",
+    params(ListOutboxQuery),
+    responses(
+        (status = 200, description = "Outbox entries", body = Vec<OutboxEntryResponse>),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "No outbox configured")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::OUTBOX_TAG
+)]
+async fn list_outbox(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Query(query): Query<ListOutboxQuery>,
+) -> impl IntoResponse {
+    let Some(outbox) = state.broker().events().outbox() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let filter = match query.status {
+        OutboxStatusFilter::All => OutboxFilter::All,
+        OutboxStatusFilter::Pending => OutboxFilter::Pending,
+        OutboxStatusFilter::Dispatched => OutboxFilter::Dispatched,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, 1000);
+
+    match outbox.list(filter, limit).await {
+        Ok(records) => Json(
+            records
+                .into_iter()
+                .map(OutboxEntryResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Get outbox entry by UUID
+#[utoipa::path(
+    get,
+    path = "/{entry_id}",
+    params(
+        ("entry_id" = Uuid, Path, description = "Outbox entry UUID")
+    ),
+    responses(
+        (status = 200, description = "Outbox entry found", body = OutboxEntryResponse),
+        (status = 404, description = "Outbox entry not found"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "No outbox configured")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::OUTBOX_TAG
+)]
+async fn get_outbox_entry(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(entry_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let Some(outbox) = state.broker().events().outbox() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    match outbox.get(&entry_id.to_string()).await {
+        Ok(Some(record)) => Json(OutboxEntryResponse::from(record)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Replay outbox entry
+///
+/// Schedule an entry for delivery again, whether it was already dispatched
+/// or the relay gave up on it
+#[utoipa::path(
+    post,
+    path = "/{entry_id}/replay",
+    params(
+        ("entry_id" = Uuid, Path, description = "Outbox entry UUID")
+    ),
+    responses(
+        (status = 202, description = "Outbox entry scheduled for delivery"),
+        (status = 404, description = "Outbox entry not found"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error"),
+        (status = 503, description = "No outbox configured")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::OUTBOX_TAG
+)]
+async fn replay_outbox_entry(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(entry_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let Some(outbox) = state.broker().events().outbox() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    match outbox.replay(&entry_id.to_string()).await {
+        Ok(true) => StatusCode::ACCEPTED.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
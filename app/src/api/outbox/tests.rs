@@ -0,0 +1,199 @@
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use domain::core::BrokerX;
+    use domain::user::{Role, UserRepoExt};
+    use tower::ServiceExt; // for `oneshot`
+    use uuid::Uuid;
+
+    use crate::api::outbox::OutboxEntryResponse;
+    use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+
+    // Create an isolated broker with a user, the authorization header of an admin and
+    // the outbox router
+    async fn create_test_setup() -> (Router, BrokerHandle, Uuid, String) {
+        let broker = BrokerX::new_for_testing().await;
+        let suffix = &Uuid::new_v4().to_string()[..8];
+        let email = format!("outbox-{suffix}@test.com");
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                email,
+                "password123".to_string(),
+                "Test".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .expect("user creation failed");
+        let admin_email = format!("outbox-admin-{suffix}@test.com");
+        let admin_id = user_repo
+            .create_user(
+                admin_email.clone(),
+                "password123".to_string(),
+                "Admin".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .expect("admin creation failed");
+        user_repo.set_role(&admin_id, Role::Admin).await.unwrap();
+        let admin = bearer(&broker, admin_id, &admin_email).await;
+
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::outbox::router(handle.clone()).split_for_parts();
+        (router.with_state(handle.clone()), handle, user_id, admin)
+    }
+
+    async fn get_entries(app: Router, admin: &str, uri: &str) -> Vec<OutboxEntryResponse> {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(uri)
+                    .header("authorization", admin)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deposit_is_recorded_in_outbox() {
+        let (app, handle, user_id, admin) = create_test_setup().await;
+
+        handle.broker().deposit(&user_id, 250.0).await.unwrap();
+
+        let entries = get_entries(app, &admin, "/?status=pending").await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].topic, "FundsDeposited");
+        assert_eq!(entries[0].payload["event"]["balance"], 250.0);
+        assert!(entries[0].dispatched_at.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_withdrawal_is_not_recorded() {
+        let (app, handle, user_id, admin) = create_test_setup().await;
+
+        assert!(handle.broker().withdraw(&user_id, 10.0).await.is_err());
+
+        let entries = get_entries(app, &admin, "/").await;
+        assert!(entries.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay_dispatched_entry() {
+        let (app, handle, user_id, admin) = create_test_setup().await;
+        handle.broker().deposit(&user_id, 100.0).await.unwrap();
+
+        let outbox = handle.broker().events().outbox().unwrap();
+        let entry = outbox.fetch_due(10, 5).await.unwrap().remove(0);
+        outbox.mark_dispatched(&entry.id).await.unwrap();
+        assert_eq!(
+            get_entries(app.clone(), &admin, "/?status=pending")
+                .await
+                .len(),
+            0
+        );
+        assert_eq!(
+            get_entries(app.clone(), &admin, "/?status=dispatched")
+                .await
+                .len(),
+            1
+        );
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(format!("/{}/replay", entry.id))
+                    .header("authorization", &admin)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let pending = get_entries(app, &admin, "/?status=pending").await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, entry.id);
+        assert_eq!(pending[0].attempts, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unknown_entry() {
+        let (app, _, _, admin) = create_test_setup().await;
+        let missing = Uuid::new_v4();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri(format!("/{missing}"))
+                    .header("authorization", &admin)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri(format!("/{missing}/replay"))
+                    .header("authorization", &admin)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_outbox_is_for_admins() {
+        let (app, handle, user_id, _) = create_test_setup().await;
+        let email = handle
+            .broker()
+            .get_user_repo()
+            .await
+            .get_user_by_id(&user_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .email;
+        let user = bearer(handle.broker(), user_id, &email).await;
+
+        for (authorization, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some(user), StatusCode::FORBIDDEN),
+        ] {
+            let mut request = Request::builder().method(Method::GET).uri("/");
+            if let Some(authorization) = authorization.as_deref() {
+                request = request.header("authorization", authorization);
+            }
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+}
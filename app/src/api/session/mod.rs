@@ -0,0 +1,72 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use domain::session::SessionError;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use super::AppState;
+use crate::web::jwt::{self, TokenError};
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// New tokens of a session. The refresh token sent is no longer valid.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    /// Lifetime of the access token, in seconds
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .with_state(state)
+        .routes(routes!(refresh))
+}
+
+/// Refresh a session
+///
+/// Exchange a refresh token for a new access token and refresh token. Each refresh
+/// token can only be used once: using it again revokes its session.
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "Session refreshed", body = TokenResponse),
+        (status = 401, description = "Invalid, expired, revoked or reused refresh token"),
+        (status = 500, description = "Internal server error")
+    ),
+    tag = super::SESSION_TAG
+)]
+async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> impl IntoResponse {
+    match jwt::refresh_session(state.broker(), &payload.refresh_token).await {
+        Ok(tokens) => Json(TokenResponse {
+            access_token: tokens.access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: jwt::access_token_ttl().num_seconds(),
+            refresh_token: tokens.refresh_token,
+        })
+        .into_response(),
+        Err(TokenError::Session(SessionError::Repo(_)) | TokenError::Jwt(_)) => {
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            warn!("Refused to refresh a session: {e}");
+            (StatusCode::UNAUTHORIZED, e.to_string()).into_response()
+        }
+    }
+}
@@ -0,0 +1,114 @@
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use domain::core::BrokerX;
+    use domain::user::UserRepoExt;
+    use tower::ServiceExt; // for `oneshot`
+    use uuid::Uuid;
+
+    use crate::api::session::TokenResponse;
+    use crate::services::BrokerHandle;
+    use crate::web::jwt::{self, SessionTokens};
+
+    async fn create_test_setup() -> (Router, BrokerHandle, SessionTokens) {
+        let broker = BrokerX::new_for_testing().await;
+        let email = format!("session-{}@test.com", &Uuid::new_v4().to_string()[..8]);
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                email.clone(),
+                "password123".to_string(),
+                "Session".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .expect("user creation failed");
+        let tokens = jwt::open_session(&broker, user_id, email, Some("test".to_string()))
+            .await
+            .unwrap();
+
+        let handle = BrokerHandle::new(broker);
+        let (router, _api) = crate::api::session::router(handle.clone()).split_for_parts();
+        (router.with_state(handle.clone()), handle, tokens)
+    }
+
+    async fn refresh(app: Router, refresh_token: &str) -> (StatusCode, Option<TokenResponse>) {
+        let body = serde_json::json!({ "refresh_token": refresh_token }).to_string();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(Method::POST)
+                    .uri("/refresh")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_refresh_rotates_the_tokens() {
+        let (app, handle, tokens) = create_test_setup().await;
+
+        let (status, refreshed) = refresh(app.clone(), &tokens.refresh_token).await;
+        assert_eq!(status, StatusCode::OK);
+        let refreshed = refreshed.unwrap();
+        assert_eq!(refreshed.token_type, "Bearer");
+        assert!(refreshed.expires_in > 0);
+        assert_ne!(refreshed.refresh_token, tokens.refresh_token);
+
+        let (claims, _) = jwt::authenticate(handle.broker(), &refreshed.access_token)
+            .await
+            .unwrap();
+        let session = jwt::verify_jwt(&tokens.access_token).unwrap().claims.sid;
+        assert_eq!(claims.sid, session);
+
+        let (status, again) = refresh(app, &refreshed.refresh_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(again.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reused_refresh_token_ends_the_session() {
+        let (app, handle, tokens) = create_test_setup().await;
+        let (_, refreshed) = refresh(app.clone(), &tokens.refresh_token).await;
+        let refreshed = refreshed.unwrap();
+
+        // Someone replays the first refresh token
+        let (status, _) = refresh(app.clone(), &tokens.refresh_token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Neither the legitimate client's tokens nor earlier access tokens work anymore
+        let (status, _) = refresh(app, &refreshed.refresh_token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        for access_token in [&tokens.access_token, &refreshed.access_token] {
+            assert!(
+                jwt::authenticate(handle.broker(), access_token)
+                    .await
+                    .is_none()
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_refresh_tokens_are_refused() {
+        let (app, _, _) = create_test_setup().await;
+        let unknown = format!("{}.secret", Uuid::new_v4().simple());
+        for token in ["", "garbage", unknown.as_str()] {
+            let (status, _) = refresh(app.clone(), token).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use domain::events::{DomainEvent, EventEnvelope, Replay};
+use domain::market_data::{MarketDataEvent, Quote, TradeTick};
+use domain::user::UserId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, interval_at};
+use tracing::{debug, warn};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use super::{AppState, auth};
+use crate::web::jwt;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StreamQuery {
+    /// Resume after this sequence, replaying the account events published since
+    pub since: Option<u64>,
+    /// JWT, for clients that cannot set headers on the WebSocket handshake
+    pub token: Option<String>,
+    /// Comma separated symbols to subscribe to market data of
+    pub symbols: Option<String>,
+}
+
+/// Message sent by the server on the stream
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message of a connection. `complete` is false when some events after the
+    /// requested sequence could not be replayed, and the account should be reloaded.
+    Welcome {
+        #[schema(value_type = String, format = Uuid)]
+        user_id: UserId,
+        sequence: u64,
+        complete: bool,
+        heartbeat_interval_secs: u64,
+    },
+    /// An order of the client was created, changed status or was executed
+    Execution {
+        sequence: u64,
+        occurred_at: DateTime<Utc>,
+        event: DomainEvent,
+    },
+    /// The client's cash balance changed by `change`
+    Balance {
+        sequence: u64,
+        occurred_at: DateTime<Utc>,
+        change: f64,
+        balance: f64,
+    },
+    Quote {
+        quote: Quote,
+    },
+    Trade {
+        trade: TradeTick,
+    },
+    /// Symbols the connection now receives market data of
+    Subscribed {
+        symbols: Vec<String>,
+    },
+    /// Sent periodically and in reply to a ping. Resuming from `sequence` loses nothing.
+    Heartbeat {
+        sequence: u64,
+        timestamp: DateTime<Utc>,
+    },
+    /// Events after `sequence` were missed, the account should be reloaded
+    Gap {
+        sequence: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Message sent by the client on the stream
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { symbols: Vec<String> },
+    Unsubscribe { symbols: Vec<String> },
+    Ping,
+}
+
+pub fn router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .with_state(state)
+        .routes(routes!(stream))
+}
+
+/// Stream account events and market data
+///
+/// Upgrades to a WebSocket streaming the execution reports and balance changes of the
+/// authenticated user, and the market data of the symbols subscribed to. Messages are
+/// JSON `ServerMessage`s, the client may send `ClientMessage`s.
+///
+/// Account events carry a sequence. Sequences increase but are not contiguous, and
+/// reconnecting with `since` set to the last one received replays the events missed.
+/// Events are read back from the outbox, so the stream carries those of every instance
+/// and a client may reconnect to any of them.
+#[utoipa::path(
+    get,
+    path = "/",
+    params(StreamQuery),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 401, description = "Missing or invalid token")
+    ),
+    tag = super::STREAM_TAG
+)]
+async fn stream(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let token = query
+        .token
+        .clone()
+        .or_else(|| jwt::extract_token_from_headers(&headers));
+    let Some(user_id) = auth::authenticate(&state, token.as_deref())
+        .await
+        .and_then(|user| user.id)
+    else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    upgrade.on_upgrade(move |socket| run(socket, state, user_id, query))
+}
+
+/// Connection closed or unusable
+struct Disconnected;
+
+/// State of one connection
+struct Session {
+    socket: WebSocket,
+    user_id: UserId,
+    /// Highest sequence of the events handled, whether they were sent or not
+    sequence: u64,
+    symbols: BTreeSet<String>,
+}
+
+impl Session {
+    async fn send(&mut self, message: &ServerMessage) -> Result<(), Disconnected> {
+        let text = serde_json::to_string(message).map_err(|_| Disconnected)?;
+        self.socket
+            .send(Message::Text(text.into()))
+            .await
+            .map_err(|_| Disconnected)
+    }
+
+    async fn forward_event(&mut self, envelope: &EventEnvelope) -> Result<(), Disconnected> {
+        self.sequence = self.sequence.max(envelope.sequence);
+        if envelope.event.user_id() != self.user_id {
+            return Ok(());
+        }
+
+        let message = match &envelope.event {
+            DomainEvent::OrderCreated { .. }
+            | DomainEvent::OrderStatusChanged { .. }
+            | DomainEvent::OrderFilled { .. } => ServerMessage::Execution {
+                sequence: envelope.sequence,
+                occurred_at: envelope.occurred_at,
+                event: envelope.event.clone(),
+            },
+            DomainEvent::FundsDeposited {
+                amount, balance, ..
+            } => ServerMessage::Balance {
+                sequence: envelope.sequence,
+                occurred_at: envelope.occurred_at,
+                change: *amount,
+                balance: *balance,
+            },
+            DomainEvent::FundsWithdrawn {
+                amount, balance, ..
+            } => ServerMessage::Balance {
+                sequence: envelope.sequence,
+                occurred_at: envelope.occurred_at,
+                change: -amount,
+                balance: *balance,
+            },
+        };
+        self.send(&message).await
+    }
+
+    async fn replay(&mut self, events: Vec<EventEnvelope>) -> Result<(), Disconnected> {
+        for envelope in &events {
+            self.forward_event(envelope).await?;
+        }
+        Ok(())
+    }
+
+    /// Replay the events missed after a lag, reporting a gap if some were lost
+    async fn resume(
+        &mut self,
+        events: Vec<EventEnvelope>,
+        complete: bool,
+    ) -> Result<(), Disconnected> {
+        if !complete {
+            self.send(&ServerMessage::Gap {
+                sequence: self.sequence,
+            })
+            .await?;
+        }
+        self.replay(events).await
+    }
+
+    async fn forward_market_data(&mut self, update: MarketDataEvent) -> Result<(), Disconnected> {
+        let message = match update {
+            MarketDataEvent::Quote(quote) if self.symbols.contains(&quote.symbol) => {
+                ServerMessage::Quote { quote }
+            }
+            MarketDataEvent::Trade(trade) if self.symbols.contains(&trade.symbol) => {
+                ServerMessage::Trade { trade }
+            }
+            _ => return Ok(()),
+        };
+        self.send(&message).await
+    }
+
+    async fn heartbeat(&mut self) -> Result<(), Disconnected> {
+        self.send(&ServerMessage::Heartbeat {
+            sequence: self.sequence,
+            timestamp: Utc::now(),
+        })
+        .await
+    }
+
+    /// Subscribe to market data of `symbols`, sending their current quote
+    async fn subscribe(
+        &mut self,
+        state: &AppState,
+        symbols: Vec<String>,
+    ) -> Result<(), Disconnected> {
+        let mut snapshots = Vec::new();
+        for symbol in symbols {
+            match state.broker().quote(&symbol).await {
+                Ok(Some(quote)) => {
+                    self.symbols.insert(symbol);
+                    snapshots.push(quote);
+                }
+                Ok(None) => {
+                    self.send(&ServerMessage::Error {
+                        message: format!("Unknown symbol {symbol}"),
+                    })
+                    .await?;
+                }
+                Err(e) => {
+                    self.send(&ServerMessage::Error {
+                        message: format!("Failed to subscribe to {symbol}: {e}"),
+                    })
+                    .await?;
+                }
+            }
+        }
+
+        self.send(&ServerMessage::Subscribed {
+            symbols: self.symbols.iter().cloned().collect(),
+        })
+        .await?;
+        for quote in snapshots {
+            self.send(&ServerMessage::Quote { quote }).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_client_message(
+        &mut self,
+        state: &AppState,
+        text: &str,
+    ) -> Result<(), Disconnected> {
+        match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Subscribe { symbols }) => self.subscribe(state, symbols).await,
+            Ok(ClientMessage::Unsubscribe { symbols }) => {
+                for symbol in &symbols {
+                    self.symbols.remove(symbol);
+                }
+                self.send(&ServerMessage::Subscribed {
+                    symbols: self.symbols.iter().cloned().collect(),
+                })
+                .await
+            }
+            Ok(ClientMessage::Ping) => self.heartbeat().await,
+            Err(e) => {
+                self.send(&ServerMessage::Error {
+                    message: format!("Invalid message: {e}"),
+                })
+                .await
+            }
+        }
+    }
+}
+
+async fn run(socket: WebSocket, state: AppState, user_id: UserId, query: StreamQuery) {
+    let broker = state.broker();
+    let after = query
+        .since
+        .unwrap_or_else(|| broker.events().last_sequence());
+    let Replay {
+        events,
+        complete,
+        mut receiver,
+    } = broker.events().subscribe_after(after);
+    let mut market_data = broker.market_data().subscribe();
+
+    let mut session = Session {
+        socket,
+        user_id,
+        sequence: after,
+        symbols: BTreeSet::new(),
+    };
+    debug!("User {} connected to the stream after {}", user_id, after);
+
+    let welcome = ServerMessage::Welcome {
+        user_id,
+        sequence: after,
+        complete,
+        heartbeat_interval_secs: HEARTBEAT_INTERVAL.as_secs(),
+    };
+    let mut result = session.send(&welcome).await;
+    if result.is_ok() {
+        result = session.replay(events).await;
+    }
+    if result.is_ok()
+        && let Some(symbols) = &query.symbols
+    {
+        let symbols = symbols
+            .split(',')
+            .map(str::trim)
+            .filter(|symbol| !symbol.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        result = session.subscribe(&state, symbols).await;
+    }
+
+    let mut heartbeat = interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    while result.is_ok() {
+        result = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(envelope) => session.forward_event(&envelope).await,
+                Err(RecvError::Lagged(missed)) => {
+                    // Events dropped by the channel are still in the bus history
+                    warn!("Stream of user {} lagged by {} events", user_id, missed);
+                    let replay = broker.events().subscribe_after(session.sequence);
+                    receiver = replay.receiver;
+                    session.resume(replay.events, replay.complete).await
+                }
+                Err(RecvError::Closed) => Err(Disconnected),
+            },
+            update = market_data.recv() => match update {
+                Ok(update) => session.forward_market_data(update).await,
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Stream of user {} skipped {} market data updates", user_id, missed);
+                    Ok(())
+                }
+                Err(RecvError::Closed) => Err(Disconnected),
+            },
+            message = session.socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    session.handle_client_message(&state, text.as_str()).await
+                }
+                Some(Ok(Message::Close(_)) | Err(_)) | None => Err(Disconnected),
+                // Pings are answered by axum
+                Some(Ok(_)) => Ok(()),
+            },
+            _ = heartbeat.tick() => session.heartbeat().await,
+        };
+    }
+    debug!("User {} disconnected from the stream", user_id);
+}
@@ -0,0 +1,333 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::{StatusCode, header};
+    use chrono::Utc;
+    use domain::clock::Clock;
+    use domain::core::BrokerX;
+    use domain::events::DomainEvent;
+    use domain::market_data::{MarketDataConfig, TradeTick};
+    use domain::order::{OrderSide, OrderType};
+    use domain::user::{UserId, UserRepoExt};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::{self, Message, client::IntoClientRequest};
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    use crate::api::stream::ServerMessage;
+    use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn create_test_setup() -> (String, BrokerHandle, UserId) {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        let user_id = create_user(&broker, "stream@test.com").await;
+        let handle = BrokerHandle::new(broker);
+        (serve(&handle).await, handle, user_id)
+    }
+
+    // The stream runs on a real server so that the connection can be upgraded
+    async fn serve(handle: &BrokerHandle) -> String {
+        let (router, _api) = crate::api::stream::router(handle.clone()).split_for_parts();
+        let app = router.with_state(handle.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("ws://{address}/")
+    }
+
+    async fn create_user(broker: &BrokerX, email: &str) -> UserId {
+        broker
+            .get_user_repo()
+            .await
+            .create_user(
+                email.to_string(),
+                "password123".to_string(),
+                "Stream".to_string(),
+                "User".to_string(),
+                100_000.0,
+            )
+            .await
+            .expect("user creation failed")
+    }
+
+    async fn connect(url: &str, handle: &BrokerHandle, user_id: UserId) -> Client {
+        let mut request = url.into_client_request().unwrap();
+        let authorization = bearer(handle.broker(), user_id, "stream@test.com").await;
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        let (client, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        client
+    }
+
+    async fn next_message(client: &mut Client) -> ServerMessage {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), client.next())
+                .await
+                .expect("no message received")
+                .expect("stream closed")
+                .unwrap();
+            if let Message::Text(text) = frame {
+                return serde_json::from_str(text.as_str()).unwrap();
+            }
+        }
+    }
+
+    async fn buy(handle: &BrokerHandle, user_id: UserId, quantity: u64) {
+        let broker = handle.broker();
+        broker
+            .create_order(
+                user_id,
+                "AAPL".to_string(),
+                quantity,
+                OrderSide::Buy,
+                OrderType::Market,
+            )
+            .await
+            .unwrap();
+        broker.run_until_idle().await;
+    }
+
+    /// Messages until the fill of an order and the balance change it caused
+    async fn messages_until_settled(client: &mut Client) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        let (mut filled, mut settled) = (false, false);
+        while !(filled && settled) {
+            let message = next_message(client).await;
+            match &message {
+                ServerMessage::Execution {
+                    event: DomainEvent::OrderFilled { .. },
+                    ..
+                } => filled = true,
+                ServerMessage::Balance { .. } => settled = true,
+                _ => {}
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_requires_authentication() {
+        let (url, _, _) = create_test_setup().await;
+
+        for url in [url.clone(), format!("{url}?token=not-a-jwt")] {
+            match tokio_tungstenite::connect_async(url).await {
+                Err(tungstenite::Error::Http(response)) => {
+                    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+                }
+                other => panic!("expected the handshake to be refused, got {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_own_execution_reports_and_balance_are_streamed() {
+        let (url, handle, user_id) = create_test_setup().await;
+        let other_id = create_user(handle.broker(), "other@test.com").await;
+        let mut client = connect(&url, &handle, user_id).await;
+        let ServerMessage::Welcome {
+            user_id: welcomed,
+            complete,
+            ..
+        } = next_message(&mut client).await
+        else {
+            panic!("expected a welcome message");
+        };
+        assert_eq!(welcomed, user_id);
+        assert!(complete);
+
+        buy(&handle, other_id, 5).await;
+        buy(&handle, user_id, 10).await;
+
+        let messages = messages_until_settled(&mut client).await;
+        let mut filled = false;
+        for message in &messages {
+            match message {
+                ServerMessage::Execution { event, .. } => {
+                    assert_eq!(event.user_id(), user_id);
+                    if let DomainEvent::OrderFilled { quantity, .. } = event {
+                        assert_eq!(*quantity, 10);
+                        filled = true;
+                    }
+                }
+                ServerMessage::Balance {
+                    change, balance, ..
+                } => {
+                    assert!(*change < 0.0);
+                    assert!((balance - (100_000.0 + change)).abs() < 1e-6);
+                }
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+        assert!(filled);
+
+        client
+            .send(Message::text(r#"{"type":"ping"}"#))
+            .await
+            .unwrap();
+        loop {
+            match next_message(&mut client).await {
+                ServerMessage::Heartbeat { .. } => break,
+                ServerMessage::Execution { event, .. } => assert_eq!(event.user_id(), user_id),
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reconnecting_replays_missed_events() {
+        let (url, handle, user_id) = create_test_setup().await;
+        let mut client = connect(&url, &handle, user_id).await;
+        next_message(&mut client).await;
+        buy(&handle, user_id, 1).await;
+        let last_sequence = messages_until_settled(&mut client)
+            .await
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Execution { sequence, .. }
+                | ServerMessage::Balance { sequence, .. } => Some(*sequence),
+                _ => None,
+            })
+            .max()
+            .unwrap();
+        client.close(None).await.unwrap();
+
+        // Events published while disconnected
+        buy(&handle, user_id, 2).await;
+        handle.broker().deposit(&user_id, 50.0).await.unwrap();
+
+        let mut client = connect(&format!("{url}?since={last_sequence}"), &handle, user_id).await;
+        let ServerMessage::Welcome {
+            sequence, complete, ..
+        } = next_message(&mut client).await
+        else {
+            panic!("expected a welcome message");
+        };
+        assert_eq!(sequence, last_sequence);
+        assert!(complete);
+
+        let mut replayed = messages_until_settled(&mut client).await;
+        while !matches!(
+            replayed.last(),
+            Some(ServerMessage::Balance { change, .. }) if *change > 0.0
+        ) {
+            replayed.push(next_message(&mut client).await);
+        }
+        assert!(replayed.iter().any(|message| matches!(
+            message,
+            ServerMessage::Execution {
+                event: DomainEvent::OrderFilled { quantity: 2, .. },
+                ..
+            }
+        )));
+        assert!(replayed.iter().all(|message| match message {
+            ServerMessage::Execution { sequence, .. } | ServerMessage::Balance { sequence, .. } =>
+                *sequence > last_sequence,
+            _ => false,
+        }));
+
+        // A sequence this server never reached cannot be resumed from
+        let mut client = connect(&format!("{url}?since=1000000"), &handle, user_id).await;
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Welcome {
+                complete: false,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_events_of_other_instances_are_streamed() {
+        let broker = BrokerX::new_for_testing_with_thread_count(0).await;
+        let replica = broker.new_for_testing_sharing_tables(0).await;
+        let user_id = create_user(&broker, "stream@test.com").await;
+        let handle = BrokerHandle::new(broker);
+        let url = serve(&handle).await;
+        let mut client = connect(&url, &handle, user_id).await;
+        let ServerMessage::Welcome { sequence, .. } = next_message(&mut client).await else {
+            panic!("expected a welcome message");
+        };
+
+        replica.deposit(&user_id, 50.0).await.unwrap();
+        let ServerMessage::Balance {
+            sequence: deposited,
+            change,
+            ..
+        } = next_message(&mut client).await
+        else {
+            panic!("expected the deposit made on the replica");
+        };
+        assert!((change - 50.0).abs() < 1e-9);
+        assert!(deposited > sequence);
+        client.close(None).await.unwrap();
+
+        // Sequences are shared, so the client can resume on the other instance
+        replica.deposit(&user_id, 25.0).await.unwrap();
+        let replica = BrokerHandle::new(replica);
+        let replica_url = serve(&replica).await;
+        let mut client = connect(
+            &format!("{replica_url}?since={deposited}"),
+            &replica,
+            user_id,
+        )
+        .await;
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Welcome { complete: true, .. }
+        ));
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Balance { change, .. } if (change - 25.0).abs() < 1e-9
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_market_data_of_subscribed_symbols() {
+        let (url, handle, user_id) = create_test_setup().await;
+        let mut client = connect(&format!("{url}?symbols=AAPL"), &handle, user_id).await;
+        next_message(&mut client).await;
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Subscribed { symbols } if symbols == ["AAPL"]
+        ));
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Quote { quote } if quote.symbol == "AAPL"
+        ));
+
+        client
+            .send(Message::text(r#"{"type":"subscribe","symbols":["NOPE"]}"#))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Error { .. }
+        ));
+        assert!(matches!(
+            next_message(&mut client).await,
+            ServerMessage::Subscribed { symbols } if symbols == ["AAPL"]
+        ));
+
+        let feed = handle.broker().market_data();
+        for (symbol, price) in [("MSFT", 400.0), ("AAPL", 151.0)] {
+            feed.publish_trade(TradeTick {
+                symbol: symbol.to_string(),
+                price,
+                quantity: 3,
+                timestamp: Utc::now(),
+            });
+        }
+        let trade = loop {
+            if let ServerMessage::Trade { trade } = next_message(&mut client).await {
+                break trade;
+            }
+        };
+        assert_eq!(trade.symbol, "AAPL");
+        assert_eq!(trade.quantity, 3);
+    }
+}
@@ -1,15 +1,24 @@
+use std::collections::HashMap;
+
 use axum::{Json, extract::Path, extract::State, http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Utc};
 use domain::Repository;
-use domain::user::{User, UserRepoExt};
+use domain::notifications::SecurityEvent;
+use domain::portfolio::Holding;
+use domain::session::{Session, SessionId, SessionRepoExt};
+use domain::user::{AuthError, Locale, Role, User, UserRepoExt, validate_password};
 use serde::{Deserialize, Serialize};
+use tracing::error;
 use utoipa::ToSchema;
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
 use uuid::Uuid;
 
 use super::AppState;
+use super::auth::{Admin, Caller};
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
@@ -22,6 +31,77 @@ pub struct UpdateUserRequest {
     pub email: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
+    /// Language of the emails sent to the user, `en` or `fr`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "fr")]
+    pub locale: Option<Locale>,
+}
+
+/// User as returned by the API, without the password hash
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct UserResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub firstname: String,
+    pub surname: String,
+    pub balance: f64,
+    pub is_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub holdings: HashMap<String, Holding>,
+    pub role: Role,
+    /// Logins are refused until then, after too many failures
+    pub locked_until: Option<DateTime<Utc>>,
+    /// Language of the emails sent to the user
+    #[schema(value_type = String, example = "en")]
+    pub locale: Locale,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id.unwrap_or_default(),
+            email: user.email,
+            firstname: user.firstname,
+            surname: user.surname,
+            balance: user.balance,
+            is_verified: user.is_verified,
+            created_at: user.created_at,
+            holdings: user.holdings,
+            role: user.role,
+            locked_until: user.locked_until,
+            locale: user.locale,
+        }
+    }
+}
+
+/// Device a user is logged in on
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    /// Whether this is the session of the request's token
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session_id: SessionId, session: Session, caller: &Caller) -> Self {
+        Self {
+            id: session_id,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+            user_agent: session.user_agent,
+            current: session_id == caller.session_id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct UpdateRoleRequest {
+    pub role: Role,
 }
 
 pub fn router(state: AppState) -> OpenApiRouter<AppState> {
@@ -29,11 +109,15 @@ pub fn router(state: AppState) -> OpenApiRouter<AppState> {
         .with_state(state)
         .routes(routes!(get_user, put_user, post_user))
         .routes(routes!(get_orders_from_user))
+        .routes(routes!(put_role))
+        .routes(routes!(delete_lock))
+        .routes(routes!(get_sessions, delete_sessions))
+        .routes(routes!(delete_session))
 }
 
 /// Get user by UUID
 ///
-/// Get a specific user by their UUID
+/// Get a specific user by their UUID. Clients can only get themselves.
 #[utoipa::path(
     get,
     path = "/{user_id}",
@@ -41,16 +125,27 @@ pub fn router(state: AppState) -> OpenApiRouter<AppState> {
         ("user_id" = Uuid, Path, description = "User UUID")
     ),
     responses(
-        (status = 200, description = "User found", body = User),
+        (status = 200, description = "User found", body = UserResponse),
         (status = 404, description = "User not found"),
-        (status = 400, description = "Invalid UUID format")
+        (status = 400, description = "Invalid UUID format"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user")
     ),
+    security(("bearer_auth" = [])),
     tag = super::USER_TAG
 )]
-async fn get_user(State(state): State<AppState>, Path(user_id): Path<Uuid>) -> impl IntoResponse {
+async fn get_user(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     let user_repo = state.broker().get_user_repo().await;
     match user_repo.get(&user_id).await {
-        Ok(Some(user)) => Json(user).into_response(),
+        Ok(Some(user)) => Json(UserResponse::from(user)).into_response(),
         Ok(None) => StatusCode::NOT_FOUND.into_response(),
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
@@ -60,6 +155,8 @@ async fn get_user(State(state): State<AppState>, Path(user_id): Path<Uuid>) -> i
 /// Create a new user with the specified UUID, or update an existing user.
 /// For creation, all fields (firstname, surname, email, password) are required.
 /// For updates, all fields are optional and only provided fields will be updated.
+/// Changing the password revokes every session of the user.
+/// Clients can only update themselves, and only admins can create users this way.
 #[utoipa::path(
     put,
     path = "/{user_id}",
@@ -68,18 +165,27 @@ async fn get_user(State(state): State<AppState>, Path(user_id): Path<Uuid>) -> i
     ),
     request_body = UpdateUserRequest,
     responses(
-        (status = 200, description = "User updated successfully", body = User),
-        (status = 201, description = "User created successfully", body = User),
+        (status = 200, description = "User updated successfully", body = UserResponse),
+        (status = 201, description = "User created successfully", body = UserResponse),
         (status = 400, description = "Invalid request data or missing required fields for creation"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user"),
+        (status = 404, description = "User deleted during the update"),
         (status = 500, description = "Internal server error")
     ),
+    security(("bearer_auth" = [])),
     tag = super::USER_TAG
 )]
 async fn put_user(
     State(state): State<AppState>,
+    caller: Caller,
     Path(user_id): Path<Uuid>,
     Json(payload): Json<UpdateUserRequest>,
 ) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     let broker = state.broker();
     let user_repo = broker.get_user_repo().await;
 
@@ -97,25 +203,53 @@ async fn put_user(
     }
 
     let (user, is_creation) = match user_repo.get(&user_id).await {
-        Ok(Some(user)) => {
-            // Update existing user
-            let mut updated_user = user;
-            if let Some(firstname) = payload.firstname {
-                updated_user.firstname = firstname;
+        Ok(Some(_)) => {
+            // Update existing user, writing only the fields of the payload so that the
+            // balance, holdings and security fields written meanwhile are kept
+            if let Some(password) = &payload.password
+                && let Err(e) = validate_password(password)
+            {
+                return (StatusCode::BAD_REQUEST, format!("Password error: {e}")).into_response();
+            }
+            let password_changed = payload.password.is_some();
+            let written = async {
+                user_repo
+                    .update_profile(&user_id, payload.firstname, payload.surname, payload.email)
+                    .await?;
+                if let Some(locale) = payload.locale {
+                    user_repo.set_locale(&user_id, locale).await?;
+                }
+                if let Some(password) = &payload.password {
+                    user_repo.set_password(&user_id, password).await?;
+                }
+                Ok::<_, AuthError>(())
+            };
+            match written.await {
+                Ok(()) => {}
+                Err(AuthError::UserNotFound) => return StatusCode::NOT_FOUND.into_response(),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
             }
-            if let Some(surname) = payload.surname {
-                updated_user.surname = surname;
+            // Whoever knew the old password must not stay logged in
+            if password_changed
+                && let Err(e) = broker
+                    .get_session_repo()
+                    .await
+                    .revoke_all_sessions(&user_id)
+                    .await
+            {
+                error!("Could not revoke the sessions of user {user_id}: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
-            if let Some(email) = payload.email {
-                updated_user.email = email;
+            if password_changed {
+                broker
+                    .send_security_alert(&user_id, SecurityEvent::PasswordChanged)
+                    .await;
             }
-            if let Some(password) = payload.password {
-                if let Err(e) = updated_user.update_password(&password) {
-                    return (StatusCode::BAD_REQUEST, format!("Password error: {e}"))
-                        .into_response();
-                }
+            match user_repo.get(&user_id).await {
+                Ok(Some(updated_user)) => (updated_user, false), // false = not a creation, it's an update
+                Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+                Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
             }
-            (updated_user, false) // false = not a creation, it's an update
         }
         Ok(None) => {
             // Create new user - all required fields must be provided for creation
@@ -155,6 +289,9 @@ async fn put_user(
                 }
             };
             new_user.id = Some(user_id);
+            if let Some(locale) = payload.locale {
+                new_user.locale = locale;
+            }
 
             match user_repo.insert(user_id, new_user.clone()).await {
                 Ok(()) => (new_user, true),
@@ -173,7 +310,7 @@ async fn put_user(
     } else {
         StatusCode::OK
     };
-    (status, Json(user)).into_response()
+    (status, Json(UserResponse::from(user))).into_response()
 }
 
 /// Create a new user
@@ -184,7 +321,7 @@ async fn put_user(
     path = "/",
     request_body = UpdateUserRequest,
     responses(
-        (status = 201, description = "User created successfully", body = User),
+        (status = 201, description = "User created successfully", body = UserResponse),
         (status = 400, description = "Invalid request data or missing required fields for creation"),
         (status = 500, description = "Internal server error")
     ),
@@ -256,12 +393,12 @@ async fn post_user(
             .into_response();
     };
 
-    (StatusCode::CREATED, Json(user)).into_response()
+    (StatusCode::CREATED, Json(UserResponse::from(user))).into_response()
 }
 
 /// Get user's orders
 ///
-/// Get orders from a specific user by their UUID
+/// Get orders from a specific user by their UUID. Clients can only get their own.
 #[utoipa::path(
     get,
     path = "/{user_id}/orders",
@@ -270,16 +407,227 @@ async fn post_user(
     ),
     responses(
         (status = 200, description = "User found", body = User),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user"),
         (status = 500, description = "Database error"),
     ),
+    security(("bearer_auth" = [])),
     tag = super::USER_TAG
 )]
 async fn get_orders_from_user(
     State(state): State<AppState>,
+    caller: Caller,
     Path(user_id): Path<Uuid>,
 ) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     match state.broker().get_orders_for_user(&user_id).await {
         Ok(orders) => Json(orders).into_response(),
         Err(_e) => StatusCode::INTERNAL_SERVER_ERROR.into_response(), // TODO: be finer here
     }
 }
+
+/// Change the role of a user
+///
+/// Grant or revoke the admin role. Only admins can change roles.
+#[utoipa::path(
+    put,
+    path = "/{user_id}/role",
+    params(
+        ("user_id" = Uuid, Path, description = "User UUID")
+    ),
+    request_body = UpdateRoleRequest,
+    responses(
+        (status = 200, description = "Role changed", body = UserResponse),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::USER_TAG
+)]
+async fn put_role(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<UpdateRoleRequest>,
+) -> impl IntoResponse {
+    let user_repo = state.broker().get_user_repo().await;
+    match user_repo.set_role(&user_id, payload.role).await {
+        Ok(()) => {}
+        Err(AuthError::UserNotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    match user_repo.get(&user_id).await {
+        Ok(Some(user)) => Json(UserResponse::from(user)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Unlock an account
+///
+/// Lift the lock set after too many failed logins and forget the failures. Only admins
+/// can unlock accounts, otherwise the lock expires on its own.
+#[utoipa::path(
+    delete,
+    path = "/{user_id}/lock",
+    params(
+        ("user_id" = Uuid, Path, description = "User UUID")
+    ),
+    responses(
+        (status = 200, description = "Account unlocked", body = UserResponse),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not an admin"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::USER_TAG
+)]
+async fn delete_lock(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.broker().unlock_user(&user_id).await {
+        Ok(()) => {}
+        Err(AuthError::UserNotFound) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    match state.broker().get_user_repo().await.get(&user_id).await {
+        Ok(Some(user)) => Json(UserResponse::from(user)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// List the sessions of a user
+///
+/// Active sessions of a user, most recently used first. Clients can only list their own.
+#[utoipa::path(
+    get,
+    path = "/{user_id}/sessions",
+    params(
+        ("user_id" = Uuid, Path, description = "User UUID")
+    ),
+    responses(
+        (status = 200, description = "Active sessions", body = Vec<SessionResponse>),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::USER_TAG
+)]
+async fn get_sessions(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state
+        .broker()
+        .get_session_repo()
+        .await
+        .active_sessions(&user_id)
+        .await
+    {
+        Ok(sessions) => Json(
+            sessions
+                .into_iter()
+                .map(|(session_id, session)| SessionResponse::new(session_id, session, &caller))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Revoke all sessions of a user
+///
+/// Log a user out of every device, including the one making the request. Clients can
+/// only revoke their own sessions.
+#[utoipa::path(
+    delete,
+    path = "/{user_id}/sessions",
+    params(
+        ("user_id" = Uuid, Path, description = "User UUID")
+    ),
+    responses(
+        (status = 204, description = "Sessions revoked"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::USER_TAG
+)]
+async fn delete_sessions(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match state
+        .broker()
+        .get_session_repo()
+        .await
+        .revoke_all_sessions(&user_id)
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Revoke a session
+///
+/// Log a user out of one device. Access tokens issued for the session stop working at
+/// once. Clients can only revoke their own sessions.
+#[utoipa::path(
+    delete,
+    path = "/{user_id}/sessions/{session_id}",
+    params(
+        ("user_id" = Uuid, Path, description = "User UUID"),
+        ("session_id" = Uuid, Path, description = "Session UUID")
+    ),
+    responses(
+        (status = 204, description = "Session revoked"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Another user"),
+        (status = 404, description = "Session not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(("bearer_auth" = [])),
+    tag = super::USER_TAG
+)]
+async fn delete_session(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path((user_id, session_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    if !caller.can_act_for(&user_id) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let session_repo = state.broker().get_session_repo().await;
+    match session_repo.get(&session_id).await {
+        Ok(Some(session)) if session.user_id == user_id => {}
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    match session_repo.revoke_session(&session_id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -5,15 +5,23 @@ mod tests {
         body::Body,
         http::{Method, Request, StatusCode},
     };
-    use domain::user::{User, UserRepoExt};
+    use domain::user::{Role, UserRepoExt};
     use serde_json::Value;
     use tower::ServiceExt; // for `oneshot`
     use uuid::Uuid;
 
+    use crate::api::user::{SessionResponse, UserResponse};
     use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
 
-    // Create test setup that is isolated and consistent
-    async fn create_test_setup() -> (Router, Uuid) {
+    // Create test setup that is isolated and consistent, with the authorization headers
+    // of the user and of an admin
+    async fn create_test_setup() -> (Router, Uuid, String, String) {
+        let (router, user_id, token, admin, _) = create_test_setup_with_broker().await;
+        (router, user_id, token, admin)
+    }
+
+    async fn create_test_setup_with_broker() -> (Router, Uuid, String, String, BrokerHandle) {
         // Use a unique ID for this test to avoid conflicts
         let test_id = Uuid::new_v4();
         let test_id_str = test_id.to_string();
@@ -44,20 +52,42 @@ mod tests {
             }
         };
 
+        let admin_email = format!("admin-{}@test.com", &test_id_str[..8]);
+        let admin_id = user_repo
+            .create_user(
+                admin_email.clone(),
+                "password123".to_string(),
+                "Admin".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .expect("admin creation failed");
+        user_repo.set_role(&admin_id, Role::Admin).await.unwrap();
+
+        let token = bearer(&broker, test_user_id, &test_email).await;
+        let admin = bearer(&broker, admin_id, &admin_email).await;
         let handle = BrokerHandle::new(broker);
         let (router, _api) = crate::api::user::router(handle.clone()).split_for_parts();
-        (router.with_state(handle), test_user_id)
+        (
+            router.with_state(handle.clone()),
+            test_user_id,
+            token,
+            admin,
+            handle,
+        )
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_user_success() {
-        let (app, test_user_id) = create_test_setup().await;
+        let (app, test_user_id, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{}", test_user_id))
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -69,9 +99,10 @@ mod tests {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let user: User = serde_json::from_slice(&body).unwrap();
+        let user: UserResponse = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(user.id, Some(test_user_id));
+        assert_eq!(user.id, test_user_id);
+        assert_eq!(user.role, Role::Client);
         assert!(user.email.starts_with("test-") && user.email.ends_with("@test.com"));
         assert_eq!(user.firstname, "Test");
         assert_eq!(user.surname, "User");
@@ -81,7 +112,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_user_not_found() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
         let non_existent_id = Uuid::new_v4();
 
         let response = app
@@ -89,6 +120,7 @@ mod tests {
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{}", non_existent_id))
+                    .header("authorization", &admin)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -100,13 +132,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_user_invalid_uuid() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri("/invalid-uuid")
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -119,13 +152,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_orders_from_user_empty() {
-        let (app, user_id) = create_test_setup().await;
+        let (app, user_id, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{user_id}/orders"))
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -143,7 +177,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_orders_from_nonexistent_user() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
         let non_existent_id = Uuid::new_v4();
 
         let response = app
@@ -151,6 +185,7 @@ mod tests {
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{non_existent_id}/orders"))
+                    .header("authorization", &admin)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -168,13 +203,14 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_orders_from_user_invalid_uuid() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri("/invalid-uuid/orders")
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -187,13 +223,14 @@ mod tests {
     // Integration test with actual order creation
     #[tokio::test(flavor = "multi_thread")]
     async fn test_get_orders_from_user_with_orders() {
-        let (app, user_id) = create_test_setup().await;
+        let (app, user_id, token, _) = create_test_setup().await;
 
         let response = app
             .oneshot(
                 Request::builder()
                     .method(Method::GET)
                     .uri(format!("/{user_id}/orders"))
+                    .header("authorization", &token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -212,7 +249,7 @@ mod tests {
     // Test error handling for database errors
     #[tokio::test(flavor = "multi_thread")]
     async fn test_error_handling() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
 
         // Test various UUID formats
         let test_cases = vec![
@@ -230,6 +267,7 @@ mod tests {
                     Request::builder()
                         .method(Method::GET)
                         .uri(format!("/{uuid_str}"))
+                        .header("authorization", &admin)
                         .body(Body::empty())
                         .unwrap(),
                 )
@@ -243,7 +281,7 @@ mod tests {
     // Comprehensive integration test for the PUT endpoint
     #[tokio::test(flavor = "multi_thread")]
     async fn test_put_user_update_existing() {
-        let (app, user_id) = create_test_setup().await;
+        let (app, user_id, token, _) = create_test_setup().await;
 
         // Test updating the user's first name
         let update_request = r#"{"firstname": "UpdatedName"}"#;
@@ -254,6 +292,7 @@ mod tests {
                 Request::builder()
                     .method(Method::PUT)
                     .uri(format!("/{user_id}"))
+                    .header("authorization", &token)
                     .header("content-type", "application/json")
                     .body(Body::from(update_request))
                     .unwrap(),
@@ -266,7 +305,7 @@ mod tests {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let updated_user: User = serde_json::from_slice(&body).unwrap();
+        let updated_user: UserResponse = serde_json::from_slice(&body).unwrap();
 
         assert_eq!(updated_user.firstname, "UpdatedName");
         assert_eq!(updated_user.surname, "User"); // Should remain unchanged
@@ -277,7 +316,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_put_user_create_new() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
         let new_user_id = Uuid::new_v4();
 
         // Generate a unique email to avoid conflicts
@@ -301,6 +340,7 @@ mod tests {
                 Request::builder()
                     .method(Method::PUT)
                     .uri(format!("/{new_user_id}"))
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(create_request))
                     .unwrap(),
@@ -313,9 +353,9 @@ mod tests {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let created_user: User = serde_json::from_slice(&body).unwrap();
+        let created_user: UserResponse = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(created_user.id, Some(new_user_id));
+        assert_eq!(created_user.id, new_user_id);
         assert_eq!(created_user.firstname, "NewUser");
         assert_eq!(created_user.surname, "Created");
         assert_eq!(created_user.email, unique_email);
@@ -325,7 +365,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_put_user_validation_errors() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, admin) = create_test_setup().await;
         let new_user_id = Uuid::new_v4();
 
         // Test creating user with invalid email
@@ -342,6 +382,7 @@ mod tests {
                 Request::builder()
                     .method(Method::PUT)
                     .uri(format!("/{new_user_id}"))
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(invalid_email_request))
                     .unwrap(),
@@ -359,6 +400,7 @@ mod tests {
                 Request::builder()
                     .method(Method::PUT)
                     .uri(format!("/{new_user_id}"))
+                    .header("authorization", &admin)
                     .header("content-type", "application/json")
                     .body(Body::from(incomplete_request))
                     .unwrap(),
@@ -371,7 +413,7 @@ mod tests {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_post_user_create() {
-        let (app, _) = create_test_setup().await;
+        let (app, _, _, _) = create_test_setup().await;
 
         // Generate a unique email to avoid conflicts
         let unique_email = format!(
@@ -408,13 +450,227 @@ mod tests {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        let created_user: User = serde_json::from_slice(&body).unwrap();
+        let created_user: UserResponse = serde_json::from_slice(&body).unwrap();
 
-        assert!(created_user.id.is_some());
+        assert!(!created_user.id.is_nil());
         assert_eq!(created_user.firstname, "PostUser");
         assert_eq!(created_user.surname, "Created");
         assert_eq!(created_user.email, unique_email);
         assert_eq!(created_user.balance, 0.0);
         assert!(!created_user.is_verified);
     }
+
+    async fn send(
+        app: Router,
+        method: Method,
+        uri: &str,
+        authorization: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let response = app
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_users_require_a_valid_token() {
+        let (app, user_id, _, _) = create_test_setup().await;
+        let uri = format!("/{user_id}");
+
+        let (status, _) = send(app.clone(), Method::GET, &uri, None, "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(app, Method::GET, &uri, Some("Bearer not-a-jwt"), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_clients_only_act_on_themselves() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let other_id = Uuid::new_v4();
+
+        for uri in [format!("/{other_id}"), format!("/{other_id}/orders")] {
+            let (status, _) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
+        // Not even to create an account with a chosen ID
+        let create_request = r#"{"firstname": "A", "surname": "B", "email": "a@test.com", "password": "password123"}"#;
+        let uri = format!("/{other_id}");
+        let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), create_request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Admins read everyone, and the password hash is never returned
+        let uri = format!("/{user_id}");
+        let (status, body) = send(app, Method::GET, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let user: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user["id"], user_id.to_string());
+        assert!(user.get("password_hash").is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_only_admins_change_roles() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let uri = format!("/{user_id}/role");
+        let promote = r#"{"role": "admin"}"#;
+
+        let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), promote).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(app.clone(), Method::PUT, &uri, Some(&admin), promote).await;
+        assert_eq!(status, StatusCode::OK);
+        let user: UserResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.role, Role::Admin);
+
+        // The new role applies to the tokens already issued
+        let uri = format!("/{}", Uuid::new_v4());
+        let (status, _) = send(app, Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_only_admins_unlock_accounts() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let uri = format!("/{user_id}/lock");
+
+        let (status, _) = send(app.clone(), Method::DELETE, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(app.clone(), Method::DELETE, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let user: UserResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.locked_until, None);
+
+        let uri = format!("/{}/lock", Uuid::new_v4());
+        let (status, _) = send(app, Method::DELETE, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_put_user_persists_updates() {
+        let (app, user_id, token, _) = create_test_setup().await;
+        let uri = format!("/{user_id}");
+
+        let update = r#"{"firstname": "Persisted", "surname": "Name"}"#;
+        let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), update).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(app, Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let user: UserResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.firstname, "Persisted");
+        assert_eq!(user.surname, "Name");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_put_user_keeps_concurrent_deposits() {
+        let (app, user_id, token, _, handle) = create_test_setup_with_broker().await;
+        let uri = format!("/{user_id}");
+        let (_, body) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        let before: UserResponse = serde_json::from_slice(&body).unwrap();
+
+        // Deposits land between the client's read and its updates, and during them
+        let deposits = async {
+            for _ in 0..20 {
+                handle.broker().deposit(&user_id, 10.0).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        };
+        let updates = async {
+            for i in 0..20 {
+                let update = format!(r#"{{"firstname": "Name {i}"}}"#);
+                let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), &update).await;
+                assert_eq!(status, StatusCode::OK);
+            }
+        };
+        tokio::join!(deposits, updates);
+
+        let (_, body) = send(app, Method::GET, &uri, Some(&token), "").await;
+        let after: UserResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(after.firstname, "Name 19");
+        assert!((after.balance - (before.balance + 200.0)).abs() < 1e-9);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_password_change_revokes_all_sessions() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let uri = format!("/{user_id}");
+
+        // Other updates leave the sessions alone
+        let rename = r#"{"firstname": "Renamed"}"#;
+        let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), rename).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let change = r#"{"password": "new-password"}"#;
+        let (status, _) = send(app.clone(), Method::PUT, &uri, Some(&token), change).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Sessions of other users are not affected
+        let (status, _) = send(app, Method::GET, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sessions_are_listed_and_revoked() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let uri = format!("/{user_id}/sessions");
+
+        let (status, body) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let sessions: Vec<SessionResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+        let session_id = sessions[0].id;
+
+        // Only sessions of the user can be revoked through their path
+        let other = format!("{uri}/{}", Uuid::new_v4());
+        let (status, _) = send(app.clone(), Method::DELETE, &other, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(app.clone(), Method::GET, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let sessions: Vec<SessionResponse> = serde_json::from_slice(&body).unwrap();
+        assert!(!sessions[0].current);
+
+        let session = format!("{uri}/{session_id}");
+        let (status, _) = send(app.clone(), Method::DELETE, &session, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(app, Method::GET, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let sessions: Vec<SessionResponse> = serde_json::from_slice(&body).unwrap();
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_log_out_everywhere() {
+        let (app, user_id, token, admin) = create_test_setup().await;
+        let uri = format!("/{user_id}/sessions");
+
+        let (status, _) = send(app.clone(), Method::DELETE, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(app.clone(), Method::GET, &uri, Some(&token), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(app, Method::GET, &uri, Some(&admin), "").await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
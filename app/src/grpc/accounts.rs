@@ -0,0 +1,97 @@
+use domain::Repository;
+use domain::user::AuthError;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use super::{AppState, authorize, caller, internal, parse_id, pb};
+
+pub struct AccountApi {
+    state: AppState,
+}
+
+impl AccountApi {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    async fn account(&self, user_id: &Uuid) -> Result<pb::Account, Status> {
+        let user = self
+            .state
+            .broker()
+            .get_user_repo()
+            .await
+            .get(user_id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::not_found("User not found"))?;
+
+        let mut holdings: Vec<pb::Holding> = user
+            .get_holdings_list()
+            .into_iter()
+            .map(pb::Holding::from)
+            .collect();
+        holdings.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        Ok(pb::Account {
+            user_id: user_id.to_string(),
+            balance: user.balance,
+            holdings,
+        })
+    }
+}
+
+/// User and amount of a deposit or withdrawal
+fn funds_request(request: &pb::FundsRequest) -> Result<(Uuid, f64), Status> {
+    let user_id = parse_id(&request.user_id, "user_id")?;
+    if !request.amount.is_finite() || request.amount <= 0.0 {
+        return Err(Status::invalid_argument("amount must be positive"));
+    }
+    Ok((user_id, request.amount))
+}
+
+fn funds_error(error: AuthError) -> Status {
+    match error {
+        AuthError::UserNotFound => Status::not_found("User not found"),
+        AuthError::NotEnoughMoneyError => Status::failed_precondition("Insufficient funds"),
+        e => internal(e),
+    }
+}
+
+#[tonic::async_trait]
+impl pb::account_service_server::AccountService for AccountApi {
+    async fn get_account(
+        &self,
+        request: Request<pb::GetAccountRequest>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let user_id = parse_id(&request.get_ref().user_id, "user_id")?;
+        authorize(&caller(&request)?, &user_id)?;
+        Ok(Response::new(self.account(&user_id).await?))
+    }
+
+    async fn deposit(
+        &self,
+        request: Request<pb::FundsRequest>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let (user_id, amount) = funds_request(request.get_ref())?;
+        authorize(&caller(&request)?, &user_id)?;
+        self.state
+            .broker()
+            .deposit(&user_id, amount)
+            .await
+            .map_err(funds_error)?;
+        Ok(Response::new(self.account(&user_id).await?))
+    }
+
+    async fn withdraw(
+        &self,
+        request: Request<pb::FundsRequest>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let (user_id, amount) = funds_request(request.get_ref())?;
+        authorize(&caller(&request)?, &user_id)?;
+        self.state
+            .broker()
+            .withdraw(&user_id, amount)
+            .await
+            .map_err(funds_error)?;
+        Ok(Response::new(self.account(&user_id).await?))
+    }
+}
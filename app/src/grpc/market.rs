@@ -0,0 +1,90 @@
+use tonic::{Request, Response, Status};
+
+use super::{AppState, candle_interval, from_timestamp, internal, pb, timestamp};
+
+const DEFAULT_BOOK_DEPTH: usize = 10;
+const MAX_BOOK_DEPTH: usize = 100;
+const DEFAULT_CANDLE_COUNT: i32 = 100;
+const MAX_CANDLE_COUNT: i32 = 1000;
+
+pub struct MarketDataApi {
+    state: AppState,
+}
+
+impl MarketDataApi {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::market_data_service_server::MarketDataService for MarketDataApi {
+    async fn get_quote(
+        &self,
+        request: Request<pb::GetQuoteRequest>,
+    ) -> Result<Response<pb::Quote>, Status> {
+        let symbol = &request.get_ref().symbol;
+        let broker = self.state.broker();
+        match broker.quote(symbol).await {
+            Ok(Some(quote)) => Ok(Response::new(pb::Quote {
+                volume: broker.market_data().volume(symbol),
+                symbol: quote.symbol,
+                bid: quote.bid,
+                ask: quote.ask,
+                last: quote.last,
+                timestamp: Some(timestamp(quote.timestamp)),
+            })),
+            Ok(None) => Err(Status::not_found("Instrument not found")),
+            Err(e) => Err(internal(e)),
+        }
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<pb::GetOrderBookRequest>,
+    ) -> Result<Response<pb::OrderBook>, Status> {
+        let request = request.get_ref();
+        let depth = request
+            .depth
+            .map_or(DEFAULT_BOOK_DEPTH, |depth| depth as usize)
+            .clamp(1, MAX_BOOK_DEPTH);
+
+        match self.state.broker().order_book(&request.symbol, depth).await {
+            Ok(Some(book)) => Ok(Response::new(pb::OrderBook::from(&book))),
+            Ok(None) => Err(Status::not_found("Instrument not found")),
+            Err(e) => Err(internal(e)),
+        }
+    }
+
+    async fn get_candles(
+        &self,
+        request: Request<pb::GetCandlesRequest>,
+    ) -> Result<Response<pb::GetCandlesResponse>, Status> {
+        let request = request.into_inner();
+        let broker = self.state.broker();
+        let interval = candle_interval(request.interval)?;
+        let length = interval.duration();
+        let to = match &request.to {
+            Some(to) => from_timestamp(to)?,
+            None => broker.clock().now(),
+        };
+        let from = match &request.from {
+            Some(from) => from_timestamp(from)?,
+            None => to - length * DEFAULT_CANDLE_COUNT,
+        }
+        .max(to - length * MAX_CANDLE_COUNT);
+        if from >= to {
+            return Err(Status::invalid_argument("from must be before to"));
+        }
+
+        match broker.candles(&request.symbol, interval, from, to).await {
+            Ok(Some(candles)) => Ok(Response::new(pb::GetCandlesResponse {
+                symbol: request.symbol,
+                interval: pb::CandleInterval::from(interval).into(),
+                candles: candles.iter().map(pb::Candle::from).collect(),
+            })),
+            Ok(None) => Err(Status::not_found("Instrument not found")),
+            Err(e) => Err(internal(e)),
+        }
+    }
+}
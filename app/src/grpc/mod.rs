@@ -0,0 +1,272 @@
+//! gRPC API, served by the same listener as the REST API.
+//!
+//! The service definition is `proto/brokerx/v1/brokerx.proto`. Requests are routed to
+//! the gRPC services by path, so they only need HTTP/2 with `application/grpc`.
+//!
+//! Like the REST API, calls other than user registration and market data need the JWT
+//! issued at login as `authorization: Bearer` metadata, and clients can only act on
+//! their own account and orders.
+
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::{Router, extract::State};
+use chrono::{DateTime, Utc};
+use domain::candles::{Candle, CandleInterval};
+use domain::order::{Order, OrderId, OrderSide, OrderStatus, OrderType};
+use domain::order_book::{OrderBook, PriceLevel};
+use domain::portfolio::Holding;
+use domain::user::{User, UserId};
+use tonic::service::Routes;
+use tonic::{Request, Status};
+use uuid::Uuid;
+
+use crate::api::auth::Caller;
+use crate::services::BrokerHandle;
+use crate::web::jwt;
+
+mod accounts;
+mod market;
+mod orders;
+mod users;
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
+/// Messages and services of the `brokerx.v1` package
+pub mod pb {
+    include!("generated/brokerx.v1.rs");
+}
+
+pub type AppState = BrokerHandle;
+
+/// Router serving every gRPC service
+pub fn create_grpc(state: AppState) -> Router {
+    Routes::new(pb::user_service_server::UserServiceServer::new(
+        users::UserApi::new(state.clone()),
+    ))
+    .add_service(pb::account_service_server::AccountServiceServer::new(
+        accounts::AccountApi::new(state.clone()),
+    ))
+    .add_service(pb::order_service_server::OrderServiceServer::new(
+        orders::OrderApi::new(state.clone()),
+    ))
+    .add_service(
+        pb::market_data_service_server::MarketDataServiceServer::new(market::MarketDataApi::new(
+            state.clone(),
+        )),
+    )
+    .into_axum_router()
+    .layer(middleware::from_fn_with_state(state, authenticate))
+}
+
+/// Attach the `Caller` of calls made with a valid token. Tonic interceptors cannot
+/// check the token, as its session is read from the database, so this runs as a
+/// middleware in front of the services, which refuse the calls needing a caller.
+async fn authenticate(
+    State(state): State<AppState>,
+    mut request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = jwt::extract_token_from_headers(request.headers())
+        && let Some(caller) = Caller::from_token(&state, &token).await
+    {
+        request.extensions_mut().insert(caller);
+    }
+    next.run(request).await
+}
+
+/// Caller of a call, refused as unauthenticated without a valid token
+fn caller<T>(request: &Request<T>) -> Result<Caller, Status> {
+    request
+        .extensions()
+        .get::<Caller>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("Missing or invalid token"))
+}
+
+/// Refuse calls on the account or orders of another user, unless made by an admin
+fn authorize(caller: &Caller, user_id: &UserId) -> Result<(), Status> {
+    if caller.can_act_for(user_id) {
+        Ok(())
+    } else {
+        Err(Status::permission_denied(
+            "Not allowed to act for this user",
+        ))
+    }
+}
+
+/// Parse a UUID field of a request
+fn parse_id(value: &str, field: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(value).map_err(|_| Status::invalid_argument(format!("Invalid {field}")))
+}
+
+fn internal(error: impl std::fmt::Display) -> Status {
+    Status::internal(error.to_string())
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        // Always below 2 000 000 000, outside of leap seconds which protobuf does not have
+        nanos: i32::try_from(at.timestamp_subsec_nanos()).unwrap_or(999_999_999),
+    }
+}
+
+fn from_timestamp(at: &prost_types::Timestamp) -> Result<DateTime<Utc>, Status> {
+    u32::try_from(at.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(at.seconds, nanos))
+        .ok_or_else(|| Status::invalid_argument("Invalid timestamp"))
+}
+
+impl From<&OrderSide> for pb::OrderSide {
+    fn from(side: &OrderSide) -> Self {
+        match side {
+            OrderSide::Buy => pb::OrderSide::Buy,
+            OrderSide::Sell => pb::OrderSide::Sell,
+        }
+    }
+}
+
+fn order_side(value: i32) -> Result<OrderSide, Status> {
+    match pb::OrderSide::try_from(value) {
+        Ok(pb::OrderSide::Buy) => Ok(OrderSide::Buy),
+        Ok(pb::OrderSide::Sell) => Ok(OrderSide::Sell),
+        _ => Err(Status::invalid_argument("side must be BUY or SELL")),
+    }
+}
+
+impl From<&OrderType> for pb::OrderType {
+    fn from(order_type: &OrderType) -> Self {
+        let kind = match order_type {
+            OrderType::Market => pb::order_type::Kind::Market(pb::MarketOrder {}),
+            OrderType::Limit(price) => {
+                pb::order_type::Kind::Limit(pb::LimitOrder { price: *price })
+            }
+            OrderType::Stop(stop_price) => pb::order_type::Kind::Stop(pb::StopOrder {
+                stop_price: *stop_price,
+            }),
+        };
+        pb::OrderType { kind: Some(kind) }
+    }
+}
+
+fn order_type(order_type: Option<pb::OrderType>) -> Result<OrderType, Status> {
+    match order_type.and_then(|order_type| order_type.kind) {
+        Some(pb::order_type::Kind::Market(_)) => Ok(OrderType::Market),
+        Some(pb::order_type::Kind::Limit(limit)) => Ok(OrderType::Limit(limit.price)),
+        Some(pb::order_type::Kind::Stop(stop)) => Ok(OrderType::Stop(stop.stop_price)),
+        None => Err(Status::invalid_argument("type is required")),
+    }
+}
+
+impl From<&OrderStatus> for pb::OrderStatus {
+    fn from(status: &OrderStatus) -> Self {
+        match status {
+            OrderStatus::Queued => pb::OrderStatus::Queued,
+            OrderStatus::Pending => pb::OrderStatus::Pending,
+            OrderStatus::PendingCancel => pb::OrderStatus::PendingCancel,
+            OrderStatus::Filled { .. } => pb::OrderStatus::Filled,
+            OrderStatus::Cancelled => pb::OrderStatus::Cancelled,
+            OrderStatus::Expired { .. } => pb::OrderStatus::Expired,
+            OrderStatus::Rejected { .. } => pb::OrderStatus::Rejected,
+        }
+    }
+}
+
+fn order(order_id: OrderId, order: &Order) -> pb::Order {
+    pb::Order {
+        id: order_id.to_string(),
+        client_id: order.client_id.to_string(),
+        symbol: order.symbol.clone(),
+        quantity: order.quantity,
+        side: pb::OrderSide::from(&order.order_side).into(),
+        r#type: Some(pb::OrderType::from(&order.order_type)),
+        status: pb::OrderStatus::from(&order.status).into(),
+        created_at: Some(timestamp(order.date)),
+        administrative: order.is_administrative,
+    }
+}
+
+impl From<&User> for pb::User {
+    fn from(user: &User) -> Self {
+        pb::User {
+            id: user.id.map(|id| id.to_string()).unwrap_or_default(),
+            email: user.email.clone(),
+            firstname: user.firstname.clone(),
+            surname: user.surname.clone(),
+            verified: user.is_verified,
+            created_at: Some(timestamp(user.created_at)),
+        }
+    }
+}
+
+impl From<&Holding> for pb::Holding {
+    fn from(holding: &Holding) -> Self {
+        pb::Holding {
+            symbol: holding.symbol.clone(),
+            quantity: holding.quantity,
+            average_cost: holding.average_cost,
+        }
+    }
+}
+
+impl From<&PriceLevel> for pb::PriceLevel {
+    fn from(level: &PriceLevel) -> Self {
+        pb::PriceLevel {
+            price: level.price,
+            quantity: level.quantity,
+            orders: level.orders as u64,
+        }
+    }
+}
+
+impl From<&OrderBook> for pb::OrderBook {
+    fn from(book: &OrderBook) -> Self {
+        pb::OrderBook {
+            symbol: book.symbol.clone(),
+            timestamp: Some(timestamp(book.timestamp)),
+            bids: book.bids.iter().map(pb::PriceLevel::from).collect(),
+            asks: book.asks.iter().map(pb::PriceLevel::from).collect(),
+        }
+    }
+}
+
+impl From<&Candle> for pb::Candle {
+    fn from(candle: &Candle) -> Self {
+        pb::Candle {
+            start: Some(timestamp(candle.start)),
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+            trades: candle.trades,
+        }
+    }
+}
+
+impl From<CandleInterval> for pb::CandleInterval {
+    fn from(interval: CandleInterval) -> Self {
+        match interval {
+            CandleInterval::OneMinute => pb::CandleInterval::OneMinute,
+            CandleInterval::FiveMinutes => pb::CandleInterval::FiveMinutes,
+            CandleInterval::OneHour => pb::CandleInterval::OneHour,
+            CandleInterval::OneDay => pb::CandleInterval::OneDay,
+        }
+    }
+}
+
+/// Candle interval of a request, one minute when unspecified
+fn candle_interval(value: i32) -> Result<CandleInterval, Status> {
+    match pb::CandleInterval::try_from(value) {
+        Ok(pb::CandleInterval::Unspecified | pb::CandleInterval::OneMinute) => {
+            Ok(CandleInterval::OneMinute)
+        }
+        Ok(pb::CandleInterval::FiveMinutes) => Ok(CandleInterval::FiveMinutes),
+        Ok(pb::CandleInterval::OneHour) => Ok(CandleInterval::OneHour),
+        Ok(pb::CandleInterval::OneDay) => Ok(CandleInterval::OneDay),
+        Err(_) => Err(Status::invalid_argument("Invalid interval")),
+    }
+}
@@ -0,0 +1,223 @@
+use std::pin::Pin;
+
+use domain::Repository;
+use domain::events::{DomainEvent, EventEnvelope, Replay};
+use domain::order::{CancelError, Order};
+use futures_util::{Stream, StreamExt, stream};
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Request, Response, Status};
+use tracing::debug;
+use uuid::Uuid;
+
+use super::{
+    AppState, authorize, caller, internal, order, order_side, order_type, parse_id, pb, timestamp,
+};
+
+type ExecutionReports = Pin<Box<dyn Stream<Item = Result<pb::ExecutionReport, Status>> + Send>>;
+
+pub struct OrderApi {
+    state: AppState,
+}
+
+impl OrderApi {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    async fn find(&self, order_id: Uuid) -> Result<Order, Status> {
+        self.state
+            .broker()
+            .get_order_repo()
+            .await
+            .get(&order_id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::not_found("Order not found"))
+    }
+
+    async fn order(&self, order_id: Uuid) -> Result<pb::Order, Status> {
+        Ok(order(order_id, &self.find(order_id).await?))
+    }
+}
+
+#[tonic::async_trait]
+impl pb::order_service_server::OrderService for OrderApi {
+    type StreamExecutionReportsStream = ExecutionReports;
+
+    async fn create_order(
+        &self,
+        request: Request<pb::CreateOrderRequest>,
+    ) -> Result<Response<pb::Order>, Status> {
+        let caller = caller(&request)?;
+        let request = request.into_inner();
+        let client_id = parse_id(&request.client_id, "client_id")?;
+        authorize(&caller, &client_id)?;
+        let side = order_side(request.side)?;
+        let kind = order_type(request.r#type)?;
+
+        let order_id = self
+            .state
+            .broker()
+            .create_order(client_id, request.symbol, request.quantity, side, kind)
+            .await
+            .map_err(|e| Status::invalid_argument(format!("Order creation error: {e}")))?;
+        Ok(Response::new(self.order(order_id).await?))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<pb::GetOrderRequest>,
+    ) -> Result<Response<pb::Order>, Status> {
+        let order_id = parse_id(&request.get_ref().order_id, "order_id")?;
+        let found = self.find(order_id).await?;
+        authorize(&caller(&request)?, &found.client_id)?;
+        Ok(Response::new(order(order_id, &found)))
+    }
+
+    async fn list_orders(
+        &self,
+        request: Request<pb::ListOrdersRequest>,
+    ) -> Result<Response<pb::ListOrdersResponse>, Status> {
+        let client_id = parse_id(&request.get_ref().client_id, "client_id")?;
+        authorize(&caller(&request)?, &client_id)?;
+        let orders = self
+            .state
+            .broker()
+            .get_orders_for_user(&client_id)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(pb::ListOrdersResponse {
+            orders: orders
+                .iter()
+                .map(|(order_id, found)| order(*order_id, found))
+                .collect(),
+        }))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<pb::CancelOrderRequest>,
+    ) -> Result<Response<pb::Order>, Status> {
+        let order_id = parse_id(&request.get_ref().order_id, "order_id")?;
+        let found = self.find(order_id).await?;
+        authorize(&caller(&request)?, &found.client_id)?;
+        match self.state.broker().cancel_order(order_id).await {
+            Ok(cancelled) => Ok(Response::new(order(order_id, &cancelled))),
+            Err(CancelError::OrderNotFound) => Err(Status::not_found("Order not found")),
+            Err(CancelError::NotCancellable) => Err(Status::failed_precondition(
+                "Order can no longer be cancelled",
+            )),
+            Err(CancelError::DbError(e)) => Err(internal(e)),
+        }
+    }
+
+    async fn stream_execution_reports(
+        &self,
+        request: Request<pb::StreamExecutionReportsRequest>,
+    ) -> Result<Response<Self::StreamExecutionReportsStream>, Status> {
+        let caller = caller(&request)?;
+        let request = request.into_inner();
+        let client_id = parse_id(&request.client_id, "client_id")?;
+        authorize(&caller, &client_id)?;
+        let events = self.state.broker().events();
+        let after = request.since.unwrap_or_else(|| events.last_sequence());
+        let Replay {
+            events: missed,
+            complete,
+            receiver,
+        } = events.subscribe_after(after);
+        if !complete {
+            return Err(Status::out_of_range(format!(
+                "Execution reports after {after} are no longer available"
+            )));
+        }
+        debug!(
+            "Streaming execution reports of client {} after {}",
+            client_id, after
+        );
+
+        let replayed: Vec<_> = missed
+            .iter()
+            .filter_map(|envelope| execution_report(client_id, envelope))
+            .map(Ok)
+            .collect();
+        let live = stream::unfold(Some(receiver), move |receiver| async move {
+            let mut receiver = receiver?;
+            loop {
+                match receiver.recv().await {
+                    Ok(envelope) => {
+                        if let Some(report) = execution_report(client_id, &envelope) {
+                            return Some((Ok(report), Some(receiver)));
+                        }
+                    }
+                    // Ending the stream, the client resumes from its last report
+                    Err(RecvError::Lagged(missed)) => {
+                        let status = Status::resource_exhausted(format!(
+                            "{missed} events were missed, resume from the last report received"
+                        ));
+                        return Some((Err(status), None));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream::iter(replayed).chain(live))))
+    }
+}
+
+/// Execution report for a domain event, if it is about an order of `client_id`
+fn execution_report(client_id: Uuid, envelope: &EventEnvelope) -> Option<pb::ExecutionReport> {
+    let (order_id, event) = match &envelope.event {
+        DomainEvent::OrderCreated {
+            order_id,
+            client_id: owner,
+            symbol,
+            quantity,
+            order_side,
+            order_type,
+        } if *owner == client_id => (
+            order_id,
+            pb::execution_report::Event::Created(pb::OrderCreated {
+                symbol: symbol.clone(),
+                quantity: *quantity,
+                side: pb::OrderSide::from(order_side).into(),
+                r#type: Some(pb::OrderType::from(order_type)),
+            }),
+        ),
+        DomainEvent::OrderStatusChanged {
+            order_id,
+            client_id: owner,
+            status,
+        } if *owner == client_id => (
+            order_id,
+            pb::execution_report::Event::StatusChanged(pb::OrderStatusChanged {
+                status: pb::OrderStatus::from(status).into(),
+            }),
+        ),
+        DomainEvent::OrderFilled {
+            order_id,
+            client_id: owner,
+            symbol,
+            quantity,
+            order_side,
+            price,
+        } if *owner == client_id => (
+            order_id,
+            pb::execution_report::Event::Filled(pb::OrderFilled {
+                symbol: symbol.clone(),
+                quantity: *quantity,
+                side: pb::OrderSide::from(order_side).into(),
+                price: *price,
+            }),
+        ),
+        _ => return None,
+    };
+    Some(pb::ExecutionReport {
+        sequence: envelope.sequence,
+        occurred_at: Some(timestamp(envelope.occurred_at)),
+        order_id: order_id.to_string(),
+        client_id: client_id.to_string(),
+        event: Some(event),
+    })
+}
@@ -0,0 +1,383 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use domain::clock::Clock;
+    use domain::core::BrokerX;
+    use domain::market_data::MarketDataConfig;
+    use domain::user::{UserId, UserRepoExt};
+    use tonic::metadata::{Ascii, MetadataValue};
+    use tonic::service::Interceptor;
+    use tonic::transport::Channel;
+    use tonic::{Code, Request, Status};
+
+    use crate::grpc::pb;
+    use crate::grpc::pb::account_service_client::AccountServiceClient;
+    use crate::grpc::pb::market_data_service_client::MarketDataServiceClient;
+    use crate::grpc::pb::order_service_client::OrderServiceClient;
+    use crate::grpc::pb::user_service_client::UserServiceClient;
+    use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+
+    /// Sends the access token of a user with each call
+    #[derive(Clone)]
+    struct Bearer(MetadataValue<Ascii>);
+
+    impl Interceptor for Bearer {
+        fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+            request
+                .metadata_mut()
+                .insert("authorization", self.0.clone());
+            Ok(request)
+        }
+    }
+
+    // Served over a real socket, gRPC needs HTTP/2
+    async fn create_test_setup() -> (Channel, BrokerHandle, UserId, Bearer) {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "grpc@test.com".to_string(),
+                "password123".to_string(),
+                "Grpc".to_string(),
+                "User".to_string(),
+                100_000.0,
+            )
+            .await
+            .expect("user creation failed");
+        let bearer = Bearer(
+            bearer(&broker, user_id, "grpc@test.com")
+                .await
+                .parse()
+                .unwrap(),
+        );
+
+        let handle = BrokerHandle::new(broker);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::grpc::create_grpc(handle.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        (channel, handle, user_id, bearer)
+    }
+
+    fn market_buy(client_id: UserId, quantity: u64) -> pb::CreateOrderRequest {
+        pb::CreateOrderRequest {
+            client_id: client_id.to_string(),
+            symbol: "AAPL".to_string(),
+            quantity,
+            side: pb::OrderSide::Buy.into(),
+            r#type: Some(pb::OrderType {
+                kind: Some(pb::order_type::Kind::Market(pb::MarketOrder {})),
+            }),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_create_and_get_user() {
+        let (channel, handle, _user_id, _bearer) = create_test_setup().await;
+        // Registering needs no token
+        let mut users = UserServiceClient::new(channel.clone());
+
+        let created = users
+            .create_user(pb::CreateUserRequest {
+                email: "new@test.com".to_string(),
+                password: "password123".to_string(),
+                firstname: "New".to_string(),
+                surname: "User".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created.email, "new@test.com");
+        assert!(!created.verified);
+
+        let token = bearer(handle.broker(), created.id.parse().unwrap(), &created.email).await;
+        let bearer = Bearer(token.parse().unwrap());
+        let fetched = UserServiceClient::with_interceptor(channel, bearer)
+            .get_user(pb::GetUserRequest {
+                user_id: created.id.clone(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(fetched, created);
+
+        let duplicate = users
+            .create_user(pb::CreateUserRequest {
+                email: "new@test.com".to_string(),
+                password: "password123".to_string(),
+                firstname: "New".to_string(),
+                surname: "User".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(duplicate.code(), Code::AlreadyExists);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deposit_and_withdraw() {
+        let (channel, _handle, user_id, bearer) = create_test_setup().await;
+        let mut accounts = AccountServiceClient::with_interceptor(channel, bearer);
+
+        let account = accounts
+            .deposit(pb::FundsRequest {
+                user_id: user_id.to_string(),
+                amount: 500.0,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!((account.balance - 100_500.0).abs() < f64::EPSILON);
+
+        let overdrawn = accounts
+            .withdraw(pb::FundsRequest {
+                user_id: user_id.to_string(),
+                amount: 1_000_000.0,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(overdrawn.code(), Code::FailedPrecondition);
+
+        let negative = accounts
+            .deposit(pb::FundsRequest {
+                user_id: user_id.to_string(),
+                amount: -1.0,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(negative.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_execution_reports_are_streamed() {
+        let (channel, handle, user_id, bearer) = create_test_setup().await;
+        let mut orders = OrderServiceClient::with_interceptor(channel, bearer);
+
+        let mut reports = orders
+            .stream_execution_reports(pb::StreamExecutionReportsRequest {
+                client_id: user_id.to_string(),
+                since: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        let order = orders
+            .create_order(market_buy(user_id, 10))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(order.status(), pb::OrderStatus::Queued);
+        handle.broker().run_until_idle().await;
+
+        let mut received = Vec::new();
+        loop {
+            let report = tokio::time::timeout(Duration::from_secs(5), reports.message())
+                .await
+                .expect("no execution report")
+                .unwrap()
+                .expect("stream ended");
+            assert_eq!(report.order_id, order.id);
+            let filled = matches!(report.event, Some(pb::execution_report::Event::Filled(_)));
+            received.push(report);
+            if filled {
+                break;
+            }
+        }
+        assert!(matches!(
+            received[0].event,
+            Some(pb::execution_report::Event::Created(_))
+        ));
+        assert!(received.windows(2).all(|w| w[0].sequence < w[1].sequence));
+
+        // Resuming replays what followed the creation
+        let mut resumed = orders
+            .stream_execution_reports(pb::StreamExecutionReportsRequest {
+                client_id: user_id.to_string(),
+                since: Some(received[0].sequence),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let next = resumed.message().await.unwrap().unwrap();
+        assert_eq!(next, received[1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancel_order() {
+        let (channel, handle, user_id, bearer) = create_test_setup().await;
+        let mut orders = OrderServiceClient::with_interceptor(channel, bearer);
+
+        let order = orders
+            .create_order(market_buy(user_id, 10))
+            .await
+            .unwrap()
+            .into_inner();
+        let cancelled = orders
+            .cancel_order(pb::CancelOrderRequest {
+                order_id: order.id.clone(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(cancelled.status(), pb::OrderStatus::PendingCancel);
+
+        handle.broker().run_until_idle().await;
+        let listed = orders
+            .list_orders(pb::ListOrdersRequest {
+                client_id: user_id.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.orders.len(), 1);
+        assert_eq!(listed.orders[0].status(), pb::OrderStatus::Cancelled);
+
+        let again = orders
+            .cancel_order(pb::CancelOrderRequest { order_id: order.id })
+            .await
+            .unwrap_err();
+        assert_eq!(again.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_calls_need_the_token_of_the_account_owner() {
+        let (channel, handle, user_id, owner) = create_test_setup().await;
+        let order = OrderServiceClient::with_interceptor(channel.clone(), owner)
+            .create_order(market_buy(user_id, 10))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let stranger_id = handle
+            .broker()
+            .get_user_repo()
+            .await
+            .create_user(
+                "stranger@test.com".to_string(),
+                "password123".to_string(),
+                "Other".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        let stranger = Bearer(
+            bearer(handle.broker(), stranger_id, "stranger@test.com")
+                .await
+                .parse()
+                .unwrap(),
+        );
+
+        let get_account = || pb::GetAccountRequest {
+            user_id: user_id.to_string(),
+        };
+        let error = AccountServiceClient::new(channel.clone())
+            .get_account(get_account())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+        let error = AccountServiceClient::with_interceptor(channel.clone(), stranger.clone())
+            .get_account(get_account())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::PermissionDenied);
+
+        let mut orders = OrderServiceClient::with_interceptor(channel.clone(), stranger);
+        let error = orders
+            .create_order(market_buy(user_id, 10))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::PermissionDenied);
+        let error = orders
+            .cancel_order(pb::CancelOrderRequest {
+                order_id: order.id.clone(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::PermissionDenied);
+        let error = OrderServiceClient::new(channel)
+            .get_order(pb::GetOrderRequest { order_id: order.id })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quote_and_order_book() {
+        let (channel, _handle, _user_id, _bearer) = create_test_setup().await;
+        let mut market = MarketDataServiceClient::new(channel);
+
+        let quote = market
+            .get_quote(pb::GetQuoteRequest {
+                symbol: "AAPL".to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(quote.symbol, "AAPL");
+        assert!(quote.bid <= quote.ask);
+
+        let book = market
+            .get_order_book(pb::GetOrderBookRequest {
+                symbol: "AAPL".to_string(),
+                depth: Some(5),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(book.symbol, "AAPL");
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+
+        let unknown = market
+            .get_quote(pb::GetQuoteRequest {
+                symbol: "NOPE".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(unknown.code(), Code::NotFound);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_invalid_arguments() {
+        let (channel, _handle, user_id, bearer) = create_test_setup().await;
+        let mut orders = OrderServiceClient::with_interceptor(channel.clone(), bearer);
+
+        let mut no_side = market_buy(user_id, 10);
+        no_side.side = pb::OrderSide::Unspecified.into();
+        let error = orders.create_order(no_side).await.unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+
+        let error = orders
+            .get_order(pb::GetOrderRequest {
+                order_id: "not-a-uuid".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+
+        let mut market = MarketDataServiceClient::new(channel);
+        let now = prost_types::Timestamp {
+            seconds: chrono::Utc::now().timestamp(),
+            nanos: 0,
+        };
+        let error = market
+            .get_candles(pb::GetCandlesRequest {
+                symbol: "AAPL".to_string(),
+                interval: pb::CandleInterval::OneMinute.into(),
+                from: Some(now),
+                to: Some(now),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,88 @@
+use domain::Repository;
+use domain::user::{AuthError, UserRepoExt};
+use tonic::{Request, Response, Status};
+
+use super::{AppState, authorize, caller, internal, parse_id, pb};
+
+pub struct UserApi {
+    state: AppState,
+}
+
+impl UserApi {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::user_service_server::UserService for UserApi {
+    async fn create_user(
+        &self,
+        request: Request<pb::CreateUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let request = request.into_inner();
+        if !request.email.contains('@') {
+            return Err(Status::invalid_argument("Invalid email format"));
+        }
+        for (field, value) in [
+            ("firstname", &request.firstname),
+            ("surname", &request.surname),
+            ("password", &request.password),
+        ] {
+            if value.is_empty() {
+                return Err(Status::invalid_argument(format!(
+                    "{field} is required for user creation"
+                )));
+            }
+        }
+
+        let user_repo = self.state.broker().get_user_repo().await;
+        let user_id = match user_repo
+            .create_user(
+                request.email,
+                request.password,
+                request.firstname,
+                request.surname,
+                0.0,
+            )
+            .await
+        {
+            Ok(user_id) => user_id,
+            Err(AuthError::UserAlreadyExists) => {
+                return Err(Status::already_exists("Email already in use"));
+            }
+            Err(AuthError::UserRepo(e)) => return Err(internal(e)),
+            Err(e) => {
+                return Err(Status::invalid_argument(format!(
+                    "User creation error: {e}"
+                )));
+            }
+        };
+
+        match user_repo.get(&user_id).await {
+            Ok(Some(user)) => Ok(Response::new(pb::User::from(&user))),
+            Ok(None) => Err(Status::internal("User retrieval error after creation")),
+            Err(e) => Err(internal(e)),
+        }
+    }
+
+    async fn get_user(
+        &self,
+        request: Request<pb::GetUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let user_id = parse_id(&request.get_ref().user_id, "user_id")?;
+        authorize(&caller(&request)?, &user_id)?;
+        match self
+            .state
+            .broker()
+            .get_user_repo()
+            .await
+            .get(&user_id)
+            .await
+        {
+            Ok(Some(user)) => Ok(Response::new(pb::User::from(&user))),
+            Ok(None) => Err(Status::not_found("User not found")),
+            Err(e) => Err(internal(e)),
+        }
+    }
+}
@@ -1,10 +1,21 @@
 mod api;
 mod config;
+mod grpc;
 mod logging;
+mod relay;
 mod services;
+#[cfg(test)]
+mod test_support;
+mod web;
+
+use std::net::SocketAddr;
 
 use color_eyre::Result;
 use domain::core::BrokerX;
+use domain::events::AuditLogSubscriber;
+use domain::user::{Role, UserRepoExt};
+use fix_gateway::{AcceptorConfig, FixAcceptor, FixStore};
+use relay::{ConfiguredSink, OutboxRelay, RelayConfig};
 use services::BrokerHandle;
 
 #[tokio::main]
@@ -14,19 +25,64 @@ async fn main() -> Result<()> {
     // Initialize logging
     logging::init()?;
     tracing::info!("Starting BrokerX application");
+    web::jwt::init(web::jwt::JwtConfig::from_env()?);
 
     let broker_x = BrokerX::new().await;
     broker_x.debug_populate().await;
+    if let Ok(email) = std::env::var("ADMIN_EMAIL") {
+        grant_admin(&broker_x, &email).await?;
+    }
     broker_x.start_order_processing().await;
+    broker_x.events().spawn_subscriber(AuditLogSubscriber);
+    broker_x
+        .events()
+        .spawn_subscriber(broker_x.notifier().await);
+    match (ConfiguredSink::from_env(), broker_x.events().outbox()) {
+        (Some(sink), Some(outbox)) => {
+            OutboxRelay::new(outbox.clone(), sink, RelayConfig::default()).spawn();
+        }
+        _ => tracing::info!("No OUTBOX_SINK configured, outbox entries will not be relayed"),
+    }
     tracing::debug!("BrokerX initialized: {broker_x:#?}");
 
     let app_state = BrokerHandle::new(broker_x);
-    let app = api::create_api(app_state);
+    match AcceptorConfig::from_env() {
+        Some(config) => {
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", config.port)).await?;
+            tracing::info!("FIX gateway listening on 127.0.0.1:{}", config.port);
+            let store = FixStore::new(app_state.shared().store()).await?;
+            FixAcceptor::new(app_state.shared(), store, config).spawn(listener);
+        }
+        None => tracing::info!("No FIX_PORT configured, the FIX gateway is disabled"),
+    }
+    let app = api::create_api(app_state.clone())
+        .merge(web::create_app(app_state.clone()))
+        .merge(grpc::create_grpc(app_state));
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
-    tracing::info!("Server running on http://127.0.0.1:3000");
+    tracing::info!("Server running on http://127.0.0.1:3000, gRPC on the same port");
+
+    // Client addresses are needed to rate limit logins per IP
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
-    axum::serve(listener, app).await?;
+    Ok(())
+}
 
+/// Give the admin role to the account of `email`, which must exist
+async fn grant_admin(broker_x: &BrokerX, email: &str) -> Result<()> {
+    let user_repo = broker_x.get_user_repo().await;
+    let Some(user_id) = user_repo
+        .get_user_by_email(email)
+        .await?
+        .and_then(|user| user.id)
+    else {
+        color_eyre::eyre::bail!("ADMIN_EMAIL {email} does not match any account");
+    };
+    user_repo.set_role(&user_id, Role::Admin).await?;
+    tracing::info!("User {} is an admin", email);
     Ok(())
 }
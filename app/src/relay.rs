@@ -0,0 +1,337 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use domain::DbError;
+use domain::events::{OutboxRecord, PostgresOutbox};
+use domain::singleton::spawn_singleton_job;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Error returned by a sink that could not accept a record
+#[derive(Debug)]
+pub struct SinkError(pub String);
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sink error: {}", self.0)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+/// Destination outbox records are relayed to
+pub trait OutboxSink: Send + Sync {
+    fn name(&self) -> &str;
+    fn deliver(
+        &self,
+        record: &OutboxRecord,
+    ) -> impl std::future::Future<Output = Result<(), SinkError>> + Send;
+}
+
+/// What is sent to a sink for each record
+#[derive(Debug, Serialize)]
+struct RelayMessage<'a> {
+    id: &'a str,
+    sequence: i64,
+    topic: &'a str,
+    created_at: chrono::DateTime<Utc>,
+    payload: &'a serde_json::Value,
+}
+
+impl<'a> From<&'a OutboxRecord> for RelayMessage<'a> {
+    fn from(record: &'a OutboxRecord) -> Self {
+        Self {
+            id: &record.id,
+            sequence: record.sequence,
+            topic: &record.topic,
+            created_at: record.created_at,
+            payload: &record.payload,
+        }
+    }
+}
+
+fn to_json_line(record: &OutboxRecord) -> Result<String, SinkError> {
+    serde_json::to_string(&RelayMessage::from(record)).map_err(|e| SinkError(e.to_string()))
+}
+
+/// Prints records to stdout, one JSON document per line
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl OutboxSink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    async fn deliver(&self, record: &OutboxRecord) -> Result<(), SinkError> {
+        println!("{}", to_json_line(record)?);
+        Ok(())
+    }
+}
+
+/// Appends records to a JSON Lines file
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl OutboxSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn deliver(&self, record: &OutboxRecord) -> Result<(), SinkError> {
+        let mut line = to_json_line(record)?;
+        line.push('\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| SinkError(format!("{}: {e}", self.path.display())))?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|e| SinkError(format!("{}: {e}", self.path.display())))
+    }
+}
+
+/// POSTs records to an HTTP endpoint. The record ID is sent as `Idempotency-Key` so
+/// the receiver can discard records delivered more than once.
+#[derive(Debug)]
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("HTTP client creation failed"),
+        }
+    }
+}
+
+impl OutboxSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&self, record: &OutboxRecord) -> Result<(), SinkError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Idempotency-Key", &record.id)
+            .json(&RelayMessage::from(record))
+            .send()
+            .await
+            .map_err(|e| SinkError(e.to_string()))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(SinkError(format!(
+                "{} answered {}",
+                self.url,
+                response.status()
+            )))
+        }
+    }
+}
+
+/// Sink selected from the configuration
+#[derive(Debug)]
+pub enum ConfiguredSink {
+    Stdout(StdoutSink),
+    File(FileSink),
+    Webhook(WebhookSink),
+}
+
+impl ConfiguredSink {
+    /// Parse a sink from `stdout`, `file:<path>` or `webhook:<url>`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            None if value == "stdout" => Some(Self::Stdout(StdoutSink)),
+            Some(("file", path)) if !path.is_empty() => {
+                Some(Self::File(FileSink::new(PathBuf::from(path))))
+            }
+            Some(("webhook", url)) if !url.is_empty() => {
+                Some(Self::Webhook(WebhookSink::new(url.to_string())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Read the sink from `OUTBOX_SINK`, if set
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var("OUTBOX_SINK").ok()?;
+        let sink = Self::parse(&value);
+        if sink.is_none() {
+            warn!("Ignoring invalid OUTBOX_SINK value: {value}");
+        }
+        sink
+    }
+}
+
+impl OutboxSink for ConfiguredSink {
+    fn name(&self) -> &str {
+        match self {
+            ConfiguredSink::Stdout(sink) => sink.name(),
+            ConfiguredSink::File(sink) => sink.name(),
+            ConfiguredSink::Webhook(sink) => sink.name(),
+        }
+    }
+
+    async fn deliver(&self, record: &OutboxRecord) -> Result<(), SinkError> {
+        match self {
+            ConfiguredSink::Stdout(sink) => sink.deliver(record).await,
+            ConfiguredSink::File(sink) => sink.deliver(record).await,
+            ConfiguredSink::Webhook(sink) => sink.deliver(record).await,
+        }
+    }
+}
+
+/// Relay tuning
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub poll_interval: Duration,
+    pub batch_size: i64,
+    /// Records are left for an operator to replay after this many failed attempts
+    pub max_attempts: i32,
+    /// Delay before the first retry, doubled on every failed attempt
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            batch_size: 100,
+            max_attempts: 10,
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Delivers outbox records to a sink and marks them dispatched once accepted.
+/// Delivery is at least once: a record may be sent again if marking it fails.
+#[derive(Debug)]
+pub struct OutboxRelay<S: OutboxSink> {
+    outbox: PostgresOutbox,
+    sink: S,
+    config: RelayConfig,
+}
+
+impl<S: OutboxSink + 'static> OutboxRelay<S> {
+    pub fn new(outbox: PostgresOutbox, sink: S, config: RelayConfig) -> Self {
+        Self {
+            outbox,
+            sink,
+            config,
+        }
+    }
+
+    /// Run the relay on its own task. Every instance may call this, but only the
+    /// leader of the outbox relays it, so records are delivered once and in order.
+    pub fn spawn(self) -> JoinHandle<()> {
+        info!(
+            "Relaying outbox {} to {}",
+            self.outbox.table(),
+            self.sink.name()
+        );
+        let name = format!("{}_relay", self.outbox.table());
+        let interval = self.config.poll_interval;
+        let relay = Arc::new(self);
+        spawn_singleton_job(name, interval, move || {
+            let relay = Arc::clone(&relay);
+            async move { relay.relay_due().await }
+        })
+    }
+
+    /// Deliver batches of due records until a batch comes back short
+    async fn relay_due(&self) {
+        loop {
+            match self.relay_once().await {
+                // A full batch means more records are probably waiting
+                Ok(delivered) if delivered as i64 >= self.config.batch_size => {}
+                Ok(_) => break,
+                Err(e) => {
+                    error!("Outbox relay to {} failed: {}", self.sink.name(), e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Deliver one batch of due records, returns how many were delivered
+    /// # Errors
+    /// Returns `DbError` if the outbox could not be read or updated
+    pub async fn relay_once(&self) -> Result<usize, DbError> {
+        let records = self
+            .outbox
+            .fetch_due(self.config.batch_size, self.config.max_attempts)
+            .await?;
+
+        let mut delivered = 0;
+        for record in records {
+            match self.sink.deliver(&record).await {
+                Ok(()) => {
+                    self.outbox.mark_dispatched(&record.id).await?;
+                    debug!(
+                        "Relayed outbox record {} to {}",
+                        record.id,
+                        self.sink.name()
+                    );
+                    delivered += 1;
+                }
+                Err(e) => {
+                    let attempts = record.attempts + 1;
+                    let retry_at = Utc::now() + self.backoff(attempts);
+                    self.outbox
+                        .record_failure(&record.id, &e.to_string(), retry_at)
+                        .await?;
+                    if attempts >= self.config.max_attempts {
+                        error!(
+                            "Giving up on outbox record {} after {} attempts: {}",
+                            record.id, attempts, e
+                        );
+                    } else {
+                        warn!(
+                            "Failed to relay outbox record {} (attempt {}): {}",
+                            record.id, attempts, e
+                        );
+                    }
+                    // The sink is likely unavailable, try again on the next poll
+                    break;
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    fn backoff(&self, attempts: i32) -> chrono::Duration {
+        let exponent = u32::try_from(attempts.saturating_sub(1))
+            .unwrap_or(0)
+            .min(16);
+        let delay = self
+            .config
+            .base_backoff
+            .saturating_mul(2_u32.pow(exponent))
+            .min(self.config.max_backoff);
+        chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX)
+    }
+}
@@ -19,4 +19,9 @@ impl BrokerHandle {
     pub fn broker(&self) -> &BrokerX {
         &self.inner
     }
+
+    /// Shared ownership of the broker, for services running next to the web server
+    pub fn shared(&self) -> Arc<BrokerX> {
+        Arc::clone(&self.inner)
+    }
 }
@@ -0,0 +1,14 @@
+//! Helpers shared by the tests of the REST, gRPC and web interfaces
+
+use domain::core::BrokerX;
+use uuid::Uuid;
+
+use crate::web::jwt;
+
+/// `authorization` header of a new session of the user
+pub async fn bearer(broker: &BrokerX, user_id: Uuid, email: &str) -> String {
+    let tokens = jwt::open_session(broker, user_id, email.to_string(), None)
+        .await
+        .unwrap();
+    format!("Bearer {}", tokens.access_token)
+}
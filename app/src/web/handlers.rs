@@ -1,24 +1,43 @@
 use askama::Template;
 use axum::{
-    extract::{Form, FromRequest, Query, State},
-    http::{StatusCode, header},
-    response::{Html, IntoResponse, Redirect, Response},
+    extract::Path,
+    extract::{ConnectInfo, Form, FromRequest, Query, State},
+    http::{Extensions, HeaderMap, StatusCode, header},
+    response::{
+        Html, IntoResponse, Redirect, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures_util::stream::{self, StreamExt};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use super::templates::{DepositTemplate, HoldingDisplayData, OrdersTemplate, PlaceOrderTemplate};
+use super::templates::{
+    DepositTemplate, HoldingDisplayData, InstrumentDisplayData, OrderStatusDisplayData,
+    OrderStatusTemplate, OrdersTemplate, PlaceOrderTemplate, SessionDisplayData, SessionsTemplate,
+    TotpTemplate,
+};
 use crate::web::{
     AppState, jwt,
     templates::{
-        DashboardTemplate, LoginTemplate, MfaVerifyTemplate, RegisterTemplate,
-        RegistrationVerifyTemplate,
+        DashboardTemplate, ForgotPasswordTemplate, LoginTemplate, MfaMethodDisplayData,
+        MfaTemplate, MfaVerifyTemplate, RegisterTemplate, RegistrationVerifyTemplate,
+        ResetPasswordTemplate,
     },
 };
 use domain::Repository;
+use domain::events::{DomainEvent, EventEnvelope, Replay};
 
-use domain::user::{AuthError, User, UserRepoExt};
+use domain::instrument::{InstrumentRepoExt, InstrumentStatus};
+use domain::notifications::SecurityEvent;
+use domain::password_reset::{PASSWORD_RESET_TTL, PasswordResetError, PasswordResetRepoExt};
+use domain::session::{SessionId, SessionRepoExt};
+use domain::user::{AuthError, Locale, MfaError, MfaMethod, User, UserRepoExt};
 
 #[derive(Deserialize)]
 pub struct LoginForm {
@@ -33,11 +52,33 @@ pub struct RegisterForm {
     pub email: String,
     pub password: String,
     pub confirm_password: String,
+    /// Language of the emails sent to the user
+    #[serde(default)]
+    pub locale: Locale,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordForm {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordQuery {
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    pub token: String,
+    pub password: String,
+    pub confirm_password: String,
 }
 
 #[derive(Deserialize)]
 pub struct MfaVerifyForm {
     pub challenge_id: String,
+    #[serde(default)]
+    pub method: MfaMethod,
     pub code: String,
 }
 
@@ -51,6 +92,8 @@ pub struct RegistrationVerifyForm {
 #[derive(Deserialize)]
 pub struct MfaQuery {
     pub challenge_id: String,
+    #[serde(default)]
+    pub method: MfaMethod,
 }
 
 #[derive(Deserialize)]
@@ -65,6 +108,15 @@ pub struct LoginQuery {}
 #[derive(Deserialize)]
 pub struct ResendMfaQuery {
     pub challenge_id: String,
+    #[serde(default)]
+    pub method: MfaMethod,
+}
+
+#[derive(Deserialize)]
+pub struct SwitchMfaQuery {
+    pub challenge_id: String,
+    pub method: MfaMethod,
+    pub to: MfaMethod,
 }
 
 #[derive(Deserialize)]
@@ -76,7 +128,7 @@ pub struct DepositForm {
 pub struct PlaceOrderForm {
     pub symbol: String,
     pub side: String,       // "buy" or "sell"
-    pub order_type: String, // "market" or "limit"
+    pub order_type: String, // "market", "limit" or "stop"
     pub quantity: String,
     pub price: String,
 }
@@ -96,6 +148,7 @@ pub async fn login_page(Query(_params): Query<LoginQuery>) -> Result<Html<String
 
 pub async fn login_submit(
     State(app_state): State<AppState>,
+    extensions: Extensions,
     Form(form): Form<LoginForm>,
 ) -> Response {
     info!("Login attempt for email: {}", form.email);
@@ -116,20 +169,33 @@ pub async fn login_submit(
 
     // First factor authentication using the domain layer
     let user_id_found = {
-        let broker = app_state.lock().unwrap();
-        match broker
-            .get_user_repo()
-            .authenticate_user(&form.email, &form.password)
+        match app_state
+            .broker()
+            .authenticate_user(&form.email, &form.password, client_ip(&extensions))
+            .await
         {
-            Ok(_) => true,
+            Ok(valid) => valid,
+            Err(
+                e @ (AuthError::TooManyAttempts(_)
+                | AuthError::IpRateLimited(_)
+                | AuthError::AccountLocked(_)),
+            ) => {
+                warn!("Login refused for email: {} - {}", form.email, e);
+                let template = LoginTemplate {
+                    error: Some(e.to_string()),
+                };
+                return match template.render() {
+                    Ok(html) => (StatusCode::TOO_MANY_REQUESTS, Html(html)).into_response(),
+                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                };
+            }
             Err(AuthError::NotVerified(user_id)) => {
-                drop(broker);
                 // start email verification MFA process
                 info!(
                     "User email not verified for email: {}, initiating verification process",
                     form.email
                 );
-                return registration_mfa(&app_state, &form.email, user_id);
+                return registration_mfa(&app_state, &form.email, user_id).await;
             }
             Err(e) => {
                 warn!("Authentication failed for email: {} - {}", form.email, e);
@@ -154,22 +220,22 @@ pub async fn login_submit(
         form.email
     );
 
-    let challenge_id_result = {
-        let broker = app_state.lock().unwrap();
-        // TODO: tokio::task::spawn_blocking ?
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(broker.mfa_service.initiate_mfa(&form.email))
-        })
-    };
+    let challenge_id_result = app_state.broker().initiate_mfa(&form.email, None).await;
 
     match challenge_id_result {
-        Ok(challenge_id) => {
+        Ok((method, challenge_id)) => {
             info!(
-                "MFA challenge initiated for email: {}, challenge_id: {}",
-                form.email, challenge_id
+                "MFA challenge initiated for email: {}, method: {}, challenge_id: {}",
+                form.email,
+                method.as_str(),
+                challenge_id
             );
             // Redirect to MFA verification page
-            Redirect::to(&format!("/verify-mfa?challenge_id={challenge_id}")).into_response()
+            Redirect::to(&format!(
+                "/verify-mfa?challenge_id={challenge_id}&method={}",
+                method.as_str()
+            ))
+            .into_response()
         }
         Err(e) => {
             error!(
@@ -248,8 +314,8 @@ pub async fn register_submit(
     // If so, is it verified yet?
 
     let user_id = {
-        let broker = app_state.lock().unwrap();
-        match broker.get_user_repo().get_user_by_email(&form.email) {
+        let user_repo = app_state.broker().get_user_repo().await;
+        match user_repo.get_user_by_email(&form.email).await {
             Err(e) => {
                 let template = RegisterTemplate {
                     error: Some(format!("Registration failed: {e}")),
@@ -279,13 +345,16 @@ pub async fn register_submit(
             Ok(None) => {
                 // Create user in the domain layer
 
-                match broker.get_user_repo().create_user(
-                    form.email.clone(),
-                    form.password.clone(),
-                    form.firstname.clone(),
-                    form.surname.clone(),
-                    1000.0, // TODO: change
-                ) {
+                match user_repo
+                    .create_user(
+                        form.email.clone(),
+                        form.password.clone(),
+                        form.firstname.clone(),
+                        form.surname.clone(),
+                        1000.0, // TODO: change
+                    )
+                    .await
+                {
                     Ok(user_id) => {
                         debug!(
                             "Created new user: {} (ID: {}) with empty portfolio",
@@ -306,21 +375,22 @@ pub async fn register_submit(
             }
         }
     };
-    registration_mfa(&app_state, &form.email, user_id)
+    // Written before the verification code so that it is sent in this language
+    if let Err(e) = app_state
+        .broker()
+        .get_user_repo()
+        .await
+        .set_locale(&user_id, form.locale)
+        .await
+    {
+        warn!("Could not set the locale of user {}: {}", user_id, e);
+    }
+    registration_mfa(&app_state, &form.email, user_id).await
 }
 
-fn registration_mfa(
-    app_state: &std::sync::Arc<std::sync::Mutex<domain::core::BrokerX>>,
-    email: &str,
-    user_id: Uuid,
-) -> axum::http::Response<axum::body::Body> {
+async fn registration_mfa(app_state: &AppState, email: &str, user_id: Uuid) -> Response {
     // Initiate MFA for email verification
-    let challenge_id_result = {
-        let broker = app_state.lock().unwrap();
-        tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(broker.mfa_service.initiate_mfa(email))
-        })
-    };
+    let challenge_id_result = app_state.broker().mfa_service.initiate_mfa(email).await;
 
     match challenge_id_result {
         Ok(challenge_id) => {
@@ -351,49 +421,205 @@ fn registration_mfa(
     }
 }
 
-pub async fn logout() -> Response {
-    // Clear JWT cookie and redirect to login
-    let mut response = Redirect::to("/login").into_response();
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        jwt::create_logout_cookie().parse().unwrap(),
-    );
-    response
-}
-fn check_token_and_execute(
-    app_state: State<AppState>,
+pub async fn logout(
+    State(app_state): State<AppState>,
     request: axum::extract::Request,
-    handler: fn(State<AppState>, User, axum::extract::Request) -> Response,
 ) -> Response {
+    // End the session so that its tokens stop working, even if they were copied
+    if let Some(session_id) = current_session(request.extensions())
+        && let Err(e) = app_state
+            .broker()
+            .get_session_repo()
+            .await
+            .revoke_session(&session_id)
+            .await
+    {
+        error!("Could not revoke session {}: {}", session_id, e);
+    }
+    // Clear JWT cookies and redirect to login
+    jwt::logged_out(Redirect::to("/login"))
+}
+
+pub async fn forgot_password_page() -> Response {
+    render_forgot_password(None, false)
+}
+
+pub async fn forgot_password_submit(
+    State(app_state): State<AppState>,
+    extensions: Extensions,
+    Form(form): Form<ForgotPasswordForm>,
+) -> Response {
+    info!("Password reset requested for email: {}", form.email);
+    let reset_url = format!("{}/reset-password", public_url());
+    // The same answer for every email, so that the form does not tell which accounts exist
+    match app_state
+        .broker()
+        .request_password_reset(form.email.trim(), &reset_url, client_ip(&extensions))
+        .await
+    {
+        Ok(()) => render_forgot_password(None, true),
+        Err(AuthError::TooManyAttempts(_) | AuthError::IpRateLimited(_)) => {
+            warn!("Password reset throttled for email: {}", form.email);
+            let error = "Too many reset links were asked for, please try again later";
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                render_forgot_password(Some(error.to_string()), false),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!(
+                "Could not issue a password reset for email: {} - {}",
+                form.email, e
+            );
+            render_forgot_password(
+                Some("Could not send a reset link, please try again later".to_string()),
+                false,
+            )
+        }
+    }
+}
+
+fn render_forgot_password(error: Option<String>, sent: bool) -> Response {
+    let template = ForgotPasswordTemplate {
+        error,
+        sent,
+        valid_minutes: PASSWORD_RESET_TTL.num_minutes(),
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Base URL of the web interface in links sent by email, from `PUBLIC_URL`. It is not
+/// taken from the request, whose `Host` header the client chooses.
+fn public_url() -> String {
+    std::env::var("PUBLIC_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| "http://127.0.0.1:3000".to_string())
+}
+
+pub async fn reset_password_page(
+    State(app_state): State<AppState>,
+    Query(query): Query<ResetPasswordQuery>,
+) -> Response {
+    let Some(token) = query.token else {
+        return render_reset_password(None, Some(PasswordResetError::InvalidToken.to_string()));
+    };
+    // Tell right away when the link cannot be used anymore
+    match app_state
+        .broker()
+        .get_password_reset_repo()
+        .await
+        .pending_reset(&token)
+        .await
+    {
+        Ok(_) => render_reset_password(Some(token), None),
+        Err(e) => render_reset_password(None, Some(e.to_string())),
+    }
+}
+
+pub async fn reset_password_submit(
+    State(app_state): State<AppState>,
+    Form(form): Form<ResetPasswordForm>,
+) -> Response {
+    if form.password != form.confirm_password {
+        return render_reset_password(Some(form.token), Some("Passwords do not match".to_string()));
+    }
+    match app_state
+        .broker()
+        .reset_password(&form.token, &form.password)
+        .await
+    {
+        Ok(user_id) => {
+            info!("Password of user {} reset from an emailed link", user_id);
+            let template = ResetPasswordTemplate {
+                token: None,
+                error: None,
+                done: true,
+            };
+            match template.render() {
+                Ok(html) => Html(html).into_response(),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+        // The link still works, let the user pick another password
+        Err(PasswordResetError::Auth(AuthError::WeakPassword)) => render_reset_password(
+            Some(form.token),
+            Some("Password must be at least 6 characters long".to_string()),
+        ),
+        Err(e @ (PasswordResetError::Auth(_) | PasswordResetError::Repo(_))) => {
+            error!("Password reset failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            warn!("Password reset refused: {}", e);
+            render_reset_password(None, Some(e.to_string()))
+        }
+    }
+}
+
+fn render_reset_password(token: Option<String>, error: Option<String>) -> Response {
+    let template = ResetPasswordTemplate {
+        token,
+        error,
+        done: false,
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Session of the request authenticated by `jwt::auth_middleware`
+/// Address of the client, when the server was started with connect info
+fn client_ip(extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip())
+}
+
+fn current_session(extensions: &Extensions) -> Option<SessionId> {
+    let claims = extensions.get::<jwt::Claims>()?;
+    Uuid::parse_str(&claims.sid).ok()
+}
+/// Get the user authenticated by `jwt::auth_middleware`
+async fn authenticated_user(app_state: &AppState, extensions: &Extensions) -> Option<User> {
     // Extract user claims from request
-    let Some(claims) = request.extensions().get::<jwt::Claims>() else {
+    let Some(claims) = extensions.get::<jwt::Claims>() else {
         warn!("No JWT claims found in request extensions");
-        return Redirect::to("/login").into_response();
+        return None;
     };
 
     // Get user from domain layer
     let Ok(user_id) = Uuid::parse_str(&claims.subject) else {
         warn!("Invalid user ID in JWT claims: {}", claims.subject);
-        return Redirect::to("/login").into_response();
+        return None;
     };
 
-    let broker = app_state.lock().unwrap();
-    let Ok(Some(user)) = broker.get_user_repo().get(&user_id) else {
+    let Ok(Some(user)) = app_state.broker().get_user_repo().await.get(&user_id).await else {
         warn!("User not found for ID: {}", user_id);
-        return Redirect::to("/login").into_response();
+        return None;
     };
-
-    drop(broker);
-    handler(app_state, user, request)
+    Some(user)
 }
 /// Dashboard handler - requires authentication
-pub async fn dashboard(app_state: State<AppState>, request: axum::extract::Request) -> Response {
-    check_token_and_execute(app_state, request, |app_state, user, _request| {
+pub async fn dashboard(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    // Taken before reading the account so that live updates miss nothing
+    let events_sequence = app_state.broker().events().last_sequence();
+
+    {
         // Fetch recent orders for the user
         let recent_orders = {
             if let Some(user_id) = user.id {
-                let broker = app_state.lock().unwrap();
-                match broker.get_orders_for_user(&user_id) {
+                match app_state.broker().get_orders_for_user(&user_id).await {
                     Ok(orders) => {
                         // Convert orders to display format and take the most recent 5
                         orders
@@ -421,16 +647,28 @@ pub async fn dashboard(app_state: State<AppState>, request: axum::extract::Reque
                 "Getting portfolio data from user holdings for {}",
                 user.email
             );
+            // Value holdings at the current market price
+            let mut prices = HashMap::new();
+            for symbol in user.holdings.keys() {
+                if let Some(price) = app_state.broker().mark_price(symbol).await {
+                    prices.insert(symbol.clone(), price);
+                }
+            }
+            let price_of = |symbol: &str| prices.get(symbol).copied();
+
             let holdings: Vec<HoldingDisplayData> = user
                 .get_holdings_list()
                 .into_iter()
-                .map(HoldingDisplayData::from_holding)
+                .map(|holding| {
+                    let current_price = price_of(&holding.symbol).unwrap_or(holding.average_cost);
+                    HoldingDisplayData::from_holding(holding, current_price)
+                })
                 .collect();
 
-            let portfolio_value = (user.get_portfolio_value() * 100.0).round() / 100.0;
-            let total_gain_loss = (user.get_total_gain_loss() * 100.0).round() / 100.0;
+            let portfolio_value = (user.get_portfolio_value(price_of) * 100.0).round() / 100.0;
+            let total_gain_loss = (user.get_total_gain_loss(price_of) * 100.0).round() / 100.0;
             let total_gain_loss_percentage =
-                (user.get_gain_loss_percentage() * 100.0).round() / 100.0;
+                (user.get_gain_loss_percentage(price_of) * 100.0).round() / 100.0;
 
             (
                 holdings,
@@ -442,6 +680,7 @@ pub async fn dashboard(app_state: State<AppState>, request: axum::extract::Reque
 
         // Create dashboard template
         let template = DashboardTemplate {
+            events_sequence,
             account_balance: user.balance,
             recent_orders,
             holdings: holdings.clone(),
@@ -467,17 +706,24 @@ pub async fn dashboard(app_state: State<AppState>, request: axum::extract::Reque
             Ok(html) => Html(html).into_response(),
             Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         }
-    })
+    }
 }
 
 /// Orders page handler - requires authentication
-pub async fn orders_page(app_state: State<AppState>, request: axum::extract::Request) -> Response {
-    check_token_and_execute(app_state, request, |app_state, user, _request| {
+pub async fn orders_page(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let events_sequence = app_state.broker().events().last_sequence();
+
+    {
         // Fetch all orders for the user
         let orders = {
             if let Some(user_id) = user.id {
-                let broker = app_state.lock().unwrap();
-                match broker.get_orders_for_user(&user_id) {
+                match app_state.broker().get_orders_for_user(&user_id).await {
                     Ok(orders) => {
                         // Convert orders to display format
                         orders
@@ -499,7 +745,10 @@ pub async fn orders_page(app_state: State<AppState>, request: axum::extract::Req
         };
 
         // Create orders template
-        let template = OrdersTemplate { orders };
+        let template = OrdersTemplate {
+            events_sequence,
+            orders,
+        };
         debug!(
             "Rendering orders page for user: {} with {} orders",
             user.email,
@@ -510,129 +759,299 @@ pub async fn orders_page(app_state: State<AppState>, request: axum::extract::Req
             Ok(html) => Html(html).into_response(),
             Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
         }
-    })
+    }
 }
 
-pub async fn mfa_verify_page(Query(params): Query<MfaQuery>) -> Result<Html<String>, StatusCode> {
-    let template = MfaVerifyTemplate {
-        challenge_id: params.challenge_id,
-        error: None,
+#[derive(Deserialize)]
+pub struct LiveUpdatesQuery {
+    /// Sequence of the latest event reflected in the page
+    pub since: Option<u64>,
+}
+
+/// Live updates of the user's orders and balance as Server-Sent Events - requires authentication
+///
+/// Updates follow the event feed, so changes made through any instance reach the page.
+/// Events carry their sequence as ID, so a reconnecting browser resumes after the last
+/// one it received, on whichever instance. A `reload` event is sent when events were
+/// missed.
+pub async fn live_updates(
+    State(app_state): State<AppState>,
+    Query(query): Query<LiveUpdatesQuery>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user_id) = authenticated_user(&app_state, request.extensions())
+        .await
+        .and_then(|user| user.id)
+    else {
+        return Redirect::to("/login").into_response();
     };
-    match template.render() {
-        Ok(html) => Ok(Html(html)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+
+    let last_event_id = request
+        .headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let events = app_state.broker().events();
+    let after = last_event_id
+        .or(query.since)
+        .unwrap_or_else(|| events.last_sequence());
+    let Replay {
+        events: missed,
+        complete,
+        receiver,
+    } = events.subscribe_after(after);
+    debug!("Streaming live updates to user {} after {}", user_id, after);
+
+    let mut replayed = Vec::new();
+    if !complete {
+        replayed.push(reload_event());
     }
+    replayed.extend(
+        missed
+            .iter()
+            .filter_map(|envelope| live_update(user_id, envelope)),
+    );
+    let live = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            let update = match receiver.recv().await {
+                Ok(envelope) => live_update(user_id, &envelope),
+                Err(RecvError::Lagged(_)) => Some(reload_event()),
+                Err(RecvError::Closed) => return None,
+            };
+            if let Some(update) = update {
+                return Some((update, receiver));
+            }
+        }
+    });
+
+    Sse::new(stream::iter(replayed).chain(live).map(Ok::<_, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Server-Sent Event updating the pages of `user_id` for a domain event, if it concerns them
+fn live_update(user_id: Uuid, envelope: &EventEnvelope) -> Option<Event> {
+    if envelope.event.user_id() != user_id {
+        return None;
+    }
+
+    let update = match &envelope.event {
+        DomainEvent::OrderCreated { order_id, .. } => Event::default()
+            .event("order_created")
+            .data(order_id.to_string()),
+        DomainEvent::OrderStatusChanged {
+            order_id, status, ..
+        } => {
+            let template = OrderStatusTemplate {
+                order: OrderStatusDisplayData::from_status(status),
+            };
+            let html = template
+                .render()
+                .inspect_err(|e| error!("Failed to render status of order {}: {}", order_id, e))
+                .ok()?;
+            Event::default()
+                .event("order_status")
+                .json_data(serde_json::json!({ "order_id": order_id, "html": html }))
+                .ok()?
+        }
+        // The status change that follows updates the pages
+        DomainEvent::OrderFilled { .. } => return None,
+        DomainEvent::FundsDeposited { balance, .. }
+        | DomainEvent::FundsWithdrawn { balance, .. } => Event::default()
+            .event("balance")
+            .json_data(serde_json::json!({ "balance": balance }))
+            .ok()?,
+    };
+    Some(update.id(envelope.sequence.to_string()))
+}
+
+fn reload_event() -> Event {
+    Event::default().event("reload").data("")
+}
+
+pub async fn mfa_verify_page(
+    State(app_state): State<AppState>,
+    Query(params): Query<MfaQuery>,
+) -> Response {
+    render_mfa_verify(&app_state, params.challenge_id, params.method, None).await
 }
 
 pub async fn mfa_verify_submit(
     State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Form(form): Form<MfaVerifyForm>,
 ) -> Response {
-    if form.code.is_empty() || form.code.len() != 6 {
-        let template = MfaVerifyTemplate {
-            challenge_id: form.challenge_id,
-            error: Some("Please enter a valid 6-digit code".to_string()),
-        };
-        return match template.render() {
-            Ok(html) => Html(html).into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    let code = form.code.trim();
+    let valid_format = match form.method {
+        MfaMethod::Email | MfaMethod::Totp => {
+            code.len() == 6 && code.chars().all(|c| c.is_ascii_digit())
+        }
+        MfaMethod::RecoveryCode => !code.is_empty(),
+    };
+    if !valid_format {
+        let error = match form.method {
+            MfaMethod::RecoveryCode => "Please enter one of your recovery codes",
+            _ => "Please enter a valid 6-digit code",
         };
+        return render_mfa_verify(
+            &app_state,
+            form.challenge_id,
+            form.method,
+            Some(error.to_string()),
+        )
+        .await;
     }
     debug!("Verifying MFA for challenge_id: {}", form.challenge_id);
     // Verify the MFA code
-    let verification_result = {
-        let broker = app_state.lock().unwrap();
-        broker
-            .mfa_service
-            .verify_mfa(&form.challenge_id, &form.code)
-    };
+    let verification_result = app_state
+        .broker()
+        .verify_mfa(
+            form.method,
+            &form.challenge_id,
+            code,
+            client_ip(&extensions),
+        )
+        .await;
     debug!(
         "MFA verification result for challenge_id {}: {:?}",
         form.challenge_id, verification_result
     );
-    match verification_result {
+    let error = match verification_result {
         Ok(true) => {
             // MFA verified successfully, now get the challenge to retrieve user info
-            let challenge = {
-                let broker = app_state.lock().unwrap();
-                broker.mfa_service.get_challenge(&form.challenge_id)
-            };
+            let challenge = app_state
+                .broker()
+                .get_mfa_challenge(form.method, &form.challenge_id)
+                .await;
             match challenge {
                 Ok(challenge) => {
                     // Get the user using the email from the challenge
-                    let user = {
-                        let broker = app_state.lock().unwrap();
-
-                        broker
-                            .get_user_repo()
-                            .get_user_by_email(&challenge.user_email)
-                    };
-                    let (user_id, email) = {
-                        if let Ok(Some(user)) = user {
-                            (user.id.unwrap(), user.email)
-                        } else {
-                            let template = MfaVerifyTemplate {
-                                challenge_id: form.challenge_id,
-                                error: Some("User account not found".to_string()),
-                            };
-                            return match template.render() {
-                                Ok(html) => Html(html).into_response(),
-                                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                            };
-                        }
+                    let user = app_state
+                        .broker()
+                        .get_user_repo()
+                        .await
+                        .get_user_by_email(&challenge.user_email)
+                        .await;
+                    let Ok(Some(User {
+                        id: Some(user_id),
+                        email,
+                        ..
+                    })) = user
+                    else {
+                        return render_mfa_verify(
+                            &app_state,
+                            form.challenge_id,
+                            form.method,
+                            Some("User account not found".to_string()),
+                        )
+                        .await;
                     };
 
-                    // Create JWT token
-                    if let Ok(token) = jwt::create_jwt(user_id, email) {
-                        // Create response with auth cookie
-                        let mut response = Redirect::to("/dashboard").into_response();
-                        response.headers_mut().insert(
-                            header::SET_COOKIE,
-                            jwt::create_auth_cookie(&token).parse().unwrap(),
-                        );
-                        response
-                    } else {
-                        let template = MfaVerifyTemplate {
-                            challenge_id: form.challenge_id,
-                            error: Some("Failed to create session".to_string()),
-                        };
-                        match template.render() {
-                            Ok(html) => Html(html).into_response(),
-                            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                        }
-                    }
-                }
-                Err(e) => {
-                    let template = MfaVerifyTemplate {
-                        challenge_id: form.challenge_id,
-                        error: Some(format!("Challenge error: {e}")),
-                    };
-                    match template.render() {
-                        Ok(html) => Html(html).into_response(),
-                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    // Open a session and set its cookies
+                    let user_agent = headers
+                        .get(header::USER_AGENT)
+                        .and_then(|agent| agent.to_str().ok())
+                        .map(ToOwned::to_owned);
+                    if let Ok(tokens) =
+                        jwt::open_session(app_state.broker(), user_id, email, user_agent).await
+                    {
+                        return jwt::with_session_cookies(Redirect::to("/dashboard"), &tokens);
                     }
+                    "Failed to create session".to_string()
                 }
+                Err(e) => format!("Challenge error: {e}"),
             }
         }
-        Ok(false) => {
-            let template = MfaVerifyTemplate {
-                challenge_id: form.challenge_id,
-                error: Some("Invalid verification code".to_string()),
+        Ok(false) => "Invalid verification code".to_string(),
+        // The challenge is gone, only a new login helps
+        Err(
+            e @ (AuthError::AccountLocked(_)
+            | AuthError::MfaFailed(MfaError::TooManyAttempts | MfaError::ChallengeExpired)),
+        ) => {
+            warn!("MFA challenge {} abandoned: {}", form.challenge_id, e);
+            let template = LoginTemplate {
+                error: Some(e.to_string()),
             };
-            match template.render() {
+            return match template.render() {
                 Ok(html) => Html(html).into_response(),
                 Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            }
+            };
+        }
+        Err(e) => format!("Verification failed: {e}"),
+    };
+    render_mfa_verify(&app_state, form.challenge_id, form.method, Some(error)).await
+}
+
+/// Verification page of a login challenge, offering the user's other methods
+async fn render_mfa_verify(
+    app_state: &AppState,
+    challenge_id: String,
+    method: MfaMethod,
+    error: Option<String>,
+) -> Response {
+    // An expired challenge has no alternatives, the error already says so
+    let alternatives = app_state
+        .broker()
+        .mfa_methods_for_challenge(method, &challenge_id)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|alternative| *alternative != method)
+        .map(|alternative| MfaMethodDisplayData {
+            method: alternative.as_str(),
+            label: alternative.label(),
+        })
+        .collect();
+    let template = MfaVerifyTemplate {
+        challenge_id,
+        method: method.as_str(),
+        alternatives,
+        error,
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Answer a login challenge with another of the user's methods
+pub async fn switch_mfa(
+    Query(params): Query<SwitchMfaQuery>,
+    State(app_state): State<AppState>,
+) -> Response {
+    match app_state
+        .broker()
+        .switch_mfa(params.method, &params.challenge_id, params.to)
+        .await
+    {
+        Ok(challenge_id) => Redirect::to(&format!(
+            "/verify-mfa?challenge_id={challenge_id}&method={}",
+            params.to.as_str()
+        ))
+        .into_response(),
+        Err(AuthError::MfaFailed(
+            e @ (MfaError::ChallengeNotFound | MfaError::ChallengeExpired),
+        )) => {
+            warn!(
+                "Cannot switch MFA method of challenge {}: {}",
+                params.challenge_id, e
+            );
+            // Redirect back to login if challenge is invalid/expired
+            Redirect::to("/login").into_response()
         }
         Err(e) => {
-            let template = MfaVerifyTemplate {
-                challenge_id: form.challenge_id,
-                error: Some(format!("Verification failed: {e}")),
-            };
-            match template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            }
+            error!(
+                "Failed to switch MFA method of challenge {}: {}",
+                params.challenge_id, e
+            );
+            render_mfa_verify(
+                &app_state,
+                params.challenge_id,
+                params.method,
+                Some(format!("Could not use {}: {e}", params.to.label())),
+            )
+            .await
         }
     }
 }
@@ -681,20 +1100,22 @@ pub async fn registration_verify_submit(
     };
 
     // Verify the MFA code
-    let verification_result = {
-        let broker = app_state.lock().unwrap();
-        broker
-            .mfa_service
-            .verify_mfa(&form.challenge_id, &form.code)
-    };
+    let verification_result = app_state
+        .broker()
+        .mfa_service
+        .verify_mfa(&form.challenge_id, &form.code)
+        .await;
 
     match verification_result {
         Ok(true) => {
             // MFA verified successfully, mark user as verified
-            let verification_success = {
-                let broker = app_state.lock().unwrap();
-                broker.get_user_repo().verify_user_email(&user_id).is_ok()
-            };
+            let verification_success = app_state
+                .broker()
+                .get_user_repo()
+                .await
+                .verify_user_email(&user_id)
+                .await
+                .is_ok();
 
             if verification_success {
                 info!("Email verification successful for user ID: {}", user_id);
@@ -742,22 +1163,31 @@ pub async fn resend_mfa(
     Query(params): Query<ResendMfaQuery>,
     State(app_state): State<AppState>,
 ) -> Response {
+    // Only email codes are sent
+    if params.method != MfaMethod::Email {
+        return Redirect::to(&format!(
+            "/verify-mfa?challenge_id={}&method={}",
+            params.challenge_id,
+            params.method.as_str()
+        ))
+        .into_response();
+    }
+
     // Get the original challenge to extract the user email
-    let challenge_result = {
-        let broker = app_state.lock().unwrap();
-        broker.mfa_service.get_challenge(&params.challenge_id)
-    };
+    let challenge_result = app_state
+        .broker()
+        .mfa_service
+        .get_challenge(&params.challenge_id)
+        .await;
 
     match challenge_result {
         Ok(challenge) => {
             // Initiate a new MFA challenge for the same user
-            let new_challenge_id_result = {
-                let broker = app_state.lock().unwrap();
-                tokio::task::block_in_place(|| {
-                    tokio::runtime::Handle::current()
-                        .block_on(broker.mfa_service.initiate_mfa(&challenge.user_email))
-                })
-            };
+            let new_challenge_id_result = app_state
+                .broker()
+                .mfa_service
+                .initiate_mfa(&challenge.user_email)
+                .await;
 
             match new_challenge_id_result {
                 Ok(new_challenge_id) => {
@@ -775,14 +1205,13 @@ pub async fn resend_mfa(
                         challenge.user_email, e
                     );
                     // Redirect back to the original MFA page with error
-                    let template = MfaVerifyTemplate {
-                        challenge_id: params.challenge_id,
-                        error: Some(format!("Failed to resend verification code: {e}")),
-                    };
-                    match template.render() {
-                        Ok(html) => Html(html).into_response(),
-                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                    }
+                    render_mfa_verify(
+                        &app_state,
+                        params.challenge_id,
+                        params.method,
+                        Some(format!("Failed to resend verification code: {e}")),
+                    )
+                    .await
                 }
             }
         }
@@ -796,14 +1225,22 @@ pub async fn resend_mfa(
         }
     }
 }
-pub async fn deposit_page(app_state: State<AppState>, request: axum::extract::Request) -> Response {
-    check_token_and_execute(app_state, request, |_app_state, _user, _request| {
-        let template = DepositTemplate { error: None };
-        match template.render() {
-            Ok(html) => Html(html).into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        }
-    })
+pub async fn deposit_page(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    if authenticated_user(&app_state, request.extensions())
+        .await
+        .is_none()
+    {
+        return Redirect::to("/login").into_response();
+    }
+
+    let template = DepositTemplate { error: None };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
 }
 pub async fn deposit_submit(
     State(app_state): State<AppState>,
@@ -811,24 +1248,13 @@ pub async fn deposit_submit(
 ) -> Response {
     let (parts, body) = request.into_parts(); // get form
 
-    // Check for authentication token
-    let Some(claims) = parts.extensions.get::<jwt::Claims>() else {
+    let Some(user) = authenticated_user(&app_state, &parts.extensions).await else {
         return Redirect::to("/login").into_response();
     };
-
-    // Get user from domain layer
-    let Ok(user_id) = Uuid::parse_str(&claims.subject) else {
+    let Some(user_id) = user.id else {
         return Redirect::to("/login").into_response();
     };
 
-    let user = {
-        let broker = app_state.lock().unwrap();
-        let Ok(Some(user)) = broker.get_user_repo().get(&user_id) else {
-            return Redirect::to("/login").into_response();
-        };
-        user
-    };
-
     let request = axum::extract::Request::from_parts(parts, body);
     let Ok(Form(form)) = Form::<DepositForm>::from_request(request, &app_state).await else {
         let template = DepositTemplate {
@@ -860,13 +1286,10 @@ pub async fn deposit_submit(
     };
 
     // Process the deposit
-    let deposit_result = {
-        let broker = app_state.lock().unwrap();
-        broker.get_user_repo().deposit_to_user(&user_id, amount)
-    };
+    let deposit_result = app_state.broker().deposit(&user_id, amount).await;
 
     match deposit_result {
-        Ok(()) => {
+        Ok(_) => {
             info!(
                 "Deposit successful for user: {} amount: {}",
                 user.email, amount
@@ -886,19 +1309,56 @@ pub async fn deposit_submit(
     }
 }
 pub async fn place_order_page(
-    app_state: State<AppState>,
+    State(app_state): State<AppState>,
     request: axum::extract::Request,
 ) -> Response {
-    check_token_and_execute(app_state, request, |_app_state, user, _request| {
-        let template = PlaceOrderTemplate {
-            error: None,
-            account_balance: user.balance,
-        };
-        match template.render() {
-            Ok(html) => Html(html).into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    render_place_order(&app_state, None, user.balance).await
+}
+
+/// Render the order form with the instruments currently open for trading
+async fn render_place_order(
+    app_state: &AppState,
+    error: Option<String>,
+    account_balance: f64,
+) -> Response {
+    let instruments = match app_state
+        .broker()
+        .get_instrument_repo()
+        .await
+        .list_instruments()
+        .await
+    {
+        Ok(instruments) => {
+            let market_data = app_state.broker().market_data();
+            instruments
+                .iter()
+                .filter(|instrument| instrument.status == InstrumentStatus::Active)
+                .map(|instrument| {
+                    InstrumentDisplayData::from_instrument(
+                        instrument,
+                        &market_data.quote(instrument),
+                    )
+                })
+                .collect()
         }
-    })
+        Err(e) => {
+            error!("Failed to load instruments: {}", e);
+            vec![]
+        }
+    };
+
+    let template = PlaceOrderTemplate {
+        error,
+        account_balance,
+        instruments,
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
 }
 #[allow(clippy::too_many_lines)]
 pub async fn place_order_submit(
@@ -907,34 +1367,21 @@ pub async fn place_order_submit(
 ) -> Response {
     let (parts, body) = request.into_parts(); // get form
 
-    // Check for authentication token
-    let Some(claims) = parts.extensions.get::<jwt::Claims>() else {
+    let Some(user) = authenticated_user(&app_state, &parts.extensions).await else {
         return Redirect::to("/login").into_response();
     };
-
-    // Get user from domain layer
-    let Ok(user_id) = Uuid::parse_str(&claims.subject) else {
+    let Some(user_id) = user.id else {
         return Redirect::to("/login").into_response();
     };
 
-    let user = {
-        let broker = app_state.lock().unwrap();
-        let Ok(Some(user)) = broker.get_user_repo().get(&user_id) else {
-            return Redirect::to("/login").into_response();
-        };
-        user
-    };
-
     let request = axum::extract::Request::from_parts(parts, body);
     let Ok(Form(form)) = Form::<PlaceOrderForm>::from_request(request, &app_state).await else {
-        let template = PlaceOrderTemplate {
-            error: Some("Invalid form data".to_string()),
-            account_balance: user.balance,
-        };
-        return match template.render() {
-            Ok(html) => Html(html).into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        };
+        return render_place_order(
+            &app_state,
+            Some("Invalid form data".to_string()),
+            user.balance,
+        )
+        .await;
     };
     info!(
         "Place order attempt for user: {} symbol: {} type: {} quantity: {} price: {}",
@@ -943,14 +1390,12 @@ pub async fn place_order_submit(
     let quantity = match form.quantity.parse::<u64>() {
         Ok(q) if q > 0 => q,
         _ => {
-            let template = PlaceOrderTemplate {
-                error: Some("Please enter a valid positive quantity".to_string()),
-                account_balance: user.balance,
-            };
-            return match template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            };
+            return render_place_order(
+                &app_state,
+                Some("Please enter a valid positive quantity".to_string()),
+                user.balance,
+            )
+            .await;
         }
     };
 
@@ -958,54 +1403,55 @@ pub async fn place_order_submit(
         "buy" => domain::order::OrderSide::Buy,
         "sell" => domain::order::OrderSide::Sell,
         _ => {
-            let template = PlaceOrderTemplate {
-                error: Some("Invalid order side".to_string()),
-                account_balance: user.balance,
-            };
-            return match template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            };
+            return render_place_order(
+                &app_state,
+                Some("Invalid order side".to_string()),
+                user.balance,
+            )
+            .await;
         }
     };
     let order_type = match form.order_type.as_str() {
         "market" => domain::order::OrderType::Market,
-        "limit" => {
-            let limit = match form.price.parse::<f64>() {
+        kind @ ("limit" | "stop") => {
+            let price = match form.price.parse::<f64>() {
                 Ok(p) if p > 0.0 => p,
                 _ => {
-                    let template = PlaceOrderTemplate {
-                        error: Some("Please enter a valid positive price".to_string()),
-                        account_balance: user.balance,
-                    };
-                    return match template.render() {
-                        Ok(html) => Html(html).into_response(),
-                        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                    };
+                    return render_place_order(
+                        &app_state,
+                        Some("Please enter a valid positive price".to_string()),
+                        user.balance,
+                    )
+                    .await;
                 }
             };
-            domain::order::OrderType::Limit(limit)
+            if kind == "limit" {
+                domain::order::OrderType::Limit(price)
+            } else {
+                domain::order::OrderType::Stop(price)
+            }
         }
         _ => {
-            let template = PlaceOrderTemplate {
-                error: Some("Invalid order type".to_string()),
-                account_balance: user.balance,
-            };
-            return match template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-            };
+            return render_place_order(
+                &app_state,
+                Some("Invalid order type".to_string()),
+                user.balance,
+            )
+            .await;
         }
     };
     {
-        let mut broker = app_state.lock().unwrap();
-        match broker.create_order(
-            user_id,
-            form.symbol.clone(),
-            quantity,
-            order_side,
-            order_type,
-        ) {
+        match app_state
+            .broker()
+            .create_order(
+                user_id,
+                form.symbol.clone(),
+                quantity,
+                order_side,
+                order_type,
+            )
+            .await
+        {
             Ok(_) => {
                 info!(
                     "Order successfully sent for user: {} symbol: {} type: {} quantity: {} price: {}",
@@ -1019,15 +1465,348 @@ pub async fn place_order_submit(
                     "Order placement failed for user: {} error: {}",
                     user.email, e
                 );
-                let template = PlaceOrderTemplate {
-                    error: Some(format!("Order placement failed: {e}")),
-                    account_balance: user.balance,
-                };
-                match template.render() {
-                    Ok(html) => Html(html).into_response(),
-                    Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-                }
+                render_place_order(
+                    &app_state,
+                    Some(format!("Order placement failed: {e}")),
+                    user.balance,
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Devices the user is logged in on
+pub async fn sessions_page(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let current = current_session(request.extensions());
+
+    let sessions = match app_state
+        .broker()
+        .get_session_repo()
+        .await
+        .active_sessions(&user_id)
+        .await
+    {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error!("Failed to list the sessions of user {}: {}", user_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let template = SessionsTemplate {
+        sessions: sessions
+            .into_iter()
+            .map(|(session_id, session)| SessionDisplayData {
+                id: session_id.to_string(),
+                device: session
+                    .user_agent
+                    .unwrap_or_else(|| "Unknown device".to_string()),
+                created_at: session.created_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+                last_used_at: session
+                    .last_used_at
+                    .format("%Y-%m-%d %H:%M UTC")
+                    .to_string(),
+                current: Some(session_id) == current,
+            })
+            .collect(),
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Log out one device. Revoking the current session logs the user out.
+pub async fn revoke_session_submit(
+    State(app_state): State<AppState>,
+    Path(session_id): Path<SessionId>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let session_repo = app_state.broker().get_session_repo().await;
+    match session_repo.get(&session_id).await {
+        Ok(Some(session)) if Some(session.user_id) == user.id => {}
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+    if let Err(e) = session_repo.revoke_session(&session_id).await {
+        error!("Could not revoke session {}: {}", session_id, e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    if current_session(request.extensions()) == Some(session_id) {
+        jwt::logged_out(Redirect::to("/login"))
+    } else {
+        Redirect::to("/sessions").into_response()
+    }
+}
+
+/// Log out every device, including this one
+pub async fn revoke_all_sessions_submit(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user_id) = authenticated_user(&app_state, request.extensions())
+        .await
+        .and_then(|user| user.id)
+    else {
+        return Redirect::to("/login").into_response();
+    };
+    match app_state
+        .broker()
+        .get_session_repo()
+        .await
+        .revoke_all_sessions(&user_id)
+        .await
+    {
+        Ok(count) => {
+            info!("Revoked {} sessions of user {}", count, user_id);
+            jwt::logged_out(Redirect::to("/login"))
+        }
+        Err(e) => {
+            error!("Could not revoke the sessions of user {}: {}", user_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeForm {
+    pub code: String,
+}
+
+/// Authenticator app of the user, with the QR code to scan while none is confirmed
+pub async fn totp_page(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    render_totp_page(&app_state, &user, None).await
+}
+
+/// Turn on the authenticator app the user scanned, once they prove it works
+pub async fn totp_confirm_submit(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let (parts, body) = request.into_parts(); // get form
+    let Some(user) = authenticated_user(&app_state, &parts.extensions).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let request = axum::extract::Request::from_parts(parts, body);
+    let Ok(Form(form)) = Form::<TotpCodeForm>::from_request(request, &app_state).await else {
+        return render_totp_page(&app_state, &user, Some("Invalid form data".to_string())).await;
+    };
+
+    match app_state
+        .broker()
+        .confirm_totp(&user_id, form.code.trim())
+        .await
+    {
+        Ok(()) => Redirect::to("/mfa/totp").into_response(),
+        Err(e) => {
+            warn!(
+                "Could not confirm the authenticator app of user {}: {}",
+                user_id, e
+            );
+            render_totp_page(
+                &app_state,
+                &user,
+                Some(format!("Could not enable the app: {e}")),
+            )
+            .await
+        }
+    }
+}
+
+/// Remove the authenticator app, the user gets codes by email again
+pub async fn totp_disable_submit(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let (parts, body) = request.into_parts(); // get form
+    let Some(user) = authenticated_user(&app_state, &parts.extensions).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let request = axum::extract::Request::from_parts(parts, body);
+    let Ok(Form(form)) = Form::<TotpCodeForm>::from_request(request, &app_state).await else {
+        return render_totp_page(&app_state, &user, Some("Invalid form data".to_string())).await;
+    };
+
+    match app_state
+        .broker()
+        .disable_totp(&user_id, form.code.trim())
+        .await
+    {
+        Ok(()) => Redirect::to("/mfa/totp").into_response(),
+        Err(e) => {
+            warn!(
+                "Could not remove the authenticator app of user {}: {}",
+                user_id, e
+            );
+            render_totp_page(
+                &app_state,
+                &user,
+                Some(format!("Could not remove the app: {e}")),
+            )
+            .await
+        }
+    }
+}
+
+async fn render_totp_page(app_state: &AppState, user: &User, error: Option<String>) -> Response {
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let confirmed_at = user.totp.as_ref().and_then(|totp| totp.confirmed_at);
+    let enrollment = if confirmed_at.is_some() {
+        None
+    } else {
+        match app_state.broker().enroll_totp(&user_id).await {
+            Ok(enrollment) => Some(enrollment),
+            Err(e) => {
+                error!("Could not enroll user {} in TOTP: {}", user_id, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
         }
+    };
+    let template = TotpTemplate {
+        enrolled_at: confirmed_at.map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string()),
+        enrollment,
+        error,
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PreferredMfaForm {
+    pub method: MfaMethod,
+}
+
+/// Second factors of the user: the one asked for first and their recovery codes
+pub async fn mfa_page(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    render_mfa_page(&user, Vec::new(), None)
+}
+
+/// Choose the second factor asked for first at login
+pub async fn preferred_mfa_submit(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let (parts, body) = request.into_parts(); // get form
+    let Some(user) = authenticated_user(&app_state, &parts.extensions).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let request = axum::extract::Request::from_parts(parts, body);
+    let Ok(Form(form)) = Form::<PreferredMfaForm>::from_request(request, &app_state).await else {
+        return render_mfa_page(&user, Vec::new(), Some("Invalid form data".to_string()));
+    };
+
+    match app_state
+        .broker()
+        .get_user_repo()
+        .await
+        .set_preferred_mfa(&user_id, form.method)
+        .await
+    {
+        Ok(()) => Redirect::to("/mfa").into_response(),
+        Err(e) => {
+            warn!(
+                "Could not set the preferred MFA method of user {}: {}",
+                user_id, e
+            );
+            render_mfa_page(&user, Vec::new(), Some(e.to_string()))
+        }
+    }
+}
+
+/// Replace the recovery codes of the user and show the new ones once
+pub async fn recovery_codes_submit(
+    State(app_state): State<AppState>,
+    request: axum::extract::Request,
+) -> Response {
+    let Some(user) = authenticated_user(&app_state, request.extensions()).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some(user_id) = user.id else {
+        return Redirect::to("/login").into_response();
+    };
+    let user_repo = app_state.broker().get_user_repo().await;
+    let codes = match user_repo.regenerate_recovery_codes(&user_id).await {
+        Ok(codes) => codes,
+        Err(e) => {
+            error!(
+                "Could not generate recovery codes for user {}: {}",
+                user_id, e
+            );
+            return render_mfa_page(&user, Vec::new(), Some(e.to_string()));
+        }
+    };
+    info!("User {} generated new recovery codes", user_id);
+    app_state
+        .broker()
+        .send_security_alert(&user_id, SecurityEvent::RecoveryCodesRegenerated)
+        .await;
+    match user_repo.get_user_by_id(&user_id).await {
+        Ok(Some(user)) => render_mfa_page(&user, codes, None),
+        _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn render_mfa_page(
+    user: &User,
+    new_recovery_codes: Vec<String>,
+    error: Option<String>,
+) -> Response {
+    let template = MfaTemplate {
+        methods: user
+            .mfa_methods()
+            .into_iter()
+            .filter(|method| *method != MfaMethod::RecoveryCode)
+            .map(|method| MfaMethodDisplayData {
+                method: method.as_str(),
+                label: method.label(),
+            })
+            .collect(),
+        preferred: user.mfa_method().as_str(),
+        totp_enrolled: user.totp_secret().is_some(),
+        recovery_codes_left: user.recovery_codes.len(),
+        new_recovery_codes,
+        error,
+    };
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     }
 }
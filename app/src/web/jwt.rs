@@ -1,127 +1,568 @@
+//! Issue and verify the JWTs authenticating web and API requests.
+//!
+//! Signing keys are listed in a JSON manifest named by `JWT_KEYS_FILE`:
+//!
+//! ```json
+//! {
+//!   "active": "2026-10",
+//!   "keys": [
+//!     { "kid": "2026-10", "algorithm": "EdDSA", "private_key": "ed25519.pem", "public_key": "ed25519.pub.pem" },
+//!     { "kid": "2026-04", "algorithm": "RS256", "public_key": "rsa.pub.pem" }
+//!   ]
+//! }
+//! ```
+//!
+//! Access tokens are short-lived and name the server-side session they were issued
+//! for, which is checked on every request so that revoking a session logs its device
+//! out at once. The session's refresh token, kept in a second cookie, gets new access
+//! tokens when they expire.
+//!
+//! Tokens are signed with the active key and carry its `kid`. Keys that are no longer
+//! active only need their public key, and keep verifying the tokens they signed until
+//! those expire, so keys can be rotated without logging everyone out.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
 use axum::{
     extract::{Request, State},
-    http::header,
+    http::{HeaderMap, header},
     middleware::Next,
     response::{IntoResponse, Redirect, Response},
 };
 use chrono::{Duration, Utc};
 use domain::Repository;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, TokenData, Validation, decode, encode};
+use domain::core::BrokerX;
+use domain::session::{SessionError, SessionId, SessionRepoExt};
+use domain::user::User;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header,
+    encode,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::web::AppState;
 
-// JWT Secret - In production, use environment variable or secure key management
-const JWT_SECRET: &[u8] = b"your_secret_key_here_change_in_production";
+const DEFAULT_ISSUER: &str = "brokerx";
+const DEFAULT_AUDIENCE: &str = "brokerx";
+const DEFAULT_TTL_SECS: i64 = 15 * 60;
+const DEFAULT_SESSION_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+static CONFIG: OnceLock<JwtConfig> = OnceLock::new();
+
+/// Use `config` to issue and verify tokens. Must be called before the first token is
+/// issued; without it an ephemeral key is used.
+pub fn init(config: JwtConfig) {
+    if CONFIG.set(config).is_err() {
+        warn!("JWT configuration already initialized, ignoring the new one");
+    }
+}
+
+fn config() -> &'static JwtConfig {
+    CONFIG.get_or_init(JwtConfig::ephemeral)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
+    #[serde(rename = "sub")]
     pub subject: String, // Subject (user ID)
-    pub email: String,   // Username for convenience
-    pub exp: i64,        // Expiration time
-    pub iat: i64,        // Issued at
+    pub email: String, // Username for convenience
+    pub iss: String,   // Issuer
+    pub aud: String,   // Audience
+    pub sid: String,   // Session the token was issued for
+    pub exp: i64,      // Expiration time
+    pub iat: i64,      // Issued at
 }
 
 impl Claims {
-    pub fn new(user_id: Uuid, email: String) -> Self {
+    fn new(user_id: Uuid, email: String, session_id: SessionId, config: &JwtConfig) -> Self {
         let now = Utc::now();
-        let exp = now + Duration::hours(24); // Token expires in 24 hours
+        let exp = now + config.ttl;
 
         Self {
             subject: user_id.to_string(),
             email,
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+            sid: session_id.to_string(),
             exp: exp.timestamp(),
             iat: now.timestamp(),
         }
     }
 }
 
-/// Generate a JWT token for the given user
-pub fn create_jwt(user_id: Uuid, email: String) -> Result<String, jsonwebtoken::errors::Error> {
-    let claims = Claims::new(user_id, email);
-    let header = Header::default();
-    let encoding_key = EncodingKey::from_secret(JWT_SECRET);
+/// Error loading the signing keys
+#[derive(Debug)]
+pub enum JwtConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Key(String, jsonwebtoken::errors::Error),
+    UnsupportedAlgorithm(String, Algorithm),
+    DuplicateKey(String),
+    UnknownActiveKey(String),
+    MissingPrivateKey(String),
+}
 
-    encode(&header, &claims, &encoding_key)
+impl fmt::Display for JwtConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtConfigError::Io(path, e) => write!(f, "Cannot read {}: {e}", path.display()),
+            JwtConfigError::Parse(path, e) => {
+                write!(f, "Invalid key manifest {}: {e}", path.display())
+            }
+            JwtConfigError::Key(kid, e) => write!(f, "Invalid key {kid}: {e}"),
+            JwtConfigError::UnsupportedAlgorithm(kid, algorithm) => write!(
+                f,
+                "Key {kid} uses {algorithm:?}, only EdDSA and RS256 are supported"
+            ),
+            JwtConfigError::DuplicateKey(kid) => write!(f, "Key {kid} is listed twice"),
+            JwtConfigError::UnknownActiveKey(kid) => {
+                write!(f, "Active key {kid} is not in the manifest")
+            }
+            JwtConfigError::MissingPrivateKey(kid) => {
+                write!(f, "Active key {kid} has no private key")
+            }
+        }
+    }
 }
 
-/// Verify and decode a JWT token
-pub fn verify_jwt(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-    let decoding_key = DecodingKey::from_secret(JWT_SECRET);
-    let validation = Validation::default();
+impl std::error::Error for JwtConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JwtConfigError::Io(_, e) => Some(e),
+            JwtConfigError::Parse(_, e) => Some(e),
+            JwtConfigError::Key(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    decode::<Claims>(token, &decoding_key, &validation)
+#[derive(Deserialize)]
+struct KeyManifest {
+    active: String,
+    keys: Vec<KeyEntry>,
 }
 
-/// Extract JWT token from Authorization header or cookie
-fn extract_token_from_request(request: &Request) -> Option<String> {
-    // Try Authorization header first (Bearer token)
-    if let Some(auth_header) = request.headers().get(header::AUTHORIZATION) {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                return auth_str.strip_prefix("Bearer ").map(ToOwned::to_owned);
+#[derive(Deserialize)]
+struct KeyEntry {
+    kid: String,
+    algorithm: Algorithm,
+    private_key: Option<PathBuf>,
+    public_key: PathBuf,
+}
+
+#[derive(Clone)]
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Keys and registered claims of the issued tokens
+#[derive(Clone)]
+pub struct JwtConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// Lifetime of the access tokens and of the cookie holding them
+    pub ttl: Duration,
+    /// Lifetime of the sessions, after which users have to log in again
+    pub session_ttl: Duration,
+    active_kid: String,
+    signing_key: EncodingKey,
+    keys: HashMap<String, VerificationKey>,
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kids: Vec<_> = self.keys.keys().collect();
+        kids.sort();
+        f.debug_struct("JwtConfig")
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("ttl", &self.ttl)
+            .field("session_ttl", &self.session_ttl)
+            .field("active_kid", &self.active_kid)
+            .field("kids", &kids)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtConfig {
+    /// Load the keys of `JWT_KEYS_FILE` and read `JWT_ISSUER`, `JWT_AUDIENCE`,
+    /// `JWT_TTL_SECS` and `SESSION_TTL_SECS`. Without `JWT_KEYS_FILE`, tokens are signed with an ephemeral key
+    /// and do not survive a restart.
+    pub fn from_env() -> Result<Self, JwtConfigError> {
+        let mut config = match std::env::var("JWT_KEYS_FILE") {
+            Ok(path) => Self::from_file(path)?,
+            Err(_) => {
+                warn!("No JWT_KEYS_FILE configured, signing tokens with an ephemeral key");
+                Self::ephemeral()
             }
+        };
+        if let Ok(issuer) = std::env::var("JWT_ISSUER") {
+            config.issuer = issuer;
         }
+        if let Ok(audience) = std::env::var("JWT_AUDIENCE") {
+            config.audience = audience;
+        }
+        if let Some(ttl) = ttl_from_env("JWT_TTL_SECS", DEFAULT_TTL_SECS) {
+            config.ttl = ttl;
+        }
+        if let Some(ttl) = ttl_from_env("SESSION_TTL_SECS", DEFAULT_SESSION_TTL_SECS) {
+            config.session_ttl = ttl;
+        }
+        Ok(config)
     }
 
-    // Try cookie as fallback
-    if let Some(cookie_header) = request.headers().get(header::COOKIE) {
-        if let Ok(cookie_str) = cookie_header.to_str() {
-            for cookie in cookie_str.split(';') {
-                let cookie = cookie.trim();
-                if let Some(token) = cookie.strip_prefix("token=") {
-                    return Some(token.to_string());
-                }
+    /// Load the keys listed in the manifest at `path`. Key paths are relative to the
+    /// manifest.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, JwtConfigError> {
+        let path = path.as_ref();
+        let manifest = std::fs::read(path).map_err(|e| JwtConfigError::Io(path.into(), e))?;
+        let manifest: KeyManifest =
+            serde_json::from_slice(&manifest).map_err(|e| JwtConfigError::Parse(path.into(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let read = |file: &Path| {
+            let file = dir.join(file);
+            std::fs::read(&file).map_err(|e| JwtConfigError::Io(file, e))
+        };
+
+        let mut keys = HashMap::new();
+        let mut signing_key = None;
+        for entry in manifest.keys {
+            let invalid = |e| JwtConfigError::Key(entry.kid.clone(), e);
+            let public_key = read(&entry.public_key)?;
+            let key = match entry.algorithm {
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(&public_key),
+                Algorithm::RS256 => DecodingKey::from_rsa_pem(&public_key),
+                other => return Err(JwtConfigError::UnsupportedAlgorithm(entry.kid, other)),
+            }
+            .map_err(invalid)?;
+            if entry.kid == manifest.active {
+                let Some(private_key) = &entry.private_key else {
+                    return Err(JwtConfigError::MissingPrivateKey(entry.kid));
+                };
+                let private_key = read(private_key)?;
+                signing_key = Some(
+                    match entry.algorithm {
+                        Algorithm::EdDSA => EncodingKey::from_ed_pem(&private_key),
+                        _ => EncodingKey::from_rsa_pem(&private_key),
+                    }
+                    .map_err(invalid)?,
+                );
+            }
+            let verification = VerificationKey {
+                algorithm: entry.algorithm,
+                key,
+            };
+            if keys.insert(entry.kid.clone(), verification).is_some() {
+                return Err(JwtConfigError::DuplicateKey(entry.kid));
             }
         }
+        let signing_key =
+            signing_key.ok_or_else(|| JwtConfigError::UnknownActiveKey(manifest.active.clone()))?;
+
+        Ok(Self {
+            issuer: DEFAULT_ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+            ttl: Duration::seconds(DEFAULT_TTL_SECS),
+            session_ttl: Duration::seconds(DEFAULT_SESSION_TTL_SECS),
+            active_kid: manifest.active,
+            signing_key,
+            keys,
+        })
+    }
+
+    /// Sign with an Ed25519 key generated for this process
+    #[must_use]
+    pub fn ephemeral() -> Self {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .expect("the system random generator is available");
+        let key_pair =
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("a generated key pair is valid");
+        let kid = format!("ephemeral-{}", Uuid::new_v4());
+        let verification = VerificationKey {
+            algorithm: Algorithm::EdDSA,
+            key: DecodingKey::from_ed_der(key_pair.public_key().as_ref()),
+        };
+
+        Self {
+            issuer: DEFAULT_ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+            ttl: Duration::seconds(DEFAULT_TTL_SECS),
+            session_ttl: Duration::seconds(DEFAULT_SESSION_TTL_SECS),
+            active_kid: kid.clone(),
+            signing_key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            keys: HashMap::from([(kid, verification)]),
+        }
     }
 
-    None
+    /// Generate an access token for a session of the given user, signed with the
+    /// active key
+    pub fn create_jwt(
+        &self,
+        user_id: Uuid,
+        email: String,
+        session_id: SessionId,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let claims = Claims::new(user_id, email, session_id, self);
+        let header = Header {
+            kid: Some(self.active_kid.clone()),
+            ..Header::new(self.keys[&self.active_kid].algorithm)
+        };
+
+        encode(&header, &claims, &self.signing_key)
+    }
+
+    /// Verify the signature, expiry, issuer and audience of a token signed by any of
+    /// the known keys
+    pub fn verify_jwt(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let Some(key) = header.kid.as_ref().and_then(|kid| self.keys.get(kid)) else {
+            return Err(ErrorKind::InvalidToken.into());
+        };
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+        decode::<Claims>(token, &key.key, &validation)
+    }
+}
+
+fn ttl_from_env(name: &str, default: i64) -> Option<Duration> {
+    let ttl = std::env::var(name).ok()?;
+    match ttl.parse::<i64>() {
+        Ok(secs) if secs > 0 => Some(Duration::seconds(secs)),
+        _ => {
+            warn!("Invalid {name} {ttl}, using {default}");
+            None
+        }
+    }
 }
 
-/// Middleware to protect routes that require authentication
+/// Lifetime of the access tokens
+pub fn access_token_ttl() -> Duration {
+    config().ttl
+}
+
+/// Generate an access token for a session of the given user
+pub fn create_jwt(
+    user_id: Uuid,
+    email: String,
+    session_id: SessionId,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    config().create_jwt(user_id, email, session_id)
+}
+
+/// Verify and decode a JWT token
+pub fn verify_jwt(token: &str) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
+    config().verify_jwt(token)
+}
+
+/// Error opening or refreshing a session
+#[derive(Debug)]
+pub enum TokenError {
+    Session(SessionError),
+    Jwt(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Session(e) => write!(f, "{e}"),
+            TokenError::Jwt(e) => write!(f, "Cannot sign the access token: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl From<SessionError> for TokenError {
+    fn from(error: SessionError) -> Self {
+        TokenError::Session(error)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        TokenError::Jwt(error)
+    }
+}
+
+/// Access and refresh token of a session
+#[derive(Debug, Clone)]
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Open a session for a user who just logged in
+pub async fn open_session(
+    broker: &BrokerX,
+    user_id: Uuid,
+    email: String,
+    user_agent: Option<String>,
+) -> Result<SessionTokens, TokenError> {
+    let (session_id, refresh_token) = broker
+        .get_session_repo()
+        .await
+        .open_session(user_id, user_agent, config().session_ttl)
+        .await
+        .map_err(SessionError::Repo)?;
+    Ok(SessionTokens {
+        access_token: create_jwt(user_id, email, session_id)?,
+        refresh_token,
+    })
+}
+
+/// Exchange a refresh token for a new access token and refresh token
+pub async fn refresh_session(
+    broker: &BrokerX,
+    refresh_token: &str,
+) -> Result<SessionTokens, TokenError> {
+    let session_repo = broker.get_session_repo().await;
+    let (session_id, session, refresh_token) = session_repo.refresh_session(refresh_token).await?;
+    let Ok(Some(user)) = broker.get_user_repo().await.get(&session.user_id).await else {
+        session_repo
+            .revoke_session(&session_id)
+            .await
+            .map_err(SessionError::Repo)?;
+        return Err(SessionError::InvalidToken.into());
+    };
+    Ok(SessionTokens {
+        access_token: create_jwt(session.user_id, user.email, session_id)?,
+        refresh_token,
+    })
+}
+
+/// Claims of a valid access token whose session is active, and the user it was
+/// issued to
+pub(crate) async fn authenticate(broker: &BrokerX, token: &str) -> Option<(Claims, User)> {
+    let claims = verify_jwt(token).ok()?.claims;
+    let user_id = Uuid::parse_str(&claims.subject).ok()?;
+    let session_id = Uuid::parse_str(&claims.sid).ok()?;
+    if !broker
+        .get_session_repo()
+        .await
+        .is_session_active(&session_id)
+        .await
+        .ok()?
+    {
+        return None;
+    }
+    let user = broker.get_user_repo().await.get(&user_id).await.ok()??;
+    Some((claims, user))
+}
+
+/// Extract JWT token from Authorization header or cookie
+pub(crate) fn extract_token_from_headers(headers: &HeaderMap) -> Option<String> {
+    // Try Authorization header first (Bearer token)
+    if let Some(auth_header) = headers.get(header::AUTHORIZATION)
+        && let Ok(auth_str) = auth_header.to_str()
+        && auth_str.starts_with("Bearer ")
+    {
+        return auth_str.strip_prefix("Bearer ").map(ToOwned::to_owned);
+    }
+
+    // Try cookie as fallback
+    cookie(headers, "token")
+}
+
+fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    let cookie_str = headers.get(header::COOKIE)?.to_str().ok()?;
+    cookie_str.split(';').find_map(|cookie| {
+        let (key, value) = cookie.trim().split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Refresh token of the web interface's `refresh_token` cookie
+pub(crate) fn extract_refresh_token(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, "refresh_token")
+}
+
+/// Middleware to protect routes that require authentication.
+///
+/// An expired access token is replaced using the refresh token cookie, without
+/// interrupting the request.
 pub async fn auth_middleware(
     State(app_state): State<AppState>,
     mut request: Request,
     next: Next,
 ) -> Response {
+    let broker = app_state.broker();
     // Extract token from request
-    let Some(token) = extract_token_from_request(&request) else {
-        // No token found, redirect to login
+    let token = extract_token_from_headers(request.headers());
+    if let Some(token) = token
+        && let Some((claims, _)) = authenticate(broker, &token).await
+    {
+        // Add user info to request extensions for use in handlers
+        request.extensions_mut().insert(claims);
+        return next.run(request).await;
+    }
+
+    // No valid access token, try to refresh the session
+    let Some(refresh_token) = extract_refresh_token(request.headers()) else {
         return Redirect::to("/login").into_response();
     };
-
-    // Verify token
-    let claims = match verify_jwt(&token) {
-        Ok(token_data) => token_data.claims,
-        Err(_) => {
-            // Invalid token, redirect to login
-            return Redirect::to("/login").into_response();
+    let tokens = match refresh_session(broker, &refresh_token).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            warn!("Could not refresh session: {e}");
+            return logged_out(Redirect::to("/login"));
         }
     };
-
-    // Verify user still exists in the system
-    let Ok(user_id) = Uuid::parse_str(&claims.subject) else {
-        return Redirect::to("/login").into_response();
+    let Ok(token_data) = verify_jwt(&tokens.access_token) else {
+        return logged_out(Redirect::to("/login"));
     };
+    request.extensions_mut().insert(token_data.claims);
 
-    {
-        let broker = app_state.lock().unwrap();
-        if broker
-            .get_user_repo()
-            .get(&user_id)
-            .is_ok_and(|u| u.is_none())
-        {
-            // User no longer exists, redirect to login
-            return Redirect::to("/login").into_response();
+    let mut response = next.run(request).await;
+    for cookie in session_cookies(&tokens) {
+        if let Ok(cookie) = cookie.parse() {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
         }
     }
+    response
+}
 
-    // Add user info to request extensions for use in handlers
-    request.extensions_mut().insert(claims);
+/// Set the cookies of a session on `response`
+pub fn with_session_cookies(response: impl IntoResponse, tokens: &SessionTokens) -> Response {
+    let mut response = response.into_response();
+    for cookie in session_cookies(tokens) {
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, cookie.parse().unwrap());
+    }
+    response
+}
+
+/// Clear the cookies of the session on `response`
+pub fn logged_out(response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    for cookie in [create_logout_cookie(), create_refresh_logout_cookie()] {
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, cookie.parse().unwrap());
+    }
+    response
+}
 
-    next.run(request).await
+fn session_cookies(tokens: &SessionTokens) -> [String; 2] {
+    [
+        create_auth_cookie(&tokens.access_token),
+        create_refresh_cookie(&tokens.refresh_token),
+    ]
 }
 
 /// Helper to create a cookie with the JWT token
@@ -129,7 +570,16 @@ pub fn create_auth_cookie(token: &str) -> String {
     format!(
         "token={}; HttpOnly; Secure; SameSite=Strict; Max-Age={}; Path=/",
         token,
-        24 * 60 * 60 // 24 hours in seconds
+        config().ttl.num_seconds()
+    )
+}
+
+/// Helper to create a cookie with the refresh token, which lives as long as the session
+pub fn create_refresh_cookie(token: &str) -> String {
+    format!(
+        "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Max-Age={}; Path=/",
+        token,
+        config().session_ttl.num_seconds()
     )
 }
 
@@ -137,3 +587,124 @@ pub fn create_auth_cookie(token: &str) -> String {
 pub fn create_logout_cookie() -> String {
     "token=; HttpOnly; Secure; SameSite=Strict; Max-Age=0; Path=/".to_string()
 }
+
+/// Helper to create a cookie that clears the refresh token
+pub fn create_refresh_logout_cookie() -> String {
+    "refresh_token=; HttpOnly; Secure; SameSite=Strict; Max-Age=0; Path=/".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(name: &str) -> JwtConfig {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/jwt")
+            .join(name);
+        JwtConfig::from_file(path).unwrap()
+    }
+
+    fn kid(token: &str) -> Option<String> {
+        decode_header(token).unwrap().kid
+    }
+
+    #[test]
+    fn test_tokens_signed_before_rotation_stay_valid() {
+        let before = manifest("before-rotation.json");
+        let after = manifest("after-rotation.json");
+        let user_id = Uuid::new_v4();
+
+        let old = before
+            .create_jwt(user_id, "old@test.com".into(), Uuid::new_v4())
+            .unwrap();
+        assert_eq!(kid(&old).as_deref(), Some("2026-04"));
+        assert_eq!(decode_header(&old).unwrap().alg, Algorithm::RS256);
+
+        let new = after
+            .create_jwt(user_id, "new@test.com".into(), Uuid::new_v4())
+            .unwrap();
+        assert_eq!(kid(&new).as_deref(), Some("2026-10"));
+        assert_eq!(decode_header(&new).unwrap().alg, Algorithm::EdDSA);
+
+        for token in [&old, &new] {
+            let claims = after.verify_jwt(token).unwrap().claims;
+            assert_eq!(claims.subject, user_id.to_string());
+        }
+        // Keys added by the rotation are unknown to the old configuration
+        assert!(before.verify_jwt(&new).is_err());
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let token = JwtConfig::ephemeral()
+            .create_jwt(Uuid::new_v4(), "a@test.com".into(), Uuid::new_v4())
+            .unwrap();
+        let error = manifest("after-rotation.json")
+            .verify_jwt(&token)
+            .unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::InvalidToken);
+    }
+
+    #[test]
+    fn test_issuer_audience_and_expiry_are_validated() {
+        let config = JwtConfig::ephemeral();
+        let token = |config: &JwtConfig| {
+            config.create_jwt(Uuid::new_v4(), "a@test.com".into(), Uuid::new_v4())
+        };
+        assert!(config.verify_jwt(&token(&config).unwrap()).is_ok());
+
+        let other = JwtConfig {
+            issuer: "other".into(),
+            ..config.clone()
+        };
+        assert_eq!(
+            config
+                .verify_jwt(&token(&other).unwrap())
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::InvalidIssuer
+        );
+
+        let other = JwtConfig {
+            audience: "other".into(),
+            ..config.clone()
+        };
+        assert_eq!(
+            config
+                .verify_jwt(&token(&other).unwrap())
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::InvalidAudience
+        );
+
+        let other = JwtConfig {
+            ttl: Duration::minutes(-5),
+            ..config.clone()
+        };
+        assert_eq!(
+            config
+                .verify_jwt(&token(&other).unwrap())
+                .unwrap_err()
+                .kind(),
+            &ErrorKind::ExpiredSignature
+        );
+    }
+
+    #[test]
+    fn test_active_key_needs_a_private_key() {
+        let dir = std::env::temp_dir().join(format!("jwt-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/jwt");
+        let public_key = testdata.join("previous.pub.pem");
+        let manifest = serde_json::json!({
+            "active": "2026-04",
+            "keys": [{ "kid": "2026-04", "algorithm": "RS256", "public_key": public_key }],
+        });
+        let path = dir.join("keys.json");
+        std::fs::write(&path, manifest.to_string()).unwrap();
+
+        let error = JwtConfig::from_file(&path).unwrap_err();
+        assert!(matches!(error, JwtConfigError::MissingPrivateKey(kid) if kid == "2026-04"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
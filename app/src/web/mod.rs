@@ -2,6 +2,10 @@ pub mod handlers;
 pub mod jwt;
 pub mod templates;
 
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
+
 use axum::{
     Router, middleware,
     routing::{get, post},
@@ -10,10 +14,13 @@ use tower_http::{services::ServeDir, trace::TraceLayer};
 
 use crate::services::BrokerHandle;
 use handlers::{
-    dashboard, deposit_page, deposit_submit, home, login_page, login_submit, logout,
-    mfa_verify_page, mfa_verify_submit, orders_page, place_order_page, place_order_submit,
+    dashboard, deposit_page, deposit_submit, forgot_password_page, forgot_password_submit, home,
+    live_updates, login_page, login_submit, logout, mfa_page, mfa_verify_page, mfa_verify_submit,
+    orders_page, place_order_page, place_order_submit, preferred_mfa_submit, recovery_codes_submit,
     register_page, register_submit, registration_verify_page, registration_verify_submit,
-    resend_mfa,
+    resend_mfa, reset_password_page, reset_password_submit, revoke_all_sessions_submit,
+    revoke_session_submit, sessions_page, switch_mfa, totp_confirm_submit, totp_disable_submit,
+    totp_page,
 };
 
 pub type AppState = BrokerHandle;
@@ -24,23 +31,46 @@ pub fn create_app(state: AppState) -> Router {
         .route("/", get(home))
         .route("/login", get(login_page).post(login_submit))
         .route("/register", get(register_page).post(register_submit))
+        // forgotten passwords are reset from a link sent by email
+        .route(
+            "/forgot-password",
+            get(forgot_password_page).post(forgot_password_submit),
+        )
+        .route(
+            "/reset-password",
+            get(reset_password_page).post(reset_password_submit),
+        )
         .route("/verify-mfa", get(mfa_verify_page).post(mfa_verify_submit))
         .route(
             "/verify-registration",
             get(registration_verify_page).post(registration_verify_submit),
         )
-        .route("/resend-mfa", get(resend_mfa));
+        .route("/resend-mfa", get(resend_mfa))
+        .route("/switch-mfa", get(switch_mfa));
 
     // Protected routes (authentication required)
     let protected_routes = Router::new()
         .route("/dashboard", get(dashboard))
         .route("/orders", get(orders_page))
+        // Server-Sent Events updating the dashboard and orders page
+        .route("/events", get(live_updates))
         // add or remove money from account
         .route("/deposit", get(deposit_page).post(deposit_submit))
         .route(
             "/place_order",
             get(place_order_page).post(place_order_submit),
         )
+        // devices the user is logged in on
+        .route("/sessions", get(sessions_page))
+        .route("/sessions/revoke-all", post(revoke_all_sessions_submit))
+        .route("/sessions/{session_id}/revoke", post(revoke_session_submit))
+        // second factors: preferred method, recovery codes and authenticator app
+        .route("/mfa", get(mfa_page))
+        .route("/mfa/preferred", post(preferred_mfa_submit))
+        .route("/mfa/recovery-codes", post(recovery_codes_submit))
+        .route("/mfa/totp", get(totp_page))
+        .route("/mfa/totp/confirm", post(totp_confirm_submit))
+        .route("/mfa/totp/disable", post(totp_disable_submit))
         .route("/logout", post(logout))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
@@ -1,7 +1,9 @@
 use askama::Template;
+use domain::instrument::Instrument;
+use domain::market_data::Quote;
 use domain::order::{Order, OrderId, OrderSide, OrderStatus, OrderType};
 use domain::portfolio::Holding;
-
+use domain::user::TotpEnrollment;
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct LoginTemplate {
@@ -14,13 +16,42 @@ pub struct RegisterTemplate {
     pub error: Option<String>,
 }
 
+#[derive(Template)]
+#[template(path = "forgot_password.html")]
+pub struct ForgotPasswordTemplate {
+    pub error: Option<String>,
+    /// The reset link was requested, the form is replaced by a notice
+    pub sent: bool,
+    pub valid_minutes: i64,
+}
+
+#[derive(Template)]
+#[template(path = "reset_password.html")]
+pub struct ResetPasswordTemplate {
+    /// Token of the reset link, `None` once it cannot be used anymore
+    pub token: Option<String>,
+    pub error: Option<String>,
+    /// The password was reset
+    pub done: bool,
+}
+
 #[derive(Template)]
 #[template(path = "mfa_verify.html")]
 pub struct MfaVerifyTemplate {
     pub challenge_id: String,
+    /// `MfaMethod` of the challenge, "email", "totp" or "recovery_code"
+    pub method: &'static str,
+    /// Other methods the user can answer with
+    pub alternatives: Vec<MfaMethodDisplayData>,
     pub error: Option<String>,
 }
 
+/// Second factor for display in templates
+pub struct MfaMethodDisplayData {
+    pub method: &'static str,
+    pub label: &'static str,
+}
+
 #[derive(Template)]
 #[template(path = "registration_verify.html")]
 pub struct RegistrationVerifyTemplate {
@@ -32,6 +63,8 @@ pub struct RegistrationVerifyTemplate {
 #[derive(Template)]
 #[template(path = "dashboard.html")]
 pub struct DashboardTemplate {
+    /// Sequence of the latest event reflected in the page, live updates resume after it
+    pub events_sequence: u64,
     pub account_balance: f64,
     pub recent_orders: Vec<OrderDisplayData>,
     pub holdings: Vec<HoldingDisplayData>,
@@ -61,7 +94,7 @@ pub struct HoldingDisplayData {
     pub symbol: String,
     pub quantity: u64,
     pub average_cost: f64,
-    pub current_price: f64, // For now, same as average cost
+    pub current_price: f64,
     pub total_value: f64,
     pub gain_loss: f64,
     pub gain_loss_percentage: f64,
@@ -77,14 +110,42 @@ pub struct DepositTemplate {
 pub struct PlaceOrderTemplate {
     pub error: Option<String>,
     pub account_balance: f64,
+    pub instruments: Vec<InstrumentDisplayData>,
+}
+
+// Struct for instrument display in the order form
+#[derive(Clone)]
+pub struct InstrumentDisplayData {
+    pub symbol: String,
+    pub name: String,
+    pub currency: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub tick_size: f64,
+    pub lot_size: u64,
 }
 
 #[derive(Template)]
 #[template(path = "orders.html")]
 pub struct OrdersTemplate {
+    pub events_sequence: u64,
     pub orders: Vec<OrderDisplayData>,
 }
 
+/// Status badge of an order, pushed to the pages showing it when the status changes
+#[derive(Template)]
+#[template(path = "order_status.html")]
+pub struct OrderStatusTemplate {
+    pub order: OrderStatusDisplayData,
+}
+
+#[derive(Clone)]
+pub struct OrderStatusDisplayData {
+    pub status: String,
+    pub status_tooltip: Option<String>,
+}
+
+#[allow(dead_code)] // No confirmation page yet, orders redirect to the dashboard
 #[derive(Template)]
 #[template(path = "order_confirmation.html")]
 pub struct OrderConfirmationTemplate {
@@ -106,9 +167,35 @@ impl OrderDisplayData {
         let (order_kind, price) = match order.order_type {
             OrderType::Market => ("Market".to_string(), 0.0), // Market orders don't have a specific price
             OrderType::Limit(p) => ("Limit".to_string(), p),
+            OrderType::Stop(p) => ("Stop".to_string(), p),
         };
 
-        let (status, status_tooltip) = match &order.status {
+        let OrderStatusDisplayData {
+            status,
+            status_tooltip,
+        } = OrderStatusDisplayData::from_status(&order.status);
+
+        let total = price * (order.quantity as f64);
+        let date = order.date.format("%Y-%m-%d %H:%M").to_string();
+
+        Self {
+            id: order_id.to_string(),
+            symbol: order.symbol,
+            quantity: order.quantity,
+            price,
+            order_type,
+            order_kind,
+            status,
+            date,
+            total,
+            status_tooltip,
+        }
+    }
+}
+
+impl OrderStatusDisplayData {
+    pub fn from_status(status: &OrderStatus) -> Self {
+        let (status, status_tooltip) = match status {
             OrderStatus::Queued => (
                 "Queued".to_string(),
                 Some("Order is waiting to be processed by the system".to_string()),
@@ -145,27 +232,15 @@ impl OrderDisplayData {
             }
         };
 
-        let total = price * (order.quantity as f64);
-        let date = order.date.format("%Y-%m-%d %H:%M").to_string();
-
         Self {
-            id: order_id.to_string(),
-            symbol: order.symbol,
-            quantity: order.quantity,
-            price,
-            order_type,
-            order_kind,
             status,
-            date,
-            total,
             status_tooltip,
         }
     }
 }
 
 impl HoldingDisplayData {
-    pub fn from_holding(holding: &Holding) -> Self {
-        let current_price = holding.average_cost; // For now, use average cost as current price
+    pub fn from_holding(holding: &Holding, current_price: f64) -> Self {
         let total_value = current_price * holding.quantity as f64;
         let cost_basis = holding.average_cost * holding.quantity as f64;
         let gain_loss = total_value - cost_basis;
@@ -186,3 +261,56 @@ impl HoldingDisplayData {
         }
     }
 }
+
+impl InstrumentDisplayData {
+    pub fn from_instrument(instrument: &Instrument, quote: &Quote) -> Self {
+        Self {
+            symbol: instrument.symbol.clone(),
+            name: instrument.name.clone(),
+            currency: instrument.currency.clone(),
+            bid: (quote.bid * 100.0).round() / 100.0,
+            ask: (quote.ask * 100.0).round() / 100.0,
+            tick_size: instrument.tick_size,
+            lot_size: instrument.lot_size,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsTemplate {
+    pub sessions: Vec<SessionDisplayData>,
+}
+
+/// Session for display in templates
+pub struct SessionDisplayData {
+    pub id: String,
+    pub device: String,
+    pub created_at: String,
+    pub last_used_at: String,
+    /// Whether this is the session of the page's request
+    pub current: bool,
+}
+
+#[derive(Template)]
+#[template(path = "totp.html")]
+pub struct TotpTemplate {
+    /// When the user confirmed their authenticator app, if they did
+    pub enrolled_at: Option<String>,
+    /// Secret to scan while the user has not confirmed an app
+    pub enrollment: Option<TotpEnrollment>,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "mfa.html")]
+pub struct MfaTemplate {
+    /// Methods the user can prefer
+    pub methods: Vec<MfaMethodDisplayData>,
+    pub preferred: &'static str,
+    pub totp_enrolled: bool,
+    pub recovery_codes_left: usize,
+    /// Codes just generated, shown once
+    pub new_recovery_codes: Vec<String>,
+    pub error: Option<String>,
+}
@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use domain::core::BrokerX;
+    use domain::user::UserRepoExt;
+    use futures_util::StreamExt;
+    use tower::ServiceExt; // for `oneshot`
+
+    use crate::services::BrokerHandle;
+    use crate::test_support::bearer;
+    use crate::web::create_app;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_live_updates_carry_events_of_other_instances() {
+        let broker = BrokerX::new_for_testing_with_thread_count(0).await;
+        let replica = broker.new_for_testing_sharing_tables(0).await;
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "live@test.com".to_string(),
+                "password123".to_string(),
+                "Live".to_string(),
+                "User".to_string(),
+                1000.0,
+            )
+            .await
+            .unwrap();
+        let authorization = bearer(&broker, user_id, "live@test.com").await;
+
+        // Kept alive like the server's state, which the stream follows
+        let handle = BrokerHandle::new(broker);
+        let response = create_app(handle.clone())
+            .oneshot(
+                Request::builder()
+                    .method(Method::GET)
+                    .uri("/events")
+                    .header("authorization", authorization)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+
+        replica.deposit(&user_id, 50.0).await.unwrap();
+        let mut received = String::new();
+        while !received.contains("event: balance") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+                .await
+                .expect("no live update received")
+                .expect("stream closed")
+                .unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(received.contains(r#"{"balance":1050.0}"#));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_forgot_password_is_throttled() {
+        let broker = BrokerX::new_for_testing_with_thread_count(0).await;
+        let app = create_app(BrokerHandle::new(broker));
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(Method::POST)
+                        .uri("/forgot-password")
+                        .header("content-type", "application/x-www-form-urlencoded")
+                        .body(Body::from("email=someone%40test.com"))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+    }
+}
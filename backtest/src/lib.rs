@@ -0,0 +1,568 @@
+//! Run trading strategies against recorded market data through BrokerX.
+//!
+//! Orders go through `BrokerX::create_order` and the order processing of an in-memory
+//! broker whose clock follows the replayed data, so a backtest sees the same pre-trade
+//! checks, fills and rejections as the live system.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use domain::clock::Clock;
+use domain::core::BrokerX;
+use domain::events::{DomainEvent, EventEnvelope};
+use domain::instrument::{
+    Instrument, InstrumentRepoExt, InstrumentStatus, PriceBand, TradingCalendar,
+};
+use domain::market_data::MarketDataConfig;
+use domain::order::{OrderId, OrderSide, OrderStatus};
+use domain::replay::{MarketReplay, ReplayError, ReplayEvent};
+use domain::user::{UserId, UserRepoExt};
+use domain::{DbError, Repository};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tracing::{debug, info, warn};
+
+mod strategy;
+
+pub use strategy::{Account, BuyAndHold, MovingAverageCrossover, OrderRequest, Strategy};
+
+/// Tick size of instruments listed for symbols missing from the instrument master
+const DEFAULT_TICK_SIZE: f64 = 0.01;
+/// Upper price band of instruments listed for symbols missing from the instrument master
+const DEFAULT_MAX_PRICE: f64 = 1_000_000.0;
+
+/// Backtest errors
+#[derive(Debug)]
+pub enum BacktestError {
+    Replay(ReplayError),
+    /// The recorded data holds no price to replay
+    NoData,
+    Setup(String),
+    DbError(DbError),
+}
+
+impl std::fmt::Display for BacktestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BacktestError::Replay(e) => write!(f, "Failed to load market data: {e}"),
+            BacktestError::NoData => write!(f, "No market data to replay"),
+            BacktestError::Setup(msg) => write!(f, "Failed to set up the broker: {msg}"),
+            BacktestError::DbError(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BacktestError {}
+
+impl From<ReplayError> for BacktestError {
+    fn from(e: ReplayError) -> Self {
+        BacktestError::Replay(e)
+    }
+}
+
+impl From<DbError> for BacktestError {
+    fn from(e: DbError) -> Self {
+        BacktestError::DbError(e)
+    }
+}
+
+/// Order executed during a backtest
+#[derive(Debug, Clone, Serialize)]
+pub struct Fill {
+    pub order_id: OrderId,
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub price: f64,
+}
+
+/// Order refused by the broker, either by pre-trade checks or during processing
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    /// `None` when the order never passed pre-trade checks
+    pub order_id: Option<OrderId>,
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub reason: String,
+}
+
+/// Outcome of a backtest
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestReport {
+    pub strategy: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Number of prices replayed
+    pub events: usize,
+    pub initial_equity: f64,
+    /// Cash plus holdings valued at their last replayed price
+    pub final_equity: f64,
+    pub pnl: f64,
+    pub return_pct: f64,
+    /// Largest fall of the equity from a previous peak
+    pub max_drawdown: f64,
+    pub max_drawdown_pct: f64,
+    pub cash: f64,
+    pub positions: BTreeMap<String, u64>,
+    pub fills: Vec<Fill>,
+    pub rejections: Vec<Rejection>,
+    /// Orders still waiting for their price when the data ran out
+    pub open_orders: usize,
+}
+
+impl std::fmt::Display for BacktestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "=== Backtest: {} ===", self.strategy)?;
+        writeln!(f, "Period: {} -> {}", self.start, self.end)?;
+        writeln!(f, "Prices replayed: {}", self.events)?;
+        writeln!(f)?;
+        writeln!(f, "Initial equity: {:.2}", self.initial_equity)?;
+        writeln!(f, "Final equity:   {:.2}", self.final_equity)?;
+        writeln!(
+            f,
+            "P&L:            {:.2} ({:.2}%)",
+            self.pnl, self.return_pct
+        )?;
+        writeln!(
+            f,
+            "Max drawdown:   {:.2} ({:.2}%)",
+            self.max_drawdown, self.max_drawdown_pct
+        )?;
+        writeln!(f, "Cash:           {:.2}", self.cash)?;
+        for (symbol, quantity) in &self.positions {
+            writeln!(f, "Position:       {quantity} {symbol}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Fills: {}", self.fills.len())?;
+        for fill in &self.fills {
+            writeln!(
+                f,
+                "  {} {:?} {} {} @ {:.2}",
+                fill.timestamp, fill.side, fill.quantity, fill.symbol, fill.price
+            )?;
+        }
+        writeln!(f, "Rejected orders: {}", self.rejections.len())?;
+        for rejection in &self.rejections {
+            writeln!(
+                f,
+                "  {} {:?} {} {}: {}",
+                rejection.timestamp,
+                rejection.side,
+                rejection.quantity,
+                rejection.symbol,
+                rejection.reason
+            )?;
+        }
+        write!(f, "Open orders: {}", self.open_orders)
+    }
+}
+
+/// Replays recorded prices into an in-memory broker and runs a strategy against it
+pub struct Backtester {
+    broker: BrokerX,
+    user_id: UserId,
+    initial_cash: f64,
+    events: Vec<ReplayEvent>,
+}
+
+impl Backtester {
+    /// Set up an in-memory broker starting at the first recorded price, with an account
+    /// holding `initial_cash`. Symbols missing from the instrument master are listed
+    /// with an always open calendar.
+    /// # Errors
+    /// Returns `BacktestError` if the replay is empty or the broker cannot be set up
+    pub async fn new(replay: &MarketReplay, initial_cash: f64) -> Result<Self, BacktestError> {
+        let events = replay.events().to_vec();
+        let start = events.first().ok_or(BacktestError::NoData)?.timestamp;
+
+        let broker = BrokerX::in_memory(Clock::simulated(start), MarketDataConfig::default()).await;
+        let instrument_repo = broker.get_instrument_repo().await;
+        for event in &events {
+            if instrument_repo
+                .get_instrument(&event.symbol)
+                .await?
+                .is_none()
+            {
+                let instrument = Self::listing_for(event);
+                info!("Listing {} for the backtest", instrument.symbol);
+                instrument_repo
+                    .create_instrument(instrument)
+                    .await
+                    .map_err(|e| BacktestError::Setup(format!("{}: {e}", event.symbol)))?;
+            }
+        }
+
+        let user_id = broker
+            .get_user_repo()
+            .await
+            .create_user(
+                "backtest@brokerx.local".to_string(),
+                "backtest".to_string(),
+                "Backtest".to_string(),
+                "Account".to_string(),
+                initial_cash,
+            )
+            .await
+            .map_err(|e| BacktestError::Setup(e.to_string()))?;
+
+        Ok(Self {
+            broker,
+            user_id,
+            initial_cash,
+            events,
+        })
+    }
+
+    fn listing_for(event: &ReplayEvent) -> Instrument {
+        let reference_price = ((event.price / DEFAULT_TICK_SIZE).round() * DEFAULT_TICK_SIZE)
+            .clamp(DEFAULT_TICK_SIZE, DEFAULT_MAX_PRICE);
+        Instrument {
+            symbol: event.symbol.clone(),
+            name: event.symbol.clone(),
+            currency: "USD".to_string(),
+            tick_size: DEFAULT_TICK_SIZE,
+            lot_size: 1,
+            price_band: PriceBand {
+                min: DEFAULT_TICK_SIZE,
+                max: DEFAULT_MAX_PRICE,
+            },
+            reference_price,
+            status: InstrumentStatus::Active,
+            trading_calendar: TradingCalendar::always_open(),
+        }
+    }
+
+    /// Broker the strategy trades with
+    #[must_use]
+    pub fn broker(&self) -> &BrokerX {
+        &self.broker
+    }
+
+    /// Replay every price, letting the broker process resting orders before the
+    /// strategy sees the price and again after its orders are placed
+    /// # Errors
+    /// Returns `BacktestError` if the account or instruments cannot be read
+    pub async fn run(&self, strategy: &mut dyn Strategy) -> Result<BacktestReport, BacktestError> {
+        let mut events = self.broker.events().subscribe();
+        let instruments: HashMap<String, Instrument> = self
+            .broker
+            .get_instrument_repo()
+            .await
+            .list_instruments()
+            .await?
+            .into_iter()
+            .map(|instrument| (instrument.symbol.clone(), instrument))
+            .collect();
+
+        let mut tracker = OrderTracker::default();
+        let mut last_prices = HashMap::new();
+        let mut peak = self.initial_cash;
+        let mut max_drawdown = 0.0_f64;
+        let mut max_drawdown_pct = 0.0_f64;
+        let mut account = self.account(&tracker).await?;
+
+        for event in &self.events {
+            let Some(instrument) = instruments.get(&event.symbol) else {
+                continue;
+            };
+            self.broker.clock().set(event.timestamp);
+            MarketReplay::apply(self.broker.market_data(), instrument, event);
+            last_prices.insert(event.symbol.clone(), event.price);
+
+            self.broker.run_until_idle().await;
+            tracker.collect(&mut events, event.timestamp);
+
+            account = self.account(&tracker).await?;
+            for request in strategy.on_market_data(event, &account) {
+                match self
+                    .broker
+                    .create_order(
+                        self.user_id,
+                        request.symbol.clone(),
+                        request.quantity,
+                        request.side.clone(),
+                        request.order_type.clone(),
+                    )
+                    .await
+                {
+                    Ok(order_id) => tracker.accepted(order_id, request),
+                    Err(e) => {
+                        debug!("Order on {} rejected: {}", request.symbol, e);
+                        tracker.rejections.push(Rejection {
+                            order_id: None,
+                            timestamp: event.timestamp,
+                            symbol: request.symbol,
+                            side: request.side,
+                            quantity: request.quantity,
+                            reason: e.to_string(),
+                        });
+                    }
+                }
+            }
+
+            self.broker.run_until_idle().await;
+            tracker.collect(&mut events, event.timestamp);
+
+            account = self.account(&tracker).await?;
+            let equity = Self::equity(&account, &last_prices);
+            peak = peak.max(equity);
+            let drawdown = peak - equity;
+            if drawdown > max_drawdown {
+                max_drawdown = drawdown;
+                max_drawdown_pct = if peak > 0.0 {
+                    drawdown / peak * 100.0
+                } else {
+                    0.0
+                };
+            }
+        }
+
+        let final_equity = Self::equity(&account, &last_prices);
+        let pnl = final_equity - self.initial_cash;
+        Ok(BacktestReport {
+            strategy: strategy.name().to_string(),
+            start: self.events.first().map_or(account.now, |e| e.timestamp),
+            end: account.now,
+            events: self.events.len(),
+            initial_equity: self.initial_cash,
+            final_equity,
+            pnl,
+            return_pct: if self.initial_cash > 0.0 {
+                pnl / self.initial_cash * 100.0
+            } else {
+                0.0
+            },
+            max_drawdown,
+            max_drawdown_pct,
+            cash: account.cash,
+            positions: account.positions.into_iter().collect(),
+            fills: tracker.fills,
+            rejections: tracker.rejections,
+            open_orders: tracker.open.len(),
+        })
+    }
+
+    async fn account(&self, tracker: &OrderTracker) -> Result<Account, BacktestError> {
+        let user = self
+            .broker
+            .get_user_repo()
+            .await
+            .get(&self.user_id)
+            .await?
+            .ok_or_else(|| BacktestError::Setup("backtest account disappeared".to_string()))?;
+        Ok(Account {
+            now: self.broker.clock().now(),
+            cash: user.balance,
+            positions: user
+                .holdings
+                .values()
+                .map(|holding| (holding.symbol.clone(), holding.quantity))
+                .collect(),
+            open_orders: tracker.open.len(),
+        })
+    }
+
+    fn equity(account: &Account, last_prices: &HashMap<String, f64>) -> f64 {
+        account.cash
+            + account
+                .positions
+                .iter()
+                .map(|(symbol, quantity)| {
+                    last_prices.get(symbol).copied().unwrap_or(0.0) * *quantity as f64
+                })
+                .sum::<f64>()
+    }
+}
+
+/// Follows the orders of the strategy through the events published by the broker
+#[derive(Default)]
+struct OrderTracker {
+    requests: HashMap<OrderId, OrderRequest>,
+    open: HashSet<OrderId>,
+    fills: Vec<Fill>,
+    rejections: Vec<Rejection>,
+}
+
+impl OrderTracker {
+    fn accepted(&mut self, order_id: OrderId, request: OrderRequest) {
+        self.open.insert(order_id);
+        self.requests.insert(order_id, request);
+    }
+
+    fn collect(&mut self, events: &mut broadcast::Receiver<EventEnvelope>, at: DateTime<Utc>) {
+        loop {
+            match events.try_recv() {
+                Ok(envelope) => self.record(envelope.event, at),
+                Err(TryRecvError::Lagged(missed)) => {
+                    warn!("Backtest missed {} broker events", missed);
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn record(&mut self, event: DomainEvent, at: DateTime<Utc>) {
+        match event {
+            DomainEvent::OrderFilled {
+                order_id,
+                symbol,
+                quantity,
+                order_side,
+                price,
+                ..
+            } if self.requests.contains_key(&order_id) => {
+                self.open.remove(&order_id);
+                self.fills.push(Fill {
+                    order_id,
+                    timestamp: at,
+                    symbol,
+                    side: order_side,
+                    quantity,
+                    price,
+                });
+            }
+            DomainEvent::OrderStatusChanged {
+                order_id, status, ..
+            } => match status {
+                OrderStatus::Rejected { .. } => {
+                    self.open.remove(&order_id);
+                    if let Some(request) = self.requests.get(&order_id) {
+                        self.rejections.push(Rejection {
+                            order_id: Some(order_id),
+                            timestamp: at,
+                            symbol: request.symbol.clone(),
+                            side: request.side.clone(),
+                            quantity: request.quantity,
+                            reason: "Rejected during order processing".to_string(),
+                        });
+                    }
+                }
+                OrderStatus::Cancelled | OrderStatus::Expired { .. } => {
+                    self.open.remove(&order_id);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domain::order::OrderType;
+    use domain::replay::{ReplayConfig, ReplayFormat};
+
+    const PRICES: &str = "timestamp,symbol,price\n\
+        2024-01-02T14:30:00Z,AAPL,100.00\n\
+        2024-01-02T14:31:00Z,AAPL,120.00\n\
+        2024-01-02T14:32:00Z,AAPL,90.00\n\
+        2024-01-02T14:33:00Z,AAPL,110.00\n";
+
+    fn replay(data: &str) -> MarketReplay {
+        MarketReplay::from_reader(
+            data.as_bytes(),
+            ReplayFormat::Csv,
+            &ReplayConfig::new("prices.csv"),
+        )
+        .unwrap()
+    }
+
+    /// Sends the same orders on the first price
+    struct Scripted(Vec<OrderRequest>);
+
+    impl Strategy for Scripted {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+
+        fn on_market_data(&mut self, _: &ReplayEvent, _: &Account) -> Vec<OrderRequest> {
+            std::mem::take(&mut self.0)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_buy_and_hold_reports_pnl_and_drawdown() {
+        let backtester = Backtester::new(&replay(PRICES), 10_000.0).await.unwrap();
+        let report = backtester
+            .run(&mut BuyAndHold::new("AAPL", 0.5))
+            .await
+            .unwrap();
+
+        // 49 shares bought at the ask, one tick above the first price
+        assert_eq!(report.fills.len(), 1);
+        assert_eq!(report.fills[0].quantity, 49);
+        assert!((report.fills[0].price - 100.01).abs() < 1e-9);
+        assert_eq!(report.positions.get("AAPL"), Some(&49));
+
+        let cash = 10_000.0 - 49.0 * 100.01;
+        assert!((report.cash - cash).abs() < 1e-6);
+        assert!((report.final_equity - (cash + 49.0 * 110.0)).abs() < 1e-6);
+        assert!((report.pnl - (report.final_equity - 10_000.0)).abs() < 1e-9);
+        // Peak at 120, trough at 90
+        assert!((report.max_drawdown - 49.0 * 30.0).abs() < 1e-6);
+        assert!(report.rejections.is_empty());
+        assert_eq!(report.open_orders, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rejected_orders_are_reported() {
+        let backtester = Backtester::new(&replay(PRICES), 1_000.0).await.unwrap();
+        let mut strategy = Scripted(vec![
+            // More than the account can pay for
+            OrderRequest::market("AAPL", 100, OrderSide::Buy),
+            // Waits for a price the data never reaches
+            OrderRequest {
+                symbol: "AAPL".to_string(),
+                quantity: 1,
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit(50.0),
+            },
+        ]);
+        let report = backtester.run(&mut strategy).await.unwrap();
+
+        assert!(report.fills.is_empty());
+        assert_eq!(report.rejections.len(), 1);
+        assert!(report.rejections[0].order_id.is_none());
+        assert!(
+            report.rejections[0]
+                .reason
+                .contains("Insufficient buying power")
+        );
+        assert_eq!(report.open_orders, 1);
+        assert!((report.final_equity - 1_000.0).abs() < 1e-9);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sells_of_unheld_shares_are_rejected() {
+        let backtester = Backtester::new(&replay(PRICES), 1_000.0).await.unwrap();
+        // Passes pre-trade checks but there are no shares to deliver
+        let mut strategy = Scripted(vec![OrderRequest::market("AAPL", 5, OrderSide::Sell)]);
+        let report = backtester.run(&mut strategy).await.unwrap();
+
+        assert!(report.fills.is_empty());
+        assert_eq!(report.rejections.len(), 1);
+        assert!(report.rejections[0].order_id.is_some());
+        assert!(report.positions.is_empty());
+        assert!((report.cash - 1_000.0).abs() < 1e-9);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unlisted_symbols_are_listed_for_the_backtest() {
+        let data = "timestamp,symbol,price\n\
+            2024-01-02T14:30:00Z,XYZ,12.34\n\
+            2024-01-02T14:31:00Z,XYZ,13.00\n";
+        let backtester = Backtester::new(&replay(data), 1_000.0).await.unwrap();
+        let report = backtester
+            .run(&mut BuyAndHold::new("XYZ", 1.0))
+            .await
+            .unwrap();
+
+        assert_eq!(report.fills.len(), 1);
+        assert_eq!(report.fills[0].symbol, "XYZ");
+        assert!(report.pnl > 0.0);
+    }
+}
@@ -0,0 +1,117 @@
+use backtest::{Backtester, BuyAndHold, MovingAverageCrossover, Strategy};
+use clap::{Parser, ValueEnum};
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use domain::replay::{MarketReplay, ReplayConfig, parse_timestamp};
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StrategyKind {
+    /// Invest the allocation of the cash at the first price and hold
+    BuyAndHold,
+    /// Trade crossovers of a fast and a slow simple moving average
+    SmaCrossover,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "backtest")]
+#[command(about = "Run a trading strategy against recorded market data through BrokerX")]
+struct Args {
+    /// Recorded market data, as CSV or JSON Lines
+    #[arg(short, long)]
+    data: PathBuf,
+
+    /// Strategy to run
+    #[arg(short, long, value_enum, default_value = "buy-and-hold")]
+    strategy: StrategyKind,
+
+    /// Symbol traded by the strategy
+    #[arg(long, default_value = "AAPL")]
+    symbol: String,
+
+    /// Starting cash of the account
+    #[arg(long, default_value = "100000")]
+    cash: f64,
+
+    /// Skip records before this timestamp
+    #[arg(long)]
+    start: Option<String>,
+
+    /// Skip records after this timestamp
+    #[arg(long)]
+    end: Option<String>,
+
+    /// Share of the cash invested by buy-and-hold, between 0 and 1
+    #[arg(long, default_value = "1.0")]
+    allocation: f64,
+
+    /// Shares bought on each crossover by sma-crossover
+    #[arg(long, default_value = "100")]
+    quantity: u64,
+
+    /// Fast moving average window of sma-crossover, in prices
+    #[arg(long, default_value = "10")]
+    fast: usize,
+
+    /// Slow moving average window of sma-crossover, in prices
+    #[arg(long, default_value = "30")]
+    slow: usize,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_bound(value: Option<&String>) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    value
+        .map(|value| parse_timestamp(value).ok_or_else(|| eyre!("Invalid timestamp: {value}")))
+        .transpose()
+}
+
+// BrokerX stops its processing with `block_in_place`, which needs the multi-threaded runtime
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::from_default_env()
+                .add_directive("backtest=info".parse()?),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+
+    let mut config = ReplayConfig::new(&args.data);
+    config.start = parse_bound(args.start.as_ref())?;
+    config.end = parse_bound(args.end.as_ref())?;
+    let replay = MarketReplay::load(&config)?;
+    info!(
+        "Loaded {} prices from {}",
+        replay.events().len(),
+        args.data.display()
+    );
+
+    let mut strategy: Box<dyn Strategy> = match args.strategy {
+        StrategyKind::BuyAndHold => Box::new(BuyAndHold::new(&args.symbol, args.allocation)),
+        StrategyKind::SmaCrossover => Box::new(MovingAverageCrossover::new(
+            &args.symbol,
+            args.quantity,
+            args.fast,
+            args.slow,
+        )),
+    };
+
+    let backtester = Backtester::new(&replay, args.cash).await?;
+    let report = backtester.run(strategy.as_mut()).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+
+    Ok(())
+}
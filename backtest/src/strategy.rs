@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use domain::order::{OrderSide, OrderType};
+use domain::replay::ReplayEvent;
+
+/// Order a strategy wants to send to the broker
+#[derive(Debug, Clone)]
+pub struct OrderRequest {
+    pub symbol: String,
+    pub quantity: u64,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+}
+
+impl OrderRequest {
+    #[must_use]
+    pub fn market(symbol: &str, quantity: u64, side: OrderSide) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            quantity,
+            side,
+            order_type: OrderType::Market,
+        }
+    }
+}
+
+/// State of the backtest account as seen by the broker when a price is replayed
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub now: DateTime<Utc>,
+    pub cash: f64,
+    /// Shares held per symbol
+    pub positions: HashMap<String, u64>,
+    /// Orders accepted by the broker that are not filled, rejected or cancelled yet
+    pub open_orders: usize,
+}
+
+impl Account {
+    #[must_use]
+    pub fn position(&self, symbol: &str) -> u64 {
+        self.positions.get(symbol).copied().unwrap_or(0)
+    }
+}
+
+/// Trading logic run against recorded market data.
+///
+/// The strategy sees every replayed price once the broker has processed it, and the
+/// orders it returns go through the same pre-trade checks and matching as live ones.
+pub trait Strategy {
+    fn name(&self) -> &str;
+
+    /// React to a replayed price, returning the orders to place
+    fn on_market_data(&mut self, event: &ReplayEvent, account: &Account) -> Vec<OrderRequest>;
+}
+
+/// Spend the available cash on a symbol at its first price, then hold
+#[derive(Debug, Clone)]
+pub struct BuyAndHold {
+    symbol: String,
+    /// Share of the cash to invest, between 0 and 1
+    allocation: f64,
+    invested: bool,
+}
+
+impl BuyAndHold {
+    #[must_use]
+    pub fn new(symbol: impl Into<String>, allocation: f64) -> Self {
+        Self {
+            symbol: symbol.into(),
+            allocation: allocation.clamp(0.0, 1.0),
+            invested: false,
+        }
+    }
+}
+
+impl Strategy for BuyAndHold {
+    fn name(&self) -> &str {
+        "buy-and-hold"
+    }
+
+    fn on_market_data(&mut self, event: &ReplayEvent, account: &Account) -> Vec<OrderRequest> {
+        if self.invested || event.symbol != self.symbol || event.price <= 0.0 {
+            return Vec::new();
+        }
+        self.invested = true;
+
+        // Keep some room for the spread, market orders are checked against the ask
+        let quantity = (account.cash * self.allocation / (event.price * 1.01)).floor() as u64;
+        if quantity == 0 {
+            return Vec::new();
+        }
+        vec![OrderRequest::market(&self.symbol, quantity, OrderSide::Buy)]
+    }
+}
+
+/// Go long a fixed quantity when the fast moving average crosses above the slow one,
+/// and close the position when it crosses back below
+#[derive(Debug, Clone)]
+pub struct MovingAverageCrossover {
+    symbol: String,
+    quantity: u64,
+    fast: usize,
+    slow: usize,
+    prices: VecDeque<f64>,
+    was_above: Option<bool>,
+}
+
+impl MovingAverageCrossover {
+    #[must_use]
+    pub fn new(symbol: impl Into<String>, quantity: u64, fast: usize, slow: usize) -> Self {
+        let fast = fast.max(1);
+        Self {
+            symbol: symbol.into(),
+            quantity,
+            fast,
+            slow: slow.max(fast + 1),
+            prices: VecDeque::new(),
+            was_above: None,
+        }
+    }
+
+    fn average(&self, window: usize) -> f64 {
+        self.prices.iter().rev().take(window).sum::<f64>() / window as f64
+    }
+}
+
+impl Strategy for MovingAverageCrossover {
+    fn name(&self) -> &str {
+        "sma-crossover"
+    }
+
+    fn on_market_data(&mut self, event: &ReplayEvent, account: &Account) -> Vec<OrderRequest> {
+        if event.symbol != self.symbol {
+            return Vec::new();
+        }
+        self.prices.push_back(event.price);
+        if self.prices.len() > self.slow {
+            self.prices.pop_front();
+        }
+        if self.prices.len() < self.slow {
+            return Vec::new();
+        }
+
+        let is_above = self.average(self.fast) > self.average(self.slow);
+        let crossed = self
+            .was_above
+            .is_some_and(|was_above| was_above != is_above);
+        self.was_above = Some(is_above);
+        if !crossed || account.open_orders > 0 {
+            return Vec::new();
+        }
+
+        let position = account.position(&self.symbol);
+        if is_above && position == 0 {
+            vec![OrderRequest::market(
+                &self.symbol,
+                self.quantity,
+                OrderSide::Buy,
+            )]
+        } else if !is_above && position > 0 {
+            vec![OrderRequest::market(
+                &self.symbol,
+                position,
+                OrderSide::Sell,
+            )]
+        } else {
+            Vec::new()
+        }
+    }
+}
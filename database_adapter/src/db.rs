@@ -1,12 +1,19 @@
 use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
 use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::outbox::{OutboxMessage, PostgresOutbox};
 
 #[derive(Debug)]
 pub enum DbError {
     SqlxError(sqlx::Error),
     SerdeError(serde_json::Error),
     TokioError(std::io::Error),
+    DuplicateKey(String),
 }
 
 impl fmt::Display for DbError {
@@ -15,6 +22,7 @@ impl fmt::Display for DbError {
             DbError::SqlxError(e) => write!(f, "Database error: {e}"),
             DbError::SerdeError(e) => write!(f, "Serialization error: {e}"),
             DbError::TokioError(e) => write!(f, "Runtime error: {e}"),
+            DbError::DuplicateKey(id) => write!(f, "Duplicate key: {id}"),
         }
     }
 }
@@ -39,6 +47,9 @@ impl From<std::io::Error> for DbError {
     }
 }
 
+/// Future returned by the storage traits, boxed so they can be used as trait objects
+pub type DbFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DbError>> + Send + 'a>>;
+
 #[allow(async_fn_in_trait)]
 pub trait Repository<T, Id> {
     /// Insert a new item with the given ID
@@ -76,180 +87,857 @@ pub trait Repository<T, Id> {
     /// # Errors
     /// - Returns `DbError` if the operation fails
     async fn find_all_by_field(&self, field: &str, value: &str) -> Result<Vec<(Id, T)>, DbError>;
+    /// Find all items by a specific field and value whose `range_field` is in
+    /// `[from, to)`, ordered by `range_field`. Values are compared as text, which
+    /// suits RFC 3339 timestamps written with the same precision.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    async fn find_range_by_field(
+        &self,
+        field: &str,
+        value: &str,
+        range_field: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(Id, T)>, DbError>;
+    /// Get all items, ordered by ID
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    async fn all(&self) -> Result<Vec<(Id, T)>, DbError>;
 }
 
-/// Generic Postgres repository, stores T as JSON
-#[derive(Clone)]
-pub struct PostgresRepo<T, Id> {
-    pool: Pool<Postgres>,
-    table: String,
-    _phantom: std::marker::PhantomData<(T, Id)>,
+/// Rows returned by `Store::select`. Fields are compared as text, like the `->>`
+/// operator.
+#[derive(Debug, Clone, Copy)]
+pub enum Selection<'a> {
+    /// Every row, ordered by ID
+    All,
+    /// Rows whose `field` is `value`, latest `date` first
+    Field { field: &'a str, value: &'a str },
+    /// Rows whose `field` is `value` and whose `range_field` is in `[from, to)`,
+    /// ordered by `range_field`
+    Range {
+        field: &'a str,
+        value: &'a str,
+        range_field: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
 }
 
-impl<T, Id> std::fmt::Debug for PostgresRepo<T, Id> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PostgresRepo")
-            .field("table", &self.table)
-            .field("_phantom", &self._phantom)
-            .finish_non_exhaustive()
-    }
+/// Storage backend of the repositories: tables of JSON documents keyed by a text ID.
+///
+/// `PostgresStore` keeps them in the database. Other backends, such as an in-memory
+/// one for tools running without a database, implement the same semantics.
+pub trait Store: Send + Sync + fmt::Debug {
+    /// Create a table unless it exists
+    fn create_table<'a>(&'a self, table: &'a str) -> DbFuture<'a, ()>;
+    /// Insert a row, fails with `DbError::DuplicateKey` if the ID is taken
+    fn insert<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()>;
+    /// Replace a row, a missing row is left alone
+    fn update<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()>;
+    /// Set one field of a row, leaving the others as they are. Returns the number of
+    /// rows changed, 0 if the row is missing.
+    fn set_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64>;
+    /// Set one field of a row only while it is null or missing. Returns the number of
+    /// rows changed, 0 if the row is missing or the field is already set.
+    fn set_field_if_null<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64>;
+    /// Set the integer `field` of a row to `value` if it is null, missing or lower.
+    /// Returns the number of rows changed, 0 if the row is missing or not lower.
+    fn raise_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: i64,
+    ) -> DbFuture<'a, u64>;
+    /// Remove a string from the array `field` of a row. Returns the number of rows
+    /// changed, 0 if the row is missing or the string is not in the array.
+    fn remove_from_array<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        element: &'a str,
+    ) -> DbFuture<'a, u64>;
+    /// Add one to the integer at `path` of a row if it is below `cap`. Returns the new
+    /// value, `None` if the row is missing or the integer already reached `cap`.
+    fn increment_below<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        path: &'a [&'a str],
+        cap: i64,
+    ) -> DbFuture<'a, Option<i64>>;
+    /// Hold a row `{ "holder", "until" }` for `holder` until `until`, if it is missing,
+    /// already held by `holder` or expired at `now`. Times are in milliseconds.
+    /// Returns the number of rows written, 0 if another holder's lease is running.
+    fn take_lease<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        holder: &'a str,
+        until: i64,
+        now: i64,
+    ) -> DbFuture<'a, u64>;
+    fn remove<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, ()>;
+    fn get<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>>;
+    fn count<'a>(&'a self, table: &'a str) -> DbFuture<'a, usize>;
+    fn select<'a>(
+        &'a self,
+        table: &'a str,
+        selection: Selection<'a>,
+        limit: Option<usize>,
+    ) -> DbFuture<'a, Vec<(String, Value)>>;
+    /// Start a transaction, which may write to any table of the store
+    fn begin(&self) -> DbFuture<'_, Box<dyn StoreTransaction>>;
 }
 
-impl<T, Id> PostgresRepo<T, Id>
-where
-    T: Serialize + DeserializeOwned + Send + Sync,
-    Id: Serialize
-        + for<'a> sqlx::Decode<'a, sqlx::Postgres>
-        + sqlx::Type<sqlx::Postgres>
-        + Send
-        + Sync,
-{
-    /// Create a new Postgres repository
+/// Writes applied together when committed, dropping the transaction discards them
+pub trait StoreTransaction: Send {
+    /// Read a row as the transaction sees it
+    fn get<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>>;
+    /// Read a row and keep other transactions from changing it until this one ends
+    fn get_for_update<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>>;
+    fn insert<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()>;
+    fn update<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()>;
+    /// Append messages to the outbox `table`
+    fn append_outbox<'a>(
+        &'a mut self,
+        table: &'a str,
+        messages: &'a [OutboxMessage],
+    ) -> DbFuture<'a, ()>;
+    fn commit(self: Box<Self>) -> DbFuture<'static, ()>;
+}
+
+/// Store keeping each table in a Postgres table of `(id, data JSONB)` rows
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    pool: Pool<Postgres>,
+}
+
+impl PostgresStore {
+    /// Connect to the database
     /// # Errors
     /// - Returns `DbError` if the operation fails
     /// # Panics
     /// - Panics if `DATABASE_URL` is not set in the environment or .env file
-    pub async fn new(table: &str) -> Result<Self, DbError> {
+    pub async fn connect() -> Result<Self, DbError> {
         dotenvy::dotenv().ok();
         let db_url = std::env::var("DATABASE_URL")
             .expect("DATABASE_URL must be set in .env file or environment");
-        let table_name = table.to_string();
-
         let pool = PgPoolOptions::new().connect(&db_url).await?;
+        Ok(Self { pool })
+    }
+
+    #[must_use]
+    pub fn pool(&self) -> &Pool<Postgres> {
+        &self.pool
+    }
+}
+
+/// Report unique violations the way every store does
+fn insert_error(error: sqlx::Error, id: String) -> DbError {
+    match error.as_database_error() {
+        Some(e) if e.is_unique_violation() => DbError::DuplicateKey(id),
+        _ => DbError::from(error),
+    }
+}
+
+impl Store for PostgresStore {
+    fn create_table<'a>(&'a self, table: &'a str) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    id   TEXT PRIMARY KEY,
+                    data JSONB NOT NULL
+                )"
+            );
+            sqlx::query(&query).execute(&self.pool).await?;
+            Ok(())
+        })
+    }
+
+    fn insert<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!("INSERT INTO {table} (id, data) VALUES ($1, $2)");
+            sqlx::query(&query)
+                .bind(&id)
+                .bind(data)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| insert_error(e, id))?;
+            Ok(())
+        })
+    }
 
-        // Ensure table exists
-        let query = format!(
-            "CREATE TABLE IF NOT EXISTS {table_name} (
-                id   TEXT PRIMARY KEY,
-                data JSONB NOT NULL
-            )"
-        );
-        sqlx::query(&query).execute(&pool).await?;
+    fn update<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!("UPDATE {table} SET data = $2 WHERE id = $1");
+            sqlx::query(&query)
+                .bind(id)
+                .bind(data)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
 
+    fn set_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64> {
+        Box::pin(async move {
+            let query =
+                format!("UPDATE {table} SET data = jsonb_set(data, ARRAY[$2], $3) WHERE id = $1");
+            let result = sqlx::query(&query)
+                .bind(id)
+                .bind(field)
+                .bind(value)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn set_field_if_null<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64> {
+        Box::pin(async move {
+            let query = format!(
+                "UPDATE {table} SET data = jsonb_set(data, ARRAY[$2], $3)
+                 WHERE id = $1 AND coalesce(data->$2, 'null') = 'null'"
+            );
+            let result = sqlx::query(&query)
+                .bind(id)
+                .bind(field)
+                .bind(value)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn raise_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: i64,
+    ) -> DbFuture<'a, u64> {
+        Box::pin(async move {
+            let query = format!(
+                "UPDATE {table} SET data = jsonb_set(data, ARRAY[$2], to_jsonb($3::bigint))
+                 WHERE id = $1 AND coalesce((data->>$2)::bigint < $3, true)"
+            );
+            let result = sqlx::query(&query)
+                .bind(id)
+                .bind(field)
+                .bind(value)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn remove_from_array<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        element: &'a str,
+    ) -> DbFuture<'a, u64> {
+        Box::pin(async move {
+            // Conditional, so of concurrent removals of the same element one changes the row
+            let query = format!(
+                "UPDATE {table} SET data = jsonb_set(data, ARRAY[$2], (data->$2) - $3::text)
+                 WHERE id = $1 AND (data->$2) ? $3::text"
+            );
+            let result = sqlx::query(&query)
+                .bind(id)
+                .bind(field)
+                .bind(element)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn increment_below<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        path: &'a [&'a str],
+        cap: i64,
+    ) -> DbFuture<'a, Option<i64>> {
+        Box::pin(async move {
+            let query = format!(
+                "UPDATE {table} SET data = jsonb_set(data, $2, to_jsonb((data #>> $2)::bigint + 1))
+                 WHERE id = $1 AND (data #>> $2)::bigint < $3
+                 RETURNING (data #>> $2)::bigint"
+            );
+            Ok(sqlx::query_scalar(&query)
+                .bind(id)
+                .bind(path)
+                .bind(cap)
+                .fetch_optional(&self.pool)
+                .await?)
+        })
+    }
+
+    fn take_lease<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        holder: &'a str,
+        until: i64,
+        now: i64,
+    ) -> DbFuture<'a, u64> {
+        Box::pin(async move {
+            let query = format!(
+                "INSERT INTO {table} AS leases (id, data)
+                 VALUES ($1, jsonb_build_object('holder', $2::text, 'until', $3::bigint))
+                 ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data
+                 WHERE leases.data->>'holder' = $2 OR (leases.data->>'until')::bigint < $4"
+            );
+            let result = sqlx::query(&query)
+                .bind(id)
+                .bind(holder)
+                .bind(until)
+                .bind(now)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn remove<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!("DELETE FROM {table} WHERE id = $1");
+            sqlx::query(&query).bind(id).execute(&self.pool).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        Box::pin(async move {
+            let query = format!("SELECT data FROM {table} WHERE id = $1");
+            Ok(sqlx::query_scalar(&query)
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?)
+        })
+    }
+
+    fn count<'a>(&'a self, table: &'a str) -> DbFuture<'a, usize> {
+        Box::pin(async move {
+            let query = format!("SELECT COUNT(*) FROM {table}");
+            let count: i64 = sqlx::query_scalar(&query).fetch_one(&self.pool).await?;
+            Ok(count.saturating_abs() as usize)
+        })
+    }
+
+    fn select<'a>(
+        &'a self,
+        table: &'a str,
+        selection: Selection<'a>,
+        limit: Option<usize>,
+    ) -> DbFuture<'a, Vec<(String, Value)>> {
+        Box::pin(async move {
+            let limit = limit.map_or(i64::MAX, |limit| i64::try_from(limit).unwrap_or(i64::MAX));
+            let rows = match selection {
+                Selection::All => {
+                    let query = format!("SELECT id, data FROM {table} ORDER BY id LIMIT $1");
+                    sqlx::query_as(&query)
+                        .bind(limit)
+                        .fetch_all(&self.pool)
+                        .await?
+                }
+                Selection::Field { field, value } => {
+                    let query = format!(
+                        "SELECT id, data FROM {table} WHERE data->>$1 = $2
+                         ORDER BY data->>'date' DESC LIMIT $3"
+                    );
+                    sqlx::query_as(&query)
+                        .bind(field)
+                        .bind(value)
+                        .bind(limit)
+                        .fetch_all(&self.pool)
+                        .await?
+                }
+                Selection::Range {
+                    field,
+                    value,
+                    range_field,
+                    from,
+                    to,
+                } => {
+                    let query = format!(
+                        "SELECT id, data FROM {table} WHERE data->>$1 = $2
+                         AND data->>$3 >= $4 AND data->>$3 < $5 ORDER BY data->>$3 LIMIT $6"
+                    );
+                    sqlx::query_as(&query)
+                        .bind(field)
+                        .bind(value)
+                        .bind(range_field)
+                        .bind(from)
+                        .bind(to)
+                        .bind(limit)
+                        .fetch_all(&self.pool)
+                        .await?
+                }
+            };
+            Ok(rows)
+        })
+    }
+
+    fn begin(&self) -> DbFuture<'_, Box<dyn StoreTransaction>> {
+        Box::pin(async move {
+            let tx = self.pool.begin().await?;
+            Ok(Box::new(PostgresTransaction { tx }) as Box<dyn StoreTransaction>)
+        })
+    }
+}
+
+struct PostgresTransaction {
+    tx: sqlx::Transaction<'static, Postgres>,
+}
+
+impl PostgresTransaction {
+    async fn fetch(&mut self, query: &str, id: String) -> Result<Option<Value>, DbError> {
+        Ok(sqlx::query_scalar(query)
+            .bind(id)
+            .fetch_optional(&mut *self.tx)
+            .await?)
+    }
+}
+
+impl StoreTransaction for PostgresTransaction {
+    fn get<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        Box::pin(async move {
+            let query = format!("SELECT data FROM {table} WHERE id = $1");
+            self.fetch(&query, id).await
+        })
+    }
+
+    fn get_for_update<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        Box::pin(async move {
+            let query = format!("SELECT data FROM {table} WHERE id = $1 FOR UPDATE");
+            self.fetch(&query, id).await
+        })
+    }
+
+    fn insert<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!("INSERT INTO {table} (id, data) VALUES ($1, $2)");
+            sqlx::query(&query)
+                .bind(&id)
+                .bind(data)
+                .execute(&mut *self.tx)
+                .await
+                .map_err(|e| insert_error(e, id))?;
+            Ok(())
+        })
+    }
+
+    fn update<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!("UPDATE {table} SET data = $2 WHERE id = $1");
+            sqlx::query(&query)
+                .bind(id)
+                .bind(data)
+                .execute(&mut *self.tx)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn append_outbox<'a>(
+        &'a mut self,
+        table: &'a str,
+        messages: &'a [OutboxMessage],
+    ) -> DbFuture<'a, ()> {
+        Box::pin(PostgresOutbox::append_in(&mut self.tx, table, messages))
+    }
+
+    fn commit(self: Box<Self>) -> DbFuture<'static, ()> {
+        Box::pin(async move {
+            self.tx.commit().await?;
+            Ok(())
+        })
+    }
+}
+
+/// Repository storing T as JSON in a table of a `Store`
+#[derive(Clone)]
+pub struct JsonRepo<T, Id> {
+    store: Arc<dyn Store>,
+    table: String,
+    _phantom: std::marker::PhantomData<(T, Id)>,
+}
+
+impl<T, Id> std::fmt::Debug for JsonRepo<T, Id> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonRepo")
+            .field("table", &self.table)
+            .field("store", &self.store)
+            .finish()
+    }
+}
+
+impl<T, Id> JsonRepo<T, Id> {
+    /// Create a repository over `table` of `store`, creating the table if needed
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn new(store: Arc<dyn Store>, table: &str) -> Result<Self, DbError> {
+        store.create_table(table).await?;
         Ok(Self {
-            pool,
+            store,
             table: table.to_string(),
             _phantom: std::marker::PhantomData,
         })
     }
+
+    #[must_use]
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    #[must_use]
+    pub fn store(&self) -> &Arc<dyn Store> {
+        &self.store
+    }
+
+    /// Start a transaction on the repository's store. Other repositories of the same
+    /// store can write in it.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn begin(&self) -> Result<Transaction, DbError> {
+        Ok(Transaction {
+            inner: self.store.begin().await?,
+        })
+    }
+
+    fn decode_rows(rows: Vec<(String, Value)>) -> Vec<(Id, T)>
+    where
+        T: DeserializeOwned,
+        Id: std::str::FromStr,
+    {
+        rows.into_iter()
+            .filter_map(|(id_str, val)| {
+                // Parse the string ID back to the proper type
+                let id = id_str.parse().ok()?;
+                let item: T = serde_json::from_value(val).ok()?;
+                Some((id, item))
+            })
+            .collect()
+    }
 }
 
-impl<T, Id> Repository<T, Id> for PostgresRepo<T, Id>
+impl<T, Id> JsonRepo<T, Id>
 where
     T: Serialize + DeserializeOwned + Send + Sync,
-    Id: ToString
-        + std::str::FromStr
-        + for<'a> sqlx::Decode<'a, sqlx::Postgres>
-        + sqlx::Type<sqlx::Postgres>
-        + Send
-        + Sync,
+    Id: ToString + Send + Sync,
 {
-    async fn insert(&self, id: Id, item: T) -> Result<(), DbError> {
-        let data = serde_json::to_value(item)?;
-        let query = format!("INSERT INTO {} (id, data) VALUES ($1, $2)", self.table);
-        let id_str = id.to_string();
+    /// Insert a new item and append messages to the outbox in a single transaction
+    /// # Errors
+    /// - Returns `DbError` if the operation fails, in which case nothing is written
+    pub async fn insert_with_outbox(
+        &self,
+        id: Id,
+        item: T,
+        outbox: &PostgresOutbox,
+        messages: &[OutboxMessage],
+    ) -> Result<(), DbError> {
+        let mut tx = self.begin().await?;
+        tx.insert(self, id, item).await?;
+        tx.append_outbox(outbox, messages).await?;
+        tx.commit().await
+    }
 
-        sqlx::query(&query)
-            .bind(id_str)
-            .bind(data)
-            .execute(&self.pool)
+    /// Set one field of an item, so concurrent updates of its other fields are kept.
+    /// Returns the number of items changed, 0 if there is none with this ID.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn set_field<V: Serialize>(
+        &self,
+        id: &Id,
+        field: &str,
+        value: V,
+    ) -> Result<u64, DbError> {
+        let value = serde_json::to_value(value)?;
+        self.store
+            .set_field(&self.table, id.to_string(), field, value)
             .await
-            .map_err(DbError::from)?;
-
-        Ok(())
     }
 
-    async fn update(&self, id: Id, item: T) -> Result<(), DbError> {
-        let data = serde_json::to_value(item)?;
-        let query = format!("UPDATE {} SET data = $2 WHERE id = $1", self.table);
-        let id_str = id.to_string();
+    /// Set one field of an item unless it is already set, as a single statement so of
+    /// concurrent writers only one succeeds. Returns the number of items changed.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn set_field_if_null<V: Serialize>(
+        &self,
+        id: &Id,
+        field: &str,
+        value: V,
+    ) -> Result<u64, DbError> {
+        let value = serde_json::to_value(value)?;
+        self.store
+            .set_field_if_null(&self.table, id.to_string(), field, value)
+            .await
+    }
 
-        sqlx::query(&query)
-            .bind(id_str)
-            .bind(data)
-            .execute(&self.pool)
+    /// Raise the integer `field` of an item to `value`, unless it is already as high.
+    /// As a single statement, of concurrent writers of the same value only one
+    /// changes the item. Returns the number of items changed.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn raise_field(&self, id: &Id, field: &str, value: i64) -> Result<u64, DbError> {
+        self.store
+            .raise_field(&self.table, id.to_string(), field, value)
             .await
-            .map_err(DbError::from)?;
+    }
 
-        Ok(())
+    /// Remove a string from the array `field` of an item, if it is still there.
+    /// Returns the number of items changed, so of concurrent removals of the same
+    /// string only one sees 1.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn remove_from_array(
+        &self,
+        id: &Id,
+        field: &str,
+        element: &str,
+    ) -> Result<u64, DbError> {
+        self.store
+            .remove_from_array(&self.table, id.to_string(), field, element)
+            .await
     }
 
-    async fn remove(&self, id: Id) -> Result<(), DbError> {
-        let query = format!("DELETE FROM {} WHERE id = $1", self.table);
-        let id_str = id.to_string();
+    /// Add one to the integer at `path` of an item unless it already reached `cap`, as a
+    /// single statement so concurrent increments never go past it. Returns the new
+    /// value, `None` if there is no item with this ID or it reached `cap`.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn increment_below(
+        &self,
+        id: &Id,
+        path: &[&str],
+        cap: i64,
+    ) -> Result<Option<i64>, DbError> {
+        self.store
+            .increment_below(&self.table, id.to_string(), path, cap)
+            .await
+    }
 
-        sqlx::query(&query)
-            .bind(id_str)
-            .execute(&self.pool)
+    /// Hold an item for `holder` until `until`, unless another holder's lease is
+    /// still running at `now`. Holders renew with the same call, and as a single
+    /// statement only one of concurrent holders gets the lease. Times are in
+    /// milliseconds. Returns the number of items written.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn take_lease(
+        &self,
+        id: &Id,
+        holder: &str,
+        until: i64,
+        now: i64,
+    ) -> Result<u64, DbError> {
+        self.store
+            .take_lease(&self.table, id.to_string(), holder, until, now)
             .await
-            .map_err(DbError::from)?;
+    }
 
-        Ok(())
+    /// Update an existing item and append messages to the outbox in a single transaction
+    /// # Errors
+    /// - Returns `DbError` if the operation fails, in which case nothing is written
+    pub async fn update_with_outbox(
+        &self,
+        id: Id,
+        item: T,
+        outbox: &PostgresOutbox,
+        messages: &[OutboxMessage],
+    ) -> Result<(), DbError> {
+        let mut tx = self.begin().await?;
+        tx.update(self, id, item).await?;
+        tx.append_outbox(outbox, messages).await?;
+        tx.commit().await
     }
+}
 
-    async fn get(&self, id: &Id) -> Result<Option<T>, DbError> {
-        let query = format!("SELECT data FROM {} WHERE id = $1", self.table);
-        let id_str = id.to_string();
+impl<T, Id> Repository<T, Id> for JsonRepo<T, Id>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+    Id: ToString + std::str::FromStr + Send + Sync,
+{
+    async fn insert(&self, id: Id, item: T) -> Result<(), DbError> {
+        let data = serde_json::to_value(item)?;
+        self.store.insert(&self.table, id.to_string(), data).await
+    }
 
-        let row: Option<serde_json::Value> = sqlx::query_scalar(&query)
-            .bind(id_str)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(DbError::from)?;
+    async fn update(&self, id: Id, item: T) -> Result<(), DbError> {
+        let data = serde_json::to_value(item)?;
+        self.store.update(&self.table, id.to_string(), data).await
+    }
 
-        Ok(row.map(|val| serde_json::from_value(val).unwrap()))
+    async fn remove(&self, id: Id) -> Result<(), DbError> {
+        self.store.remove(&self.table, id.to_string()).await
+    }
+
+    async fn get(&self, id: &Id) -> Result<Option<T>, DbError> {
+        let row = self.store.get(&self.table, id.to_string()).await?;
+        Ok(row.map(serde_json::from_value).transpose()?)
     }
 
     async fn len(&self) -> Result<usize, DbError> {
-        let query = format!("SELECT COUNT(*) FROM {}", self.table);
+        self.store.count(&self.table).await
+    }
 
-        let (count,): (i64,) = sqlx::query_as(&query)
-            .fetch_one(&self.pool)
-            .await
-            .map_err(DbError::from)?;
+    async fn find_by_field(&self, field: &str, value: &str) -> Result<Option<T>, DbError> {
+        let rows = self
+            .store
+            .select(&self.table, Selection::Field { field, value }, Some(1))
+            .await?;
+        Ok(rows
+            .into_iter()
+            .next()
+            .map(|(_, data)| serde_json::from_value(data))
+            .transpose()?)
+    }
 
-        Ok(count.saturating_abs() as usize)
+    async fn find_all_by_field(&self, field: &str, value: &str) -> Result<Vec<(Id, T)>, DbError> {
+        let rows = self
+            .store
+            .select(&self.table, Selection::Field { field, value }, None)
+            .await?;
+        Ok(Self::decode_rows(rows))
     }
 
-    async fn find_by_field(&self, field: &str, value: &str) -> Result<Option<T>, DbError> {
-        let query = format!(
-            "SELECT data FROM {} WHERE data->>$1 = $2 LIMIT 1",
-            self.table
-        );
-
-        let row: Option<serde_json::Value> = sqlx::query_scalar(&query)
-            .bind(field)
-            .bind(value)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(DbError::from)?;
+    async fn find_range_by_field(
+        &self,
+        field: &str,
+        value: &str,
+        range_field: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(Id, T)>, DbError> {
+        let selection = Selection::Range {
+            field,
+            value,
+            range_field,
+            from,
+            to,
+        };
+        let rows = self.store.select(&self.table, selection, None).await?;
+        Ok(Self::decode_rows(rows))
+    }
 
-        Ok(row.map(|val| serde_json::from_value(val).unwrap()))
+    async fn all(&self) -> Result<Vec<(Id, T)>, DbError> {
+        let rows = self.store.select(&self.table, Selection::All, None).await?;
+        Ok(Self::decode_rows(rows))
     }
+}
 
-    async fn find_all_by_field(&self, field: &str, value: &str) -> Result<Vec<(Id, T)>, DbError> {
-        let query = format!(
-            "SELECT id, data FROM {} WHERE data->>$1 = $2 ORDER BY data->>'date' DESC",
-            self.table
-        );
-
-        let rows: Vec<(String, serde_json::Value)> = sqlx::query_as(&query)
-            .bind(field)
-            .bind(value)
-            .fetch_all(&self.pool)
-            .await
-            .map_err(DbError::from)?;
+/// Transaction on a `Store`, writing to the repositories of that store
+pub struct Transaction {
+    inner: Box<dyn StoreTransaction>,
+}
 
-        let result = rows
-            .into_iter()
-            .filter_map(|(id_str, val)| {
-                // Parse the string ID back to the proper type
-                let id = id_str.parse().ok()?;
-                let item: T = serde_json::from_value(val).ok()?;
-                Some((id, item))
-            })
-            .collect();
+impl std::fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transaction").finish_non_exhaustive()
+    }
+}
+
+impl Transaction {
+    /// Read an item of `repo` within the transaction, without locking it
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn get<T: DeserializeOwned, Id: ToString>(
+        &mut self,
+        repo: &JsonRepo<T, Id>,
+        id: &Id,
+    ) -> Result<Option<T>, DbError> {
+        let row = self.inner.get(&repo.table, id.to_string()).await?;
+        Ok(row.map(serde_json::from_value).transpose()?)
+    }
+
+    /// Read an item of `repo` and lock it until the transaction commits or is dropped,
+    /// so concurrent read-modify-writes of the item, from any instance, run one at a time
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn get_for_update<T: DeserializeOwned, Id: ToString>(
+        &mut self,
+        repo: &JsonRepo<T, Id>,
+        id: &Id,
+    ) -> Result<Option<T>, DbError> {
+        let row = self
+            .inner
+            .get_for_update(&repo.table, id.to_string())
+            .await?;
+        Ok(row.map(serde_json::from_value).transpose()?)
+    }
+
+    /// Insert a new item into `repo`
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn insert<T: Serialize, Id: ToString>(
+        &mut self,
+        repo: &JsonRepo<T, Id>,
+        id: Id,
+        item: T,
+    ) -> Result<(), DbError> {
+        let data = serde_json::to_value(item)?;
+        self.inner.insert(&repo.table, id.to_string(), data).await
+    }
 
-        Ok(result)
+    /// Update an existing item of `repo`
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn update<T: Serialize, Id: ToString>(
+        &mut self,
+        repo: &JsonRepo<T, Id>,
+        id: Id,
+        item: T,
+    ) -> Result<(), DbError> {
+        let data = serde_json::to_value(item)?;
+        self.inner.update(&repo.table, id.to_string(), data).await
+    }
+
+    /// Append messages to `outbox`
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn append_outbox(
+        &mut self,
+        outbox: &PostgresOutbox,
+        messages: &[OutboxMessage],
+    ) -> Result<(), DbError> {
+        self.inner.append_outbox(outbox.table(), messages).await
+    }
+
+    /// Apply the writes
+    /// # Errors
+    /// - Returns `DbError` if the operation fails, in which case nothing is written
+    pub async fn commit(self) -> Result<(), DbError> {
+        self.inner.commit().await
     }
 }
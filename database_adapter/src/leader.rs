@@ -0,0 +1,70 @@
+use sqlx::{Connection, PgConnection};
+
+use crate::db::DbError;
+
+/// Cluster-wide leadership for a named role, backed by a Postgres session-level
+/// advisory lock. The lock lives on a dedicated connection, so it is released as
+/// soon as the holder drops it or its process dies.
+pub struct LeaderLock {
+    name: String,
+    conn: PgConnection,
+}
+
+impl std::fmt::Debug for LeaderLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LeaderLock")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LeaderLock {
+    /// Try to become the leader for `name`. Returns `None` if another session holds it.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    /// # Panics
+    /// - Panics if `DATABASE_URL` is not set in the environment or .env file
+    pub async fn try_acquire(name: &str) -> Result<Option<Self>, DbError> {
+        dotenvy::dotenv().ok();
+        let db_url = std::env::var("DATABASE_URL")
+            .expect("DATABASE_URL must be set in .env file or environment");
+
+        let mut conn = PgConnection::connect(&db_url).await?;
+        let acquired: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtext($1))")
+            .bind(name)
+            .fetch_one(&mut conn)
+            .await?;
+
+        if acquired {
+            Ok(Some(Self {
+                name: name.to_string(),
+                conn,
+            }))
+        } else {
+            conn.close().await?;
+            Ok(None)
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check that the session holding the lock is still alive
+    pub async fn is_held(&mut self) -> bool {
+        self.conn.ping().await.is_ok()
+    }
+
+    /// Give up leadership
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn release(mut self) -> Result<(), DbError> {
+        sqlx::query("SELECT pg_advisory_unlock(hashtext($1))")
+            .bind(&self.name)
+            .execute(&mut self.conn)
+            .await?;
+        self.conn.close().await?;
+        Ok(())
+    }
+}
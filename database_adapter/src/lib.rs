@@ -1,2 +1,6 @@
 pub mod db;
+pub mod leader;
+pub mod outbox;
+pub mod queue;
+#[cfg(test)]
 mod tests;
@@ -0,0 +1,290 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, postgres::PgListener, postgres::PgPoolOptions};
+use uuid::Uuid;
+
+use crate::db::DbError;
+
+/// Message to append to an outbox, usually in the same transaction as the state change
+/// that produced it
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub topic: String,
+    pub payload: serde_json::Value,
+}
+
+/// Outbox row as stored in the database
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OutboxRecord {
+    pub id: String,
+    /// Insertion order of the record across all writers
+    pub sequence: i64,
+    pub topic: String,
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub dispatched_at: Option<DateTime<Utc>>,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+/// Filter used when listing outbox records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxFilter {
+    All,
+    Pending,
+    Dispatched,
+}
+
+/// Postgres outbox table, written to by repositories within their own transactions
+/// and drained by a relay
+#[derive(Clone)]
+pub struct PostgresOutbox {
+    pool: Pool<Postgres>,
+    table: String,
+}
+
+impl std::fmt::Debug for PostgresOutbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostgresOutbox")
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresOutbox {
+    /// Create a new Postgres outbox
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    /// # Panics
+    /// - Panics if `DATABASE_URL` is not set in the environment or .env file
+    pub async fn new(table: &str) -> Result<Self, DbError> {
+        dotenvy::dotenv().ok();
+        let db_url = std::env::var("DATABASE_URL")
+            .expect("DATABASE_URL must be set in .env file or environment");
+
+        let pool = PgPoolOptions::new().connect(&db_url).await?;
+
+        // Ensure table exists
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                id              TEXT PRIMARY KEY,
+                sequence        BIGSERIAL,
+                topic           TEXT NOT NULL,
+                payload         JSONB NOT NULL,
+                created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+                dispatched_at   TIMESTAMPTZ,
+                attempts        INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                last_error      TEXT
+            )"
+        );
+        sqlx::query(&query).execute(&pool).await?;
+
+        Ok(Self {
+            pool,
+            table: table.to_string(),
+        })
+    }
+
+    #[must_use]
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Channel notified when messages are appended to `table`
+    fn channel(table: &str) -> String {
+        format!("{table}_appended")
+    }
+
+    /// Append messages outside of any repository transaction
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn append(&self, messages: &[OutboxMessage]) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await?;
+        Self::append_in(&mut tx, &self.table, messages).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Append messages within an existing transaction
+    pub(crate) async fn append_in(
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        table: &str,
+        messages: &[OutboxMessage],
+    ) -> Result<(), DbError> {
+        let query = format!("INSERT INTO {table} (id, topic, payload) VALUES ($1, $2, $3)");
+        for message in messages {
+            sqlx::query(&query)
+                .bind(message.id.to_string())
+                .bind(&message.topic)
+                .bind(&message.payload)
+                .execute(&mut **tx)
+                .await?;
+        }
+        if !messages.is_empty() {
+            // Delivered on commit, so listeners never wake up before the messages are visible
+            sqlx::query("SELECT pg_notify($1, '')")
+                .bind(Self::channel(table))
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Listen for messages appended by any process using the same database
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn listen(&self) -> Result<OutboxListener, DbError> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(&Self::channel(&self.table)).await?;
+        Ok(OutboxListener(listener))
+    }
+
+    /// Records with a sequence above `sequence`, in sequence order, whether dispatched or
+    /// not. A transaction holding a lower sequence may still be uncommitted, so a gap
+    /// in the sequences may fill later.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn fetch_after(
+        &self,
+        sequence: i64,
+        limit: i64,
+    ) -> Result<Vec<OutboxRecord>, DbError> {
+        let query = format!(
+            "SELECT * FROM {} WHERE sequence > $1 ORDER BY sequence LIMIT $2",
+            self.table
+        );
+        Ok(sqlx::query_as(&query)
+            .bind(sequence)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Records due for delivery, oldest first. Records that reached `max_attempts`
+    /// are left for an operator to replay. Records are not claimed, so a single relay
+    /// should drain an outbox at a time.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn fetch_due(
+        &self,
+        limit: i64,
+        max_attempts: i32,
+    ) -> Result<Vec<OutboxRecord>, DbError> {
+        let query = format!(
+            "SELECT * FROM {} WHERE dispatched_at IS NULL AND attempts < $1
+             AND next_attempt_at <= now() ORDER BY sequence LIMIT $2",
+            self.table
+        );
+        Ok(sqlx::query_as(&query)
+            .bind(max_attempts)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Mark a record as delivered
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn mark_dispatched(&self, id: &str) -> Result<(), DbError> {
+        let query = format!(
+            "UPDATE {} SET dispatched_at = now(), attempts = attempts + 1, last_error = NULL
+             WHERE id = $1",
+            self.table
+        );
+        sqlx::query(&query).bind(id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Record a failed delivery attempt and schedule the next one
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn record_failure(
+        &self,
+        id: &str,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<(), DbError> {
+        let query = format!(
+            "UPDATE {} SET attempts = attempts + 1, last_error = $2, next_attempt_at = $3
+             WHERE id = $1",
+            self.table
+        );
+        sqlx::query(&query)
+            .bind(id)
+            .bind(error)
+            .bind(retry_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// List records, newest first
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn list(
+        &self,
+        filter: OutboxFilter,
+        limit: i64,
+    ) -> Result<Vec<OutboxRecord>, DbError> {
+        let condition = match filter {
+            OutboxFilter::All => "TRUE",
+            OutboxFilter::Pending => "dispatched_at IS NULL",
+            OutboxFilter::Dispatched => "dispatched_at IS NOT NULL",
+        };
+        let query = format!(
+            "SELECT * FROM {} WHERE {condition} ORDER BY sequence DESC LIMIT $1",
+            self.table
+        );
+        Ok(sqlx::query_as(&query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Get a record by ID
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn get(&self, id: &str) -> Result<Option<OutboxRecord>, DbError> {
+        let query = format!("SELECT * FROM {} WHERE id = $1", self.table);
+        Ok(sqlx::query_as(&query)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Schedule a record for delivery again, whether it was dispatched or gave up.
+    /// Returns false if the record does not exist.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn replay(&self, id: &str) -> Result<bool, DbError> {
+        let query = format!(
+            "UPDATE {} SET dispatched_at = NULL, attempts = 0, next_attempt_at = now(),
+             last_error = NULL WHERE id = $1",
+            self.table
+        );
+        let result = sqlx::query(&query).bind(id).execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Wakes up when messages are appended to a `PostgresOutbox`
+pub struct OutboxListener(PgListener);
+
+impl std::fmt::Debug for OutboxListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutboxListener").finish_non_exhaustive()
+    }
+}
+
+impl OutboxListener {
+    /// Wait for the next append notification. Fails if the notifications are lost and
+    /// cannot be restored.
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    pub async fn wait(&mut self) -> Result<(), DbError> {
+        self.0.recv().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres, postgres::PgListener, postgres::PgPoolOptions};
+use uuid::Uuid;
+
+use crate::db::{DbError, DbFuture};
+
+/// Job claimed from a `JobQueue`
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct QueueJob {
+    pub job_id: Uuid,
+    pub group_id: Uuid,
+    pub priority: i16,
+    /// Number of times the job was claimed, including this one
+    pub attempts: i32,
+}
+
+/// Work queue with priority lanes and fair turns between groups.
+///
+/// Jobs are served by ascending priority. Within a priority, groups take turns,
+/// least recently served first, and a group may have up to its weight of jobs
+/// claimed per turn, so a group with many jobs cannot starve the others.
+/// Workers hold the jobs they claim under a lease; a job whose lease expired (for
+/// example because its worker died) can be claimed again.
+pub trait JobQueue: Send + Sync + std::fmt::Debug {
+    /// Enqueue a job, or move it to a more urgent priority if it is already queued.
+    /// A job pushed while it is being processed runs again once its worker is done.
+    fn push(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()>;
+    /// Enqueue a job unless it is already queued or being processed
+    fn push_if_absent(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()>;
+    /// Claim the next available job for `worker` for the duration of `lease`
+    fn claim<'a>(&'a self, worker: &'a str, lease: Duration) -> DbFuture<'a, Option<QueueJob>>;
+    /// Remove a job once `worker` is done with it. Returns false if the job was
+    /// pushed again meanwhile and stays queued, or if the lease was lost.
+    fn complete<'a>(&'a self, job_id: Uuid, worker: &'a str) -> DbFuture<'a, bool>;
+    /// Give a job back to the queue, at the back of its group, after `delay`
+    fn release<'a>(
+        &'a self,
+        job_id: Uuid,
+        worker: &'a str,
+        priority: i16,
+        delay: Duration,
+    ) -> DbFuture<'a, ()>;
+    /// Drop a job regardless of its state
+    fn remove(&self, job_id: Uuid) -> DbFuture<'_, ()>;
+    /// Set how many jobs of a group are served per round. Groups default to 1.
+    fn set_group_weight(&self, group_id: Uuid, weight: u32) -> DbFuture<'_, ()>;
+    /// Number of queued jobs, including the ones being processed
+    fn len(&self) -> DbFuture<'_, usize>;
+    /// Check if the queue is empty
+    fn is_empty(&self) -> DbFuture<'_, bool> {
+        Box::pin(async move { Ok(self.len().await? == 0) })
+    }
+    /// Listen for jobs pushed by any user of the queue
+    fn listen(&self) -> DbFuture<'_, Box<dyn QueueListener>>;
+}
+
+/// Wakes up when a job is pushed to a `JobQueue`
+pub trait QueueListener: Send {
+    /// Wait for the next push notification. Fails if the notifications are lost
+    /// and cannot be restored.
+    fn wait(&mut self) -> DbFuture<'_, ()>;
+}
+
+/// Durable work queue shared by every process using the same database.
+///
+/// Workers claim jobs with `FOR UPDATE SKIP LOCKED`, so a job is never served
+/// to two workers while its lease runs.
+#[derive(Clone)]
+pub struct PostgresQueue {
+    pool: Pool<Postgres>,
+    table: String,
+}
+
+impl std::fmt::Debug for PostgresQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostgresQueue")
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresQueue {
+    /// Create a new Postgres queue
+    /// # Errors
+    /// - Returns `DbError` if the operation fails
+    /// # Panics
+    /// - Panics if `DATABASE_URL` is not set in the environment or .env file
+    pub async fn new(table: &str) -> Result<Self, DbError> {
+        dotenvy::dotenv().ok();
+        let db_url = std::env::var("DATABASE_URL")
+            .expect("DATABASE_URL must be set in .env file or environment");
+
+        let pool = PgPoolOptions::new().connect(&db_url).await?;
+
+        // Ensure tables exist
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                job_id           UUID PRIMARY KEY,
+                group_id         UUID NOT NULL,
+                priority         SMALLINT NOT NULL,
+                enqueued_at      TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp(),
+                available_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+                lease_owner      TEXT,
+                lease_expires_at TIMESTAMPTZ,
+                requeued         BOOLEAN NOT NULL DEFAULT FALSE,
+                attempts         INTEGER NOT NULL DEFAULT 0
+            )"
+        );
+        sqlx::query(&query).execute(&pool).await?;
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {table}_groups (
+                group_id      UUID PRIMARY KEY,
+                weight        INTEGER NOT NULL DEFAULT 1 CHECK (weight > 0),
+                served        INTEGER NOT NULL DEFAULT 0,
+                turn_ended_at TIMESTAMPTZ
+            )"
+        );
+        sqlx::query(&query).execute(&pool).await?;
+
+        Ok(Self {
+            pool,
+            table: table.to_string(),
+        })
+    }
+
+    /// Channel notified whenever a job becomes available
+    #[must_use]
+    pub fn channel(&self) -> String {
+        format!("{}_ready", self.table)
+    }
+
+    async fn push_with(
+        &self,
+        query: &str,
+        job_id: Uuid,
+        group_id: Uuid,
+        priority: i16,
+    ) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(query)
+            .bind(job_id)
+            .bind(group_id)
+            .bind(priority)
+            .execute(&mut *tx)
+            .await?;
+        // Delivered on commit, so listeners never wake up before the job is visible
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(self.channel())
+            .bind(job_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+impl JobQueue for PostgresQueue {
+    fn push(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            let query = format!(
+                "INSERT INTO {0} (job_id, group_id, priority) VALUES ($1, $2, $3)
+                 ON CONFLICT (job_id) DO UPDATE SET
+                    priority = LEAST({0}.priority, EXCLUDED.priority),
+                    available_at = now(),
+                    requeued = {0}.lease_owner IS NOT NULL",
+                self.table
+            );
+            self.push_with(&query, job_id, group_id, priority).await
+        })
+    }
+
+    fn push_if_absent(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            let query = format!(
+                "INSERT INTO {} (job_id, group_id, priority) VALUES ($1, $2, $3)
+                 ON CONFLICT (job_id) DO NOTHING",
+                self.table
+            );
+            self.push_with(&query, job_id, group_id, priority).await
+        })
+    }
+
+    fn claim<'a>(&'a self, worker: &'a str, lease: Duration) -> DbFuture<'a, Option<QueueJob>> {
+        Box::pin(async move {
+            let query = format!(
+                "WITH next AS (
+                    SELECT q.job_id FROM {0} q LEFT JOIN {0}_groups g ON g.group_id = q.group_id
+                    WHERE q.available_at <= now()
+                      AND (q.lease_expires_at IS NULL OR q.lease_expires_at < now())
+                    ORDER BY q.priority, g.turn_ended_at NULLS FIRST, q.enqueued_at
+                    LIMIT 1
+                    FOR UPDATE OF q SKIP LOCKED
+                ),
+                claimed AS (
+                    UPDATE {0} q SET
+                        lease_owner = $1,
+                        lease_expires_at = now() + $2 * INTERVAL '1 millisecond',
+                        requeued = FALSE,
+                        attempts = q.attempts + 1
+                    FROM next WHERE q.job_id = next.job_id
+                    RETURNING q.job_id, q.group_id, q.priority, q.attempts
+                ),
+                turn AS (
+                    INSERT INTO {0}_groups (group_id, served, turn_ended_at)
+                    SELECT group_id, 0, clock_timestamp() FROM claimed
+                    ON CONFLICT (group_id) DO UPDATE SET
+                        served = ({0}_groups.served + 1) % {0}_groups.weight,
+                        turn_ended_at = CASE
+                            WHEN {0}_groups.served + 1 >= {0}_groups.weight THEN clock_timestamp()
+                            ELSE {0}_groups.turn_ended_at
+                        END
+                )
+                SELECT job_id, group_id, priority, attempts FROM claimed",
+                self.table
+            );
+            let lease_ms = i64::try_from(lease.as_millis()).unwrap_or(i64::MAX);
+            Ok(sqlx::query_as(&query)
+                .bind(worker)
+                .bind(lease_ms)
+                .fetch_optional(&self.pool)
+                .await?)
+        })
+    }
+
+    fn complete<'a>(&'a self, job_id: Uuid, worker: &'a str) -> DbFuture<'a, bool> {
+        Box::pin(async move {
+            let query = format!(
+                "DELETE FROM {} WHERE job_id = $1 AND lease_owner = $2 AND NOT requeued",
+                self.table
+            );
+            let deleted = sqlx::query(&query)
+                .bind(job_id)
+                .bind(worker)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+            if deleted > 0 {
+                return Ok(true);
+            }
+
+            let query = format!(
+                "UPDATE {} SET lease_owner = NULL, lease_expires_at = NULL, requeued = FALSE,
+                 enqueued_at = clock_timestamp()
+                 WHERE job_id = $1 AND lease_owner = $2",
+                self.table
+            );
+            sqlx::query(&query)
+                .bind(job_id)
+                .bind(worker)
+                .execute(&self.pool)
+                .await?;
+            Ok(false)
+        })
+    }
+
+    fn release<'a>(
+        &'a self,
+        job_id: Uuid,
+        worker: &'a str,
+        priority: i16,
+        delay: Duration,
+    ) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            let query = format!(
+                "UPDATE {} SET lease_owner = NULL, lease_expires_at = NULL, requeued = FALSE,
+                 priority = LEAST(priority, $3), enqueued_at = clock_timestamp(),
+                 available_at = now() + $4 * INTERVAL '1 millisecond'
+                 WHERE job_id = $1 AND lease_owner = $2",
+                self.table
+            );
+            let delay_ms = i64::try_from(delay.as_millis()).unwrap_or(i64::MAX);
+            sqlx::query(&query)
+                .bind(job_id)
+                .bind(worker)
+                .bind(priority)
+                .bind(delay_ms)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, job_id: Uuid) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            let query = format!("DELETE FROM {} WHERE job_id = $1", self.table);
+            sqlx::query(&query).bind(job_id).execute(&self.pool).await?;
+            Ok(())
+        })
+    }
+
+    fn set_group_weight(&self, group_id: Uuid, weight: u32) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            let weight = i32::try_from(weight.max(1)).unwrap_or(i32::MAX);
+            let query = format!(
+                "INSERT INTO {}_groups (group_id, weight) VALUES ($1, $2)
+                 ON CONFLICT (group_id) DO UPDATE SET weight = EXCLUDED.weight",
+                self.table
+            );
+            sqlx::query(&query)
+                .bind(group_id)
+                .bind(weight)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn len(&self) -> DbFuture<'_, usize> {
+        Box::pin(async move {
+            let query = format!("SELECT COUNT(*) FROM {}", self.table);
+            let count: i64 = sqlx::query_scalar(&query).fetch_one(&self.pool).await?;
+            Ok(count.saturating_abs() as usize)
+        })
+    }
+
+    fn listen(&self) -> DbFuture<'_, Box<dyn QueueListener>> {
+        Box::pin(async move {
+            let mut listener = PgListener::connect_with(&self.pool).await?;
+            listener.listen(&self.channel()).await?;
+            Ok(Box::new(PostgresQueueListener(listener)) as Box<dyn QueueListener>)
+        })
+    }
+}
+
+/// Receives the notifications sent on commit by `PostgresQueue` pushes
+struct PostgresQueueListener(PgListener);
+
+impl QueueListener for PostgresQueueListener {
+    fn wait(&mut self) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            self.0.recv().await?;
+            Ok(())
+        })
+    }
+}
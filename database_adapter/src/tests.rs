@@ -8,14 +8,16 @@ struct User {
 
 #[tokio::test]
 async fn test_postgres_repo_crud() -> anyhow::Result<()> {
-    use crate::db::{PostgresRepo, Repository};
+    use crate::db::{JsonRepo, PostgresStore, Repository};
+    use std::sync::Arc;
     // Each test uses a fresh table to avoid conflicts
     let table = format!(
         "users_test_{}",
         uuid::Uuid::new_v4().to_string().replace('-', "")
     );
 
-    let repo = PostgresRepo::<User, String>::new(&table).await?;
+    let store = Arc::new(PostgresStore::connect().await?);
+    let repo = JsonRepo::<User, String>::new(store, &table).await?;
 
     // Insert
     let user = User {
@@ -41,6 +43,10 @@ async fn test_postgres_repo_crud() -> anyhow::Result<()> {
     let count = repo.len().await?;
     assert_eq!(count, 1);
 
+    // All
+    let all = repo.all().await?;
+    assert_eq!(all, vec![("1".to_string(), updated.clone())]);
+
     // Remove
     repo.remove("1".to_string()).await?;
     let fetched3 = repo.get(&"1".to_string()).await?;
@@ -48,3 +54,278 @@ async fn test_postgres_repo_crud() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_outbox_written_with_state_change() -> anyhow::Result<()> {
+    use crate::db::{JsonRepo, PostgresStore, Repository};
+    use crate::outbox::{OutboxFilter, OutboxMessage, PostgresOutbox};
+    use std::sync::Arc;
+    let suffix = uuid::Uuid::new_v4().to_string().replace('-', "");
+
+    let store = Arc::new(PostgresStore::connect().await?);
+    let repo = JsonRepo::<User, String>::new(store, &format!("users_test_{suffix}")).await?;
+    let outbox = PostgresOutbox::new(&format!("outbox_test_{suffix}")).await?;
+
+    let user = User {
+        name: "Bob".into(),
+        email: "bob@example.com".into(),
+    };
+    let message = |topic: &str| OutboxMessage {
+        id: uuid::Uuid::new_v4(),
+        topic: topic.into(),
+        payload: serde_json::json!({ "name": "Bob" }),
+    };
+
+    // State change and outbox entry are committed together
+    let created = message("UserCreated");
+    let id = created.id.to_string();
+    repo.insert_with_outbox("1".to_string(), user.clone(), &outbox, &[created])
+        .await?;
+    assert_eq!(repo.get(&"1".to_string()).await?, Some(user.clone()));
+    assert_eq!(outbox.list(OutboxFilter::Pending, 10).await?.len(), 1);
+
+    // A failing state change leaves no outbox entry behind
+    let duplicate = repo
+        .insert_with_outbox("1".to_string(), user, &outbox, &[message("UserCreated")])
+        .await;
+    assert!(duplicate.is_err());
+    assert_eq!(outbox.list(OutboxFilter::All, 10).await?.len(), 1);
+
+    // Dispatch, then replay
+    assert_eq!(outbox.fetch_due(10, 5).await?.len(), 1);
+    outbox.mark_dispatched(&id).await?;
+    assert!(outbox.fetch_due(10, 5).await?.is_empty());
+    assert_eq!(outbox.list(OutboxFilter::Dispatched, 10).await?.len(), 1);
+
+    assert!(outbox.replay(&id).await?);
+    let record = outbox.get(&id).await?.expect("record exists");
+    assert!(record.dispatched_at.is_none());
+    assert_eq!(record.attempts, 0);
+    assert!(!outbox.replay("missing").await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_outbox_appends_are_notified_and_followed() -> anyhow::Result<()> {
+    use crate::outbox::{OutboxMessage, PostgresOutbox};
+    use std::time::Duration;
+    let suffix = uuid::Uuid::new_v4().to_string().replace('-', "");
+
+    // Another process appending to the same outbox
+    let outbox = PostgresOutbox::new(&format!("outbox_test_{suffix}")).await?;
+    let other = PostgresOutbox::new(outbox.table()).await?;
+    let mut listener = outbox.listen().await?;
+
+    let messages: Vec<OutboxMessage> = ["First", "Second"]
+        .into_iter()
+        .map(|topic| OutboxMessage {
+            id: uuid::Uuid::new_v4(),
+            topic: topic.into(),
+            payload: serde_json::json!({}),
+        })
+        .collect();
+    other.append(&messages).await?;
+    tokio::time::timeout(Duration::from_secs(5), listener.wait()).await??;
+
+    let records = outbox.fetch_after(0, 10).await?;
+    let topics: Vec<&str> = records.iter().map(|r| r.topic.as_str()).collect();
+    assert_eq!(topics, vec!["First", "Second"]);
+    let after_first = outbox.fetch_after(records[0].sequence, 10).await?;
+    assert_eq!(after_first.len(), 1);
+    assert_eq!(after_first[0].topic, "Second");
+
+    Ok(())
+}
+
+mod queue {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use crate::leader::LeaderLock;
+    use crate::queue::{JobQueue, PostgresQueue};
+
+    const LEASE: Duration = Duration::from_secs(30);
+
+    async fn new_queue() -> PostgresQueue {
+        let table = format!("queue_test_{}", Uuid::new_v4().simple());
+        PostgresQueue::new(&table)
+            .await
+            .expect("queue creation failed")
+    }
+
+    /// Claim and complete the next job
+    async fn pop(queue: &PostgresQueue) -> Option<Uuid> {
+        let job = queue.claim("worker", LEASE).await.unwrap()?;
+        assert!(queue.complete(job.job_id, "worker").await.unwrap());
+        Some(job.job_id)
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_across_groups() {
+        let queue = new_queue().await;
+        let flooder = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let flood: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        for job_id in &flood {
+            queue.push(*job_id, flooder, 2).await.unwrap();
+        }
+        let other_job = Uuid::new_v4();
+        queue.push(other_job, other, 2).await.unwrap();
+
+        assert_eq!(pop(&queue).await, Some(flood[0]));
+        assert_eq!(pop(&queue).await, Some(other_job));
+        assert_eq!(pop(&queue).await, Some(flood[1]));
+        assert_eq!(queue.len().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin() {
+        let queue = new_queue().await;
+        let heavy = Uuid::new_v4();
+        let light = Uuid::new_v4();
+        queue.set_group_weight(heavy, 2).await.unwrap();
+
+        let heavy_jobs: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for job_id in &heavy_jobs {
+            queue.push(*job_id, heavy, 2).await.unwrap();
+        }
+        let light_job = Uuid::new_v4();
+        queue.push(light_job, light, 2).await.unwrap();
+
+        assert_eq!(pop(&queue).await, Some(heavy_jobs[0]));
+        assert_eq!(pop(&queue).await, Some(heavy_jobs[1]));
+        assert_eq!(pop(&queue).await, Some(light_job));
+        assert_eq!(pop(&queue).await, Some(heavy_jobs[2]));
+        assert!(queue.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_priority_lanes() {
+        let queue = new_queue().await;
+        let group = Uuid::new_v4();
+
+        let regular = Uuid::new_v4();
+        let to_cancel = Uuid::new_v4();
+        let liquidation = Uuid::new_v4();
+        queue.push(regular, group, 2).await.unwrap();
+        queue.push(to_cancel, group, 2).await.unwrap();
+        queue.push(to_cancel, group, 1).await.unwrap();
+        queue.push(liquidation, group, 0).await.unwrap();
+
+        assert_eq!(queue.len().await.unwrap(), 3);
+        assert_eq!(pop(&queue).await, Some(liquidation));
+        assert_eq!(pop(&queue).await, Some(to_cancel));
+        assert_eq!(pop(&queue).await, Some(regular));
+        assert_eq!(pop(&queue).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_claimed_job_is_not_served_twice() {
+        let queue = new_queue().await;
+        let group = Uuid::new_v4();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        queue.push(first, group, 2).await.unwrap();
+        queue.push(second, Uuid::new_v4(), 2).await.unwrap();
+
+        let (a, b, c) = tokio::join!(
+            queue.claim("a", LEASE),
+            queue.claim("b", LEASE),
+            queue.claim("c", LEASE)
+        );
+        let mut claimed: Vec<Uuid> = [a, b, c]
+            .into_iter()
+            .filter_map(|job| job.unwrap().map(|job| job.job_id))
+            .collect();
+        claimed.sort();
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(claimed, expected);
+
+        // Only the lease owner can complete a job
+        assert!(!queue.complete(first, "intruder").await.unwrap());
+        assert_eq!(queue.len().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_is_reclaimed() {
+        let queue = new_queue().await;
+        let job_id = Uuid::new_v4();
+        queue.push(job_id, Uuid::new_v4(), 2).await.unwrap();
+
+        let job = queue
+            .claim("crashed", Duration::from_millis(50))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.attempts, 1);
+        assert!(queue.claim("other", LEASE).await.unwrap().is_none());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let job = queue.claim("other", LEASE).await.unwrap().unwrap();
+        assert_eq!(job.job_id, job_id);
+        assert_eq!(job.attempts, 2);
+        assert!(!queue.complete(job_id, "crashed").await.unwrap());
+        assert!(queue.complete(job_id, "other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_push_while_claimed_runs_again() {
+        let queue = new_queue().await;
+        let job_id = Uuid::new_v4();
+        let group = Uuid::new_v4();
+        queue.push(job_id, group, 2).await.unwrap();
+
+        let job = queue.claim("worker", LEASE).await.unwrap().unwrap();
+        queue.push(job_id, group, 1).await.unwrap();
+        assert!(!queue.complete(job.job_id, "worker").await.unwrap());
+
+        let job = queue.claim("worker", LEASE).await.unwrap().unwrap();
+        assert_eq!(job.job_id, job_id);
+        assert_eq!(job.priority, 1);
+    }
+
+    #[tokio::test]
+    async fn test_release_delays_job() {
+        let queue = new_queue().await;
+        let job_id = Uuid::new_v4();
+        queue.push(job_id, Uuid::new_v4(), 2).await.unwrap();
+
+        queue.claim("worker", LEASE).await.unwrap().unwrap();
+        queue
+            .release(job_id, "worker", 2, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(queue.claim("worker", LEASE).await.unwrap().is_none());
+        assert_eq!(queue.len().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_notifies_listeners() {
+        let queue = new_queue().await;
+        let mut listener = queue.listen().await.unwrap();
+
+        queue.push(Uuid::new_v4(), Uuid::new_v4(), 2).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), listener.wait())
+            .await
+            .expect("no notification received")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_leader_lock_is_exclusive() {
+        let name = format!("leader_test_{}", Uuid::new_v4().simple());
+
+        let mut leader = LeaderLock::try_acquire(&name).await.unwrap().unwrap();
+        assert!(leader.is_held().await);
+        assert!(LeaderLock::try_acquire(&name).await.unwrap().is_none());
+
+        leader.release().await.unwrap();
+        let successor = LeaderLock::try_acquire(&name).await.unwrap();
+        assert!(successor.is_some());
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use database_adapter::db::{DbError, JsonRepo, Repository};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, broadcast::error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::market_data::{MarketDataEvent, MarketDataFeed, TradeTick};
+
+/// Length of a candle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    /// Every interval candles are aggregated at
+    pub const ALL: [Self; 4] = [
+        Self::OneMinute,
+        Self::FiveMinutes,
+        Self::OneHour,
+        Self::OneDay,
+    ];
+
+    /// Parse `1m`, `5m`, `1h` or `1d`
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|interval| interval.as_str() == value)
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::OneMinute => "1m",
+            Self::FiveMinutes => "5m",
+            Self::OneHour => "1h",
+            Self::OneDay => "1d",
+        }
+    }
+
+    #[must_use]
+    pub fn duration(self) -> TimeDelta {
+        match self {
+            Self::OneMinute => TimeDelta::minutes(1),
+            Self::FiveMinutes => TimeDelta::minutes(5),
+            Self::OneHour => TimeDelta::hours(1),
+            Self::OneDay => TimeDelta::days(1),
+        }
+    }
+
+    /// Start of the candle holding `at`. Candles are aligned on UTC midnight.
+    #[must_use]
+    pub fn start_of(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let length = self.duration().num_seconds();
+        let start = at.timestamp().div_euclid(length) * length;
+        DateTime::from_timestamp(start, 0).unwrap_or(at)
+    }
+}
+
+/// Open, high, low and close prices and traded volume of a symbol over an interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Candle {
+    pub symbol: String,
+    pub interval: CandleInterval,
+    /// Start of the interval, inclusive
+    #[serde(with = "candle_start")]
+    pub start: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    /// Number of trades aggregated
+    pub trades: u64,
+}
+
+/// Candle starts are stored with a fixed precision so that ranges can be queried
+/// by comparing them as text
+mod candle_start {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        start: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::storage_key(*start))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        DateTime::<Utc>::deserialize(deserializer)
+    }
+}
+
+fn storage_key(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl Candle {
+    fn from_trade(trade: &TradeTick, interval: CandleInterval) -> Self {
+        Self {
+            symbol: trade.symbol.clone(),
+            interval,
+            start: interval.start_of(trade.timestamp),
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.quantity,
+            trades: 1,
+        }
+    }
+
+    /// Repository ID of a candle
+    #[must_use]
+    pub fn id(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.symbol,
+            self.interval.as_str(),
+            storage_key(self.start)
+        )
+    }
+
+    /// Add a trade of the candle's interval. Trades older than the latest one
+    /// aggregated only move the high, low and volume.
+    fn add(&mut self, trade: &TradeTick, is_latest: bool) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        if is_latest {
+            self.close = trade.price;
+        }
+        self.volume += trade.quantity;
+        self.trades += 1;
+    }
+}
+
+pub type CandleRepo = JsonRepo<Candle, String>;
+
+#[allow(async_fn_in_trait)]
+pub trait CandleRepoExt {
+    /// Candles of a symbol at `interval` starting in `[from, to)`, oldest first
+    async fn get_candles(
+        &self,
+        symbol: &str,
+        interval: CandleInterval,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Candle>, DbError>;
+}
+
+impl CandleRepoExt for CandleRepo {
+    async fn get_candles(
+        &self,
+        symbol: &str,
+        interval: CandleInterval,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Candle>, DbError> {
+        Ok(self
+            .find_range_by_field(
+                "symbol",
+                symbol,
+                "start",
+                &storage_key(interval.start_of(from)),
+                &storage_key(to),
+            )
+            .await?
+            .into_iter()
+            .map(|(_, candle)| candle)
+            .filter(|candle| candle.interval == interval)
+            .collect())
+    }
+}
+
+/// Latest candle of a symbol and interval seen by an aggregator, with the time of its
+/// latest trade
+#[derive(Debug)]
+struct LiveCandle {
+    start: DateTime<Utc>,
+    last_trade: DateTime<Utc>,
+}
+
+/// Aggregates trades into candles at every interval and stores them.
+///
+/// Candles are written as trades arrive, so the current candle of each interval
+/// can be read from the repository before it closes. Each instance aggregates the
+/// trades it executes into the same rows, merging them under a row lock.
+#[derive(Debug, Clone)]
+pub struct CandleAggregator {
+    repo: CandleRepo,
+    live: Arc<Mutex<HashMap<(String, CandleInterval), LiveCandle>>>,
+}
+
+impl CandleAggregator {
+    #[must_use]
+    pub fn new(repo: CandleRepo) -> Self {
+        Self {
+            repo,
+            live: Arc::default(),
+        }
+    }
+
+    /// Add a trade to the candles holding it
+    /// # Errors
+    /// Returns `DbError` if a candle cannot be stored
+    pub async fn record(&self, trade: &TradeTick) -> Result<(), DbError> {
+        let mut live = self.live.lock().await;
+        for interval in CandleInterval::ALL {
+            let start = interval.start_of(trade.timestamp);
+            let key = (trade.symbol.clone(), interval);
+            let is_latest = match live.get(&key) {
+                Some(current) if current.start == start => trade.timestamp >= current.last_trade,
+                // Late trade of a candle that already closed
+                Some(current) if current.start > start => false,
+                _ => true,
+            };
+            if is_latest {
+                live.insert(
+                    key,
+                    LiveCandle {
+                        start,
+                        last_trade: trade.timestamp,
+                    },
+                );
+            }
+            match self.store(trade, interval, is_latest).await {
+                // Another instance created the candle first, merge into it
+                Err(DbError::DuplicateKey(_)) => self.store(trade, interval, is_latest).await?,
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a trade to the stored candle holding it, creating the candle if needed
+    async fn store(
+        &self,
+        trade: &TradeTick,
+        interval: CandleInterval,
+        is_latest: bool,
+    ) -> Result<(), DbError> {
+        let candle = Candle::from_trade(trade, interval);
+        let id = candle.id();
+        let mut tx = self.repo.begin().await?;
+        match tx.get_for_update(&self.repo, &id).await? {
+            Some(mut stored) => {
+                stored.add(trade, is_latest);
+                tx.update(&self.repo, id, stored).await?;
+            }
+            None => tx.insert(&self.repo, id, candle).await?,
+        }
+        tx.commit().await
+    }
+
+    /// Aggregate the trades published by `feed` on a background task
+    #[must_use]
+    pub fn spawn(&self, feed: &MarketDataFeed) -> JoinHandle<()> {
+        let mut receiver = feed.subscribe();
+        let aggregator = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(MarketDataEvent::Trade(trade)) => {
+                        if let Err(e) = aggregator.record(&trade).await {
+                            error!("Failed to store candles of {}: {}", trade.symbol, e);
+                        }
+                    }
+                    Ok(MarketDataEvent::Quote(_)) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Candle aggregation missed {} market data events", missed);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn trade(price: f64, quantity: u64, timestamp: &str) -> TradeTick {
+        TradeTick {
+            symbol: "AAPL".to_string(),
+            price,
+            quantity,
+            timestamp: at(timestamp),
+        }
+    }
+
+    #[test]
+    fn test_candles_are_aligned_on_their_interval() {
+        let time = at("2024-01-02T14:37:42.5Z");
+        assert_eq!(
+            CandleInterval::OneMinute.start_of(time),
+            at("2024-01-02T14:37:00Z")
+        );
+        assert_eq!(
+            CandleInterval::FiveMinutes.start_of(time),
+            at("2024-01-02T14:35:00Z")
+        );
+        assert_eq!(
+            CandleInterval::OneHour.start_of(time),
+            at("2024-01-02T14:00:00Z")
+        );
+        assert_eq!(
+            CandleInterval::OneDay.start_of(time),
+            at("2024-01-02T00:00:00Z")
+        );
+        assert_eq!(
+            CandleInterval::parse("5m"),
+            Some(CandleInterval::FiveMinutes)
+        );
+        assert_eq!(CandleInterval::parse("2m"), None);
+    }
+
+    #[tokio::test]
+    async fn test_trades_are_aggregated_into_stored_candles() {
+        let repo = CandleRepo::new(Arc::new(InMemoryStore::new()), "candles")
+            .await
+            .unwrap();
+        let aggregator = CandleAggregator::new(repo.clone());
+        for trade in [
+            trade(100.0, 10, "2024-01-02T14:30:05Z"),
+            trade(103.0, 5, "2024-01-02T14:30:20Z"),
+            trade(99.0, 1, "2024-01-02T14:30:40Z"),
+            trade(101.0, 2, "2024-01-02T14:31:10Z"),
+            // Late trade of the first minute
+            trade(98.0, 4, "2024-01-02T14:30:50Z"),
+        ] {
+            aggregator.record(&trade).await.unwrap();
+        }
+
+        let minutes = repo
+            .get_candles(
+                "AAPL",
+                CandleInterval::OneMinute,
+                at("2024-01-02T14:30:30Z"),
+                at("2024-01-02T14:32:00Z"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(minutes.len(), 2);
+        let first = &minutes[0];
+        assert_eq!(first.start, at("2024-01-02T14:30:00Z"));
+        assert_eq!(
+            (first.open, first.high, first.low, first.close),
+            (100.0, 103.0, 98.0, 99.0)
+        );
+        assert_eq!((first.volume, first.trades), (20, 4));
+        assert_eq!(minutes[1].open, 101.0);
+
+        let hours = repo
+            .get_candles(
+                "AAPL",
+                CandleInterval::OneHour,
+                at("2024-01-02T00:00:00Z"),
+                at("2024-01-03T00:00:00Z"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].close, hours[0].volume), (101.0, 22));
+    }
+
+    #[tokio::test]
+    async fn test_instances_merge_their_trades_into_the_same_candles() {
+        let repo = CandleRepo::new(Arc::new(InMemoryStore::new()), "candles")
+            .await
+            .unwrap();
+        let first = CandleAggregator::new(repo.clone());
+        let second = CandleAggregator::new(repo.clone());
+        first
+            .record(&trade(100.0, 10, "2024-01-02T14:30:05Z"))
+            .await
+            .unwrap();
+        second
+            .record(&trade(97.0, 5, "2024-01-02T14:30:20Z"))
+            .await
+            .unwrap();
+        first
+            .record(&trade(102.0, 1, "2024-01-02T14:30:40Z"))
+            .await
+            .unwrap();
+
+        let minutes = repo
+            .get_candles(
+                "AAPL",
+                CandleInterval::OneMinute,
+                at("2024-01-02T14:30:00Z"),
+                at("2024-01-02T14:31:00Z"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(minutes.len(), 1);
+        let candle = &minutes[0];
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (100.0, 102.0, 97.0, 102.0)
+        );
+        assert_eq!((candle.volume, candle.trades), (16, 3));
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use in_memory_adapter::TimeSource;
+
+/// Source of the current time for order processing.
+///
+/// The system clock is used in production. A simulated clock only moves when told
+/// to, which lets backtests run historical scenarios as fast as they can.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    simulated: Option<Arc<Mutex<DateTime<Utc>>>>,
+}
+
+impl Clock {
+    #[must_use]
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// Clock frozen at `start` until it is set
+    #[must_use]
+    pub fn simulated(start: DateTime<Utc>) -> Self {
+        Self {
+            simulated: Some(Arc::new(Mutex::new(start))),
+        }
+    }
+
+    #[must_use]
+    pub fn now(&self) -> DateTime<Utc> {
+        match &self.simulated {
+            Some(now) => *now
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+            None => Utc::now(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_simulated(&self) -> bool {
+        self.simulated.is_some()
+    }
+
+    /// Move a simulated clock to `now`. Simulated time never goes backwards and the
+    /// system clock cannot be set, so both cases are ignored.
+    pub fn set(&self, now: DateTime<Utc>) {
+        if let Some(current) = &self.simulated {
+            let mut current = current
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            *current = (*current).max(now);
+        }
+    }
+
+    /// Time source for adapters that schedule work, such as the order queue
+    #[must_use]
+    pub fn time_source(&self) -> TimeSource {
+        let clock = self.clone();
+        Arc::new(move || clock.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_simulated_clock_only_moves_forward() {
+        let start = Utc::now() - TimeDelta::days(365);
+        let clock = Clock::simulated(start);
+        let source = clock.time_source();
+        assert_eq!(clock.now(), start);
+
+        clock.set(start + TimeDelta::minutes(5));
+        assert_eq!(source(), start + TimeDelta::minutes(5));
+        clock.set(start);
+        assert_eq!(clock.now(), start + TimeDelta::minutes(5));
+
+        let system = Clock::system();
+        system.set(start);
+        assert!(system.now() > start);
+    }
+}
@@ -1,17 +1,51 @@
-use database_adapter::db::Repository;
-use mfa_adapter::{EmailConfig, EmailOtpProvider, mfa::MfaService};
-use tracing::info;
+use database_adapter::db::{DbError, Repository, Store};
+use mfa_adapter::{
+    ConfiguredTransport, EmailConfig, EmailOtpProvider, Mailer, MfaError, Notification,
+    OtpChallenge, PasswordResetEmail, RecoveryCodeProvider, SecurityEvent, TotpConfig,
+    TotpEnrollment, TotpProvider, mfa::MfaService,
+};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use chrono::{DateTime, Utc};
 
 use crate::{
-    order::{Order, OrderId, OrderRepo, OrderRepoExt, OrderSide, OrderStatus, OrderType},
+    candles::{Candle, CandleInterval, CandleRepoExt},
+    clock::Clock,
+    events::{DomainEvent, EventBus},
+    instrument::{InstrumentRepo, InstrumentRepoExt},
+    login_guard::{LoginGuard, LoginGuardConfig},
+    market_data::{MarketDataConfig, MarketDataFeed, Quote},
+    mfa_challenge::PostgresChallengeStore,
+    notifications::EmailNotifier,
+    order::{
+        CancelError, Order, OrderId, OrderRepo, OrderRepoExt, OrderSide, OrderStatus, OrderType,
+    },
+    order_book::OrderBook,
     order_processing::ProcessingPool,
-    pre_trade::{PreTradeError, PreTradeValidator},
-    user::{UserId, UserRepo, UserRepoExt},
+    password_reset::{
+        PASSWORD_RESET_TTL, PasswordResetError, PasswordResetRepo, PasswordResetRepoExt,
+    },
+    pre_trade::{PreTradeContext, PreTradeError, PreTradeValidator},
+    scheduling::OrderPriority,
+    session::{SessionRepo, SessionRepoExt},
+    user::{
+        AuthError, MfaMethod, TotpAuthenticator, UserId, UserMfaSecrets, UserRepo, UserRepoExt,
+        validate_password,
+    },
 };
 
 #[derive(Debug)]
 pub struct BrokerX {
-    pub mfa_service: MfaService<EmailOtpProvider>,
+    pub mfa_service:
+        MfaService<EmailOtpProvider<PostgresChallengeStore, ConfiguredTransport, UserMfaSecrets>>,
+    pub totp_service: MfaService<TotpProvider<UserMfaSecrets, PostgresChallengeStore>>,
+    pub recovery_service: MfaService<RecoveryCodeProvider<UserMfaSecrets, PostgresChallengeStore>>,
+    mailer: Mailer,
+    login_guard: LoginGuard,
+    /// Throttles the password reset links asked for
+    reset_guard: LoginGuard,
     pre_trade_validator: PreTradeValidator,
     processing_pool: ProcessingPool,
 }
@@ -23,10 +57,28 @@ impl BrokerX {
 
     pub async fn with_thread_count(num_threads: usize) -> Self {
         let order_processing_pool = ProcessingPool::new(num_threads).await;
+        let mfa_secrets = Self::mfa_secrets(&order_processing_pool).await;
+        let mailer = Mailer::new(&EmailConfig::from_env().expect("Email config creation failed"));
         BrokerX {
-            mfa_service: MfaService::new(EmailOtpProvider::new(
-                EmailConfig::from_env().expect("Email config creation failed"),
+            mfa_service: MfaService::new(
+                EmailOtpProvider::with_mailer(
+                    mailer.clone(),
+                    Self::challenge_store(&order_processing_pool, MfaMethod::Email).await,
+                )
+                .with_recipients(mfa_secrets.clone()),
+            ),
+            totp_service: MfaService::new(TotpProvider::with_challenge_store(
+                mfa_secrets.clone(),
+                TotpConfig::from_env(),
+                Self::challenge_store(&order_processing_pool, MfaMethod::Totp).await,
             )),
+            recovery_service: MfaService::new(RecoveryCodeProvider::with_challenge_store(
+                mfa_secrets,
+                Self::challenge_store(&order_processing_pool, MfaMethod::RecoveryCode).await,
+            )),
+            mailer,
+            login_guard: LoginGuard::new(LoginGuardConfig::from_env()),
+            reset_guard: LoginGuard::new(LoginGuardConfig::password_resets()),
             pre_trade_validator: PreTradeValidator::with_default_config(),
             processing_pool: order_processing_pool,
         }
@@ -40,13 +92,495 @@ impl BrokerX {
 
     /// Create a test-friendly BrokerX instance with specified thread count
     pub async fn new_for_testing_with_thread_count(num_threads: usize) -> Self {
-        let order_processing_pool = ProcessingPool::new_for_testing(num_threads).await;
+        Self::for_testing(ProcessingPool::new_for_testing(num_threads).await).await
+    }
+
+    /// Create another test instance over the tables of this one, as a second replica
+    /// of the application would
+    pub async fn new_for_testing_sharing_tables(&self, num_threads: usize) -> Self {
+        Self::for_testing(
+            self.processing_pool
+                .new_for_testing_sharing_tables(num_threads)
+                .await,
+        )
+        .await
+    }
+
+    /// Create a BrokerX instance that keeps all its data in memory and follows `clock`.
+    /// Orders are only processed when `run_until_idle` is called.
+    /// See `ProcessingPool::in_memory`.
+    pub async fn in_memory(clock: Clock, market_data_config: MarketDataConfig) -> Self {
+        Self::for_testing(ProcessingPool::in_memory(clock, market_data_config).await).await
+    }
+
+    /// Services configured with defaults and a capturing mailer around `processing_pool`
+    async fn for_testing(processing_pool: ProcessingPool) -> Self {
+        let mfa_secrets = Self::mfa_secrets(&processing_pool).await;
+        let mailer = Mailer::new(&EmailConfig::new_test_config());
         BrokerX {
-            mfa_service: MfaService::new(EmailOtpProvider::new_for_testing()),
+            mfa_service: MfaService::new(
+                EmailOtpProvider::with_mailer(
+                    mailer.clone(),
+                    Self::challenge_store(&processing_pool, MfaMethod::Email).await,
+                )
+                .with_recipients(mfa_secrets.clone()),
+            ),
+            totp_service: MfaService::new(TotpProvider::with_challenge_store(
+                mfa_secrets.clone(),
+                TotpConfig::default(),
+                Self::challenge_store(&processing_pool, MfaMethod::Totp).await,
+            )),
+            recovery_service: MfaService::new(RecoveryCodeProvider::with_challenge_store(
+                mfa_secrets,
+                Self::challenge_store(&processing_pool, MfaMethod::RecoveryCode).await,
+            )),
+            mailer,
+            login_guard: LoginGuard::new(LoginGuardConfig::default()),
+            reset_guard: LoginGuard::new(LoginGuardConfig::password_resets()),
             pre_trade_validator: PreTradeValidator::with_default_config(),
-            processing_pool: order_processing_pool,
+            processing_pool,
+        }
+    }
+
+    /// Sends the emails of every service, MFA codes included
+    pub fn mailer(&self) -> &Mailer {
+        &self.mailer
+    }
+
+    /// Emails users about their account. Spawn it on the event bus to send fill and
+    /// deposit notifications.
+    pub async fn notifier(&self) -> EmailNotifier {
+        EmailNotifier::new(self.get_user_repo().await, self.mailer.clone())
+    }
+
+    /// Tell a user about a change to the security of their account
+    pub async fn send_security_alert(&self, user_id: &UserId, event: SecurityEvent) {
+        self.notifier().await.security_alert(user_id, event).await;
+    }
+
+    /// Second factor secrets stored with the users of `pool`
+    async fn mfa_secrets(pool: &ProcessingPool) -> UserMfaSecrets {
+        UserMfaSecrets(pool.shared_state.lock().await.user_repo.clone())
+    }
+
+    /// Challenges of `method`, stored with the other tables of `pool` so that every
+    /// instance sees them
+    async fn challenge_store(pool: &ProcessingPool, method: MfaMethod) -> PostgresChallengeStore {
+        let challenge_repo = pool.shared_state.lock().await.challenge_repo.clone();
+        PostgresChallengeStore::new(challenge_repo, method)
+    }
+
+    /// Open the second-factor challenge of a user who passed the first factor, with
+    /// `method` or, when it is `None`, the method they prefer
+    /// # Errors
+    /// Returns `AuthError` if the user does not exist, did not enroll `method` or the
+    /// challenge cannot be sent
+    pub async fn initiate_mfa(
+        &self,
+        email: &str,
+        method: Option<MfaMethod>,
+    ) -> Result<(MfaMethod, String), AuthError> {
+        let user = self
+            .get_user_repo()
+            .await
+            .get_user_by_email(email)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let method = match method {
+            Some(method) if !user.mfa_methods().contains(&method) => {
+                return Err(AuthError::MfaMethodUnavailable(method));
+            }
+            Some(method) => method,
+            None => user.mfa_method(),
+        };
+        let challenge_id = match method {
+            MfaMethod::Email => self.mfa_service.initiate_mfa(&user.email).await,
+            MfaMethod::Totp => self.totp_service.initiate_mfa(&user.email).await,
+            MfaMethod::RecoveryCode => self.recovery_service.initiate_mfa(&user.email).await,
+        }
+        .map_err(AuthError::MfaFailed)?;
+        Ok((method, challenge_id))
+    }
+
+    /// Answer the challenge of a login with another of the user's methods instead
+    /// # Errors
+    /// Returns `AuthError` if the challenge expired or the user did not enroll `to`
+    pub async fn switch_mfa(
+        &self,
+        method: MfaMethod,
+        challenge_id: &str,
+        to: MfaMethod,
+    ) -> Result<String, AuthError> {
+        let challenge = self
+            .get_mfa_challenge(method, challenge_id)
+            .await
+            .map_err(AuthError::MfaFailed)?;
+        let (_, challenge_id) = self.initiate_mfa(&challenge.user_email, Some(to)).await?;
+        Ok(challenge_id)
+    }
+
+    /// Methods that can answer the login of a challenge, to offer alternatives
+    /// # Errors
+    /// Returns `AuthError` if the challenge expired or its user no longer exists
+    pub async fn mfa_methods_for_challenge(
+        &self,
+        method: MfaMethod,
+        challenge_id: &str,
+    ) -> Result<Vec<MfaMethod>, AuthError> {
+        let challenge = self
+            .get_mfa_challenge(method, challenge_id)
+            .await
+            .map_err(AuthError::MfaFailed)?;
+        let user = self
+            .get_user_repo()
+            .await
+            .get_user_by_email(&challenge.user_email)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        Ok(user.mfa_methods())
+    }
+
+    /// Check the password of a user, unless the account or the client IP failed too
+    /// many logins recently. Failures count towards backoff and lockout.
+    /// # Errors
+    /// Returns `AuthError::TooManyAttempts`, `AuthError::IpRateLimited` or
+    /// `AuthError::AccountLocked` when the login is refused before the password is
+    /// checked, or the errors of `UserRepoExt::authenticate_user`
+    pub async fn authenticate_user(
+        &self,
+        email: &str,
+        password: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<bool, AuthError> {
+        self.login_guard.check(email, ip)?;
+        let result = self
+            .get_user_repo()
+            .await
+            .authenticate_user(email, password)
+            .await;
+        // Unknown emails count too, so that accounts cannot be probed faster
+        if matches!(result, Ok(false) | Err(AuthError::UserNotFound)) {
+            self.record_login_failure(email, ip).await?;
+        }
+        result
+    }
+
+    /// Count a failed login, locking the account once it failed too many in a row
+    async fn record_login_failure(&self, email: &str, ip: Option<IpAddr>) -> Result<(), AuthError> {
+        if !self.login_guard.record_failure(email, ip) {
+            return Ok(());
+        }
+        let user_repo = self.get_user_repo().await;
+        let Some(user_id) = user_repo
+            .get_user_by_email(email)
+            .await?
+            .and_then(|user| user.id)
+        else {
+            return Ok(());
+        };
+        let until = Utc::now()
+            + chrono::Duration::from_std(self.login_guard.config().lockout_duration)
+                .unwrap_or(chrono::Duration::MAX);
+        user_repo.set_locked_until(&user_id, Some(until)).await?;
+        warn!(
+            "Locked user {} until {} after too many failed logins",
+            user_id, until
+        );
+        self.send_security_alert(&user_id, SecurityEvent::AccountLocked { until })
+            .await;
+        Err(AuthError::AccountLocked(until))
+    }
+
+    /// Lift the lock of an account and forget its failed logins
+    /// # Errors
+    /// Returns `AuthError` if the user does not exist
+    pub async fn unlock_user(&self, user_id: &UserId) -> Result<(), AuthError> {
+        let user_repo = self.get_user_repo().await;
+        let user = user_repo
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        user_repo.set_locked_until(user_id, None).await?;
+        self.login_guard.record_success(&user.email);
+        info!("Unlocked user {}", user_id);
+        Ok(())
+    }
+
+    /// Email a single-use link to `reset_url` letting the owner of `email` choose a new
+    /// password. Unknown emails are ignored, and a failed email is only logged, so that
+    /// the answer does not tell which accounts exist. Requests for an email or from an
+    /// IP are throttled, whether the account exists or not.
+    /// # Errors
+    /// Returns `AuthError::TooManyAttempts` or `AuthError::IpRateLimited` while the
+    /// email or IP asked for too many links, or `AuthError::UserRepo` if the reset
+    /// cannot be stored
+    pub async fn request_password_reset(
+        &self,
+        email: &str,
+        reset_url: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), AuthError> {
+        self.reset_guard.check(email, ip)?;
+        self.reset_guard.record_failure(email, ip);
+        let Some(user) = self.get_user_repo().await.get_user_by_email(email).await? else {
+            info!("Password reset asked for unknown email {}", email);
+            return Ok(());
+        };
+        let Some(user_id) = user.id else {
+            return Ok(());
+        };
+        let token = self
+            .get_password_reset_repo()
+            .await
+            .issue_reset(user_id, PASSWORD_RESET_TTL)
+            .await
+            .map_err(AuthError::UserRepo)?;
+        let notification = Notification::PasswordReset(PasswordResetEmail {
+            link: format!("{reset_url}?token={token}"),
+            valid_minutes: PASSWORD_RESET_TTL.num_minutes().unsigned_abs(),
+        });
+        if let Err(e) = self
+            .mailer
+            .send(&user.email, user.locale, &notification)
+            .await
+        {
+            warn!(
+                "Could not email a password reset to user {}: {}",
+                user_id, e
+            );
+        }
+        Ok(())
+    }
+
+    /// Set the password of the user who received `token`, then log them out of every
+    /// device and lift the lock of their account. The token is only used once the new
+    /// password is accepted.
+    /// # Errors
+    /// Returns `PasswordResetError` if the token is invalid, expired or used, or
+    /// `PasswordResetError::Auth` with `AuthError::WeakPassword` if the password is refused
+    pub async fn reset_password(
+        &self,
+        token: &str,
+        new_password: &str,
+    ) -> Result<UserId, PasswordResetError> {
+        let reset_repo = self.get_password_reset_repo().await;
+        reset_repo.pending_reset(token).await?;
+        validate_password(new_password)?;
+
+        let user_id = reset_repo.use_reset(token).await?;
+        // Only the password, the account may be trading meanwhile
+        self.get_user_repo()
+            .await
+            .set_password(&user_id, new_password)
+            .await?;
+        // Whoever knew the old password must not stay logged in
+        self.get_session_repo()
+            .await
+            .revoke_all_sessions(&user_id)
+            .await?;
+        self.unlock_user(&user_id).await?;
+        info!("Reset the password of user {}", user_id);
+        self.send_security_alert(&user_id, SecurityEvent::PasswordChanged)
+            .await;
+        Ok(user_id)
+    }
+
+    /// Check the code answering a challenge opened by `initiate_mfa`. A recovery code
+    /// is burned once it is accepted. Wrong codes count as failed logins.
+    /// # Errors
+    /// Returns `AuthError::MfaFailed` if the challenge does not exist, expired or the
+    /// code is wrong, and `AuthError::AccountLocked` if the account is locked
+    pub async fn verify_mfa(
+        &self,
+        method: MfaMethod,
+        challenge_id: &str,
+        code: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<bool, AuthError> {
+        let challenge = self
+            .get_mfa_challenge(method, challenge_id)
+            .await
+            .map_err(AuthError::MfaFailed)?;
+        match self.check_mfa_code(method, challenge_id, code).await {
+            Ok(true) => {
+                // The account may have been locked since its password was checked
+                let user = self
+                    .get_user_repo()
+                    .await
+                    .get_user_by_email(&challenge.user_email)
+                    .await?
+                    .ok_or(AuthError::UserNotFound)?;
+                if let Some(until) = user.locked_at(Utc::now()) {
+                    return Err(AuthError::AccountLocked(until));
+                }
+                self.login_guard.record_success(&challenge.user_email);
+                Ok(true)
+            }
+            Ok(false) => Ok(false),
+            Err(e @ (MfaError::InvalidCode | MfaError::TooManyAttempts)) => {
+                self.record_login_failure(&challenge.user_email, ip).await?;
+                Err(AuthError::MfaFailed(e))
+            }
+            Err(e) => Err(AuthError::MfaFailed(e)),
+        }
+    }
+
+    async fn check_mfa_code(
+        &self,
+        method: MfaMethod,
+        challenge_id: &str,
+        code: &str,
+    ) -> Result<bool, MfaError> {
+        match method {
+            MfaMethod::Email => self.mfa_service.verify_mfa(challenge_id, code).await,
+            MfaMethod::Totp => self.totp_service.verify_mfa(challenge_id, code).await,
+            MfaMethod::RecoveryCode => {
+                self.recovery_service.verify_mfa(challenge_id, code).await?;
+                let challenge = self.recovery_service.get_challenge(challenge_id).await?;
+                let code_hash = self
+                    .recovery_service
+                    .provider()
+                    .used_code(challenge_id)
+                    .await?;
+                // Another login may have used the same code since the challenge opened
+                match self
+                    .get_user_repo()
+                    .await
+                    .burn_recovery_code(&challenge.user_email, &code_hash)
+                    .await
+                {
+                    Ok(true) => Ok(true),
+                    Ok(false) => Err(MfaError::InvalidCode),
+                    Err(e) => {
+                        warn!("Could not burn a recovery code: {}", e);
+                        Err(MfaError::ServiceUnavailable)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Challenge opened by `initiate_mfa`
+    /// # Errors
+    /// Returns `MfaError` if the challenge does not exist or expired
+    pub async fn get_mfa_challenge(
+        &self,
+        method: MfaMethod,
+        challenge_id: &str,
+    ) -> Result<OtpChallenge, MfaError> {
+        match method {
+            MfaMethod::Email => self.mfa_service.get_challenge(challenge_id).await,
+            MfaMethod::Totp => self.totp_service.get_challenge(challenge_id).await,
+            MfaMethod::RecoveryCode => self.recovery_service.get_challenge(challenge_id).await,
+        }
+    }
+
+    /// Start enrolling an authenticator app, or resume an enrollment that was not
+    /// confirmed. The secret is only used to log in once `confirm_totp` succeeds.
+    /// # Errors
+    /// Returns `AuthError` if the user does not exist or already enrolled an app
+    pub async fn enroll_totp(&self, user_id: &UserId) -> Result<TotpEnrollment, AuthError> {
+        let user_repo = self.get_user_repo().await;
+        let user = user_repo
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let secret = match user.totp {
+            Some(totp) if totp.confirmed_at.is_some() => {
+                return Err(AuthError::TotpAlreadyEnrolled);
+            }
+            Some(totp) => totp.secret,
+            None => {
+                let secret = TotpProvider::<UserMfaSecrets>::generate_secret();
+                let pending = TotpAuthenticator {
+                    secret: secret.clone(),
+                    confirmed_at: None,
+                };
+                user_repo.set_totp(user_id, Some(pending)).await?;
+                secret
+            }
+        };
+        self.totp_service
+            .provider()
+            .enrollment(&user.email, &secret)
+            .map_err(AuthError::MfaFailed)
+    }
+
+    /// Confirm the enrollment started by `enroll_totp` with a code from the app
+    /// # Errors
+    /// Returns `AuthError` if no enrollment is pending or the code is wrong
+    pub async fn confirm_totp(&self, user_id: &UserId, code: &str) -> Result<(), AuthError> {
+        let user_repo = self.get_user_repo().await;
+        let user = user_repo
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let Some(mut totp) = user.totp else {
+            return Err(AuthError::MfaFailed(MfaError::NotEnrolled));
+        };
+        if totp.confirmed_at.is_some() {
+            return Err(AuthError::TotpAlreadyEnrolled);
+        }
+        self.check_totp_code(&user.email, &totp.secret, code)
+            .await?;
+        totp.confirmed_at = Some(chrono::Utc::now());
+        user_repo.set_totp(user_id, Some(totp)).await?;
+        info!("User {} enrolled an authenticator app", user_id);
+        self.send_security_alert(user_id, SecurityEvent::TotpEnabled)
+            .await;
+        Ok(())
+    }
+
+    /// Remove the authenticator app of a user, who goes back to email codes. Needs a
+    /// current code from the app.
+    /// # Errors
+    /// Returns `AuthError` if no app is enrolled or the code is wrong
+    pub async fn disable_totp(&self, user_id: &UserId, code: &str) -> Result<(), AuthError> {
+        let user_repo = self.get_user_repo().await;
+        let user = user_repo
+            .get_user_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let secret = user
+            .totp_secret()
+            .ok_or(AuthError::MfaFailed(MfaError::NotEnrolled))?;
+        self.check_totp_code(&user.email, secret, code).await?;
+        user_repo.set_totp(user_id, None).await?;
+        info!("User {} removed their authenticator app", user_id);
+        self.send_security_alert(user_id, SecurityEvent::TotpDisabled)
+            .await;
+        Ok(())
+    }
+
+    async fn check_totp_code(
+        &self,
+        email: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<(), AuthError> {
+        match self
+            .totp_service
+            .provider()
+            .verify_code(email, secret, code)
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(AuthError::MfaFailed(MfaError::InvalidCode)),
+            Err(e) => Err(AuthError::MfaFailed(e)),
         }
     }
+
+    /// Process every order that is due in the calling task.
+    /// Returns the number of processing steps that ran.
+    pub async fn run_until_idle(&self) -> usize {
+        self.processing_pool.run_until_idle().await
+    }
+
+    /// Clock used to date orders and to decide when they can trade
+    #[must_use]
+    pub fn clock(&self) -> &Clock {
+        &self.processing_pool.clock
+    }
     #[must_use]
     pub async fn get_user_repo(&self) -> UserRepo {
         self.processing_pool
@@ -65,6 +599,192 @@ impl BrokerX {
             .order_repo
             .clone()
     }
+    #[must_use]
+    pub async fn get_session_repo(&self) -> SessionRepo {
+        self.processing_pool
+            .shared_state
+            .lock()
+            .await
+            .session_repo
+            .clone()
+    }
+    #[must_use]
+    pub async fn get_password_reset_repo(&self) -> PasswordResetRepo {
+        self.processing_pool
+            .shared_state
+            .lock()
+            .await
+            .password_reset_repo
+            .clone()
+    }
+    #[must_use]
+    pub async fn get_instrument_repo(&self) -> InstrumentRepo {
+        self.processing_pool
+            .shared_state
+            .lock()
+            .await
+            .instrument_repo
+            .clone()
+    }
+    /// Store holding the broker's tables, in the database or in memory
+    #[must_use]
+    pub fn store(&self) -> Arc<dyn Store> {
+        self.processing_pool.store()
+    }
+    /// Bus on which order, fill and account events are published
+    #[must_use]
+    pub fn events(&self) -> &EventBus {
+        &self.processing_pool.event_bus
+    }
+
+    /// Feed providing the quotes used for pre-trade checks, fills and valuation
+    #[must_use]
+    pub fn market_data(&self) -> &MarketDataFeed {
+        &self.processing_pool.market_data
+    }
+
+    /// Current price of an instrument, used to value holdings.
+    /// Returns `None` if the instrument is not listed.
+    pub async fn mark_price(&self, symbol: &str) -> Option<f64> {
+        let instrument = self
+            .get_instrument_repo()
+            .await
+            .get_instrument(symbol)
+            .await
+            .ok()??;
+        Some(self.market_data().quote(&instrument).last)
+    }
+
+    /// Current quote of an instrument.
+    /// Returns `None` if the instrument is not listed.
+    /// # Errors
+    /// Returns `DbError` if the instrument master cannot be read
+    pub async fn quote(&self, symbol: &str) -> Result<Option<Quote>, DbError> {
+        let instrument = self
+            .get_instrument_repo()
+            .await
+            .get_instrument(symbol)
+            .await?;
+        Ok(instrument.map(|instrument| self.market_data().quote(&instrument)))
+    }
+
+    /// Candles of an instrument at `interval` starting in `[from, to)`, oldest first.
+    /// Returns `None` if the instrument is not listed.
+    /// # Errors
+    /// Returns `DbError` if the instrument master or the candles cannot be read
+    pub async fn candles(
+        &self,
+        symbol: &str,
+        interval: CandleInterval,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Option<Vec<Candle>>, DbError> {
+        let state = self.processing_pool.shared_state.lock().await;
+        if state
+            .instrument_repo
+            .get_instrument(symbol)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        let candles = state
+            .candle_repo
+            .get_candles(symbol, interval, from, to)
+            .await?;
+        Ok(Some(candles))
+    }
+
+    /// Limit orders resting on an instrument, aggregated into at most `depth` price
+    /// levels per side. Returns `None` if the instrument is not listed.
+    /// # Errors
+    /// Returns `DbError` if the instrument master or the orders cannot be read
+    pub async fn order_book(
+        &self,
+        symbol: &str,
+        depth: usize,
+    ) -> Result<Option<OrderBook>, DbError> {
+        let state = self.processing_pool.shared_state.lock().await;
+        if state
+            .instrument_repo
+            .get_instrument(symbol)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        let orders = state.order_repo.get_orders_for_symbol(symbol).await?;
+        Ok(Some(OrderBook::from_orders(
+            symbol,
+            orders.iter().map(|(_, order)| order),
+            depth,
+            self.clock().now(),
+        )))
+    }
+
+    /// Deposit money into a user's account
+    /// # Errors
+    /// Returns `AuthError` if the user does not exist or the repository fails
+    pub async fn deposit(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError> {
+        let state = self.processing_pool.shared_state.lock().await;
+        let mut tx = state.user_repo.begin().await.map_err(AuthError::UserRepo)?;
+        let mut user = tx
+            .get_for_update(&state.user_repo, user_id)
+            .await
+            .map_err(AuthError::UserRepo)?
+            .ok_or(AuthError::UserNotFound)?;
+        user.deposit(amount);
+        let balance = user.get_balance();
+
+        tx.update(&state.user_repo, *user_id, user)
+            .await
+            .map_err(AuthError::UserRepo)?;
+        self.events()
+            .commit(
+                tx,
+                vec![DomainEvent::FundsDeposited {
+                    user_id: *user_id,
+                    amount,
+                    balance,
+                }],
+            )
+            .await
+            .map_err(AuthError::UserRepo)?;
+        Ok(balance)
+    }
+
+    /// Withdraw money from a user's account
+    /// # Errors
+    /// Returns `AuthError` if the user does not exist, lacks funds or the repository fails
+    pub async fn withdraw(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError> {
+        let state = self.processing_pool.shared_state.lock().await;
+        let mut tx = state.user_repo.begin().await.map_err(AuthError::UserRepo)?;
+        let mut user = tx
+            .get_for_update(&state.user_repo, user_id)
+            .await
+            .map_err(AuthError::UserRepo)?
+            .ok_or(AuthError::UserNotFound)?;
+        user.withdraw(amount)
+            .map_err(|_e| AuthError::NotEnoughMoneyError)?;
+        let balance = user.get_balance();
+
+        tx.update(&state.user_repo, *user_id, user)
+            .await
+            .map_err(AuthError::UserRepo)?;
+        self.events()
+            .commit(
+                tx,
+                vec![DomainEvent::FundsWithdrawn {
+                    user_id: *user_id,
+                    amount,
+                    balance,
+                }],
+            )
+            .await
+            .map_err(AuthError::UserRepo)?;
+        Ok(balance)
+    }
+
     pub async fn start_order_processing(&self) {
         self.processing_pool.start().await;
     }
@@ -97,27 +817,41 @@ impl BrokerX {
         order_side: OrderSide,
         order_type: OrderType,
     ) -> Result<OrderId, PreTradeError> {
-        // Get user balance for pre-trade checks
-        let user_balance = {
+        // Get user balance and instrument reference data for pre-trade checks
+        let (user_balance, instrument) = {
             let state = self.processing_pool.shared_state.lock().await;
-            match state.user_repo.get(&client_id).await {
+            let user_balance = match state.user_repo.get(&client_id).await {
                 Ok(Some(user)) => user.balance,
                 Ok(None) => 0.0,
                 Err(_) => 0.0,
-            }
+            };
+            let instrument = state
+                .instrument_repo
+                .get_instrument(&symbol)
+                .await
+                .map_err(PreTradeError::DbError)?
+                .ok_or_else(|| PreTradeError::UnknownInstrument {
+                    symbol: symbol.clone(),
+                })?;
+            (user_balance, instrument)
         };
 
-        // Pre-trade validation
+        // Pre-trade validation against the current quote
+        let context = PreTradeContext {
+            user_balance,
+            market_price: self.market_data().quote(&instrument).price_for(&order_side),
+            at: self.clock().now(),
+        };
         self.pre_trade_validator.validate_order(
+            &instrument,
             &order_side,
             &order_type,
-            &symbol,
             quantity,
-            user_balance,
+            &context,
         )?;
 
         // Create order after validation passes
-        let date = chrono::Utc::now();
+        let date = self.clock().now();
         let order = Order {
             client_id,
             date,
@@ -126,22 +860,193 @@ impl BrokerX {
             order_side,
             order_type,
             status: OrderStatus::Queued,
+            is_administrative: false,
         };
 
-        // Create order in the thread pool's repository
-        let order_id = {
+        let order_id = self
+            .enqueue_order(order)
+            .await
+            .map_err(PreTradeError::DbError)?;
+
+        info!("Pre-trade checks validated for {order_id}");
+
+        Ok(order_id)
+    }
+
+    /// Creates an administrative order, such as a liquidation, on behalf of a client.
+    /// Administrative orders skip pre-trade checks and are processed ahead of client orders.
+    /// # Errors
+    /// Returns `DbError` if the order could not be stored.
+    pub async fn create_administrative_order(
+        &self,
+        client_id: UserId,
+        symbol: String,
+        quantity: u64,
+        order_side: OrderSide,
+        order_type: OrderType,
+    ) -> Result<OrderId, database_adapter::db::DbError> {
+        let order = Order {
+            client_id,
+            date: self.clock().now(),
+            symbol,
+            quantity,
+            order_side,
+            order_type,
+            status: OrderStatus::Queued,
+            is_administrative: true,
+        };
+
+        let order_id = self.enqueue_order(order).await?;
+        info!("Administrative order {order_id} created for {client_id}");
+
+        Ok(order_id)
+    }
+
+    /// Requests the cancellation of an order. The order is moved to `PendingCancel`
+    /// and scheduled ahead of new orders.
+    /// # Errors
+    /// Returns `CancelError` if the order does not exist or is already final.
+    pub async fn cancel_order(&self, order_id: OrderId) -> Result<Order, CancelError> {
+        let order = {
             let state = self.processing_pool.shared_state.lock().await;
-            state
+            // Locked so a worker filling the order on another instance is waited for
+            let mut tx = state
                 .order_repo
-                .create_order(order)
+                .begin()
                 .await
-                .map_err(PreTradeError::DbError)?
+                .map_err(CancelError::DbError)?;
+            let mut order = tx
+                .get_for_update(&state.order_repo, &order_id)
+                .await
+                .map_err(CancelError::DbError)?
+                .ok_or(CancelError::OrderNotFound)?;
+
+            match order.status {
+                OrderStatus::Queued | OrderStatus::Pending => {}
+                OrderStatus::PendingCancel => return Ok(order),
+                _ => return Err(CancelError::NotCancellable),
+            }
+
+            order.status = OrderStatus::PendingCancel;
+            tx.update(&state.order_repo, order_id, order.clone())
+                .await
+                .map_err(CancelError::DbError)?;
+            self.events()
+                .commit(
+                    tx,
+                    vec![DomainEvent::OrderStatusChanged {
+                        order_id,
+                        client_id: order.client_id,
+                        status: order.status.clone(),
+                    }],
+                )
+                .await
+                .map_err(CancelError::DbError)?;
+            order
         };
 
-        info!("Pre-trade checks validated for {order_id}");
+        self.processing_pool
+            .submit_order(order_id, order.client_id, OrderPriority::Cancel)
+            .await
+            .map_err(CancelError::DbError)?;
+
+        Ok(order)
+    }
+
+    /// Overwrite the status of an order, bypassing its lifecycle. The order is locked
+    /// like for a fill, the change is published, and an order put back in the
+    /// lifecycle is queued to be processed. Returns `None` if the order does not exist.
+    /// # Errors
+    /// Returns `DbError` if the order could not be updated or queued.
+    pub async fn set_order_status(
+        &self,
+        order_id: OrderId,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, database_adapter::db::DbError> {
+        let order = {
+            let state = self.processing_pool.shared_state.lock().await;
+            let mut tx = state.order_repo.begin().await?;
+            let Some(mut order) = tx.get_for_update(&state.order_repo, &order_id).await? else {
+                return Ok(None);
+            };
+            order.status = status;
+            tx.update(&state.order_repo, order_id, order.clone())
+                .await?;
+            self.events()
+                .commit(
+                    tx,
+                    vec![DomainEvent::OrderStatusChanged {
+                        order_id,
+                        client_id: order.client_id,
+                        status: order.status.clone(),
+                    }],
+                )
+                .await?;
+            order
+        };
+
+        if matches!(
+            order.status,
+            OrderStatus::Queued | OrderStatus::Pending | OrderStatus::PendingCancel
+        ) {
+            self.processing_pool
+                .submit_order(order_id, order.client_id, OrderPriority::for_order(&order))
+                .await?;
+        }
+        Ok(Some(order))
+    }
+
+    /// Sets how many consecutive orders a client may have processed per scheduling round.
+    /// Clients default to a weight of 1.
+    /// # Errors
+    /// Returns `DbError` if the weight could not be stored.
+    pub async fn set_client_weight(
+        &self,
+        client_id: UserId,
+        weight: u32,
+    ) -> Result<(), database_adapter::db::DbError> {
+        self.processing_pool
+            .set_client_weight(client_id, weight)
+            .await
+    }
+
+    /// Expires orders that have been waiting for execution for longer than `max_age`.
+    /// Returns the number of expired orders.
+    /// # Errors
+    /// Returns `DbError` if the orders could not be loaded or updated.
+    pub async fn expire_orders_older_than(
+        &self,
+        max_age: chrono::TimeDelta,
+    ) -> Result<usize, database_adapter::db::DbError> {
+        self.processing_pool.expire_orders_older_than(max_age).await
+    }
+
+    /// Stores a new order and submits it to the processing pool
+    async fn enqueue_order(&self, order: Order) -> Result<OrderId, database_adapter::db::DbError> {
+        let order_id = OrderId::new_v4();
+        let client_id = order.client_id;
+        let priority = OrderPriority::for_order(&order);
+        let created = DomainEvent::OrderCreated {
+            order_id,
+            client_id,
+            symbol: order.symbol.clone(),
+            quantity: order.quantity,
+            order_side: order.order_side.clone(),
+            order_type: order.order_type.clone(),
+        };
+
+        // Create order in the thread pool's repository
+        {
+            let state = self.processing_pool.shared_state.lock().await;
+            self.events()
+                .commit_insert(&state.order_repo, order_id, order, vec![created])
+                .await?;
+        }
 
         // Submit to processing pool
-        self.processing_pool.submit_order(order_id).await;
+        self.processing_pool
+            .submit_order(order_id, client_id, priority)
+            .await?;
 
         Ok(order_id)
     }
@@ -187,3 +1092,309 @@ impl Drop for BrokerX {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login_guard::LoginGuardConfig;
+    use crate::market_data::MarketDataConfig;
+
+    async fn broker_with_user(email: &str) -> (BrokerX, UserId) {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                email.to_string(),
+                "aaaaaa".to_string(),
+                "Locked".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        user_repo.verify_user_email(&user_id).await.unwrap();
+        (broker, user_id)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_email_codes_are_sent_through_the_mailer() {
+        let (broker, _) = broker_with_user("mailed@test.com").await;
+        let (method, challenge_id) = broker
+            .initiate_mfa("mailed@test.com", Some(MfaMethod::Email))
+            .await
+            .unwrap();
+
+        let email = broker
+            .mailer()
+            .transport()
+            .captured()
+            .unwrap()
+            .last_sent_to("mailed@test.com")
+            .unwrap();
+        let code = email
+            .html_body
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|word| word.len() == 6)
+            .unwrap();
+        assert!(
+            broker
+                .verify_mfa(method, &challenge_id, code, None)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_registration_code_is_sent_in_the_user_locale() {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                "nouveau@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Nouvel".to_string(),
+                "Utilisateur".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        user_repo
+            .set_locale(&user_id, crate::user::Locale::Fr)
+            .await
+            .unwrap();
+        broker
+            .mfa_service
+            .initiate_mfa("nouveau@test.com")
+            .await
+            .unwrap();
+
+        let email = broker
+            .mailer()
+            .transport()
+            .captured()
+            .unwrap()
+            .last_sent_to("nouveau@test.com")
+            .unwrap();
+        assert_eq!(email.subject, "BrokerX - Confirmez votre adresse email");
+    }
+
+    /// Token of the last reset link emailed to `email`
+    fn emailed_reset_token(broker: &BrokerX, email: &str) -> String {
+        let email = broker
+            .mailer()
+            .transport()
+            .captured()
+            .unwrap()
+            .last_sent_to(email)
+            .unwrap();
+        let (_, token) = email.text_body.split_once("?token=").unwrap();
+        token.split_whitespace().next().unwrap().to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_password_reset_by_email() {
+        let (broker, user_id) = broker_with_user("forgetful@test.com").await;
+        let (session_id, _) = broker
+            .get_session_repo()
+            .await
+            .open_session(user_id, None, chrono::TimeDelta::days(1))
+            .await
+            .unwrap();
+        broker
+            .request_password_reset(
+                "forgetful@test.com",
+                "http://brokerx.test/reset-password",
+                None,
+            )
+            .await
+            .unwrap();
+        let token = emailed_reset_token(&broker, "forgetful@test.com");
+
+        assert!(matches!(
+            broker.reset_password(&token, "abc").await,
+            Err(PasswordResetError::Auth(AuthError::WeakPassword))
+        ));
+        // A refused password does not use the token
+        assert_eq!(
+            broker.reset_password(&token, "n3w-passw0rd").await.unwrap(),
+            user_id
+        );
+        assert!(matches!(
+            broker.reset_password(&token, "an0ther-one").await,
+            Err(PasswordResetError::Used)
+        ));
+
+        let user_repo = broker.get_user_repo().await;
+        assert!(
+            user_repo
+                .authenticate_user("forgetful@test.com", "n3w-passw0rd")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !broker
+                .get_session_repo()
+                .await
+                .is_session_active(&session_id)
+                .await
+                .unwrap()
+        );
+        let alert = broker
+            .mailer()
+            .transport()
+            .captured()
+            .unwrap()
+            .last_sent_to("forgetful@test.com")
+            .unwrap();
+        assert_eq!(alert.subject, "BrokerX - Security alert");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_password_reset_of_unknown_email_sends_nothing() {
+        let (broker, _) = broker_with_user("known@test.com").await;
+        broker
+            .request_password_reset(
+                "unknown@test.com",
+                "http://brokerx.test/reset-password",
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(
+            broker
+                .mailer()
+                .transport()
+                .captured()
+                .unwrap()
+                .sent()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_password_reset_requests_are_throttled() {
+        let (broker, _) = broker_with_user("flooded@test.com").await;
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+        for _ in 0..3 {
+            broker
+                .request_password_reset("flooded@test.com", "http://brokerx.test/reset", ip)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            broker
+                .request_password_reset("flooded@test.com", "http://brokerx.test/reset", None)
+                .await,
+            Err(AuthError::TooManyAttempts(_))
+        ));
+        // Unknown emails count too, so they cannot be told apart
+        for _ in 0..3 {
+            broker
+                .request_password_reset("nobody@test.com", "http://brokerx.test/reset", None)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            broker
+                .request_password_reset("nobody@test.com", "http://brokerx.test/reset", None)
+                .await,
+            Err(AuthError::TooManyAttempts(_))
+        ));
+        let sent = broker.mailer().transport().captured().unwrap().sent();
+        assert_eq!(sent.len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reset_token_is_used_once_when_replayed() {
+        let (broker, user_id) = broker_with_user("replayed@test.com").await;
+        broker
+            .request_password_reset("replayed@test.com", "http://brokerx.test/reset", None)
+            .await
+            .unwrap();
+        let token = emailed_reset_token(&broker, "replayed@test.com");
+
+        let (first, second) = tokio::join!(
+            broker.reset_password(&token, "f1rst-passw0rd"),
+            broker.reset_password(&token, "s3cond-passw0rd")
+        );
+        let used: Vec<_> = [first, second].into_iter().filter_map(Result::ok).collect();
+        assert_eq!(used, vec![user_id]);
+    }
+
+    // BrokerX blocks on its processing pool when dropped
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_logins_back_off_then_lock_the_account() {
+        let (broker, user_id) = broker_with_user("locked@test.com").await;
+        let config = LoginGuardConfig::default();
+
+        for _ in 1..config.account_free_failures {
+            assert!(
+                !broker
+                    .authenticate_user("locked@test.com", "wrong", None)
+                    .await
+                    .unwrap()
+            );
+        }
+        assert!(
+            !broker
+                .authenticate_user("locked@test.com", "wrong", None)
+                .await
+                .unwrap()
+        );
+        // Even the right password waits for the backoff
+        assert!(matches!(
+            broker
+                .authenticate_user("locked@test.com", "aaaaaa", None)
+                .await,
+            Err(AuthError::TooManyAttempts(_))
+        ));
+
+        // Wrong second factors count towards the lock, and are not slowed down since
+        // each challenge caps its own attempts
+        let codes = broker
+            .get_user_repo()
+            .await
+            .regenerate_recovery_codes(&user_id)
+            .await
+            .unwrap();
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+        for _ in config.account_free_failures..config.lockout_failures - 1 {
+            let (method, challenge_id) = broker
+                .initiate_mfa("locked@test.com", Some(MfaMethod::RecoveryCode))
+                .await
+                .unwrap();
+            assert!(matches!(
+                broker.verify_mfa(method, &challenge_id, "wrong", ip).await,
+                Err(AuthError::MfaFailed(MfaError::InvalidCode))
+            ));
+        }
+        let (method, challenge_id) = broker
+            .initiate_mfa("locked@test.com", Some(MfaMethod::RecoveryCode))
+            .await
+            .unwrap();
+        assert!(matches!(
+            broker.verify_mfa(method, &challenge_id, "wrong", ip).await,
+            Err(AuthError::AccountLocked(_))
+        ));
+        assert!(matches!(
+            broker
+                .verify_mfa(method, &challenge_id, &codes[0], ip)
+                .await,
+            Err(AuthError::AccountLocked(_))
+        ));
+        assert!(matches!(
+            broker
+                .authenticate_user("locked@test.com", "aaaaaa", None)
+                .await,
+            Err(AuthError::AccountLocked(_))
+        ));
+
+        broker.unlock_user(&user_id).await.unwrap();
+        assert!(
+            broker
+                .authenticate_user("locked@test.com", "aaaaaa", None)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,649 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use database_adapter::db::{DbError, JsonRepo, Transaction};
+use database_adapter::outbox::OutboxMessage;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::order::{OrderId, OrderSide, OrderStatus, OrderType};
+use crate::user::UserId;
+
+pub use database_adapter::outbox::{OutboxFilter, OutboxRecord, PostgresOutbox};
+
+/// Number of events an in-process subscriber may lag behind before missing some
+const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+/// Number of recent events kept for subscribers resuming after a disconnect
+const DEFAULT_HISTORY_CAPACITY: usize = 4096;
+/// Number of outbox records read at once by the feed
+const FEED_BATCH_SIZE: i64 = 256;
+/// How often the feed reads the outbox without being notified of appends
+const FEED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the feed waits for a missing sequence, held by a transaction that has not
+/// committed yet, before skipping it as rolled back
+const FEED_GAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Delay before following the outbox again after an error
+const FEED_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Something that happened in the domain that other components may react to
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum DomainEvent {
+    /// An order passed pre-trade checks and was stored
+    OrderCreated {
+        #[schema(value_type = String, format = Uuid)]
+        order_id: OrderId,
+        #[schema(value_type = String, format = Uuid)]
+        client_id: UserId,
+        symbol: String,
+        quantity: u64,
+        order_side: OrderSide,
+        order_type: OrderType,
+    },
+    /// An order moved to a new status
+    OrderStatusChanged {
+        #[schema(value_type = String, format = Uuid)]
+        order_id: OrderId,
+        #[schema(value_type = String, format = Uuid)]
+        client_id: UserId,
+        status: OrderStatus,
+    },
+    /// An order was executed
+    OrderFilled {
+        #[schema(value_type = String, format = Uuid)]
+        order_id: OrderId,
+        #[schema(value_type = String, format = Uuid)]
+        client_id: UserId,
+        symbol: String,
+        quantity: u64,
+        order_side: OrderSide,
+        price: f64,
+    },
+    /// Money was added to an account
+    FundsDeposited {
+        #[schema(value_type = String, format = Uuid)]
+        user_id: UserId,
+        amount: f64,
+        balance: f64,
+    },
+    /// Money was taken from an account
+    FundsWithdrawn {
+        #[schema(value_type = String, format = Uuid)]
+        user_id: UserId,
+        amount: f64,
+        balance: f64,
+    },
+}
+
+impl DomainEvent {
+    /// User the event relates to
+    #[must_use]
+    pub fn user_id(&self) -> UserId {
+        match self {
+            DomainEvent::OrderCreated { client_id, .. }
+            | DomainEvent::OrderStatusChanged { client_id, .. }
+            | DomainEvent::OrderFilled { client_id, .. } => *client_id,
+            DomainEvent::FundsDeposited { user_id, .. }
+            | DomainEvent::FundsWithdrawn { user_id, .. } => *user_id,
+        }
+    }
+
+    /// Name of the event, used as the outbox topic
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DomainEvent::OrderCreated { .. } => "OrderCreated",
+            DomainEvent::OrderStatusChanged { .. } => "OrderStatusChanged",
+            DomainEvent::OrderFilled { .. } => "OrderFilled",
+            DomainEvent::FundsDeposited { .. } => "FundsDeposited",
+            DomainEvent::FundsWithdrawn { .. } => "FundsWithdrawn",
+        }
+    }
+}
+
+/// A published event along with its delivery metadata
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EventEnvelope {
+    #[schema(value_type = String, format = Uuid)]
+    pub id: Uuid,
+    /// Publication order of the event: its outbox sequence, shared by every instance,
+    /// when the bus has an outbox, else its order within this process
+    pub sequence: u64,
+    pub occurred_at: DateTime<Utc>,
+    pub event: DomainEvent,
+}
+
+/// Event delivery errors
+#[derive(Debug)]
+pub enum EventError {
+    Delivery(String),
+    DbError(DbError),
+}
+
+impl std::fmt::Display for EventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventError::Delivery(msg) => write!(f, "Event delivery failed: {msg}"),
+            EventError::DbError(db_error) => write!(f, "Database error: {db_error}"),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+/// Events published after a given sequence, followed by a live subscription
+#[derive(Debug)]
+pub struct Replay {
+    /// Retained events published after the requested sequence, in publication order
+    pub events: Vec<EventEnvelope>,
+    /// False when events after the requested sequence are no longer retained, or the
+    /// sequence was never reached by this process
+    pub complete: bool,
+    /// Receives the events published after `events`
+    pub receiver: broadcast::Receiver<EventEnvelope>,
+}
+
+/// Recent events of the feed
+#[derive(Debug, Default)]
+struct History {
+    events: VecDeque<EventEnvelope>,
+    /// Highest sequence dropped from the history
+    evicted: u64,
+    /// Sequence of the latest event fed
+    latest: u64,
+}
+
+/// Component reacting to domain events
+pub trait EventSubscriber: Send + Sync {
+    fn name(&self) -> &str;
+    fn handle(
+        &self,
+        envelope: &EventEnvelope,
+    ) -> impl std::future::Future<Output = Result<(), EventError>> + Send;
+}
+
+/// Publish/subscribe bus for domain events.
+///
+/// In-process subscribers receive the events published by this process, so that their
+/// side effects happen once. When an outbox is configured, events are written to it in
+/// the same transaction as the state change that caused them, and a relay delivers them
+/// to external systems.
+///
+/// The feed, which client streams subscribe to with `subscribe_after`, carries the
+/// events of every instance: it follows the outbox when there is one, and is the same
+/// as the in-process events otherwise.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<EventEnvelope>,
+    sequence: Arc<AtomicU64>,
+    feed: broadcast::Sender<EventEnvelope>,
+    history: Arc<Mutex<History>>,
+    outbox: Option<PostgresOutbox>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Create an in-process only event bus
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(DEFAULT_CHANNEL_CAPACITY);
+        let (feed, _) = broadcast::channel(DEFAULT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            sequence: Arc::new(AtomicU64::new(0)),
+            feed,
+            history: Arc::default(),
+            outbox: None,
+        }
+    }
+
+    /// Create an event bus that persists every event to the given outbox. Its feed is
+    /// empty until `spawn_feed` is called.
+    #[must_use]
+    pub fn with_outbox(outbox: PostgresOutbox) -> Self {
+        Self {
+            outbox: Some(outbox),
+            ..Self::new()
+        }
+    }
+
+    /// Outbox events are persisted to, if any
+    #[must_use]
+    pub fn outbox(&self) -> Option<&PostgresOutbox> {
+        self.outbox.as_ref()
+    }
+
+    /// Insert an item and record the resulting events atomically, then publish them
+    /// # Errors
+    /// Returns `DbError` if the write fails, in which case no event is published
+    pub async fn commit_insert<T>(
+        &self,
+        repo: &JsonRepo<T, Uuid>,
+        id: Uuid,
+        item: T,
+        events: Vec<DomainEvent>,
+    ) -> Result<(), DbError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync,
+    {
+        let mut tx = repo.begin().await?;
+        tx.insert(repo, id, item).await?;
+        self.commit(tx, events).await
+    }
+
+    /// Update an item and record the resulting events atomically, then publish them
+    /// # Errors
+    /// Returns `DbError` if the write fails, in which case no event is published
+    pub async fn commit_update<T>(
+        &self,
+        repo: &JsonRepo<T, Uuid>,
+        id: Uuid,
+        item: T,
+        events: Vec<DomainEvent>,
+    ) -> Result<(), DbError>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync,
+    {
+        let mut tx = repo.begin().await?;
+        tx.update(repo, id, item).await?;
+        self.commit(tx, events).await
+    }
+
+    /// Record the events in the transaction holding the writes that caused them,
+    /// commit it, then publish them
+    /// # Errors
+    /// Returns `DbError` if the commit fails, in which case no event is published
+    pub async fn commit(
+        &self,
+        mut tx: Transaction,
+        events: Vec<DomainEvent>,
+    ) -> Result<(), DbError> {
+        let envelopes = self.envelopes(events);
+        if let Some(outbox) = &self.outbox {
+            let messages = Self::outbox_messages(&envelopes)?;
+            tx.append_outbox(outbox, &messages).await?;
+        }
+        tx.commit().await?;
+        self.broadcast(envelopes);
+        Ok(())
+    }
+
+    /// Publish an event that is not tied to a state change
+    pub async fn publish(&self, event: DomainEvent) {
+        let envelopes = self.envelopes(vec![event]);
+
+        if let Some(outbox) = &self.outbox {
+            let result = match Self::outbox_messages(&envelopes) {
+                Ok(messages) => outbox.append(&messages).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!(
+                    "Failed to persist event {} to outbox: {}",
+                    envelopes[0].id, e
+                );
+            }
+        }
+
+        self.broadcast(envelopes);
+    }
+
+    /// Subscribe to events published by this process from now on
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.sender.subscribe()
+    }
+
+    /// Sequence of the latest event of the feed
+    #[must_use]
+    pub fn last_sequence(&self) -> u64 {
+        self.history
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .latest
+    }
+
+    /// Subscribe to the feed after `sequence`, replaying the retained events.
+    ///
+    /// Every event fed after `sequence` is either replayed or received, never both.
+    #[must_use]
+    pub fn subscribe_after(&self, sequence: u64) -> Replay {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        // Subscribing under the lock, events fed from now on are not in the history
+        let receiver = self.feed.subscribe();
+        Replay {
+            events: history
+                .events
+                .iter()
+                .filter(|envelope| envelope.sequence > sequence)
+                .cloned()
+                .collect(),
+            complete: history.evicted <= sequence && sequence <= history.latest,
+            receiver,
+        }
+    }
+
+    /// Feed the events appended to the outbox by every instance on a background task,
+    /// starting with the latest retained ones. Does nothing without an outbox.
+    #[must_use]
+    pub fn spawn_feed(&self) -> Option<JoinHandle<()>> {
+        let outbox = self.outbox.clone()?;
+        let bus = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                if let Err(e) = bus.follow_outbox(&outbox).await {
+                    error!("Event feed lost the outbox: {}", e);
+                }
+                tokio::time::sleep(FEED_RETRY_DELAY).await;
+            }
+        }))
+    }
+
+    /// Feed the outbox records after the latest event fed, waking up on appends
+    async fn follow_outbox(&self, outbox: &PostgresOutbox) -> Result<(), DbError> {
+        // Listening first, so no append goes unnoticed between reading and waiting
+        let mut listener = outbox.listen().await?;
+        let mut cursor = self.last_sequence();
+        if cursor == 0 {
+            let mut recent = outbox
+                .list(OutboxFilter::All, DEFAULT_HISTORY_CAPACITY as i64)
+                .await?;
+            recent.reverse();
+            if let Some(oldest) = recent.first() {
+                let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+                history.evicted = Self::record_sequence(oldest).saturating_sub(1);
+            }
+            for record in recent {
+                cursor = Self::record_sequence(&record);
+                self.feed_record(record);
+            }
+        }
+
+        let mut gap_since = None;
+        loop {
+            loop {
+                let records = outbox.fetch_after(cursor as i64, FEED_BATCH_SIZE).await?;
+                let fetched = records.len();
+                for record in records {
+                    let sequence = Self::record_sequence(&record);
+                    if sequence != cursor + 1 {
+                        let since = *gap_since.get_or_insert_with(Instant::now);
+                        if since.elapsed() < FEED_GAP_TIMEOUT {
+                            break;
+                        }
+                        warn!("Event feed skipping sequences {}..{}", cursor + 1, sequence);
+                    }
+                    gap_since = None;
+                    cursor = sequence;
+                    self.feed_record(record);
+                }
+                if gap_since.is_some() || fetched < FEED_BATCH_SIZE as usize {
+                    break;
+                }
+            }
+            // Woken up by appends, and periodically to get past gaps
+            if let Ok(notified) = tokio::time::timeout(FEED_POLL_INTERVAL, listener.wait()).await {
+                notified?;
+            }
+        }
+    }
+
+    fn record_sequence(record: &OutboxRecord) -> u64 {
+        record.sequence.try_into().unwrap_or_default()
+    }
+
+    fn feed_record(&self, record: OutboxRecord) {
+        let sequence = Self::record_sequence(&record);
+        match serde_json::from_value::<EventEnvelope>(record.payload) {
+            Ok(envelope) => self.feed(vec![EventEnvelope {
+                sequence,
+                ..envelope
+            }]),
+            Err(e) => warn!("Event feed skipping outbox record {}: {}", record.id, e),
+        }
+    }
+
+    /// Run an in-process subscriber on its own task
+    pub fn spawn_subscriber<S: EventSubscriber + 'static>(&self, subscriber: S) -> JoinHandle<()> {
+        let mut receiver = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(envelope) => {
+                        if let Err(e) = subscriber.handle(&envelope).await {
+                            warn!(
+                                "Subscriber {} failed to handle event {}: {}",
+                                subscriber.name(),
+                                envelope.sequence,
+                                e
+                            );
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Subscriber {} missed {} events", subscriber.name(), missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    fn envelopes(&self, events: Vec<DomainEvent>) -> Vec<EventEnvelope> {
+        events
+            .into_iter()
+            .map(|event| EventEnvelope {
+                id: Uuid::new_v4(),
+                sequence: self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+                occurred_at: Utc::now(),
+                event,
+            })
+            .collect()
+    }
+
+    fn outbox_messages(envelopes: &[EventEnvelope]) -> Result<Vec<OutboxMessage>, DbError> {
+        envelopes
+            .iter()
+            .map(|envelope| {
+                Ok(OutboxMessage {
+                    id: envelope.id,
+                    topic: envelope.event.kind().to_string(),
+                    payload: serde_json::to_value(envelope)?,
+                })
+            })
+            .collect()
+    }
+
+    fn broadcast(&self, envelopes: Vec<EventEnvelope>) {
+        for envelope in &envelopes {
+            // No receivers is not an error, nobody is listening yet
+            let _ = self.sender.send(envelope.clone());
+        }
+        // With an outbox, the feed gets the events once read back from it
+        if self.outbox.is_none() {
+            self.feed(envelopes);
+        }
+    }
+
+    fn feed(&self, envelopes: Vec<EventEnvelope>) {
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        for envelope in envelopes {
+            if history.events.len() == DEFAULT_HISTORY_CAPACITY
+                && let Some(evicted) = history.events.pop_front()
+            {
+                history.evicted = history.evicted.max(evicted.sequence);
+            }
+            history.latest = envelope.sequence;
+            history.events.push_back(envelope.clone());
+            let _ = self.feed.send(envelope);
+        }
+    }
+}
+
+/// Writes every domain event to the audit log
+#[derive(Debug, Default)]
+pub struct AuditLogSubscriber;
+
+impl EventSubscriber for AuditLogSubscriber {
+    fn name(&self) -> &'static str {
+        "audit-log"
+    }
+
+    async fn handle(&self, envelope: &EventEnvelope) -> Result<(), EventError> {
+        let event = serde_json::to_string(&envelope.event)
+            .map_err(|e| EventError::Delivery(e.to_string()))?;
+        info!(
+            target: "audit",
+            sequence = envelope.sequence,
+            user_id = %envelope.event.user_id(),
+            "{event}"
+        );
+        Ok(())
+    }
+}
+
+/// Event counters maintained by `MetricsSubscriber`
+#[derive(Debug, Default)]
+pub struct EventMetrics {
+    pub orders_created: AtomicU64,
+    pub orders_filled: AtomicU64,
+    pub status_changes: AtomicU64,
+    pub deposits: AtomicU64,
+    pub withdrawals: AtomicU64,
+}
+
+/// Counts domain events by kind
+#[derive(Debug, Default, Clone)]
+pub struct MetricsSubscriber {
+    metrics: Arc<EventMetrics>,
+}
+
+impl MetricsSubscriber {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shared handle to the counters
+    #[must_use]
+    pub fn metrics(&self) -> Arc<EventMetrics> {
+        Arc::clone(&self.metrics)
+    }
+}
+
+impl EventSubscriber for MetricsSubscriber {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    async fn handle(&self, envelope: &EventEnvelope) -> Result<(), EventError> {
+        let counter = match envelope.event {
+            DomainEvent::OrderCreated { .. } => &self.metrics.orders_created,
+            DomainEvent::OrderFilled { .. } => &self.metrics.orders_filled,
+            DomainEvent::OrderStatusChanged { .. } => &self.metrics.status_changes,
+            DomainEvent::FundsDeposited { .. } => &self.metrics.deposits,
+            DomainEvent::FundsWithdrawn { .. } => &self.metrics.withdrawals,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn deposit(user_id: UserId, amount: f64) -> DomainEvent {
+        DomainEvent::FundsDeposited {
+            user_id,
+            amount,
+            balance: amount,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        let bus = EventBus::new();
+        let mut receiver = bus.subscribe();
+        let user_id = Uuid::new_v4();
+
+        bus.publish(deposit(user_id, 10.0)).await;
+        bus.publish(deposit(user_id, 20.0)).await;
+
+        let first = receiver.recv().await.unwrap();
+        let second = receiver.recv().await.unwrap();
+        assert_eq!(first.sequence, 1);
+        assert_eq!(second.sequence, 2);
+        assert_eq!(second.event.user_id(), user_id);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_subscriber() {
+        let bus = EventBus::new();
+        let subscriber = MetricsSubscriber::new();
+        let metrics = subscriber.metrics();
+        let handle = bus.spawn_subscriber(subscriber);
+
+        bus.publish(deposit(Uuid::new_v4(), 5.0)).await;
+        bus.publish(deposit(Uuid::new_v4(), 5.0)).await;
+
+        for _ in 0..50 {
+            if metrics.deposits.load(Ordering::Relaxed) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.deposits.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.orders_created.load(Ordering::Relaxed), 0);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_after_replays_missed_events() {
+        let bus = EventBus::new();
+        let user_id = Uuid::new_v4();
+        for amount in [1.0, 2.0, 3.0] {
+            bus.publish(deposit(user_id, amount)).await;
+        }
+
+        let mut replay = bus.subscribe_after(1);
+        assert!(replay.complete);
+        let replayed: Vec<u64> = replay.events.iter().map(|e| e.sequence).collect();
+        assert_eq!(replayed, vec![2, 3]);
+
+        bus.publish(deposit(user_id, 4.0)).await;
+        assert_eq!(replay.receiver.recv().await.unwrap().sequence, 4);
+        assert_eq!(bus.last_sequence(), 4);
+
+        // A sequence from before a restart cannot be resumed from
+        let stale = bus.subscribe_after(10);
+        assert!(!stale.complete);
+        assert!(stale.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_evicted_events_make_replay_incomplete() {
+        let bus = EventBus::new();
+        for _ in 0..=DEFAULT_HISTORY_CAPACITY {
+            bus.publish(deposit(Uuid::new_v4(), 1.0)).await;
+        }
+
+        let replay = bus.subscribe_after(0);
+        assert!(!replay.complete);
+        assert_eq!(replay.events.len(), DEFAULT_HISTORY_CAPACITY);
+        assert!(bus.subscribe_after(1).complete);
+    }
+}
@@ -0,0 +1,333 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, Utc, Weekday};
+use database_adapter::db::{DbError, JsonRepo, Repository};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Trading status of an instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum InstrumentStatus {
+    /// Open for trading
+    Active,
+    /// Trading is temporarily suspended
+    Halted,
+    /// No longer listed, kept for history
+    Delisted,
+}
+
+/// Allowed limit price range
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PriceBand {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// When an instrument can be traded, in the exchange's local time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TradingCalendar {
+    /// Days the market opens, such as `Mon`
+    #[schema(value_type = Vec<String>)]
+    pub trading_days: Vec<Weekday>,
+    pub open: NaiveTime,
+    /// Inclusive
+    pub close: NaiveTime,
+    /// Offset of the exchange's local time from UTC
+    pub utc_offset_minutes: i32,
+    /// Days the market is closed even though they are trading days
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl TradingCalendar {
+    /// Calendar of a market that never closes
+    #[must_use]
+    pub fn always_open() -> Self {
+        Self {
+            trading_days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ],
+            open: NaiveTime::MIN,
+            close: NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).unwrap_or(NaiveTime::MIN),
+            utc_offset_minutes: 0,
+            holidays: Vec::new(),
+        }
+    }
+
+    /// Check if the market is open at the given instant
+    #[must_use]
+    pub fn is_open_at(&self, at: DateTime<Utc>) -> bool {
+        let Some(offset) = FixedOffset::east_opt(self.utc_offset_minutes * 60) else {
+            return false;
+        };
+        let local = at.with_timezone(&offset);
+        let time = local.time();
+
+        self.trading_days.contains(&local.weekday())
+            && !self.holidays.contains(&local.date_naive())
+            && time >= self.open
+            && time <= self.close
+    }
+}
+
+/// Reference data of a tradable instrument
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Instrument {
+    pub symbol: String,
+    pub name: String,
+    /// ISO 4217 currency code
+    pub currency: String,
+    /// Smallest price increment
+    pub tick_size: f64,
+    /// Quantities must be a multiple of the lot size
+    pub lot_size: u64,
+    pub price_band: PriceBand,
+    /// Price used to estimate the cost of market orders
+    pub reference_price: f64,
+    pub status: InstrumentStatus,
+    pub trading_calendar: TradingCalendar,
+}
+
+impl Instrument {
+    /// Check that the reference data is consistent
+    /// # Errors
+    /// Returns `InstrumentError::Invalid` describing the first problem found
+    pub fn validate(&self) -> Result<(), InstrumentError> {
+        let invalid = |reason: &str| Err(InstrumentError::Invalid(reason.to_string()));
+
+        if self.symbol.is_empty()
+            || !self
+                .symbol
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.')
+        {
+            return invalid("symbol must be uppercase letters, digits or dots");
+        }
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return invalid("currency must be a three letter ISO code");
+        }
+        if self.tick_size.is_nan() || self.tick_size <= 0.0 {
+            return invalid("tick size must be positive");
+        }
+        if self.lot_size == 0 {
+            return invalid("lot size must be positive");
+        }
+        if self.price_band.min.is_nan()
+            || self.price_band.min <= 0.0
+            || self.price_band.max < self.price_band.min
+        {
+            return invalid("price band must be positive with min <= max");
+        }
+        if self.reference_price < self.price_band.min || self.reference_price > self.price_band.max
+        {
+            return invalid("reference price must be within the price band");
+        }
+        if FixedOffset::east_opt(self.trading_calendar.utc_offset_minutes * 60).is_none() {
+            return invalid("UTC offset is out of range");
+        }
+        if self.trading_calendar.close < self.trading_calendar.open {
+            return invalid("market must close after it opens");
+        }
+        Ok(())
+    }
+
+    /// Check if the instrument accepts orders at the given instant
+    #[must_use]
+    pub fn is_tradable_at(&self, at: DateTime<Utc>) -> bool {
+        self.status == InstrumentStatus::Active && self.trading_calendar.is_open_at(at)
+    }
+}
+
+/// Instruments listed when the instrument master is empty
+fn initial_listings() -> Vec<Instrument> {
+    [
+        ("AAPL", "Apple Inc.", 1000.0, 150.0),
+        ("GOOGL", "Alphabet Inc.", 5000.0, 2800.0),
+        ("MSFT", "Microsoft Corporation", 1000.0, 420.0),
+        ("TSLA", "Tesla, Inc.", 2000.0, 245.0),
+    ]
+    .into_iter()
+    .map(|(symbol, name, max_price, reference_price)| Instrument {
+        symbol: symbol.to_string(),
+        name: name.to_string(),
+        currency: "USD".to_string(),
+        tick_size: 0.01,
+        lot_size: 1,
+        price_band: PriceBand {
+            min: 1.0,
+            max: max_price,
+        },
+        reference_price,
+        status: InstrumentStatus::Active,
+        trading_calendar: TradingCalendar::always_open(),
+    })
+    .collect()
+}
+
+/// Instrument master errors
+#[derive(Debug)]
+pub enum InstrumentError {
+    NotFound,
+    AlreadyExists,
+    Invalid(String),
+    DbError(DbError),
+}
+
+impl std::fmt::Display for InstrumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstrumentError::NotFound => write!(f, "Instrument not found"),
+            InstrumentError::AlreadyExists => write!(f, "Instrument already exists"),
+            InstrumentError::Invalid(reason) => write!(f, "Invalid instrument: {reason}"),
+            InstrumentError::DbError(db_error) => write!(f, "Database error: {db_error}"),
+        }
+    }
+}
+
+impl std::error::Error for InstrumentError {}
+
+impl From<DbError> for InstrumentError {
+    fn from(error: DbError) -> Self {
+        InstrumentError::DbError(error)
+    }
+}
+
+/// Instrument master, keyed by symbol
+pub type InstrumentRepo = JsonRepo<Instrument, String>;
+
+#[allow(async_fn_in_trait)]
+pub trait InstrumentRepoExt {
+    async fn list_instruments(&self) -> Result<Vec<Instrument>, DbError>;
+    async fn get_instrument(&self, symbol: &str) -> Result<Option<Instrument>, DbError>;
+    async fn create_instrument(&self, instrument: Instrument) -> Result<(), InstrumentError>;
+    async fn update_instrument(&self, instrument: Instrument) -> Result<(), InstrumentError>;
+    async fn remove_instrument(&self, symbol: &str) -> Result<(), InstrumentError>;
+    /// List the initial instruments if the master is empty
+    async fn seed_if_empty(&self) -> Result<(), InstrumentError>;
+}
+
+impl InstrumentRepoExt for InstrumentRepo {
+    async fn list_instruments(&self) -> Result<Vec<Instrument>, DbError> {
+        Ok(self
+            .all()
+            .await?
+            .into_iter()
+            .map(|(_, instrument)| instrument)
+            .collect())
+    }
+
+    async fn get_instrument(&self, symbol: &str) -> Result<Option<Instrument>, DbError> {
+        self.get(&symbol.to_string()).await
+    }
+
+    async fn create_instrument(&self, instrument: Instrument) -> Result<(), InstrumentError> {
+        instrument.validate()?;
+        if self.get_instrument(&instrument.symbol).await?.is_some() {
+            return Err(InstrumentError::AlreadyExists);
+        }
+        self.insert(instrument.symbol.clone(), instrument).await?;
+        Ok(())
+    }
+
+    async fn update_instrument(&self, instrument: Instrument) -> Result<(), InstrumentError> {
+        instrument.validate()?;
+        if self.get_instrument(&instrument.symbol).await?.is_none() {
+            return Err(InstrumentError::NotFound);
+        }
+        self.update(instrument.symbol.clone(), instrument).await?;
+        Ok(())
+    }
+
+    async fn remove_instrument(&self, symbol: &str) -> Result<(), InstrumentError> {
+        if self.get_instrument(symbol).await?.is_none() {
+            return Err(InstrumentError::NotFound);
+        }
+        self.remove(symbol.to_string()).await?;
+        Ok(())
+    }
+
+    async fn seed_if_empty(&self) -> Result<(), InstrumentError> {
+        if !self.is_empty().await? {
+            return Ok(());
+        }
+        for instrument in initial_listings() {
+            self.create_instrument(instrument).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn regular_session() -> TradingCalendar {
+        TradingCalendar {
+            trading_days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            open: NaiveTime::from_hms_opt(9, 30, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            utc_offset_minutes: -5 * 60,
+            holidays: vec![NaiveDate::from_ymd_opt(2025, 12, 25).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_calendar_hours() {
+        let calendar = regular_session();
+        // Wednesday 2025-12-10, 15:00 UTC is 10:00 local
+        assert!(calendar.is_open_at(Utc.with_ymd_and_hms(2025, 12, 10, 15, 0, 0).unwrap()));
+        // 13:00 UTC is 08:00 local
+        assert!(!calendar.is_open_at(Utc.with_ymd_and_hms(2025, 12, 10, 13, 0, 0).unwrap()));
+        // Saturday
+        assert!(!calendar.is_open_at(Utc.with_ymd_and_hms(2025, 12, 13, 15, 0, 0).unwrap()));
+        // Holiday
+        assert!(!calendar.is_open_at(Utc.with_ymd_and_hms(2025, 12, 25, 15, 0, 0).unwrap()));
+        // The local date is used, 02:00 UTC on Thursday is still Wednesday evening
+        assert!(!calendar.is_open_at(Utc.with_ymd_and_hms(2025, 12, 11, 2, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_initial_listings_are_valid() {
+        for instrument in initial_listings() {
+            assert!(instrument.validate().is_ok(), "{}", instrument.symbol);
+            assert!(instrument.is_tradable_at(Utc::now()));
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_inconsistent_data() {
+        let valid = initial_listings().remove(0);
+
+        let mut instrument = valid.clone();
+        instrument.symbol = "aapl".to_string();
+        assert!(matches!(
+            instrument.validate(),
+            Err(InstrumentError::Invalid(_))
+        ));
+
+        let mut instrument = valid.clone();
+        instrument.lot_size = 0;
+        assert!(matches!(
+            instrument.validate(),
+            Err(InstrumentError::Invalid(_))
+        ));
+
+        let mut instrument = valid;
+        instrument.reference_price = instrument.price_band.max + 1.0;
+        assert!(matches!(
+            instrument.validate(),
+            Err(InstrumentError::Invalid(_))
+        ));
+    }
+}
@@ -1,8 +1,24 @@
+pub mod candles;
+pub mod clock;
 pub mod core;
+pub mod events;
+pub mod instrument;
+pub mod login_guard;
+pub mod market_data;
+pub mod mfa_challenge;
+pub mod notifications;
 pub mod order;
+pub mod order_book;
 mod order_processing;
+pub mod password;
+pub mod password_reset;
 pub mod portfolio;
 mod pre_trade;
+pub mod replay;
+mod scheduling;
+pub mod session;
+pub mod singleton;
 pub mod user;
 
-pub use database_adapter::db::Repository;
+pub use database_adapter::db::{DbError, Repository};
+pub use pre_trade::PreTradeError;
@@ -0,0 +1,333 @@
+//! Brute-force protection of logins.
+//!
+//! Failed logins and wrong second-factor codes are counted per account and per client
+//! IP. After a few free failures, each new one doubles the time before the next
+//! attempt is accepted. An account that keeps failing is locked for a while, see
+//! `User::locked_until`; the counters themselves only live in memory.
+//!
+//! A guard with `LoginGuardConfig::password_resets` slows down the password reset
+//! links asked for an email or from an IP the same way, without ever locking.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::user::AuthError;
+
+/// Entries are only pruned past this many, to keep `record_failure` cheap
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginGuardConfig {
+    /// Failures of an account before backoff starts
+    pub account_free_failures: u32,
+    /// Failures from an IP before backoff starts, higher since users can share an IP
+    pub ip_free_failures: u32,
+    /// Wait after the first failure past the free ones, doubled by each next one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Consecutive failures of an account that lock it
+    pub lockout_failures: u32,
+    pub lockout_duration: Duration,
+    /// Failures older than this are forgotten
+    pub forget_after: Duration,
+}
+
+impl Default for LoginGuardConfig {
+    fn default() -> Self {
+        Self {
+            account_free_failures: 3,
+            ip_free_failures: 20,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(15 * 60),
+            lockout_failures: 10,
+            lockout_duration: Duration::from_secs(30 * 60),
+            forget_after: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl LoginGuardConfig {
+    /// Limits on asking for password reset links, which each send an email. Every
+    /// request counts as a failure, and they never lock the account.
+    #[must_use]
+    pub fn password_resets() -> Self {
+        Self {
+            account_free_failures: 3,
+            ip_free_failures: 10,
+            base_delay: Duration::from_secs(60),
+            lockout_failures: u32::MAX,
+            ..Self::default()
+        }
+    }
+
+    /// Read `LOGIN_BACKOFF_AFTER`, `LOGIN_IP_BACKOFF_AFTER`, `LOGIN_BACKOFF_MAX_SECS`,
+    /// `LOGIN_LOCKOUT_AFTER` and `LOGIN_LOCKOUT_SECS`, falling back to the defaults
+    #[must_use]
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            let value = std::env::var(name).ok()?;
+            let parsed = value.parse().ok();
+            if parsed.is_none() {
+                warn!("Ignoring invalid {name} value: {value}");
+            }
+            parsed
+        }
+
+        let mut config = Self::default();
+        if let Some(failures) = var("LOGIN_BACKOFF_AFTER") {
+            config.account_free_failures = failures;
+        }
+        if let Some(failures) = var("LOGIN_IP_BACKOFF_AFTER") {
+            config.ip_free_failures = failures;
+        }
+        if let Some(secs) = var("LOGIN_BACKOFF_MAX_SECS") {
+            config.max_delay = Duration::from_secs(secs);
+        }
+        if let Some(failures) = var::<u32>("LOGIN_LOCKOUT_AFTER") {
+            config.lockout_failures = failures.max(1);
+        }
+        if let Some(secs) = var("LOGIN_LOCKOUT_SECS") {
+            config.lockout_duration = Duration::from_secs(secs);
+        }
+        config
+    }
+
+    /// Wait imposed after `failures` consecutive failures, `free` of which are free
+    fn delay(&self, failures: u32, free: u32) -> Duration {
+        if failures < free.max(1) {
+            return Duration::ZERO;
+        }
+        let doublings = (failures - free.max(1)).min(31);
+        self.base_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+/// Failures per key, with the backoff they impose
+#[derive(Debug)]
+struct FailureCounter<K> {
+    entries: Mutex<HashMap<K, Failures>>,
+}
+
+impl<K: Eq + Hash> FailureCounter<K> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Time left before `key` may try again
+    fn retry_after(
+        &self,
+        key: &K,
+        config: &LoginGuardConfig,
+        free: u32,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut entries = self.entries.lock().unwrap();
+        let failures = *entries.get(key)?;
+        let elapsed = now.saturating_duration_since(failures.last);
+        if elapsed > config.forget_after {
+            entries.remove(key);
+            return None;
+        }
+        let wait = config.delay(failures.count, free).checked_sub(elapsed)?;
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    /// Returns the consecutive failures of `key`, this one included
+    fn record_failure(&self, key: K, config: &LoginGuardConfig, now: Instant) -> u32 {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() > PRUNE_THRESHOLD {
+            entries.retain(|_, failures| {
+                now.saturating_duration_since(failures.last) <= config.forget_after
+            });
+        }
+        let failures = entries.entry(key).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        if now.saturating_duration_since(failures.last) > config.forget_after {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        failures.count
+    }
+
+    fn reset(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// Counts the failed logins of accounts and client IPs and slows them down
+#[derive(Debug)]
+pub struct LoginGuard {
+    config: LoginGuardConfig,
+    accounts: FailureCounter<String>,
+    ips: FailureCounter<IpAddr>,
+}
+
+impl LoginGuard {
+    #[must_use]
+    pub fn new(config: LoginGuardConfig) -> Self {
+        Self {
+            config,
+            accounts: FailureCounter::new(),
+            ips: FailureCounter::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &LoginGuardConfig {
+        &self.config
+    }
+
+    /// Whether a login of `email` from `ip` may be attempted now
+    /// # Errors
+    /// Returns `AuthError::IpRateLimited` or `AuthError::TooManyAttempts` while the
+    /// client IP or the account is backing off
+    pub fn check(&self, email: &str, ip: Option<IpAddr>) -> Result<(), AuthError> {
+        self.check_at(email, ip, Instant::now())
+    }
+
+    fn check_at(&self, email: &str, ip: Option<IpAddr>, now: Instant) -> Result<(), AuthError> {
+        if let Some(ip) = ip
+            && let Some(wait) =
+                self.ips
+                    .retry_after(&ip, &self.config, self.config.ip_free_failures, now)
+        {
+            return Err(AuthError::IpRateLimited(wait));
+        }
+        let email = email.to_lowercase();
+        if let Some(wait) =
+            self.accounts
+                .retry_after(&email, &self.config, self.config.account_free_failures, now)
+        {
+            return Err(AuthError::TooManyAttempts(wait));
+        }
+        Ok(())
+    }
+
+    /// Count a wrong password or code, returns whether the account must now be locked
+    pub fn record_failure(&self, email: &str, ip: Option<IpAddr>) -> bool {
+        self.record_failure_at(email, ip, Instant::now())
+    }
+
+    fn record_failure_at(&self, email: &str, ip: Option<IpAddr>, now: Instant) -> bool {
+        if let Some(ip) = ip {
+            self.ips.record_failure(ip, &self.config, now);
+        }
+        let email = email.to_lowercase();
+        let failures = self
+            .accounts
+            .record_failure(email.clone(), &self.config, now);
+        if failures >= self.config.lockout_failures {
+            // The lock takes over, backoff starts afresh once it is lifted
+            self.accounts.reset(&email);
+            return true;
+        }
+        false
+    }
+
+    /// Forget the failures of an account after it logged in or was unlocked
+    pub fn record_success(&self, email: &str) {
+        self.accounts.reset(&email.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(LoginGuardConfig {
+            account_free_failures: 2,
+            ip_free_failures: 4,
+            lockout_failures: 6,
+            ..LoginGuardConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_account_backoff_doubles() {
+        let guard = guard();
+        let start = Instant::now();
+        assert!(!guard.record_failure_at("a@test.com", None, start));
+        assert!(guard.check_at("a@test.com", None, start).is_ok());
+        assert!(!guard.record_failure_at("a@test.com", None, start));
+        assert!(matches!(
+            guard.check_at("a@test.com", None, start),
+            Err(AuthError::TooManyAttempts(wait)) if wait == Duration::from_secs(1)
+        ));
+        // Case does not matter, other accounts are not affected
+        assert!(guard.check_at("A@test.com", None, start).is_err());
+        assert!(guard.check_at("b@test.com", None, start).is_ok());
+
+        let later = start + Duration::from_secs(1);
+        assert!(guard.check_at("a@test.com", None, later).is_ok());
+        assert!(!guard.record_failure_at("a@test.com", None, later));
+        assert!(matches!(
+            guard.check_at("a@test.com", None, later),
+            Err(AuthError::TooManyAttempts(wait)) if wait == Duration::from_secs(2)
+        ));
+
+        guard.record_success("a@test.com");
+        assert!(guard.check_at("a@test.com", None, later).is_ok());
+    }
+
+    #[test]
+    fn test_ip_backoff_spans_accounts() {
+        let guard = guard();
+        let ip = Some(IpAddr::from([192, 0, 2, 1]));
+        let now = Instant::now();
+        for account in ["a", "b", "c", "d"] {
+            guard.record_failure_at(&format!("{account}@test.com"), ip, now);
+        }
+        assert!(matches!(
+            guard.check_at("e@test.com", ip, now),
+            Err(AuthError::IpRateLimited(_))
+        ));
+        assert!(guard.check_at("e@test.com", None, now).is_ok());
+    }
+
+    #[test]
+    fn test_lockout_and_forgetting() {
+        let guard = guard();
+        let now = Instant::now();
+        for _ in 1..6 {
+            assert!(!guard.record_failure_at("a@test.com", None, now));
+        }
+        assert!(guard.record_failure_at("a@test.com", None, now));
+        // The count starts over after a lock
+        assert!(guard.check_at("a@test.com", None, now).is_ok());
+
+        guard.record_failure_at("b@test.com", None, now);
+        guard.record_failure_at("b@test.com", None, now);
+        let much_later = now + Duration::from_secs(2 * 60 * 60);
+        assert!(guard.check_at("b@test.com", None, much_later).is_ok());
+        assert!(!guard.record_failure_at("b@test.com", None, much_later));
+        assert!(guard.check_at("b@test.com", None, much_later).is_ok());
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let config = LoginGuardConfig::default();
+        assert_eq!(config.delay(2, 3), Duration::ZERO);
+        assert_eq!(config.delay(3, 3), Duration::from_secs(1));
+        assert_eq!(config.delay(5, 3), Duration::from_secs(4));
+        assert_eq!(config.delay(100, 3), config.max_delay);
+    }
+}
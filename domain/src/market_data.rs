@@ -0,0 +1,721 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::instrument::{Instrument, InstrumentRepo, InstrumentRepoExt, InstrumentStatus};
+use crate::order::OrderSide;
+use crate::replay::{MarketReplay, ReplayConfig};
+
+/// Seconds in a year, the time unit of model drifts and volatilities
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+/// Capacity of the market data broadcast channel
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// Best bid and offer of an instrument, with the last traded price
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Quote {
+    pub symbol: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub last: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Quote {
+    #[must_use]
+    pub fn mid(&self) -> f64 {
+        (self.bid + self.ask) / 2.0
+    }
+
+    /// Price a market order on `side` would trade at
+    #[must_use]
+    pub fn price_for(&self, side: &OrderSide) -> f64 {
+        match side {
+            OrderSide::Buy => self.ask,
+            OrderSide::Sell => self.bid,
+        }
+    }
+}
+
+/// Trade printed on the market
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TradeTick {
+    pub symbol: String,
+    pub price: f64,
+    pub quantity: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Update published by the market data feed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum MarketDataEvent {
+    Quote(Quote),
+    Trade(TradeTick),
+}
+
+/// Stochastic model generating the next price of an instrument
+pub trait PriceModel: std::fmt::Debug + Send + Sync {
+    /// Price after `dt` years, starting from `price`. `reference` is the instrument's
+    /// reference price, used as a scale or an anchor by some models.
+    fn next_price(&self, price: f64, reference: f64, dt: f64, rng: &mut StdRng) -> f64;
+}
+
+/// Standard normal sample (Box-Muller)
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.random::<f64>().max(f64::MIN_POSITIVE);
+    let u2: f64 = rng.random();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Arithmetic random walk, steps are proportional to the reference price
+#[derive(Debug, Clone)]
+pub struct RandomWalk {
+    pub volatility: f64,
+}
+
+impl PriceModel for RandomWalk {
+    fn next_price(&self, price: f64, reference: f64, dt: f64, rng: &mut StdRng) -> f64 {
+        price + self.volatility * reference * dt.sqrt() * standard_normal(rng)
+    }
+}
+
+/// Geometric Brownian motion, the usual log-normal stock price model
+#[derive(Debug, Clone)]
+pub struct GeometricBrownianMotion {
+    pub drift: f64,
+    pub volatility: f64,
+}
+
+impl PriceModel for GeometricBrownianMotion {
+    fn next_price(&self, price: f64, _reference: f64, dt: f64, rng: &mut StdRng) -> f64 {
+        let exponent = (self.drift - self.volatility.powi(2) / 2.0) * dt
+            + self.volatility * dt.sqrt() * standard_normal(rng);
+        price * exponent.exp()
+    }
+}
+
+/// Ornstein-Uhlenbeck process pulling the price back to the reference price
+#[derive(Debug, Clone)]
+pub struct MeanReversion {
+    /// Rate at which the price reverts, per year
+    pub speed: f64,
+    pub volatility: f64,
+}
+
+impl PriceModel for MeanReversion {
+    fn next_price(&self, price: f64, reference: f64, dt: f64, rng: &mut StdRng) -> f64 {
+        // Exact discretisation, stable whatever the step
+        let decay = (-self.speed * dt).exp();
+        let variance = if self.speed > 0.0 {
+            (1.0 - decay * decay) / (2.0 * self.speed)
+        } else {
+            dt
+        };
+        reference
+            + (price - reference) * decay
+            + self.volatility * reference * variance.sqrt() * standard_normal(rng)
+    }
+}
+
+/// Model selected from the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceModelKind {
+    RandomWalk,
+    GeometricBrownianMotion,
+    MeanReversion,
+}
+
+impl PriceModelKind {
+    /// Parse `random_walk`, `gbm` or `mean_reversion`
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "random_walk" => Some(Self::RandomWalk),
+            "gbm" | "geometric_brownian_motion" => Some(Self::GeometricBrownianMotion),
+            "mean_reversion" => Some(Self::MeanReversion),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the price model of one instrument
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelConfig {
+    pub kind: PriceModelKind,
+    /// Annualised volatility
+    pub volatility: f64,
+    /// Annualised drift, used by geometric Brownian motion
+    pub drift: f64,
+    /// Reversion speed per year, used by mean reversion
+    pub reversion_speed: f64,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            kind: PriceModelKind::GeometricBrownianMotion,
+            volatility: 0.3,
+            drift: 0.0,
+            reversion_speed: 50.0,
+        }
+    }
+}
+
+impl ModelConfig {
+    #[must_use]
+    pub fn build(&self) -> Box<dyn PriceModel> {
+        match self.kind {
+            PriceModelKind::RandomWalk => Box::new(RandomWalk {
+                volatility: self.volatility,
+            }),
+            PriceModelKind::GeometricBrownianMotion => Box::new(GeometricBrownianMotion {
+                drift: self.drift,
+                volatility: self.volatility,
+            }),
+            PriceModelKind::MeanReversion => Box::new(MeanReversion {
+                speed: self.reversion_speed,
+                volatility: self.volatility,
+            }),
+        }
+    }
+}
+
+/// Market data simulation settings
+#[derive(Debug, Clone)]
+pub struct MarketDataConfig {
+    /// Seed of the price generators, each instrument derives its own from it
+    pub seed: u64,
+    pub default_model: ModelConfig,
+    /// Per-symbol overrides of the default model
+    pub models: HashMap<String, ModelConfig>,
+    /// How often the simulation advances, `None` to only advance on `tick`
+    pub tick_interval: Option<Duration>,
+    /// Simulated seconds elapsed per real second
+    pub time_scale: f64,
+    /// Distance between the last price and the bid or ask, in ticks
+    pub half_spread_ticks: u32,
+    /// Recorded data to replay instead of simulating prices
+    pub replay: Option<ReplayConfig>,
+}
+
+impl Default for MarketDataConfig {
+    fn default() -> Self {
+        Self {
+            seed: 42,
+            default_model: ModelConfig::default(),
+            models: HashMap::new(),
+            tick_interval: None,
+            time_scale: 1.0,
+            half_spread_ticks: 1,
+            replay: None,
+        }
+    }
+}
+
+impl MarketDataConfig {
+    /// Read the configuration from `MARKET_DATA_*` variables, using defaults for the
+    /// missing ones. The simulation runs every second unless `MARKET_DATA_TICK_MS` is 0,
+    /// or a file is replayed with `MARKET_DATA_REPLAY_FILE`.
+    #[must_use]
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            let value = std::env::var(name).ok()?;
+            let parsed = value.parse().ok();
+            if parsed.is_none() {
+                warn!("Ignoring invalid {name} value: {value}");
+            }
+            parsed
+        }
+
+        // Instances behind the same load balancer must quote the same prices, so the
+        // seed is fixed unless configured
+        let mut config = Self {
+            seed: var("MARKET_DATA_SEED").unwrap_or(Self::default().seed),
+            tick_interval: Some(Duration::from_secs(1)),
+            time_scale: 60.0,
+            replay: ReplayConfig::from_env(),
+            ..Self::default()
+        };
+        if let Ok(model) = std::env::var("MARKET_DATA_MODEL") {
+            match PriceModelKind::parse(&model) {
+                Some(kind) => config.default_model.kind = kind,
+                None => warn!("Ignoring invalid MARKET_DATA_MODEL value: {model}"),
+            }
+        }
+        if let Some(volatility) = var("MARKET_DATA_VOLATILITY") {
+            config.default_model.volatility = volatility;
+        }
+        if let Some(drift) = var("MARKET_DATA_DRIFT") {
+            config.default_model.drift = drift;
+        }
+        if let Some(speed) = var("MARKET_DATA_REVERSION_SPEED") {
+            config.default_model.reversion_speed = speed;
+        }
+        if let Some(tick_ms) = var::<u64>("MARKET_DATA_TICK_MS") {
+            config.tick_interval = (tick_ms > 0).then(|| Duration::from_millis(tick_ms));
+        }
+        if let Some(time_scale) = var("MARKET_DATA_TIME_SCALE") {
+            config.time_scale = time_scale;
+        }
+        if let Ok(overrides) = std::env::var("MARKET_DATA_SYMBOL_MODELS") {
+            for entry in overrides
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+            {
+                match config.parse_override(entry.trim()) {
+                    Some((symbol, model)) => {
+                        config.models.insert(symbol, model);
+                    }
+                    None => warn!("Ignoring invalid MARKET_DATA_SYMBOL_MODELS entry: {entry}"),
+                }
+            }
+        }
+        config
+    }
+
+    /// Parse a `SYMBOL=model[:volatility]` override, starting from the default model
+    fn parse_override(&self, entry: &str) -> Option<(String, ModelConfig)> {
+        let (symbol, spec) = entry.split_once('=')?;
+        let (kind, volatility) = match spec.split_once(':') {
+            Some((kind, volatility)) => (kind, Some(volatility.parse().ok()?)),
+            None => (spec, None),
+        };
+        let model = ModelConfig {
+            kind: PriceModelKind::parse(kind)?,
+            volatility: volatility.unwrap_or(self.default_model.volatility),
+            ..self.default_model.clone()
+        };
+        Some((symbol.to_string(), model))
+    }
+
+    fn model_for(&self, symbol: &str) -> &ModelConfig {
+        self.models.get(symbol).unwrap_or(&self.default_model)
+    }
+
+    fn seed_for(&self, symbol: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        self.seed ^ hasher.finish()
+    }
+}
+
+/// Simulation state of one instrument
+#[derive(Debug)]
+struct Book {
+    model: Box<dyn PriceModel>,
+    rng: StdRng,
+    quote: Quote,
+}
+
+/// Quantity traded on one UTC day
+#[derive(Debug, Clone, Copy)]
+struct DailyVolume {
+    date: NaiveDate,
+    quantity: u64,
+}
+
+#[derive(Debug)]
+struct FeedState {
+    config: MarketDataConfig,
+    books: Mutex<HashMap<String, Book>>,
+    volumes: Mutex<HashMap<String, DailyVolume>>,
+    sender: broadcast::Sender<MarketDataEvent>,
+}
+
+/// Source of quotes and trades for every listed instrument.
+///
+/// Prices are simulated from the configured models or replayed from recorded data.
+/// Quotes and trades from other sources can be injected with `publish_quote` and
+/// `publish_trade`; all of them are broadcast to subscribers.
+#[derive(Debug, Clone)]
+pub struct MarketDataFeed {
+    state: Arc<FeedState>,
+}
+
+impl MarketDataFeed {
+    #[must_use]
+    pub fn new(config: MarketDataConfig) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            state: Arc::new(FeedState {
+                config,
+                books: Mutex::new(HashMap::new()),
+                volumes: Mutex::new(HashMap::new()),
+                sender,
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &MarketDataConfig {
+        &self.state.config
+    }
+
+    /// Receive every quote and trade published from now on
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<MarketDataEvent> {
+        self.state.sender.subscribe()
+    }
+
+    /// Last quote of a symbol, if the feed has seen it
+    #[must_use]
+    pub fn last_quote(&self, symbol: &str) -> Option<Quote> {
+        self.books().get(symbol).map(|book| book.quote.clone())
+    }
+
+    /// Current quote of an instrument. Instruments the feed has not seen yet start
+    /// at their reference price.
+    #[must_use]
+    pub fn quote(&self, instrument: &Instrument) -> Quote {
+        let mut books = self.books();
+        self.book_for(&mut books, instrument, Utc::now())
+            .quote
+            .clone()
+    }
+
+    /// Advance the simulated prices of the active instruments to `now`
+    pub fn tick(&self, instruments: &[Instrument], now: DateTime<Utc>) {
+        let mut events = Vec::new();
+        {
+            let mut books = self.books();
+            for instrument in instruments {
+                if instrument.status != InstrumentStatus::Active {
+                    continue;
+                }
+                let half_spread = self.half_spread(instrument);
+                let time_scale = self.state.config.time_scale;
+                let book = self.book_for(&mut books, instrument, now);
+                let elapsed = (now - book.quote.timestamp).as_seconds_f64();
+                if elapsed <= 0.0 {
+                    continue;
+                }
+
+                let dt = elapsed * time_scale / SECONDS_PER_YEAR;
+                let price = book.model.next_price(
+                    book.quote.last,
+                    instrument.reference_price,
+                    dt,
+                    &mut book.rng,
+                );
+                let last = Self::normalise(instrument, price);
+                let lots = book.rng.random_range(1..=10_u64);
+
+                book.quote = Quote {
+                    symbol: instrument.symbol.clone(),
+                    bid: Self::normalise(instrument, last - half_spread),
+                    ask: Self::normalise(instrument, last + half_spread),
+                    last,
+                    timestamp: now,
+                };
+                events.push(MarketDataEvent::Trade(TradeTick {
+                    symbol: instrument.symbol.clone(),
+                    price: last,
+                    quantity: lots * instrument.lot_size.max(1),
+                    timestamp: now,
+                }));
+                events.push(MarketDataEvent::Quote(book.quote.clone()));
+            }
+        }
+        for event in events {
+            self.broadcast(event);
+        }
+    }
+
+    /// Replace the quote of a symbol, for instance from recorded data
+    pub fn publish_quote(&self, quote: Quote) {
+        {
+            let mut books = self.books();
+            match books.get_mut(&quote.symbol) {
+                Some(book) => book.quote = quote.clone(),
+                None => {
+                    let config = &self.state.config;
+                    books.insert(
+                        quote.symbol.clone(),
+                        Book {
+                            model: config.model_for(&quote.symbol).build(),
+                            rng: StdRng::seed_from_u64(config.seed_for(&quote.symbol)),
+                            quote: quote.clone(),
+                        },
+                    );
+                }
+            }
+        }
+        self.broadcast(MarketDataEvent::Quote(quote));
+    }
+
+    /// Broadcast a trade, for instance from recorded data or a broker execution
+    pub fn publish_trade(&self, trade: TradeTick) {
+        self.broadcast(MarketDataEvent::Trade(trade));
+    }
+
+    /// Quantity of a symbol traded on the UTC day of its last trade
+    #[must_use]
+    pub fn volume(&self, symbol: &str) -> u64 {
+        self.volumes()
+            .get(symbol)
+            .map_or(0, |volume| volume.quantity)
+    }
+
+    /// Advance prices on a background task every `tick_interval`, picking up
+    /// instruments as they are listed, or replay the configured file.
+    /// Returns `None` if neither is configured.
+    #[must_use]
+    pub fn spawn(&self, instrument_repo: InstrumentRepo) -> Option<JoinHandle<()>> {
+        if let Some(replay) = &self.state.config.replay {
+            let replay = replay.clone();
+            let feed = self.clone();
+            return Some(tokio::spawn(async move {
+                match MarketReplay::load(&replay) {
+                    Ok(market_replay) => market_replay.run(feed, instrument_repo).await,
+                    Err(e) => error!("Failed to load {}: {}", replay.path.display(), e),
+                }
+            }));
+        }
+
+        let interval = self.state.config.tick_interval?;
+        let feed = self.clone();
+        info!("Simulating market data every {:?}", interval);
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match instrument_repo.list_instruments().await {
+                    Ok(instruments) => feed.tick(&instruments, Utc::now()),
+                    Err(e) => error!("Failed to load instruments for market data: {}", e),
+                }
+            }
+        }))
+    }
+
+    fn broadcast(&self, event: MarketDataEvent) {
+        if let MarketDataEvent::Trade(trade) = &event {
+            self.record_volume(trade);
+        }
+        // Nobody listening is fine
+        let _ = self.state.sender.send(event);
+    }
+
+    fn record_volume(&self, trade: &TradeTick) {
+        let date = trade.timestamp.date_naive();
+        let mut volumes = self.volumes();
+        let volume = volumes
+            .entry(trade.symbol.clone())
+            .or_insert(DailyVolume { date, quantity: 0 });
+        if date > volume.date {
+            *volume = DailyVolume { date, quantity: 0 };
+        }
+        // Late trades of a previous day do not count towards the current one
+        if date == volume.date {
+            volume.quantity += trade.quantity;
+        }
+    }
+
+    fn volumes(&self) -> std::sync::MutexGuard<'_, HashMap<String, DailyVolume>> {
+        self.state
+            .volumes
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn books(&self) -> std::sync::MutexGuard<'_, HashMap<String, Book>> {
+        self.state
+            .books
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn book_for<'a>(
+        &self,
+        books: &'a mut HashMap<String, Book>,
+        instrument: &Instrument,
+        now: DateTime<Utc>,
+    ) -> &'a mut Book {
+        let config = &self.state.config;
+        let half_spread = self.half_spread(instrument);
+        books.entry(instrument.symbol.clone()).or_insert_with(|| {
+            let last = Self::normalise(instrument, instrument.reference_price);
+            Book {
+                model: config.model_for(&instrument.symbol).build(),
+                rng: StdRng::seed_from_u64(config.seed_for(&instrument.symbol)),
+                quote: Quote {
+                    symbol: instrument.symbol.clone(),
+                    bid: Self::normalise(instrument, last - half_spread),
+                    ask: Self::normalise(instrument, last + half_spread),
+                    last,
+                    timestamp: now,
+                },
+            }
+        })
+    }
+
+    pub(crate) fn half_spread(&self, instrument: &Instrument) -> f64 {
+        f64::from(self.state.config.half_spread_ticks) * instrument.tick_size
+    }
+
+    /// Keep a price within the instrument's band and on its tick grid
+    fn normalise(instrument: &Instrument, price: f64) -> f64 {
+        let band = instrument.price_band;
+        let price = price.clamp(band.min, band.max);
+        (price / instrument.tick_size).round() * instrument.tick_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::{PriceBand, TradingCalendar};
+    use chrono::TimeDelta;
+
+    fn instrument(symbol: &str) -> Instrument {
+        Instrument {
+            symbol: symbol.to_string(),
+            name: symbol.to_string(),
+            currency: "USD".to_string(),
+            tick_size: 0.01,
+            lot_size: 1,
+            price_band: PriceBand {
+                min: 1.0,
+                max: 1000.0,
+            },
+            reference_price: 150.0,
+            status: InstrumentStatus::Active,
+            trading_calendar: TradingCalendar::always_open(),
+        }
+    }
+
+    fn config(kind: PriceModelKind) -> MarketDataConfig {
+        MarketDataConfig {
+            default_model: ModelConfig {
+                kind,
+                ..ModelConfig::default()
+            },
+            // A simulated day per tick, so prices move noticeably
+            time_scale: 86_400.0,
+            ..MarketDataConfig::default()
+        }
+    }
+
+    fn path(feed: &MarketDataFeed, instrument: &Instrument, steps: i64) -> Vec<f64> {
+        let start = feed.quote(instrument).timestamp;
+        (1..=steps)
+            .map(|step| {
+                feed.tick(
+                    std::slice::from_ref(instrument),
+                    start + TimeDelta::seconds(step),
+                );
+                feed.last_quote(&instrument.symbol).unwrap().last
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_prices() {
+        let aapl = instrument("AAPL");
+        for kind in [
+            PriceModelKind::RandomWalk,
+            PriceModelKind::GeometricBrownianMotion,
+            PriceModelKind::MeanReversion,
+        ] {
+            let first = path(&MarketDataFeed::new(config(kind)), &aapl, 50);
+            let second = path(&MarketDataFeed::new(config(kind)), &aapl, 50);
+            assert_eq!(first, second, "{kind:?}");
+            assert!(first.windows(2).any(|w| w[0] != w[1]), "{kind:?}");
+
+            let mut other_seed = config(kind);
+            other_seed.seed += 1;
+            assert_ne!(
+                first,
+                path(&MarketDataFeed::new(other_seed), &aapl, 50),
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_prices_respect_band_and_tick() {
+        let mut aapl = instrument("AAPL");
+        aapl.price_band = PriceBand {
+            min: 149.0,
+            max: 151.0,
+        };
+        let mut config = config(PriceModelKind::GeometricBrownianMotion);
+        config.default_model.volatility = 5.0;
+        let feed = MarketDataFeed::new(config);
+
+        for price in path(&feed, &aapl, 200) {
+            assert!((149.0..=151.0).contains(&price));
+            assert!(((price / 0.01).round() * 0.01 - price).abs() < 1e-9);
+        }
+        let quote = feed.last_quote("AAPL").unwrap();
+        assert!(quote.bid <= quote.last && quote.last <= quote.ask);
+    }
+
+    #[test]
+    fn test_mean_reversion_stays_near_reference() {
+        let aapl = instrument("AAPL");
+        let mut config = config(PriceModelKind::MeanReversion);
+        config.default_model.reversion_speed = 500.0;
+        let feed = MarketDataFeed::new(config);
+
+        let prices = path(&feed, &aapl, 500);
+        let average = prices.iter().sum::<f64>() / prices.len() as f64;
+        assert!((average - 150.0).abs() < 5.0, "average {average}");
+    }
+
+    #[tokio::test]
+    async fn test_ticks_are_broadcast() {
+        let aapl = instrument("AAPL");
+        let mut halted = instrument("HALT");
+        halted.status = InstrumentStatus::Halted;
+        let feed = MarketDataFeed::new(config(PriceModelKind::RandomWalk));
+        let start = feed.quote(&aapl).timestamp;
+        let mut receiver = feed.subscribe();
+
+        feed.tick(&[aapl, halted], start + TimeDelta::seconds(1));
+
+        assert!(matches!(
+            receiver.recv().await.unwrap(),
+            MarketDataEvent::Trade(TradeTick { ref symbol, .. }) if symbol == "AAPL"
+        ));
+        assert!(matches!(
+            receiver.recv().await.unwrap(),
+            MarketDataEvent::Quote(Quote { ref symbol, .. }) if symbol == "AAPL"
+        ));
+        assert!(receiver.try_recv().is_err());
+        assert!(feed.last_quote("HALT").is_none());
+    }
+
+    #[test]
+    fn test_volume_is_counted_per_day() {
+        let feed = MarketDataFeed::new(MarketDataConfig::default());
+        let day = DateTime::parse_from_rfc3339("2024-01-02T15:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let trade = |quantity, timestamp| TradeTick {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            quantity,
+            timestamp,
+        };
+
+        feed.publish_trade(trade(100, day));
+        feed.publish_trade(trade(50, day + TimeDelta::hours(1)));
+        assert_eq!(feed.volume("AAPL"), 150);
+
+        feed.publish_trade(trade(10, day + TimeDelta::days(1)));
+        feed.publish_trade(trade(500, day));
+        assert_eq!(feed.volume("AAPL"), 10);
+        assert_eq!(feed.volume("MSFT"), 0);
+    }
+}
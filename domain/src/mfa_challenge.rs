@@ -0,0 +1,229 @@
+//! Second-factor challenges kept in the database, so that any instance can verify a
+//! challenge opened by another and challenges survive restarts.
+//!
+//! Challenges of every method share one table. Each row records its method, and a
+//! store only sees the rows of its own.
+
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use database_adapter::db::{DbError, JsonRepo, Repository};
+use mfa_adapter::{ChallengeStore, MfaError, OtpChallenge};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::user::MfaMethod;
+
+pub type ChallengeRepo = JsonRepo<StoredChallenge, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredChallenge {
+    pub method: MfaMethod,
+    /// RFC 3339 with a fixed precision, so that expiry can be compared as text
+    expires_at: String,
+    pub challenge: OtpChallenge,
+}
+
+impl StoredChallenge {
+    fn new(method: MfaMethod, challenge: OtpChallenge) -> Self {
+        Self {
+            method,
+            expires_at: timestamp(challenge.expires_at.into()),
+            challenge,
+        }
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[allow(async_fn_in_trait)]
+pub trait ChallengeRepoExt {
+    /// Remove the challenges of `method` expired at `now`, returns how many were removed
+    async fn purge_expired(&self, method: MfaMethod, now: DateTime<Utc>) -> Result<usize, DbError>;
+    /// Remove the challenges of every method expired at `now`
+    async fn purge_all_expired(&self, now: DateTime<Utc>) -> Result<usize, DbError>;
+}
+
+impl ChallengeRepoExt for ChallengeRepo {
+    async fn purge_expired(&self, method: MfaMethod, now: DateTime<Utc>) -> Result<usize, DbError> {
+        let expired = self
+            .find_range_by_field("method", method.as_str(), "expires_at", "", &timestamp(now))
+            .await?;
+        for (id, _) in &expired {
+            self.remove(id.clone()).await?;
+        }
+        Ok(expired.len())
+    }
+
+    async fn purge_all_expired(&self, now: DateTime<Utc>) -> Result<usize, DbError> {
+        let mut purged = 0;
+        for method in MfaMethod::ALL {
+            purged += self.purge_expired(method, now).await?;
+        }
+        Ok(purged)
+    }
+}
+
+/// Challenges of one second-factor method, see the module documentation
+#[derive(Debug, Clone)]
+pub struct PostgresChallengeStore {
+    repo: ChallengeRepo,
+    method: MfaMethod,
+}
+
+impl PostgresChallengeStore {
+    #[must_use]
+    pub fn new(repo: ChallengeRepo, method: MfaMethod) -> Self {
+        Self { repo, method }
+    }
+}
+
+fn unavailable(e: DbError) -> MfaError {
+    error!("MFA challenge store failed: {}", e);
+    MfaError::ServiceUnavailable
+}
+
+impl ChallengeStore for PostgresChallengeStore {
+    async fn insert(&self, challenge: OtpChallenge) -> Result<(), MfaError> {
+        self.repo
+            .insert(
+                challenge.id.clone(),
+                StoredChallenge::new(self.method, challenge),
+            )
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn get(&self, challenge_id: &str) -> Result<Option<OtpChallenge>, MfaError> {
+        let stored = self
+            .repo
+            .get(&challenge_id.to_string())
+            .await
+            .map_err(unavailable)?;
+        Ok(stored
+            .filter(|stored| stored.method == self.method)
+            .map(|stored| stored.challenge))
+    }
+
+    async fn update(&self, challenge: OtpChallenge) -> Result<(), MfaError> {
+        self.repo
+            .update(
+                challenge.id.clone(),
+                StoredChallenge::new(self.method, challenge),
+            )
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn remove(&self, challenge_id: &str) -> Result<(), MfaError> {
+        self.repo
+            .remove(challenge_id.to_string())
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn count_wrong_code(
+        &self,
+        challenge_id: &str,
+        cap: u32,
+    ) -> Result<Option<u32>, MfaError> {
+        let attempts = self
+            .repo
+            .increment_below(
+                &challenge_id.to_string(),
+                &["challenge", "attempts"],
+                cap.into(),
+            )
+            .await
+            .map_err(unavailable)?;
+        Ok(attempts.and_then(|attempts| u32::try_from(attempts).ok()))
+    }
+
+    async fn purge_expired(&self, now: SystemTime) -> Result<usize, MfaError> {
+        self.repo
+            .purge_expired(self.method, now.into())
+            .await
+            .map_err(unavailable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn challenge(id: &str, expires_at: SystemTime) -> OtpChallenge {
+        OtpChallenge {
+            id: id.to_string(),
+            user_email: "test@example.com".to_string(),
+            code_hash: String::new(),
+            verified: false,
+            attempts: 0,
+            created_at: expires_at - Duration::from_secs(300),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stores_only_see_their_method() {
+        let repo = ChallengeRepo::new(Arc::new(InMemoryStore::new()), "mfa_challenges")
+            .await
+            .unwrap();
+        let email = PostgresChallengeStore::new(repo.clone(), MfaMethod::Email);
+        let totp = PostgresChallengeStore::new(repo, MfaMethod::Totp);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        email.insert(challenge("a", expires_at)).await.unwrap();
+
+        assert!(email.get("a").await.unwrap().is_some());
+        assert!(totp.get("a").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_codes_are_counted_up_to_the_cap() {
+        let repo = ChallengeRepo::new(Arc::new(InMemoryStore::new()), "mfa_challenges")
+            .await
+            .unwrap();
+        let email = PostgresChallengeStore::new(repo, MfaMethod::Email);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        email.insert(challenge("a", expires_at)).await.unwrap();
+
+        assert_eq!(email.count_wrong_code("a", 2).await.unwrap(), Some(1));
+        assert_eq!(email.count_wrong_code("a", 2).await.unwrap(), Some(2));
+        assert_eq!(email.count_wrong_code("a", 2).await.unwrap(), None);
+        assert_eq!(email.get("a").await.unwrap().unwrap().attempts, 2);
+        assert_eq!(email.count_wrong_code("gone", 2).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired_challenges() {
+        let repo = ChallengeRepo::new(Arc::new(InMemoryStore::new()), "mfa_challenges")
+            .await
+            .unwrap();
+        let email = PostgresChallengeStore::new(repo.clone(), MfaMethod::Email);
+        let totp = PostgresChallengeStore::new(repo.clone(), MfaMethod::Totp);
+        let now = SystemTime::now();
+        email
+            .insert(challenge("old", now - Duration::from_secs(1)))
+            .await
+            .unwrap();
+        email
+            .insert(challenge("new", now + Duration::from_secs(60)))
+            .await
+            .unwrap();
+        totp.insert(challenge("old_totp", now - Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(email.purge_expired(now).await.unwrap(), 1);
+        assert!(email.get("old").await.unwrap().is_none());
+        assert!(email.get("new").await.unwrap().is_some());
+        assert!(totp.get("old_totp").await.unwrap().is_some());
+
+        assert_eq!(repo.purge_all_expired(now.into()).await.unwrap(), 1);
+        assert_eq!(repo.len().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+//! Emails telling users what happened to their account.
+//!
+//! Fills and deposits are picked up from the event bus, security alerts are sent by
+//! the operations that cause them. Emails are written in the user's locale. A failed
+//! email never fails the operation it reports on.
+
+pub use mfa_adapter::SecurityEvent;
+use mfa_adapter::{
+    DepositEmail, Mailer, Notification, OrderFilledEmail, SecurityAlertEmail, TradeSide,
+};
+use tracing::warn;
+
+use crate::events::{DomainEvent, EventEnvelope, EventError, EventSubscriber};
+use crate::order::OrderSide;
+use crate::user::{UserId, UserRepo, UserRepoExt};
+
+/// Emails users about their account, in their language
+#[derive(Clone)]
+pub struct EmailNotifier {
+    user_repo: UserRepo,
+    mailer: Mailer,
+}
+
+impl std::fmt::Debug for EmailNotifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailNotifier")
+            .field("mailer", &self.mailer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EmailNotifier {
+    #[must_use]
+    pub fn new(user_repo: UserRepo, mailer: Mailer) -> Self {
+        Self { user_repo, mailer }
+    }
+
+    /// Send `notification` to the user
+    /// # Errors
+    /// Returns `EventError` if the user cannot be found or the email cannot be sent
+    pub async fn notify(
+        &self,
+        user_id: &UserId,
+        notification: &Notification,
+    ) -> Result<(), EventError> {
+        let user = self
+            .user_repo
+            .get_user_by_id(user_id)
+            .await
+            .map_err(|e| EventError::Delivery(e.to_string()))?
+            .ok_or_else(|| EventError::Delivery(format!("User {user_id} not found")))?;
+        self.mailer
+            .send(&user.email, user.locale, notification)
+            .await
+            .map_err(|e| EventError::Delivery(e.to_string()))
+    }
+
+    /// Warn the user about `event`, logging rather than returning failures
+    pub async fn security_alert(&self, user_id: &UserId, event: SecurityEvent) {
+        let notification = Notification::SecurityAlert(SecurityAlertEmail { event });
+        if let Err(e) = self.notify(user_id, &notification).await {
+            warn!("Could not send a security alert to user {}: {}", user_id, e);
+        }
+    }
+}
+
+impl EventSubscriber for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email-notifications"
+    }
+
+    async fn handle(&self, envelope: &EventEnvelope) -> Result<(), EventError> {
+        let notification = match &envelope.event {
+            DomainEvent::OrderFilled {
+                order_id,
+                symbol,
+                quantity,
+                order_side,
+                price,
+                ..
+            } => Notification::OrderFilled(OrderFilledEmail {
+                order_id: order_id.to_string(),
+                symbol: symbol.clone(),
+                side: match order_side {
+                    OrderSide::Buy => TradeSide::Buy,
+                    OrderSide::Sell => TradeSide::Sell,
+                },
+                quantity: *quantity,
+                price: *price,
+            }),
+            DomainEvent::FundsDeposited {
+                amount, balance, ..
+            } => Notification::Deposit(DepositEmail {
+                amount: *amount,
+                balance: *balance,
+            }),
+            DomainEvent::OrderCreated { .. }
+            | DomainEvent::OrderStatusChanged { .. }
+            | DomainEvent::FundsWithdrawn { .. } => return Ok(()),
+        };
+        self.notify(&envelope.event.user_id(), &notification).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mfa_adapter::EmailConfig;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::user::Locale;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_deposits_are_emailed_in_the_user_locale() {
+        let user_repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = user_repo
+            .create_user(
+                "french@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Jeanne".to_string(),
+                "Dupont".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        user_repo.set_locale(&user_id, Locale::Fr).await.unwrap();
+        let mailer = Mailer::new(&EmailConfig::new_test_config());
+        let notifier = EmailNotifier::new(user_repo, mailer.clone());
+
+        let deposit = EventEnvelope {
+            id: Uuid::new_v4(),
+            sequence: 1,
+            occurred_at: chrono::Utc::now(),
+            event: DomainEvent::FundsDeposited {
+                user_id,
+                amount: 1500.0,
+                balance: 1500.0,
+            },
+        };
+        notifier.handle(&deposit).await.unwrap();
+        // Withdrawals are not emailed
+        let withdrawal = EventEnvelope {
+            event: DomainEvent::FundsWithdrawn {
+                user_id,
+                amount: 500.0,
+                balance: 1000.0,
+            },
+            ..deposit
+        };
+        notifier.handle(&withdrawal).await.unwrap();
+
+        let sent = mailer.transport().captured().unwrap().sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "french@test.com");
+        assert_eq!(sent[0].subject, "BrokerX - Dépôt reçu");
+        assert!(sent[0].text_body.contains("1\u{202f}500,00"));
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
 use database_adapter::db::DbError;
-use database_adapter::db::PostgresRepo;
+use database_adapter::db::JsonRepo;
 use database_adapter::db::Repository;
 use serde::Deserialize;
 use serde::Serialize;
@@ -27,15 +27,18 @@ pub enum OrderStatus {
     Rejected { date: NaiveDateTime }, // TODO: reason?
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
-#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub enum OrderType {
     Market,
     Limit(f64),
+    /// Stop market order: becomes a market order once the market trades through
+    /// the stop price, upwards for buys and downwards for sells
+    Stop(f64),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -48,17 +51,41 @@ pub struct Order {
     pub status: OrderStatus,
     pub order_type: OrderType,
     pub order_side: OrderSide,
+    /// Orders placed by the system on the client's behalf, such as liquidations
+    #[serde(default)]
+    pub is_administrative: bool,
 }
 
 pub type OrderId = Uuid;
 
-pub type OrderRepo = PostgresRepo<Order, OrderId>;
+/// Order cancellation errors
+#[derive(Debug)]
+pub enum CancelError {
+    OrderNotFound,
+    NotCancellable,
+    DbError(DbError),
+}
+
+impl std::fmt::Display for CancelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelError::OrderNotFound => write!(f, "Order not found"),
+            CancelError::NotCancellable => write!(f, "Order cannot be cancelled"),
+            CancelError::DbError(db_error) => write!(f, "Database error: {db_error}"),
+        }
+    }
+}
+
+impl std::error::Error for CancelError {}
+
+pub type OrderRepo = JsonRepo<Order, OrderId>;
 
 #[allow(async_fn_in_trait)]
 pub trait OrderRepoExt {
     async fn create_order(&self, order: Order) -> Result<OrderId, DbError>;
     async fn get_orders_for_user(&self, user_id: &UserId)
     -> Result<Vec<(OrderId, Order)>, DbError>;
+    async fn get_orders_for_symbol(&self, symbol: &str) -> Result<Vec<(OrderId, Order)>, DbError>;
 }
 
 impl OrderRepoExt for OrderRepo {
@@ -75,4 +102,8 @@ impl OrderRepoExt for OrderRepo {
         self.find_all_by_field("client_id", &user_id.to_string())
             .await
     }
+
+    async fn get_orders_for_symbol(&self, symbol: &str) -> Result<Vec<(OrderId, Order)>, DbError> {
+        self.find_all_by_field("symbol", symbol).await
+    }
 }
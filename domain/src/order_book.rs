@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::order::{Order, OrderSide, OrderStatus, OrderType};
+
+/// Resting quantity at one price
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PriceLevel {
+    pub price: f64,
+    pub quantity: u64,
+    /// Number of orders resting at this price
+    pub orders: usize,
+}
+
+/// Limit orders resting on an instrument, aggregated by price.
+/// Stop orders are not shown until they trigger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OrderBook {
+    pub symbol: String,
+    pub timestamp: DateTime<Utc>,
+    /// Best (highest) price first
+    pub bids: Vec<PriceLevel>,
+    /// Best (lowest) price first
+    pub asks: Vec<PriceLevel>,
+}
+
+impl OrderBook {
+    /// Aggregate the live limit orders of `symbol` into at most `depth` levels per side
+    #[must_use]
+    pub fn from_orders<'a>(
+        symbol: &str,
+        orders: impl IntoIterator<Item = &'a Order>,
+        depth: usize,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        // Prices are keyed in ticks of a thousandth of a cent so that equal prices
+        // computed differently still share a level
+        let mut bids: BTreeMap<i64, PriceLevel> = BTreeMap::new();
+        let mut asks: BTreeMap<i64, PriceLevel> = BTreeMap::new();
+
+        for order in orders {
+            let OrderType::Limit(price) = order.order_type else {
+                continue;
+            };
+            if order.symbol != symbol
+                || !matches!(order.status, OrderStatus::Queued | OrderStatus::Pending)
+            {
+                continue;
+            }
+            let side = match order.order_side {
+                OrderSide::Buy => &mut bids,
+                OrderSide::Sell => &mut asks,
+            };
+            let level = side
+                .entry((price * 100_000.0).round() as i64)
+                .or_insert(PriceLevel {
+                    price,
+                    quantity: 0,
+                    orders: 0,
+                });
+            level.quantity += order.quantity;
+            level.orders += 1;
+        }
+
+        Self {
+            symbol: symbol.to_string(),
+            timestamp,
+            bids: bids.into_values().rev().take(depth).collect(),
+            asks: asks.into_values().take(depth).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn order(symbol: &str, side: OrderSide, order_type: OrderType, quantity: u64) -> Order {
+        Order {
+            client_id: Uuid::new_v4(),
+            date: Utc::now(),
+            symbol: symbol.to_string(),
+            quantity,
+            status: OrderStatus::Pending,
+            order_type,
+            order_side: side,
+            is_administrative: false,
+        }
+    }
+
+    #[test]
+    fn test_limit_orders_are_aggregated_by_price() {
+        let mut filled = order("AAPL", OrderSide::Buy, OrderType::Limit(149.0), 7);
+        filled.status = OrderStatus::Filled {
+            date: Utc::now().naive_utc(),
+        };
+        let orders = [
+            order("AAPL", OrderSide::Buy, OrderType::Limit(148.5), 10),
+            order("AAPL", OrderSide::Buy, OrderType::Limit(149.0), 5),
+            order("AAPL", OrderSide::Buy, OrderType::Limit(149.0), 15),
+            order("AAPL", OrderSide::Buy, OrderType::Limit(147.0), 1),
+            order("AAPL", OrderSide::Sell, OrderType::Limit(151.0), 3),
+            order("AAPL", OrderSide::Sell, OrderType::Limit(150.5), 4),
+            order("AAPL", OrderSide::Sell, OrderType::Stop(140.0), 100),
+            order("AAPL", OrderSide::Buy, OrderType::Market, 100),
+            order("MSFT", OrderSide::Buy, OrderType::Limit(400.0), 100),
+            filled,
+        ];
+
+        let book = OrderBook::from_orders("AAPL", &orders, 2, Utc::now());
+
+        assert_eq!(
+            book.bids,
+            vec![
+                PriceLevel {
+                    price: 149.0,
+                    quantity: 20,
+                    orders: 2
+                },
+                PriceLevel {
+                    price: 148.5,
+                    quantity: 10,
+                    orders: 1
+                },
+            ]
+        );
+        assert_eq!(
+            book.asks,
+            vec![
+                PriceLevel {
+                    price: 150.5,
+                    quantity: 4,
+                    orders: 1
+                },
+                PriceLevel {
+                    price: 151.0,
+                    quantity: 3,
+                    orders: 1
+                },
+            ]
+        );
+    }
+}
@@ -1,139 +1,350 @@
-use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
 
-use database_adapter::db::Repository;
-use rand::random;
+use database_adapter::db::{DbError, PostgresStore, Repository, Store, Transaction};
+use database_adapter::queue::{JobQueue, PostgresQueue};
+use in_memory_adapter::{InMemoryQueue, InMemoryStore};
 use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
-use tracing::{debug, error, info};
-
-use crate::order::{Order, OrderId, OrderRepo, OrderSide, OrderStatus};
-use crate::user::{UserRepo, UserRepoExt};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::candles::{CandleAggregator, CandleRepo};
+use crate::clock::Clock;
+use crate::events::{DomainEvent, EventBus, PostgresOutbox};
+use crate::instrument::{InstrumentRepo, InstrumentRepoExt};
+use crate::market_data::{MarketDataConfig, MarketDataFeed, Quote, TradeTick};
+use crate::mfa_challenge::{ChallengeRepo, ChallengeRepoExt};
+use crate::order::{Order, OrderId, OrderRepo, OrderSide, OrderStatus, OrderType};
+use crate::password_reset::PasswordResetRepo;
+use crate::scheduling::OrderPriority;
+use crate::session::SessionRepo;
+use crate::singleton::spawn_singleton_job;
+use crate::user::{AuthError, User, UserId, UserRepo};
+
+/// How long a worker may hold an order before another worker can claim it
+const LEASE_DURATION: Duration = Duration::from_secs(30);
+/// Delay before an order whose processing failed is retried
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Delay before a pending order that cannot trade yet is checked again
+const PENDING_RECHECK_DELAY: Duration = Duration::from_secs(1);
+/// How often idle workers look for work when no notification arrives
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Orders still waiting for execution after this long are expired
+const ORDER_TIME_TO_LIVE: chrono::TimeDelta = chrono::TimeDelta::hours(24);
+/// How often the expiry job runs
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+/// How often expired second-factor challenges are purged
+const CHALLENGE_PURGE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Shared state between main task and order processing tasks
 #[derive(Debug)]
 pub struct SharedState {
     pub order_repo: OrderRepo,
     pub user_repo: UserRepo,
-    pub order_queue: VecDeque<OrderId>,
+    pub instrument_repo: InstrumentRepo,
+    pub candle_repo: CandleRepo,
+    pub session_repo: SessionRepo,
+    pub challenge_repo: ChallengeRepo,
+    pub password_reset_repo: PasswordResetRepo,
     pub is_running: bool,
 }
 
-/// Order processing task pool
+/// Order processing task pool.
+///
+/// The work queue lives in the database, so several instances can process
+/// orders from the same tables without processing an order twice.
 #[derive(Debug)]
 pub struct ProcessingPool {
-    _worker_handles: Vec<tokio::task::JoinHandle<()>>,
+    _worker_handles: Vec<JoinHandle<()>>,
+    background_handles: Vec<JoinHandle<()>>,
     pub shared_state: Arc<Mutex<SharedState>>,
+    pub event_bus: EventBus,
+    pub market_data: MarketDataFeed,
+    pub clock: Clock,
+    store: Arc<dyn Store>,
+    queue: Arc<dyn JobQueue>,
+    tables: PoolTables,
     work_available: Arc<Notify>,
     should_stop: Arc<Mutex<bool>>,
 }
 
+/// Everything a worker needs to process orders
+#[derive(Debug, Clone)]
+struct WorkerContext {
+    shared_state: Arc<Mutex<SharedState>>,
+    queue: Arc<dyn JobQueue>,
+    event_bus: EventBus,
+    market_data: MarketDataFeed,
+    clock: Clock,
+}
+
 #[derive(Debug)]
 enum ProcessingError {
     DbError,
 }
-impl ProcessingPool {
-    pub async fn new(num_threads: usize) -> Self {
-        let shared_state = Arc::new(Mutex::new(SharedState {
-            order_repo: OrderRepo::new("orders")
-                .await
-                .expect("orders repo failed to load"),
-            user_repo: UserRepo::new("users")
-                .await
-                .expect("users repo failed to load"),
-            order_queue: VecDeque::new(),
-            is_running: false,
-        }));
-
-        // Get all queued and pending orders from the database and add them to the queue
-        {
-            let mut state = shared_state.lock().await;
-            match state
-                .order_repo
-                .find_all_by_field("status", "Pending")
-                .await
-            {
-                Ok(orders) => {
-                    for (uuid, _order) in orders {
-                        state.order_queue.push_back(uuid);
-                    }
-                    info!(
-                        "Loaded {} queued/pending orders into processing queue",
-                        state.order_queue.len()
-                    );
-                }
-                Err(e) => {
-                    error!("Failed to load queued/pending orders: {}", e);
-                }
-            }
-        }
-
-        let work_available = Arc::new(Notify::new());
-        let should_stop = Arc::new(Mutex::new(false));
-        let mut worker_handles = Vec::new();
 
-        // Spawn worker tasks
-        for thread_id in 0..num_threads {
-            let shared_state_clone = Arc::clone(&shared_state);
-            let work_available_clone = Arc::clone(&work_available);
-            let should_stop_clone = Arc::clone(&should_stop);
+/// Adapters a pool is built from, besides its repositories
+struct WorkerContextParts {
+    store: Arc<dyn Store>,
+    queue: Arc<dyn JobQueue>,
+    tables: PoolTables,
+    event_bus: EventBus,
+    market_data: MarketDataFeed,
+    clock: Clock,
+}
 
-            let handle = tokio::spawn(async move {
-                Self::worker_task(
-                    thread_id,
-                    shared_state_clone,
-                    work_available_clone,
-                    should_stop_clone,
-                )
-                .await;
-            });
+/// Tables used by a processing pool
+#[derive(Debug, Clone)]
+struct PoolTables {
+    orders: String,
+    users: String,
+    instruments: String,
+    candles: String,
+    sessions: String,
+    challenges: String,
+    password_resets: String,
+    outbox: String,
+    queue: String,
+}
 
-            worker_handles.push(handle);
+impl Default for PoolTables {
+    fn default() -> Self {
+        Self {
+            orders: "orders".to_string(),
+            users: "users".to_string(),
+            instruments: "instruments".to_string(),
+            candles: "candles".to_string(),
+            sessions: "sessions".to_string(),
+            challenges: "mfa_challenges".to_string(),
+            password_resets: "password_resets".to_string(),
+            outbox: "event_outbox".to_string(),
+            queue: "order_queue".to_string(),
         }
+    }
+}
 
+impl ProcessingPool {
+    pub async fn new(num_threads: usize) -> Self {
+        let pool = Self::with_tables(
+            num_threads,
+            PoolTables::default(),
+            MarketDataConfig::from_env(),
+        )
+        .await;
+        pool.recover_orders().await;
         info!("Started order processing pool with {} tasks", num_threads);
-
-        Self {
-            _worker_handles: worker_handles,
-            shared_state,
-            work_available,
-            should_stop,
-        }
+        pool
     }
+
     /// Create `ProcessingPool` for testing with unique table names to avoid conflicts
     pub async fn new_for_testing(num_threads: usize) -> Self {
-        use uuid::Uuid;
         let test_id = Uuid::new_v4().simple().to_string();
-        let orders_table = format!("orders_test_{}", &test_id[..8]);
-        let users_table = format!("users_test_{}", &test_id[..8]);
+        let test_id = &test_id[..8];
+
+        Self::new_for_testing_with_tables(
+            num_threads,
+            PoolTables {
+                orders: format!("orders_test_{test_id}"),
+                users: format!("users_test_{test_id}"),
+                instruments: format!("instruments_test_{test_id}"),
+                candles: format!("candles_test_{test_id}"),
+                sessions: format!("sessions_test_{test_id}"),
+                challenges: format!("mfa_challenges_test_{test_id}"),
+                password_resets: format!("password_resets_test_{test_id}"),
+                outbox: format!("outbox_test_{test_id}"),
+                queue: format!("order_queue_test_{test_id}"),
+            },
+        )
+        .await
+    }
+
+    /// Create another test pool over the tables of this one, as a second instance of
+    /// the application would
+    pub async fn new_for_testing_sharing_tables(&self, num_threads: usize) -> Self {
+        Self::new_for_testing_with_tables(num_threads, self.tables.clone()).await
+    }
+
+    async fn new_for_testing_with_tables(num_threads: usize, tables: PoolTables) -> Self {
+        // Skip recovering existing orders for tests to keep them isolated
+        let pool = Self::with_tables(num_threads, tables, MarketDataConfig::default()).await;
+        info!(
+            "Started test order processing pool with {} tasks",
+            num_threads
+        );
+        pool
+    }
+
+    async fn with_tables(
+        num_threads: usize,
+        tables: PoolTables,
+        market_data_config: MarketDataConfig,
+    ) -> Self {
+        let store: Arc<dyn Store> = Arc::new(
+            PostgresStore::connect()
+                .await
+                .expect("database connection failed"),
+        );
+        let state = Self::shared_state(&store, &tables).await;
+        let challenge_repo = state.challenge_repo.clone();
+        let event_bus = EventBus::with_outbox(
+            PostgresOutbox::new(&tables.outbox)
+                .await
+                .expect("outbox failed to load"),
+        );
+        let queue = PostgresQueue::new(&tables.queue)
+            .await
+            .expect("order queue failed to load");
+
+        let mut pool = Self::from_parts(
+            num_threads,
+            state,
+            WorkerContextParts {
+                store,
+                queue: Arc::new(queue),
+                tables: tables.clone(),
+                event_bus,
+                market_data: MarketDataFeed::new(market_data_config),
+                clock: Clock::system(),
+            },
+        )
+        .await;
+
+        let listener_handle = tokio::spawn(Self::notification_task(
+            Arc::clone(&pool.queue),
+            Arc::clone(&pool.work_available),
+        ));
+
+        let expiry_handle = {
+            let context = pool.context();
+            spawn_singleton_job(
+                format!("{}_expiry", tables.orders),
+                EXPIRY_INTERVAL,
+                move || {
+                    let context = context.clone();
+                    async move {
+                        if let Err(e) = Self::expire_orders(&context, ORDER_TIME_TO_LIVE).await {
+                            error!("Order expiry failed: {}", e);
+                        }
+                    }
+                },
+            )
+        };
+
+        let purge_handle = spawn_singleton_job(
+            format!("{}_purge", tables.challenges),
+            CHALLENGE_PURGE_INTERVAL,
+            move || {
+                let challenge_repo = challenge_repo.clone();
+                async move {
+                    match challenge_repo.purge_all_expired(chrono::Utc::now()).await {
+                        Ok(0) => {}
+                        Ok(purged) => debug!("Purged {} expired MFA challenges", purged),
+                        Err(e) => error!("MFA challenge purge failed: {}", e),
+                    }
+                }
+            },
+        );
+
+        pool.background_handles
+            .extend([listener_handle, expiry_handle, purge_handle]);
+        pool.background_handles.extend(pool.event_bus.spawn_feed());
+        pool
+    }
 
-        let shared_state = Arc::new(Mutex::new(SharedState {
-            order_repo: OrderRepo::new(&orders_table)
+    /// Open the repositories of `tables` in `store`
+    async fn shared_state(store: &Arc<dyn Store>, tables: &PoolTables) -> SharedState {
+        SharedState {
+            order_repo: OrderRepo::new(Arc::clone(store), &tables.orders)
                 .await
                 .expect("orders repo failed to load"),
-            user_repo: UserRepo::new(&users_table)
+            user_repo: UserRepo::new(Arc::clone(store), &tables.users)
                 .await
                 .expect("users repo failed to load"),
-            order_queue: VecDeque::new(),
+            instrument_repo: InstrumentRepo::new(Arc::clone(store), &tables.instruments)
+                .await
+                .expect("instruments repo failed to load"),
+            candle_repo: CandleRepo::new(Arc::clone(store), &tables.candles)
+                .await
+                .expect("candles repo failed to load"),
+            session_repo: SessionRepo::new(Arc::clone(store), &tables.sessions)
+                .await
+                .expect("sessions repo failed to load"),
+            challenge_repo: ChallengeRepo::new(Arc::clone(store), &tables.challenges)
+                .await
+                .expect("MFA challenges repo failed to load"),
+            password_reset_repo: PasswordResetRepo::new(Arc::clone(store), &tables.password_resets)
+                .await
+                .expect("password resets repo failed to load"),
             is_running: false,
-        }));
+        }
+    }
+
+    /// Create a pool whose orders, users, instruments and queue live in memory.
+    ///
+    /// No worker task is started: orders are processed when `run_until_idle` is
+    /// called, following `clock`. Events are only published in-process, and neither
+    /// orders nor second-factor challenges are expired in the background. This runs the real order lifecycle without
+    /// a database, for backtests.
+    /// # Panics
+    /// Panics if the initial instruments cannot be listed
+    pub async fn in_memory(clock: Clock, market_data_config: MarketDataConfig) -> Self {
+        let store: Arc<dyn Store> = Arc::new(InMemoryStore::new());
+        let tables = PoolTables::default();
+        let state = Self::shared_state(&store, &tables).await;
+        let queue = InMemoryQueue::new(clock.time_source());
+
+        Self::from_parts(
+            0,
+            state,
+            WorkerContextParts {
+                store,
+                queue: Arc::new(queue),
+                tables,
+                event_bus: EventBus::new(),
+                market_data: MarketDataFeed::new(market_data_config),
+                clock,
+            },
+        )
+        .await
+    }
+
+    /// Seed the instruments, then start the market data feed, candle aggregation and
+    /// `num_threads` workers
+    async fn from_parts(num_threads: usize, state: SharedState, parts: WorkerContextParts) -> Self {
+        state
+            .instrument_repo
+            .seed_if_empty()
+            .await
+            .expect("instruments repo failed to seed");
+        let candle_handle =
+            CandleAggregator::new(state.candle_repo.clone()).spawn(&parts.market_data);
+        let market_data_handle = parts.market_data.spawn(state.instrument_repo.clone());
+        let context = WorkerContext {
+            shared_state: Arc::new(Mutex::new(state)),
+            queue: parts.queue,
+            event_bus: parts.event_bus,
+            market_data: parts.market_data,
+            clock: parts.clock,
+        };
 
-        // Skip loading existing orders for tests to keep them isolated
         let work_available = Arc::new(Notify::new());
         let should_stop = Arc::new(Mutex::new(false));
+        let instance_id = Uuid::new_v4().simple().to_string();
         let mut worker_handles = Vec::new();
 
         // Spawn worker tasks
         for thread_id in 0..num_threads {
-            let shared_state_clone = Arc::clone(&shared_state);
+            let worker_id = format!("{}-{thread_id}", &instance_id[..8]);
+            let context_clone = context.clone();
             let work_available_clone = Arc::clone(&work_available);
             let should_stop_clone = Arc::clone(&should_stop);
 
             let handle = tokio::spawn(async move {
                 Self::worker_task(
-                    thread_id,
-                    shared_state_clone,
+                    worker_id,
+                    context_clone,
                     work_available_clone,
                     should_stop_clone,
                 )
@@ -143,97 +354,200 @@ impl ProcessingPool {
             worker_handles.push(handle);
         }
 
-        info!(
-            "Started test order processing pool with {} tasks",
-            num_threads
-        );
-
         Self {
             _worker_handles: worker_handles,
-            shared_state,
+            background_handles: std::iter::once(candle_handle)
+                .chain(market_data_handle)
+                .collect(),
+            shared_state: context.shared_state,
+            event_bus: context.event_bus,
+            market_data: context.market_data,
+            clock: context.clock,
+            store: parts.store,
+            queue: context.queue,
+            tables: parts.tables,
             work_available,
             should_stop,
         }
     }
 
+    /// Store the pool's repositories live in, for components keeping their own tables
+    /// next to them
+    #[must_use]
+    pub fn store(&self) -> Arc<dyn Store> {
+        Arc::clone(&self.store)
+    }
+
+    fn context(&self) -> WorkerContext {
+        WorkerContext {
+            shared_state: Arc::clone(&self.shared_state),
+            queue: Arc::clone(&self.queue),
+            event_bus: self.event_bus.clone(),
+            market_data: self.market_data.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    /// Process orders in the calling task until none is due, and return how many
+    /// processing steps ran. Orders waiting for the market are due again once the
+    /// clock moved past their recheck delay.
+    pub async fn run_until_idle(&self) -> usize {
+        let context = self.context();
+        let mut steps = 0;
+        while Self::process_next(&context, "inline").await {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Queue orders that are waiting for processing but missing from the queue, such
+    /// as orders stored right before a crash
+    async fn recover_orders(&self) {
+        let state = self.shared_state.lock().await;
+        let mut recovered = 0;
+        for status in ["Queued", "Pending", "PendingCancel"] {
+            match state.order_repo.find_all_by_field("status", status).await {
+                Ok(orders) => {
+                    for (order_id, order) in orders {
+                        let priority = OrderPriority::for_order(&order);
+                        match self
+                            .queue
+                            .push_if_absent(order_id, order.client_id, priority.rank())
+                            .await
+                        {
+                            Ok(()) => recovered += 1,
+                            Err(e) => error!("Failed to queue order {}: {}", order_id, e),
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to load {} orders: {}", status, e);
+                }
+            }
+        }
+        info!("Checked {} unfinished orders against the queue", recovered);
+    }
+
     async fn worker_task(
-        thread_id: usize,
-        shared_state: Arc<Mutex<SharedState>>,
+        worker_id: String,
+        context: WorkerContext,
         work_available: Arc<Notify>,
         should_stop: Arc<Mutex<bool>>,
     ) {
-        debug!("Order processing task {} started", thread_id);
+        debug!("Order processing worker {} started", worker_id);
 
         loop {
             // Check if we should stop
-            {
-                let stop = should_stop.lock().await;
-                if *stop {
-                    debug!("Order processing task {} stopping", thread_id);
-                    break;
-                }
+            if *should_stop.lock().await {
+                debug!("Order processing worker {} stopping", worker_id);
+                break;
             }
 
-            // Get next order to process
-            let order_id = {
-                let mut state = shared_state.lock().await;
-
-                // Wait for work if queue is empty
-                while state.order_queue.is_empty() && state.is_running {
-                    let stop = should_stop.lock().await;
-                    if *stop {
-                        break;
-                    }
-                    drop(stop);
-                    drop(state);
-
-                    // Wait for notification or timeout
-                    tokio::select! {
-                        _ = work_available.notified() => {},
-                        _ = sleep(Duration::from_millis(1000)) => {},
-                    }
-
-                    state = shared_state.lock().await;
+            // Process next order
+            let is_running = context.shared_state.lock().await.is_running;
+            if !is_running || !Self::process_next(&context, &worker_id).await {
+                // Wait for notification or timeout
+                tokio::select! {
+                    () = work_available.notified() => {},
+                    () = sleep(IDLE_POLL_INTERVAL) => {},
                 }
+                continue;
+            }
 
-                // Check again if we should stop
-                let stop = should_stop.lock().await;
-                if *stop {
-                    break;
-                }
-                drop(stop);
+            // Add a small delay after processing to prevent tight loops
+            // This is especially important for orders that get re-queued
+            sleep(Duration::from_millis(10)).await;
+        }
 
-                state.order_queue.pop_front()
-            };
+        debug!("Order processing worker {} terminated", worker_id);
+    }
 
-            // Process the order if we got one
-            if let Some(order_id) = order_id {
-                if let Err(_) = Self::process_order(thread_id, order_id, &shared_state).await {
-                    error!("Task {} failed to process order {}", thread_id, order_id);
-                }
+    /// Claim the next due order and advance it. Returns false if no order was due.
+    async fn process_next(context: &WorkerContext, worker_id: &str) -> bool {
+        let queue = &context.queue;
+        let job = queue
+            .claim(worker_id, LEASE_DURATION)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Worker {} failed to claim an order: {}", worker_id, e);
+                None
+            });
+        let Some(job) = job else {
+            return false;
+        };
 
-                // Add a small delay after processing to prevent tight loops
-                // This is especially important for orders that get re-queued
-                sleep(Duration::from_millis(10)).await;
-            } else {
-                // No work available, sleep longer to reduce CPU usage when idle
-                sleep(Duration::from_millis(100)).await;
+        let result = match Self::process_order(context, worker_id, job.job_id).await {
+            Ok(Some((priority, delay))) => {
+                queue
+                    .release(job.job_id, worker_id, priority.rank(), delay)
+                    .await
             }
+            Ok(None) => queue.complete(job.job_id, worker_id).await.map(|_| ()),
+            Err(_) => {
+                error!(
+                    "Worker {} failed to process order {}",
+                    worker_id, job.job_id
+                );
+                queue
+                    .release(job.job_id, worker_id, job.priority, RETRY_DELAY)
+                    .await
+            }
+        };
+        if let Err(e) = result {
+            error!(
+                "Worker {} failed to update queue entry of order {}: {}",
+                worker_id, job.job_id, e
+            );
         }
+        true
+    }
 
-        debug!("Order processing task {} terminated", thread_id);
+    /// Wake up workers when any instance queues an order
+    async fn notification_task(queue: Arc<dyn JobQueue>, work_available: Arc<Notify>) {
+        loop {
+            match queue.listen().await {
+                Ok(mut listener) => loop {
+                    if let Err(e) = listener.wait().await {
+                        warn!("Lost order queue notifications: {}", e);
+                        break;
+                    }
+                    work_available.notify_one();
+                },
+                Err(e) => error!("Failed to listen for queued orders: {}", e),
+            }
+            sleep(IDLE_POLL_INTERVAL).await;
+        }
     }
 
+    /// Advance an order through its lifecycle. Returns the lane to re-queue it in, and
+    /// after which delay, if it needs further processing.
     async fn process_order(
-        thread_id: usize,
+        context: &WorkerContext,
+        worker_id: &str,
         order_id: OrderId,
-        shared_state: &Arc<Mutex<SharedState>>,
-    ) -> Result<(), ProcessingError> {
-        let mut state = shared_state.lock().await;
-
-        if let Some(mut order) = state
+    ) -> Result<Option<(OrderPriority, Duration)>, ProcessingError> {
+        let WorkerContext {
+            shared_state,
+            event_bus,
+            market_data,
+            clock,
+            ..
+        } = context;
+        let state = shared_state.lock().await;
+        let mut events = Vec::new();
+        let mut trade = None;
+        let mut requeue = None;
+        let mut settled_user = None;
+
+        // The order and the account stay locked until the outcome is committed, so a
+        // cancel or a deposit on another instance waits for it instead of being lost
+        let mut tx = state
             .order_repo
-            .get(&order_id)
+            .begin()
+            .await
+            .map_err(|_e| ProcessingError::DbError)?;
+        if let Some(mut order) = tx
+            .get_for_update(&state.order_repo, &order_id)
             .await
             .map_err(|_e| ProcessingError::DbError)?
         {
@@ -241,112 +555,234 @@ impl ProcessingPool {
 
             match &order.status {
                 OrderStatus::Queued => {
-                    debug!("Task {} processing queued order {}", thread_id, order_id);
+                    debug!("Worker {} processing queued order {}", worker_id, order_id);
                     // Move to pending status
                     order.status = OrderStatus::Pending;
                     // Re-queue for further processing
-                    state.order_queue.push_back(order_id);
+                    requeue = Some((OrderPriority::for_order(&order), Duration::ZERO));
                 }
                 OrderStatus::Pending => {
-                    debug!("Task {} executing pending order {}", thread_id, order_id);
-                    // Simulate order matching with randomization
-                    match random::<u32>() % 4 {
-                        0 => {
-                            let execution_price = 100.0;
-
-                            let funds_result = match order.order_side {
-                                OrderSide::Buy => {
-                                    // Deduct funds from user's account
-                                    state
-                                        .user_repo
-                                        .withdraw_from_user(
-                                            &order.client_id,
-                                            execution_price * order.quantity as f64,
-                                        )
-                                        .await
+                    debug!("Worker {} executing pending order {}", worker_id, order_id);
+                    let quote = match tx
+                        .get(&state.instrument_repo, &order.symbol)
+                        .await
+                        .map_err(|_e| ProcessingError::DbError)?
+                    {
+                        Some(instrument) if instrument.is_tradable_at(clock.now()) => {
+                            Some(market_data.quote(&instrument))
+                        }
+                        Some(_) => None,
+                        None => {
+                            warn!(
+                                "Worker {} rejecting order {} on unknown instrument {}",
+                                worker_id, order_id, order.symbol
+                            );
+                            order.status = OrderStatus::Rejected {
+                                date: clock.now().naive_local(),
+                            };
+                            None
+                        }
+                    };
+
+                    match quote.and_then(|quote| Self::execution_price(&order, &quote)) {
+                        Some(execution_price) => {
+                            match Self::settle_fill(&state, &mut tx, &order, execution_price).await
+                            {
+                                Ok((user, funds_event)) => {
+                                    settled_user = Some(user);
+                                    events.push(funds_event);
+                                    events.push(DomainEvent::OrderFilled {
+                                        order_id,
+                                        client_id: order.client_id,
+                                        symbol: order.symbol.clone(),
+                                        quantity: order.quantity,
+                                        order_side: order.order_side.clone(),
+                                        price: execution_price,
+                                    });
+                                    trade = Some(TradeTick {
+                                        symbol: order.symbol.clone(),
+                                        price: execution_price,
+                                        quantity: order.quantity,
+                                        timestamp: clock.now(),
+                                    });
+                                    // Order filled completely
+                                    order.status = OrderStatus::Filled {
+                                        date: clock.now().naive_local(),
+                                    };
+                                    info!(
+                                        "Worker {} filled order {} completely",
+                                        worker_id, order_id
+                                    );
                                 }
-                                OrderSide::Sell => {
-                                    // Add funds to user's account
-                                    state
-                                        .user_repo
-                                        .deposit_to_user(
-                                            &order.client_id,
-                                            execution_price * order.quantity as f64,
-                                        )
-                                        .await
+                                Err(e) => {
+                                    // Failed to update user funds or holdings, reject order
+                                    order.status = OrderStatus::Rejected {
+                                        date: clock.now().naive_local(),
+                                    };
+                                    error!(
+                                        "Worker {} rejected order {}: {}",
+                                        worker_id, order_id, e
+                                    );
                                 }
-                            };
-
-                            if funds_result.is_ok() {
-                                Self::update_portfolio_for_filled_order_async(
-                                    &state,
-                                    &order,
-                                    execution_price,
-                                )
-                                .await;
-                                // Order filled completely
-                                order.status = OrderStatus::Filled {
-                                    date: chrono::Utc::now().naive_local(),
-                                };
-                                info!("Task {} filled order {} completely", thread_id, order_id);
-                            } else {
-                                // Failed to update user funds, reject order
-                                order.status = OrderStatus::Rejected {
-                                    date: chrono::Utc::now().naive_local(),
-                                };
-                                error!(
-                                    "Task {} rejected order {} due to insufficient funds",
-                                    thread_id, order_id
-                                );
                             }
                         }
-                        _ => {
-                            // Keep pending, re-queue
-                            state.order_queue.push_back(order_id);
+                        None if matches!(order.status, OrderStatus::Pending) => {
+                            // Market closed or limit not reached yet, check again later
+                            requeue =
+                                Some((OrderPriority::for_order(&order), PENDING_RECHECK_DELAY));
                         }
+                        None => {}
                     }
                 }
                 OrderStatus::PendingCancel => {
-                    debug!("Task {} cancelling order {}", thread_id, order_id);
+                    debug!("Worker {} cancelling order {}", worker_id, order_id);
                     order.status = OrderStatus::Cancelled;
-                    info!("Task {} cancelled order {}", thread_id, order_id);
+                    info!("Worker {} cancelled order {}", worker_id, order_id);
                 }
                 _ => {
                     error!(
-                        "Task {} encountered order {} in unexpected state: {}",
-                        thread_id, order_id, old_status
+                        "Worker {} encountered order {} in unexpected state: {}",
+                        worker_id, order_id, old_status
                     );
                 }
             }
 
-            state
-                .order_repo
-                .update(order_id, order)
+            if format!("{:?}", order.status) != old_status {
+                events.push(DomainEvent::OrderStatusChanged {
+                    order_id,
+                    client_id: order.client_id,
+                    status: order.status.clone(),
+                });
+            }
+
+            // The account, the order and their events are written together, so a fill
+            // is never recorded on one side only
+            let client_id = order.client_id;
+            if let Some(user) = settled_user {
+                tx.update(&state.user_repo, client_id, user)
+                    .await
+                    .map_err(|_e| ProcessingError::DbError)?;
+            }
+            tx.update(&state.order_repo, order_id, order)
+                .await
+                .map_err(|_e| ProcessingError::DbError)?;
+            event_bus
+                .commit(tx, events)
                 .await
                 .map_err(|_e| ProcessingError::DbError)?;
+            // Executions print on the tape once they are recorded
+            if let Some(trade) = trade {
+                market_data.publish_trade(trade);
+            }
         } else {
             error!(
-                "Task {} could not find order {} in repository",
-                thread_id, order_id
+                "Worker {} could not find order {} in repository",
+                worker_id, order_id
             );
         }
-        Ok(())
+        Ok(requeue)
     }
 
-    /// Submit a new order for processing
-    pub async fn submit_order(&self, order_id: OrderId) {
-        let mut state = self.shared_state.lock().await;
-        state.order_queue.push_back(order_id);
-        state.is_running = true;
+    /// Price at which an order trades against `quote`, if it is marketable.
+    /// Marketable limit orders trade at the quote, which is at least as good as the limit.
+    /// Triggered stop orders trade at the quote like market orders.
+    fn execution_price(order: &Order, quote: &Quote) -> Option<f64> {
+        let price = quote.price_for(&order.order_side);
+        match (&order.order_type, &order.order_side) {
+            (OrderType::Market, _) => Some(price),
+            (OrderType::Limit(limit), OrderSide::Buy) => (price <= *limit).then_some(price),
+            (OrderType::Limit(limit), OrderSide::Sell) => (price >= *limit).then_some(price),
+            (OrderType::Stop(stop), OrderSide::Buy) => (price >= *stop).then_some(price),
+            (OrderType::Stop(stop), OrderSide::Sell) => (price <= *stop).then_some(price),
+        }
+    }
+
+    /// Submit an order for processing in the given scheduling lane
+    /// # Errors
+    /// Returns `DbError` if the order could not be queued
+    pub async fn submit_order(
+        &self,
+        order_id: OrderId,
+        client_id: UserId,
+        priority: OrderPriority,
+    ) -> Result<(), DbError> {
+        self.queue
+            .push(order_id, client_id, priority.rank())
+            .await?;
+        self.shared_state.lock().await.is_running = true;
 
         // Notify worker tasks that work is available
         self.work_available.notify_one();
 
-        debug!(
-            "Submitted order {} to processing pool (queue size: {})",
-            order_id,
-            state.order_queue.len()
-        );
+        debug!("Submitted order {} to processing pool", order_id);
+        Ok(())
+    }
+
+    /// Set how many consecutive orders a client may have processed per scheduling round
+    /// # Errors
+    /// Returns `DbError` if the weight could not be stored
+    pub async fn set_client_weight(&self, client_id: UserId, weight: u32) -> Result<(), DbError> {
+        self.queue.set_group_weight(client_id, weight).await
+    }
+
+    /// Expire orders that have been waiting for execution for longer than `max_age`.
+    /// This normally runs on the leader instance only, every `EXPIRY_INTERVAL`.
+    /// # Errors
+    /// Returns `DbError` if the orders could not be loaded or updated
+    pub async fn expire_orders_older_than(
+        &self,
+        max_age: chrono::TimeDelta,
+    ) -> Result<usize, DbError> {
+        Self::expire_orders(&self.context(), max_age).await
+    }
+
+    async fn expire_orders(
+        context: &WorkerContext,
+        max_age: chrono::TimeDelta,
+    ) -> Result<usize, DbError> {
+        let WorkerContext {
+            shared_state,
+            queue,
+            event_bus,
+            clock,
+            ..
+        } = context;
+        let state = shared_state.lock().await;
+        let cutoff = clock.now() - max_age;
+        let mut expired = 0;
+
+        for status in ["Queued", "Pending"] {
+            for (order_id, order) in state.order_repo.find_all_by_field("status", status).await? {
+                if order.date > cutoff {
+                    continue;
+                }
+                // Re-read under lock, a worker may have filled or cancelled it since
+                let mut tx = state.order_repo.begin().await?;
+                let Some(mut order) = tx.get_for_update(&state.order_repo, &order_id).await? else {
+                    continue;
+                };
+                if !matches!(order.status, OrderStatus::Queued | OrderStatus::Pending) {
+                    continue;
+                }
+                order.status = OrderStatus::Expired {
+                    date: clock.now().naive_local(),
+                };
+                let event = DomainEvent::OrderStatusChanged {
+                    order_id,
+                    client_id: order.client_id,
+                    status: order.status.clone(),
+                };
+                tx.update(&state.order_repo, order_id, order).await?;
+                event_bus.commit(tx, vec![event]).await?;
+                queue.remove(order_id).await?;
+                expired += 1;
+            }
+        }
+
+        if expired > 0 {
+            info!("Expired {} orders older than {}", expired, max_age);
+        }
+        Ok(expired)
     }
 
     /// Start processing orders
@@ -370,57 +806,74 @@ impl ProcessingPool {
 
         // Wake up all waiting tasks
         self.work_available.notify_waiters();
+        for handle in &self.background_handles {
+            handle.abort();
+        }
 
         info!("Order processing pool stop signal sent");
     }
 
-    /// Update portfolio when an order is filled
-    async fn update_portfolio_for_filled_order_async(
+    /// Move funds and holdings for a filled order, locking the account in `tx`. Returns
+    /// the updated account and the funds event, to be committed with the order.
+    async fn settle_fill(
         state: &SharedState,
+        tx: &mut Transaction,
         order: &Order,
         execution_price: f64,
-    ) {
-        let quantity_change = match order.order_side {
-            OrderSide::Buy => order.quantity as i64,
-            OrderSide::Sell => -(order.quantity as i64),
-        };
-
-        // Update the user's holdings
-        match state.user_repo.get(&order.client_id).await {
-            Ok(Some(mut user)) => {
-                user.update_holding(&order.symbol, quantity_change, execution_price);
-                if let Err(e) = state.user_repo.update(order.client_id, user).await {
-                    error!(
-                        "Failed to save updated user {} after order {}: {}",
-                        order.client_id, order.symbol, e
-                    );
-                } else {
-                    info!(
-                        "Updated portfolio for user {}: {} {} shares of {} at ${}",
-                        order.client_id,
-                        if quantity_change > 0 {
-                            "bought"
-                        } else {
-                            "sold"
-                        },
-                        quantity_change.abs(),
-                        order.symbol,
-                        execution_price
-                    );
-                }
-            }
-            Ok(None) => {
-                error!(
-                    "User {} not found when trying to update holdings after order {}",
-                    order.client_id, order.symbol
-                );
+    ) -> Result<(User, DomainEvent), AuthError> {
+        let mut user = tx
+            .get_for_update(&state.user_repo, &order.client_id)
+            .await
+            .map_err(AuthError::UserRepo)?
+            .ok_or(AuthError::UserNotFound)?;
+
+        let notional = execution_price * order.quantity as f64;
+        let (quantity_change, funds_event) = match order.order_side {
+            OrderSide::Buy => {
+                // Deduct funds from user's account
+                user.withdraw(notional)
+                    .map_err(|_e| AuthError::NotEnoughMoneyError)?;
+                (
+                    order.quantity as i64,
+                    DomainEvent::FundsWithdrawn {
+                        user_id: order.client_id,
+                        amount: notional,
+                        balance: user.get_balance(),
+                    },
+                )
             }
-            Err(e) => {
-                error!(
-                    "Failed to load user {} for portfolio update after order {}: {}",
-                    order.client_id, order.symbol, e
-                );
+            OrderSide::Sell => {
+                // Only shares held can be sold
+                let held = user.holdings.get(&order.symbol).map_or(0, |h| h.quantity);
+                if held < order.quantity {
+                    return Err(AuthError::NotEnoughSharesError);
+                }
+                // Add funds to user's account
+                user.deposit(notional);
+                (
+                    -(order.quantity as i64),
+                    DomainEvent::FundsDeposited {
+                        user_id: order.client_id,
+                        amount: notional,
+                        balance: user.get_balance(),
+                    },
+                )
             }
-        }
+        };
+        user.update_holding(&order.symbol, quantity_change, execution_price);
+
+        debug!(
+            "Settling fill for user {}: {} {} shares of {} at ${}",
+            order.client_id,
+            if quantity_change > 0 {
+                "buying"
+            } else {
+                "selling"
+            },
+            quantity_change.abs(),
+            order.symbol,
+            execution_price
+        );
+        Ok((user, funds_event))
     }
 }
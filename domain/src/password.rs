@@ -0,0 +1,179 @@
+//! Password hashing with Argon2id, stored in PHC string format.
+//!
+//! Hashes written before Argon2id was introduced are `hash_` followed by the password.
+//! They still verify, and `needs_rehash` reports them so they are replaced the next
+//! time the user logs in.
+
+use std::sync::LazyLock;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+const LEGACY_PREFIX: &str = "hash_";
+const SALT_LENGTH: usize = 16;
+
+static CONFIG: LazyLock<HashConfig> = LazyLock::new(HashConfig::from_env);
+
+/// Cost parameters of Argon2id. Raising them makes existing hashes be rehashed on the
+/// next login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashConfig {
+    /// Memory used per hash, in KiB
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashConfig {
+    /// OWASP's recommendation: 19 MiB, 2 iterations, 1 lane
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl HashConfig {
+    /// Read `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`, falling
+    /// back to the defaults for missing values and to all defaults for invalid ones
+    #[must_use]
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let config = Self {
+            memory_kib: var("ARGON2_MEMORY_KIB", default.memory_kib),
+            iterations: var("ARGON2_ITERATIONS", default.iterations),
+            parallelism: var("ARGON2_PARALLELISM", default.parallelism),
+        };
+        match config.params() {
+            Ok(_) => config,
+            Err(e) => {
+                warn!("Invalid Argon2 parameters {config:?} ({e}), using the defaults");
+                default
+            }
+        }
+    }
+
+    fn params(&self) -> Result<Params, argon2::Error> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        let params = self
+            .params()
+            .expect("Argon2 parameters are validated when the configuration is read");
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+
+    /// Hash `password` with a random salt
+    #[must_use]
+    pub fn hash(&self, password: &str) -> String {
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::rng().fill_bytes(&mut salt);
+        let salt = SaltString::encode_b64(&salt).expect("the salt length is valid");
+        self.hasher()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("hashing cannot fail with valid parameters and salt")
+            .to_string()
+    }
+
+    /// Whether `stored` was not hashed with Argon2id and these parameters
+    #[must_use]
+    pub fn needs_rehash(&self, stored: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(stored) else {
+            return true;
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&hash).map_or(true, |params| {
+                params.m_cost() != self.memory_kib
+                    || params.t_cost() != self.iterations
+                    || params.p_cost() != self.parallelism
+            })
+    }
+}
+
+/// Hash `password` with the configured parameters
+#[must_use]
+pub fn hash(password: &str) -> String {
+    CONFIG.hash(password)
+}
+
+/// Whether `password` matches `stored`, a PHC string or a legacy hash
+#[must_use]
+pub fn verify(password: &str, stored: &str) -> bool {
+    if let Some(legacy) = stored.strip_prefix(LEGACY_PREFIX) {
+        // Compared in constant time, so the time taken does not reveal a matching prefix
+        return legacy.as_bytes().ct_eq(password.as_bytes()).into();
+    }
+    // Parameters come from the PHC string, not from the configuration
+    PasswordHash::new(stored).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Whether `stored` should be replaced by a hash with the configured parameters
+#[must_use]
+pub fn needs_rehash(stored: &str) -> bool {
+    CONFIG.needs_rehash(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters, the tests are about the format
+    const CONFIG: HashConfig = HashConfig {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_hash_is_salted_phc_string() {
+        let first = CONFIG.hash("correct horse");
+        let second = CONFIG.hash("correct horse");
+        assert!(first.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert_ne!(first, second);
+        assert!(!first.contains("correct horse"));
+
+        assert!(verify("correct horse", &first));
+        assert!(verify("correct horse", &second));
+        assert!(!verify("battery staple", &first));
+    }
+
+    #[test]
+    fn test_legacy_hashes_verify_and_need_rehash() {
+        assert!(verify("aaaaaa", "hash_aaaaaa"));
+        assert!(!verify("bbbbbb", "hash_aaaaaa"));
+        assert!(!verify("aaa", "hash_aaaaaa"));
+        assert!(!verify("aaaaaaa", "hash_aaaaaa"));
+        assert!(CONFIG.needs_rehash("hash_aaaaaa"));
+        assert!(!verify("", "garbage"));
+    }
+
+    #[test]
+    fn test_changed_parameters_need_rehash() {
+        let hash = CONFIG.hash("correct horse");
+        assert!(!CONFIG.needs_rehash(&hash));
+
+        let stronger = HashConfig {
+            iterations: 2,
+            ..CONFIG
+        };
+        assert!(stronger.needs_rehash(&hash));
+        // Hashes with other parameters still verify
+        assert!(verify("correct horse", &hash));
+    }
+}
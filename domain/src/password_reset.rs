@@ -0,0 +1,223 @@
+//! Single-use tokens letting users who forgot their password choose a new one.
+//!
+//! Asking for a reset emails a link carrying a token, `<reset id>.<secret>`, of which
+//! only a hash is stored. The token can be used once, before it expires. Asking again
+//! replaces the links sent before.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use database_adapter::db::{DbError, JsonRepo, Repository};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::session::{hash_secret, new_secret};
+use crate::user::{AuthError, UserId};
+
+pub type PasswordResetId = Uuid;
+pub type PasswordResetRepo = JsonRepo<PasswordReset, PasswordResetId>;
+
+/// How long a reset link can be followed
+pub const PASSWORD_RESET_TTL: TimeDelta = TimeDelta::minutes(30);
+
+/// A reset asked for by a user, until it is used or expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub user_id: UserId,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// When the password was reset, or the reset replaced by a newer one
+    pub used_at: Option<DateTime<Utc>>,
+    token_hash: String,
+}
+
+#[derive(Debug)]
+pub enum PasswordResetError {
+    InvalidToken,
+    Expired,
+    Used,
+    /// The new password was refused, the token can still be used
+    Auth(AuthError),
+    Repo(DbError),
+}
+
+impl std::fmt::Display for PasswordResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordResetError::InvalidToken => write!(f, "Invalid password reset link"),
+            PasswordResetError::Expired => write!(f, "Password reset link expired"),
+            PasswordResetError::Used => write!(f, "Password reset link already used"),
+            PasswordResetError::Auth(err) => write!(f, "{err}"),
+            PasswordResetError::Repo(err) => {
+                write!(f, "Password reset repository error: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PasswordResetError {}
+
+impl From<DbError> for PasswordResetError {
+    fn from(error: DbError) -> Self {
+        PasswordResetError::Repo(error)
+    }
+}
+
+impl From<AuthError> for PasswordResetError {
+    fn from(error: AuthError) -> Self {
+        PasswordResetError::Auth(error)
+    }
+}
+
+fn reset_token(reset_id: PasswordResetId, secret: &str) -> String {
+    format!("{}.{secret}", reset_id.simple())
+}
+
+#[allow(async_fn_in_trait)]
+pub trait PasswordResetRepoExt {
+    /// Open a reset lasting `ttl`, replacing the pending ones of the user. Returns
+    /// the token to send them.
+    async fn issue_reset(&self, user_id: UserId, ttl: TimeDelta) -> Result<String, DbError>;
+    /// Find the pending reset of `token`, without using it
+    async fn pending_reset(
+        &self,
+        token: &str,
+    ) -> Result<(PasswordResetId, PasswordReset), PasswordResetError>;
+    /// Mark the reset of `token` used. Returns the user whose password may be reset.
+    async fn use_reset(&self, token: &str) -> Result<UserId, PasswordResetError>;
+}
+
+impl PasswordResetRepoExt for PasswordResetRepo {
+    async fn issue_reset(&self, user_id: UserId, ttl: TimeDelta) -> Result<String, DbError> {
+        let now = Utc::now();
+        for (reset_id, reset) in self
+            .find_all_by_field("user_id", &user_id.to_string())
+            .await?
+        {
+            if reset.used_at.is_none() {
+                self.set_field_if_null(&reset_id, "used_at", now).await?;
+            }
+        }
+
+        let reset_id = Uuid::new_v4();
+        let secret = new_secret();
+        let reset = PasswordReset {
+            user_id,
+            created_at: now,
+            expires_at: now + ttl,
+            used_at: None,
+            token_hash: hash_secret(&secret),
+        };
+        self.insert(reset_id, reset).await?;
+        Ok(reset_token(reset_id, &secret))
+    }
+
+    async fn pending_reset(
+        &self,
+        token: &str,
+    ) -> Result<(PasswordResetId, PasswordReset), PasswordResetError> {
+        let (reset_id, secret) = token
+            .split_once('.')
+            .ok_or(PasswordResetError::InvalidToken)?;
+        let reset_id = Uuid::parse_str(reset_id).map_err(|_| PasswordResetError::InvalidToken)?;
+        let reset = self
+            .get(&reset_id)
+            .await?
+            .ok_or(PasswordResetError::InvalidToken)?;
+
+        if hash_secret(secret) != reset.token_hash {
+            return Err(PasswordResetError::InvalidToken);
+        }
+        if reset.used_at.is_some() {
+            return Err(PasswordResetError::Used);
+        }
+        if Utc::now() >= reset.expires_at {
+            return Err(PasswordResetError::Expired);
+        }
+        Ok((reset_id, reset))
+    }
+
+    async fn use_reset(&self, token: &str) -> Result<UserId, PasswordResetError> {
+        let (reset_id, reset) = self.pending_reset(token).await?;
+        // Only one of concurrent uses of the token sees it still unused
+        match self
+            .set_field_if_null(&reset_id, "used_at", Utc::now())
+            .await?
+        {
+            0 => Err(PasswordResetError::Used),
+            _ => Ok(reset.user_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_reset_token_can_be_used_once() {
+        let repo = PasswordResetRepo::new(Arc::new(InMemoryStore::new()), "password_resets")
+            .await
+            .unwrap();
+        let user_id = Uuid::new_v4();
+        let token = repo.issue_reset(user_id, PASSWORD_RESET_TTL).await.unwrap();
+
+        let (reset_id, reset) = repo.pending_reset(&token).await.unwrap();
+        assert_eq!(reset.user_id, user_id);
+        // Only the hash of the secret is stored
+        let stored = serde_json::to_string(&repo.get(&reset_id).await.unwrap()).unwrap();
+        assert!(!stored.contains(token.split_once('.').unwrap().1));
+
+        assert_eq!(repo.use_reset(&token).await.unwrap(), user_id);
+        assert!(matches!(
+            repo.use_reset(&token).await,
+            Err(PasswordResetError::Used)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_new_reset_replaces_pending_ones() {
+        let repo = PasswordResetRepo::new(Arc::new(InMemoryStore::new()), "password_resets")
+            .await
+            .unwrap();
+        let user_id = Uuid::new_v4();
+        let first = repo.issue_reset(user_id, PASSWORD_RESET_TTL).await.unwrap();
+        let other_user = repo
+            .issue_reset(Uuid::new_v4(), PASSWORD_RESET_TTL)
+            .await
+            .unwrap();
+        let second = repo.issue_reset(user_id, PASSWORD_RESET_TTL).await.unwrap();
+
+        assert!(matches!(
+            repo.pending_reset(&first).await,
+            Err(PasswordResetError::Used)
+        ));
+        assert!(repo.pending_reset(&second).await.is_ok());
+        assert!(repo.pending_reset(&other_user).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_forged_tokens_are_refused() {
+        let repo = PasswordResetRepo::new(Arc::new(InMemoryStore::new()), "password_resets")
+            .await
+            .unwrap();
+        let expired = repo
+            .issue_reset(Uuid::new_v4(), TimeDelta::seconds(-1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            repo.use_reset(&expired).await,
+            Err(PasswordResetError::Expired)
+        ));
+
+        let (reset_id, _) = expired.split_once('.').unwrap();
+        let forged = format!("{reset_id}.{}", "0".repeat(64));
+        let unknown = format!("{}.secret", Uuid::new_v4().simple());
+        for token in ["", "no-separator", "not-a-uuid.secret", &forged, &unknown] {
+            assert!(matches!(
+                repo.use_reset(token).await,
+                Err(PasswordResetError::InvalidToken)
+            ));
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
 
+use crate::instrument::{Instrument, InstrumentStatus};
 use crate::order::{OrderSide, OrderType};
 
 /// Pre-trade validation errors
@@ -22,9 +23,20 @@ pub enum PreTradeError {
         requested: f64,
     },
     InvalidQuantity,
+    UnknownInstrument {
+        symbol: String,
+    },
     InactiveInstrument {
         symbol: String,
     },
+    MarketClosed {
+        symbol: String,
+    },
+    InvalidLotSize {
+        symbol: String,
+        quantity: u64,
+        lot_size: u64,
+    },
     InvalidTickSize {
         symbol: String,
         price: f64,
@@ -60,9 +72,25 @@ impl std::fmt::Display for PreTradeError {
                 )
             }
             PreTradeError::InvalidQuantity => write!(f, "Invalid quantity: must be greater than 0"),
+            PreTradeError::UnknownInstrument { symbol } => {
+                write!(f, "Unknown instrument {symbol}")
+            }
             PreTradeError::InactiveInstrument { symbol } => {
                 write!(f, "Instrument {symbol} is not active")
             }
+            PreTradeError::MarketClosed { symbol } => {
+                write!(f, "Market for {symbol} is closed")
+            }
+            PreTradeError::InvalidLotSize {
+                symbol,
+                quantity,
+                lot_size,
+            } => {
+                write!(
+                    f,
+                    "Invalid lot size for {symbol}: quantity {quantity} is not a multiple of {lot_size}"
+                )
+            }
             PreTradeError::InvalidTickSize {
                 symbol,
                 price,
@@ -82,45 +110,32 @@ impl std::fmt::Display for PreTradeError {
 
 impl std::error::Error for PreTradeError {}
 
-/// Configuration for pre-trade validation rules
+/// Configuration for pre-trade validation rules.
+/// Per-instrument rules come from the instrument master.
 #[derive(Debug, Clone)]
 pub struct PreTradeConfig {
     pub max_position_size: u64,
     pub max_notional_per_order: f64,
-    pub active_instruments: Vec<String>,
-    pub tick_sizes: HashMap<String, f64>,
-    pub price_bands: HashMap<String, (f64, f64)>, // (min, max)
 }
 
 impl Default for PreTradeConfig {
     fn default() -> Self {
-        let mut tick_sizes = HashMap::new();
-        tick_sizes.insert("AAPL".to_string(), 0.01);
-        tick_sizes.insert("GOOGL".to_string(), 0.01);
-        tick_sizes.insert("MSFT".to_string(), 0.01);
-        tick_sizes.insert("TSLA".to_string(), 0.01);
-
-        let mut price_bands = HashMap::new();
-        price_bands.insert("AAPL".to_string(), (1.0, 1000.0));
-        price_bands.insert("GOOGL".to_string(), (1.0, 5000.0));
-        price_bands.insert("MSFT".to_string(), (1.0, 1000.0));
-        price_bands.insert("TSLA".to_string(), (1.0, 2000.0));
-
         Self {
             max_position_size: 10000,
             max_notional_per_order: 100_000_000.0,
-            active_instruments: vec![
-                "AAPL".to_string(),
-                "GOOGL".to_string(),
-                "MSFT".to_string(),
-                "TSLA".to_string(),
-            ],
-            tick_sizes,
-            price_bands,
         }
     }
 }
 
+/// Account and market state an order is validated against
+#[derive(Debug, Clone)]
+pub struct PreTradeContext {
+    pub user_balance: f64,
+    /// Price a market order would currently trade at
+    pub market_price: f64,
+    pub at: DateTime<Utc>,
+}
+
 /// Pre-trade validation service
 #[derive(Debug)]
 pub struct PreTradeValidator {
@@ -136,16 +151,16 @@ impl PreTradeValidator {
         Self::new(PreTradeConfig::default())
     }
 
-    /// Validates an order against pre-trade rules
+    /// Validates an order on `instrument` against pre-trade rules
     /// # Errors
     /// Returns `PreTradeError` if any validation fails
     pub fn validate_order(
         &self,
+        instrument: &Instrument,
         order_side: &OrderSide,
         order_type: &OrderType,
-        symbol: &str,
         quantity: u64,
-        user_balance: f64,
+        context: &PreTradeContext,
     ) -> Result<(), PreTradeError> {
         // Sanity check: quantity > 0
         if quantity == 0 {
@@ -153,9 +168,25 @@ impl PreTradeValidator {
         }
 
         // Check if instrument is active
-        if !self.config.active_instruments.contains(&symbol.to_string()) {
+        if instrument.status != InstrumentStatus::Active {
             return Err(PreTradeError::InactiveInstrument {
-                symbol: symbol.to_string(),
+                symbol: instrument.symbol.clone(),
+            });
+        }
+
+        // Check trading hours
+        if !instrument.trading_calendar.is_open_at(context.at) {
+            return Err(PreTradeError::MarketClosed {
+                symbol: instrument.symbol.clone(),
+            });
+        }
+
+        // Check lot size
+        if !quantity.is_multiple_of(instrument.lot_size.max(1)) {
+            return Err(PreTradeError::InvalidLotSize {
+                symbol: instrument.symbol.clone(),
+                quantity,
+                lot_size: instrument.lot_size,
             });
         }
 
@@ -167,14 +198,20 @@ impl PreTradeValidator {
             });
         }
 
-        // Price validation for limit orders
-        if let OrderType::Limit(price) = order_type {
-            self.validate_limit_order_price(symbol, *price, quantity, order_side, user_balance)?;
+        // Price validation for limit and stop orders
+        if let OrderType::Limit(price) | OrderType::Stop(price) = order_type {
+            self.validate_limit_order_price(
+                instrument,
+                *price,
+                quantity,
+                order_side,
+                context.user_balance,
+            )?;
         }
 
         // For market orders, validate with estimated prices
         if matches!(order_type, OrderType::Market) {
-            self.validate_market_order(symbol, quantity, order_side, user_balance)?;
+            self.validate_market_order(quantity, order_side, context)?;
         }
 
         Ok(())
@@ -182,33 +219,31 @@ impl PreTradeValidator {
 
     fn validate_limit_order_price(
         &self,
-        symbol: &str,
+        instrument: &Instrument,
         price: f64,
         quantity: u64,
         order_side: &OrderSide,
         user_balance: f64,
     ) -> Result<(), PreTradeError> {
         // Check price bands
-        if let Some((min_price, max_price)) = self.config.price_bands.get(symbol) {
-            if price < *min_price || price > *max_price {
-                return Err(PreTradeError::InvalidPrice {
-                    reason: format!(
-                        "Price {price:.2} outside allowed band [{min_price:.2}, {max_price:.2}]"
-                    ),
-                });
-            }
+        let band = instrument.price_band;
+        if price < band.min || price > band.max {
+            return Err(PreTradeError::InvalidPrice {
+                reason: format!(
+                    "Price {price:.2} outside allowed band [{:.2}, {:.2}]",
+                    band.min, band.max
+                ),
+            });
         }
 
         // Check tick size alignment
-        if let Some(tick_size) = self.config.tick_sizes.get(symbol) {
-            let remainder = (price / tick_size) % 1.0;
-            if remainder.abs() > f64::EPSILON {
-                return Err(PreTradeError::InvalidTickSize {
-                    symbol: symbol.to_string(),
-                    price,
-                    tick_size: *tick_size,
-                });
-            }
+        let remainder = (price / instrument.tick_size) % 1.0;
+        if remainder.abs() > f64::EPSILON {
+            return Err(PreTradeError::InvalidTickSize {
+                symbol: instrument.symbol.clone(),
+                price,
+                tick_size: instrument.tick_size,
+            });
         }
 
         // Notional value check
@@ -233,13 +268,13 @@ impl PreTradeValidator {
 
     fn validate_market_order(
         &self,
-        symbol: &str,
         quantity: u64,
         order_side: &OrderSide,
-        user_balance: f64,
+        context: &PreTradeContext,
     ) -> Result<(), PreTradeError> {
-        // Estimate with reasonable market price for basic checks
-        let estimated_price = self.get_estimated_price(symbol);
+        // Estimate with the current market price for basic checks
+        let estimated_price = context.market_price;
+        let user_balance = context.user_balance;
         let estimated_notional = estimated_price * (quantity as f64);
 
         if estimated_notional > self.config.max_notional_per_order {
@@ -258,31 +293,47 @@ impl PreTradeValidator {
 
         Ok(())
     }
-
-    fn get_estimated_price(&self, symbol: &str) -> f64 {
-        match symbol {
-            "AAPL" => 150.0,
-            "GOOGL" => 2800.0,
-            "MSFT" => 420.0,
-            "TSLA" => 245.0,
-            _ => 100.0, // Default estimate
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instrument::{PriceBand, TradingCalendar};
+
+    fn context(user_balance: f64) -> PreTradeContext {
+        PreTradeContext {
+            user_balance,
+            market_price: 150.0,
+            at: Utc::now(),
+        }
+    }
+
+    fn aapl() -> Instrument {
+        Instrument {
+            symbol: "AAPL".to_string(),
+            name: "Apple Inc.".to_string(),
+            currency: "USD".to_string(),
+            tick_size: 0.01,
+            lot_size: 1,
+            price_band: PriceBand {
+                min: 1.0,
+                max: 1000.0,
+            },
+            reference_price: 150.0,
+            status: InstrumentStatus::Active,
+            trading_calendar: TradingCalendar::always_open(),
+        }
+    }
 
     #[test]
     fn test_valid_limit_buy_order() {
         let validator = PreTradeValidator::with_default_config();
         let result = validator.validate_order(
+            &aapl(),
             &OrderSide::Buy,
             &OrderType::Limit(150.50),
-            "AAPL",
             100,
-            20000.0,
+            &context(20000.0),
         );
         assert!(result.is_ok());
     }
@@ -291,11 +342,11 @@ mod tests {
     fn test_insufficient_buying_power() {
         let validator = PreTradeValidator::with_default_config();
         let result = validator.validate_order(
+            &aapl(),
             &OrderSide::Buy,
             &OrderType::Limit(150.50),
-            "AAPL",
             100,
-            1000.0, // Not enough for 100 * 150.50 = 15,050
+            &context(1000.0), // Not enough for 100 * 150.50 = 15,050
         );
         assert!(matches!(
             result,
@@ -307,11 +358,11 @@ mod tests {
     fn test_invalid_quantity() {
         let validator = PreTradeValidator::with_default_config();
         let result = validator.validate_order(
+            &aapl(),
             &OrderSide::Buy,
             &OrderType::Limit(150.50),
-            "AAPL",
             0, // Invalid quantity
-            20000.0,
+            &context(20000.0),
         );
         assert!(matches!(result, Err(PreTradeError::InvalidQuantity)));
     }
@@ -319,12 +370,14 @@ mod tests {
     #[test]
     fn test_inactive_instrument() {
         let validator = PreTradeValidator::with_default_config();
+        let mut instrument = aapl();
+        instrument.status = InstrumentStatus::Halted;
         let result = validator.validate_order(
+            &instrument,
             &OrderSide::Buy,
             &OrderType::Limit(50.0),
-            "INVALID", // Not in active instruments
             100,
-            20000.0,
+            &context(20000.0),
         );
         assert!(matches!(
             result,
@@ -336,11 +389,83 @@ mod tests {
     fn test_price_outside_bands() {
         let validator = PreTradeValidator::with_default_config();
         let result = validator.validate_order(
+            &aapl(),
             &OrderSide::Buy,
             &OrderType::Limit(2000.0), // Outside AAPL band (1.0, 1000.0)
-            "AAPL",
             100,
-            300_000.0,
+            &context(300_000.0),
+        );
+        assert!(matches!(result, Err(PreTradeError::InvalidPrice { .. })));
+    }
+
+    #[test]
+    fn test_quantity_not_multiple_of_lot_size() {
+        let validator = PreTradeValidator::with_default_config();
+        let mut instrument = aapl();
+        instrument.lot_size = 10;
+        let result = validator.validate_order(
+            &instrument,
+            &OrderSide::Buy,
+            &OrderType::Market,
+            15,
+            &context(20000.0),
+        );
+        assert!(matches!(result, Err(PreTradeError::InvalidLotSize { .. })));
+    }
+
+    #[test]
+    fn test_market_closed() {
+        let validator = PreTradeValidator::with_default_config();
+        let mut instrument = aapl();
+        instrument.trading_calendar.trading_days.clear();
+        let result = validator.validate_order(
+            &instrument,
+            &OrderSide::Buy,
+            &OrderType::Market,
+            10,
+            &context(20000.0),
+        );
+        assert!(matches!(result, Err(PreTradeError::MarketClosed { .. })));
+    }
+
+    #[test]
+    fn test_market_order_uses_market_price() {
+        let validator = PreTradeValidator::with_default_config();
+        let context = PreTradeContext {
+            market_price: 500.0,
+            ..context(2000.0)
+        };
+        // 10 * 500 exceeds the balance, 10 * 150 would not
+        let result =
+            validator.validate_order(&aapl(), &OrderSide::Buy, &OrderType::Market, 10, &context);
+        assert!(matches!(
+            result,
+            Err(PreTradeError::InsufficientBuyingPower { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stop_price_is_validated() {
+        let validator = PreTradeValidator::with_default_config();
+        // The buying power is checked at the stop price
+        let result = validator.validate_order(
+            &aapl(),
+            &OrderSide::Buy,
+            &OrderType::Stop(160.0),
+            100,
+            &context(15000.0),
+        );
+        assert!(matches!(
+            result,
+            Err(PreTradeError::InsufficientBuyingPower { .. })
+        ));
+
+        let result = validator.validate_order(
+            &aapl(),
+            &OrderSide::Sell,
+            &OrderType::Stop(2000.0),
+            10,
+            &context(0.0),
         );
         assert!(matches!(result, Err(PreTradeError::InvalidPrice { .. })));
     }
@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::instrument::{Instrument, InstrumentRepo, InstrumentRepoExt};
+use crate::market_data::{MarketDataFeed, Quote, TradeTick};
+
+/// Bar length assumed when a symbol has a single bar
+const DEFAULT_BAR_INTERVAL: TimeDelta = TimeDelta::minutes(1);
+
+/// Errors raised while loading recorded market data
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+    InvalidRecord {
+        line: usize,
+        reason: String,
+    },
+    UnsupportedFormat(PathBuf),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Cannot read market data file: {e}"),
+            ReplayError::Csv(e) => write!(f, "Invalid CSV market data: {e}"),
+            ReplayError::Json { line, error } => {
+                write!(f, "Invalid JSON market data on line {line}: {error}")
+            }
+            ReplayError::InvalidRecord { line, reason } => {
+                write!(f, "Invalid market data record on line {line}: {reason}")
+            }
+            ReplayError::UnsupportedFormat(path) => write!(
+                f,
+                "Unsupported market data file {}, expected .csv or .jsonl",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ReplayError {
+    fn from(e: csv::Error) -> Self {
+        ReplayError::Csv(e)
+    }
+}
+
+/// Layout of a recorded market data file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl ReplayFormat {
+    /// Guess the format from the file extension
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Replay settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    /// Recorded seconds replayed per real second, `f64::INFINITY` to replay without pauses
+    pub speed: f64,
+    /// Records before this timestamp are skipped
+    pub start: Option<DateTime<Utc>>,
+    /// Records after this timestamp are skipped
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl ReplayConfig {
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            speed: 1.0,
+            start: None,
+            end: None,
+        }
+    }
+
+    /// Read `MARKET_DATA_REPLAY_*` variables. Returns `None` unless
+    /// `MARKET_DATA_REPLAY_FILE` is set.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let mut config = Self::new(std::env::var("MARKET_DATA_REPLAY_FILE").ok()?);
+        if let Ok(speed) = std::env::var("MARKET_DATA_REPLAY_SPEED") {
+            match speed.parse::<f64>() {
+                Ok(speed) if speed > 0.0 => config.speed = speed,
+                _ => warn!("Ignoring invalid MARKET_DATA_REPLAY_SPEED value: {speed}"),
+            }
+        }
+        for (name, bound) in [
+            ("MARKET_DATA_REPLAY_START", &mut config.start),
+            ("MARKET_DATA_REPLAY_END", &mut config.end),
+        ] {
+            if let Ok(value) = std::env::var(name) {
+                match parse_timestamp(&value) {
+                    Some(timestamp) => *bound = Some(timestamp),
+                    None => warn!("Ignoring invalid {name} value: {value}"),
+                }
+            }
+        }
+        Some(config)
+    }
+}
+
+/// One recorded price of an instrument
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayEvent {
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub price: f64,
+    pub quantity: Option<u64>,
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+}
+
+/// Record as found in a file: either a tick with a `price` or an OHLC bar
+#[derive(Debug, Deserialize)]
+struct RawRecord {
+    timestamp: String,
+    symbol: String,
+    #[serde(default)]
+    price: Option<f64>,
+    #[serde(default)]
+    quantity: Option<u64>,
+    #[serde(default)]
+    bid: Option<f64>,
+    #[serde(default)]
+    ask: Option<f64>,
+    #[serde(default)]
+    open: Option<f64>,
+    #[serde(default)]
+    high: Option<f64>,
+    #[serde(default)]
+    low: Option<f64>,
+    #[serde(default)]
+    close: Option<f64>,
+    #[serde(default)]
+    volume: Option<f64>,
+}
+
+/// Record with its timestamp parsed, before bars are expanded into prices
+#[derive(Debug)]
+enum Record {
+    Tick(ReplayEvent),
+    Bar {
+        timestamp: DateTime<Utc>,
+        symbol: String,
+        /// Open, high, low and close, in the order they are replayed
+        path: [f64; 4],
+        volume: Option<f64>,
+    },
+}
+
+impl Record {
+    fn parse(raw: RawRecord, line: usize) -> Result<Self, ReplayError> {
+        let invalid = |reason: String| ReplayError::InvalidRecord { line, reason };
+        let timestamp = parse_timestamp(&raw.timestamp)
+            .ok_or_else(|| invalid(format!("invalid timestamp {}", raw.timestamp)))?;
+
+        if let Some(price) = raw.price {
+            if price <= 0.0 {
+                return Err(invalid(format!("price {price} is not positive")));
+            }
+            return Ok(Record::Tick(ReplayEvent {
+                timestamp,
+                symbol: raw.symbol,
+                price,
+                quantity: raw.quantity,
+                bid: raw.bid,
+                ask: raw.ask,
+            }));
+        }
+
+        match (raw.open, raw.high, raw.low, raw.close) {
+            (Some(open), Some(high), Some(low), Some(close)) => {
+                if low <= 0.0
+                    || ![open, close]
+                        .iter()
+                        .all(|price| (low..=high).contains(price))
+                {
+                    return Err(invalid("inconsistent OHLC values".to_string()));
+                }
+                // Usual path assumption: up bars visit the low first, down bars the high
+                let path = if close >= open {
+                    [open, low, high, close]
+                } else {
+                    [open, high, low, close]
+                };
+                Ok(Record::Bar {
+                    timestamp,
+                    symbol: raw.symbol,
+                    path,
+                    volume: raw.volume,
+                })
+            }
+            _ => Err(invalid(
+                "expected a price or open, high, low and close".to_string(),
+            )),
+        }
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            Record::Tick(event) => event.timestamp,
+            Record::Bar { timestamp, .. } => *timestamp,
+        }
+    }
+
+    fn symbol(&self) -> &str {
+        match self {
+            Record::Tick(event) => &event.symbol,
+            Record::Bar { symbol, .. } => symbol,
+        }
+    }
+}
+
+/// Parse an RFC 3339 timestamp, a UTC `YYYY-MM-DD HH:MM:SS` date time or a
+/// `YYYY-MM-DD` date
+#[must_use]
+pub fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|timestamp| timestamp.and_utc())
+}
+
+/// Recorded prices ready to be replayed into a `MarketDataFeed`
+#[derive(Debug, Clone)]
+pub struct MarketReplay {
+    events: Vec<ReplayEvent>,
+    speed: f64,
+}
+
+impl MarketReplay {
+    /// Load the file described by `config`, keeping the records between its start and
+    /// end timestamps
+    /// # Errors
+    /// Returns `ReplayError` if the file cannot be read or holds an invalid record
+    pub fn load(config: &ReplayConfig) -> Result<Self, ReplayError> {
+        let format = ReplayFormat::from_path(&config.path)
+            .ok_or_else(|| ReplayError::UnsupportedFormat(config.path.clone()))?;
+        let file = File::open(&config.path)?;
+        let mut replay = Self::from_reader(file, format, config)?;
+        replay.speed = config.speed;
+        Ok(replay)
+    }
+
+    /// Parse recorded data in `format`, keeping the records between the start and end
+    /// timestamps of `config`
+    /// # Errors
+    /// Returns `ReplayError` if the data cannot be read or holds an invalid record
+    pub fn from_reader(
+        reader: impl std::io::Read,
+        format: ReplayFormat,
+        config: &ReplayConfig,
+    ) -> Result<Self, ReplayError> {
+        let mut records = match format {
+            ReplayFormat::Csv => {
+                let mut csv = csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    .from_reader(reader);
+                let mut records = Vec::new();
+                for (index, raw) in csv.deserialize::<RawRecord>().enumerate() {
+                    // The header is line 1
+                    records.push(Record::parse(raw?, index + 2)?);
+                }
+                records
+            }
+            ReplayFormat::JsonLines => {
+                let mut records = Vec::new();
+                for (index, line) in BufReader::new(reader).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let raw = serde_json::from_str::<RawRecord>(&line).map_err(|error| {
+                        ReplayError::Json {
+                            line: index + 1,
+                            error,
+                        }
+                    })?;
+                    records.push(Record::parse(raw, index + 1)?);
+                }
+                records
+            }
+        };
+
+        // Stable, so records sharing a timestamp keep the file order
+        records.sort_by_key(Record::timestamp);
+        let events = Self::expand(records)
+            .into_iter()
+            .filter(|event| config.start.is_none_or(|start| event.timestamp >= start))
+            .filter(|event| config.end.is_none_or(|end| event.timestamp <= end))
+            .collect();
+
+        Ok(Self {
+            events,
+            speed: config.speed,
+        })
+    }
+
+    /// Turn bars into their open, high, low and close prices, spread over the bar
+    fn expand(records: Vec<Record>) -> Vec<ReplayEvent> {
+        // Bar length of each symbol, from the gap to its next bar
+        let mut next_bar: HashMap<String, DateTime<Utc>> = HashMap::new();
+        let mut intervals = vec![None; records.len()];
+        for (index, record) in records.iter().enumerate().rev() {
+            if let Record::Bar { timestamp, .. } = record {
+                intervals[index] = next_bar
+                    .insert(record.symbol().to_string(), *timestamp)
+                    .map(|next| next - *timestamp);
+            }
+        }
+
+        let mut last_interval: HashMap<String, TimeDelta> = HashMap::new();
+        let mut events = Vec::new();
+        for (record, interval) in records.into_iter().zip(intervals) {
+            match record {
+                Record::Tick(event) => events.push(event),
+                Record::Bar {
+                    timestamp,
+                    symbol,
+                    path,
+                    volume,
+                } => {
+                    let interval = interval
+                        .or_else(|| last_interval.get(&symbol).copied())
+                        .unwrap_or(DEFAULT_BAR_INTERVAL);
+                    last_interval.insert(symbol.clone(), interval);
+                    // The volume is reported with the close
+                    let quantity = volume.map(|volume| volume.round() as u64);
+                    for (step, price) in path.into_iter().enumerate() {
+                        events.push(ReplayEvent {
+                            timestamp: timestamp + interval * step as i32 / 4,
+                            symbol: symbol.clone(),
+                            price,
+                            quantity: if step == 3 { quantity } else { None },
+                            bid: None,
+                            ask: None,
+                        });
+                    }
+                }
+            }
+        }
+        // Spreading bars can interleave them with later ticks of other symbols
+        events.sort_by_key(|event| event.timestamp);
+        events
+    }
+
+    #[must_use]
+    pub fn events(&self) -> &[ReplayEvent] {
+        &self.events
+    }
+
+    /// Publish one recorded price to the feed, quoting around it when the record
+    /// has no bid or ask
+    pub fn apply(feed: &MarketDataFeed, instrument: &Instrument, event: &ReplayEvent) {
+        let half_spread = feed.half_spread(instrument);
+        let on_tick = |price: f64| (price / instrument.tick_size).round() * instrument.tick_size;
+        let last = on_tick(event.price);
+
+        if let Some(quantity) = event.quantity {
+            feed.publish_trade(TradeTick {
+                symbol: event.symbol.clone(),
+                price: last,
+                quantity,
+                timestamp: event.timestamp,
+            });
+        }
+        feed.publish_quote(Quote {
+            symbol: event.symbol.clone(),
+            bid: event.bid.unwrap_or_else(|| on_tick(last - half_spread)),
+            ask: event.ask.unwrap_or_else(|| on_tick(last + half_spread)),
+            last,
+            timestamp: event.timestamp,
+        });
+    }
+
+    /// Publish every recorded price to the feed, pausing between them according to
+    /// the replay speed. Records of unlisted instruments are skipped.
+    pub async fn run(self, feed: MarketDataFeed, instrument_repo: InstrumentRepo) {
+        info!(
+            "Replaying {} market data records at {}x",
+            self.events.len(),
+            self.speed
+        );
+        let mut instruments: HashMap<String, Option<Instrument>> = HashMap::new();
+        let mut previous: Option<DateTime<Utc>> = None;
+
+        for event in &self.events {
+            if let Some(previous) = previous {
+                let gap = (event.timestamp - previous).as_seconds_f64() / self.speed;
+                if gap > 0.0 {
+                    tokio::time::sleep(Duration::from_secs_f64(gap)).await;
+                }
+            }
+            previous = Some(event.timestamp);
+
+            if !instruments.contains_key(&event.symbol) {
+                let instrument = match instrument_repo.get_instrument(&event.symbol).await {
+                    Ok(instrument) => instrument,
+                    Err(e) => {
+                        error!("Failed to load instrument {}: {}", event.symbol, e);
+                        continue;
+                    }
+                };
+                if instrument.is_none() {
+                    warn!("Skipping replayed prices of unlisted {}", event.symbol);
+                }
+                instruments.insert(event.symbol.clone(), instrument);
+            }
+            if let Some(Some(instrument)) = instruments.get(&event.symbol) {
+                Self::apply(&feed, instrument, event);
+            }
+        }
+        info!("Market data replay finished");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::{InstrumentStatus, PriceBand, TradingCalendar};
+    use crate::market_data::{MarketDataConfig, MarketDataEvent};
+
+    fn load(data: &str, format: ReplayFormat, config: &ReplayConfig) -> MarketReplay {
+        MarketReplay::from_reader(data.as_bytes(), format, config).unwrap()
+    }
+
+    fn aapl() -> Instrument {
+        Instrument {
+            symbol: "AAPL".to_string(),
+            name: "Apple Inc.".to_string(),
+            currency: "USD".to_string(),
+            tick_size: 0.01,
+            lot_size: 1,
+            price_band: PriceBand {
+                min: 1.0,
+                max: 1000.0,
+            },
+            reference_price: 150.0,
+            status: InstrumentStatus::Active,
+            trading_calendar: TradingCalendar::always_open(),
+        }
+    }
+
+    #[test]
+    fn test_csv_ticks_are_sorted_and_filtered() {
+        let data = "timestamp,symbol,price,quantity,bid,ask
+2024-03-01T14:30:02Z,AAPL,151.0,100,,
+2024-03-01T14:30:00Z,AAPL,150.0,,149.9,150.1
+2024-03-01 14:30:01,MSFT,420.5,10,,
+2024-03-01T14:30:03Z,AAPL,152.0,5,,
+";
+        let all = load(data, ReplayFormat::Csv, &ReplayConfig::new("ticks.csv"));
+        let prices: Vec<f64> = all.events().iter().map(|event| event.price).collect();
+        assert_eq!(prices, vec![150.0, 420.5, 151.0, 152.0]);
+        assert_eq!(all.events()[0].bid, Some(149.9));
+        assert_eq!(all.events()[0].quantity, None);
+
+        let config = ReplayConfig {
+            start: parse_timestamp("2024-03-01T14:30:01Z"),
+            end: parse_timestamp("2024-03-01T14:30:02Z"),
+            ..ReplayConfig::new("ticks.csv")
+        };
+        let window = load(data, ReplayFormat::Csv, &config);
+        let symbols: Vec<&str> = window.events().iter().map(|e| e.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["MSFT", "AAPL"]);
+    }
+
+    #[test]
+    fn test_bars_are_spread_over_their_interval() {
+        let data = r#"{"timestamp": "2024-03-01T14:30:00Z", "symbol": "AAPL", "open": 150.0, "high": 152.0, "low": 149.0, "close": 151.0, "volume": 1200}
+
+{"timestamp": "2024-03-01T14:31:00Z", "symbol": "AAPL", "open": 151.0, "high": 151.5, "low": 148.0, "close": 148.5}
+"#;
+        let replay = load(
+            data,
+            ReplayFormat::JsonLines,
+            &ReplayConfig::new("bars.jsonl"),
+        );
+        let prices: Vec<f64> = replay.events().iter().map(|event| event.price).collect();
+        // Up bar visits the low first, down bar the high first
+        assert_eq!(
+            prices,
+            vec![150.0, 149.0, 152.0, 151.0, 151.0, 151.5, 148.0, 148.5]
+        );
+        // The last bar reuses the one minute interval of the previous one
+        let start = parse_timestamp("2024-03-01T14:30:00Z").unwrap();
+        let offsets: Vec<i64> = replay
+            .events()
+            .iter()
+            .map(|event| (event.timestamp - start).num_seconds())
+            .collect();
+        assert_eq!(offsets, vec![0, 15, 30, 45, 60, 75, 90, 105]);
+        assert_eq!(replay.events()[3].quantity, Some(1200));
+    }
+
+    #[test]
+    fn test_invalid_records_are_reported() {
+        let config = ReplayConfig::new("bad.csv");
+        let missing_price = "timestamp,symbol,quantity\n2024-03-01,AAPL,10\n";
+        let result =
+            MarketReplay::from_reader(missing_price.as_bytes(), ReplayFormat::Csv, &config);
+        assert!(matches!(
+            result,
+            Err(ReplayError::InvalidRecord { line: 2, .. })
+        ));
+
+        let bad_bar = "{\"timestamp\": \"2024-03-01\", \"symbol\": \"AAPL\", \"open\": 10, \"high\": 9, \"low\": 8, \"close\": 9}";
+        let result =
+            MarketReplay::from_reader(bad_bar.as_bytes(), ReplayFormat::JsonLines, &config);
+        assert!(matches!(
+            result,
+            Err(ReplayError::InvalidRecord { line: 1, .. })
+        ));
+
+        assert_eq!(ReplayFormat::from_path(Path::new("data.txt")), None);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_prices_update_the_feed() {
+        let feed = MarketDataFeed::new(MarketDataConfig::default());
+        let mut receiver = feed.subscribe();
+        let data = "timestamp,symbol,price,quantity\n2024-03-01T14:30:00Z,AAPL,123.456,50\n";
+        let replay = load(data, ReplayFormat::Csv, &ReplayConfig::new("ticks.csv"));
+
+        MarketReplay::apply(&feed, &aapl(), &replay.events()[0]);
+
+        let quote = feed.last_quote("AAPL").unwrap();
+        assert!((quote.last - 123.46).abs() < 1e-9);
+        assert!((quote.bid - 123.45).abs() < 1e-9);
+        assert!((quote.ask - 123.47).abs() < 1e-9);
+        // Later quotes of the instrument come from the replayed price
+        assert_eq!(feed.quote(&aapl()), quote);
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(MarketDataEvent::Trade(TradeTick { quantity: 50, .. }))
+        ));
+        assert!(matches!(receiver.try_recv(), Ok(MarketDataEvent::Quote(_))));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::order::{Order, OrderStatus};
+
+/// Scheduling lane of an order, from highest to lowest priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderPriority {
+    /// Administrative orders such as liquidations
+    Administrative,
+    /// Orders waiting to be cancelled
+    Cancel,
+    /// Regular client orders, scheduled fairly across clients
+    Normal,
+}
+
+impl OrderPriority {
+    /// Lane an order should be scheduled in given its current state
+    #[must_use]
+    pub fn for_order(order: &Order) -> Self {
+        if matches!(order.status, OrderStatus::PendingCancel) {
+            OrderPriority::Cancel
+        } else if order.is_administrative {
+            OrderPriority::Administrative
+        } else {
+            OrderPriority::Normal
+        }
+    }
+
+    /// Priority of the lane in the work queue, lower is served first
+    #[must_use]
+    pub fn rank(self) -> i16 {
+        match self {
+            OrderPriority::Administrative => 0,
+            OrderPriority::Cancel => 1,
+            OrderPriority::Normal => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{OrderSide, OrderType};
+    use uuid::Uuid;
+
+    fn order(status: OrderStatus, is_administrative: bool) -> Order {
+        Order {
+            client_id: Uuid::new_v4(),
+            date: chrono::Utc::now(),
+            symbol: "AAPL".to_string(),
+            quantity: 1,
+            status,
+            order_type: OrderType::Market,
+            order_side: OrderSide::Buy,
+            is_administrative,
+        }
+    }
+
+    #[test]
+    fn test_lane_for_order() {
+        assert_eq!(
+            OrderPriority::for_order(&order(OrderStatus::Pending, false)),
+            OrderPriority::Normal
+        );
+        assert_eq!(
+            OrderPriority::for_order(&order(OrderStatus::Queued, true)),
+            OrderPriority::Administrative
+        );
+        // A cancel request moves even an administrative order to the cancel lane
+        assert_eq!(
+            OrderPriority::for_order(&order(OrderStatus::PendingCancel, true)),
+            OrderPriority::Cancel
+        );
+    }
+
+    #[test]
+    fn test_rank_follows_lane_order() {
+        let lanes = [
+            OrderPriority::Administrative,
+            OrderPriority::Cancel,
+            OrderPriority::Normal,
+        ];
+        for pair in lanes.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].rank() < pair[1].rank());
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Server-side sessions behind the short-lived access tokens.
+//!
+//! Logging in opens a session and hands out a refresh token, `<session id>.<secret>`,
+//! of which only a hash is stored. Every refresh replaces the secret. A secret that
+//! was already replaced can only come from a copy of the token, so presenting it
+//! revokes the session. Refreshes and revocations lock the session, so of two
+//! refreshes with the same token only one gets a new token.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use database_adapter::db::{DbError, JsonRepo, Repository};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::user::UserId;
+
+pub type SessionId = Uuid;
+pub type SessionRepo = JsonRepo<Session, SessionId>;
+
+const SECRET_LENGTH: usize = 32;
+
+/// A login on one device, until it expires or is revoked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: UserId,
+    pub created_at: DateTime<Utc>,
+    /// Last time the refresh token was used
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Client the session was opened from, as reported by its `User-Agent`
+    pub user_agent: Option<String>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    refresh_token_hash: String,
+}
+
+impl Session {
+    #[must_use]
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && now < self.expires_at
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    InvalidToken,
+    Expired,
+    Revoked,
+    /// A refresh token that was already used, the session has been revoked
+    Reused,
+    Repo(DbError),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::InvalidToken => write!(f, "Invalid refresh token"),
+            SessionError::Expired => write!(f, "Session expired"),
+            SessionError::Revoked => write!(f, "Session revoked"),
+            SessionError::Reused => write!(f, "Refresh token reused, session revoked"),
+            SessionError::Repo(err) => write!(f, "Session repository error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<DbError> for SessionError {
+    fn from(error: DbError) -> Self {
+        SessionError::Repo(error)
+    }
+}
+
+pub(crate) fn new_secret() -> String {
+    let mut secret = [0u8; SECRET_LENGTH];
+    rand::rng().fill_bytes(&mut secret);
+    hex(&secret)
+}
+
+pub(crate) fn hash_secret(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+fn refresh_token(session_id: SessionId, secret: &str) -> String {
+    format!("{}.{secret}", session_id.simple())
+}
+
+#[allow(async_fn_in_trait)]
+pub trait SessionRepoExt {
+    /// Open a session lasting `ttl`. Returns its ID and refresh token.
+    async fn open_session(
+        &self,
+        user_id: UserId,
+        user_agent: Option<String>,
+        ttl: TimeDelta,
+    ) -> Result<(SessionId, String), DbError>;
+    /// Replace the refresh token of a session. Returns the session and the new token.
+    async fn refresh_session(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(SessionId, Session, String), SessionError>;
+    async fn is_session_active(&self, session_id: &SessionId) -> Result<bool, DbError>;
+    /// Active sessions of a user, most recently used first
+    async fn active_sessions(&self, user_id: &UserId)
+    -> Result<Vec<(SessionId, Session)>, DbError>;
+    async fn revoke_session(&self, session_id: &SessionId) -> Result<(), DbError>;
+    /// Returns the number of sessions revoked
+    async fn revoke_all_sessions(&self, user_id: &UserId) -> Result<usize, DbError>;
+}
+
+impl SessionRepoExt for SessionRepo {
+    async fn open_session(
+        &self,
+        user_id: UserId,
+        user_agent: Option<String>,
+        ttl: TimeDelta,
+    ) -> Result<(SessionId, String), DbError> {
+        let now = Utc::now();
+        let session_id = Uuid::new_v4();
+        let secret = new_secret();
+        let session = Session {
+            user_id,
+            created_at: now,
+            last_used_at: now,
+            expires_at: now + ttl,
+            user_agent,
+            revoked_at: None,
+            refresh_token_hash: hash_secret(&secret),
+        };
+        self.insert(session_id, session).await?;
+        Ok((session_id, refresh_token(session_id, &secret)))
+    }
+
+    async fn refresh_session(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(SessionId, Session, String), SessionError> {
+        let (session_id, secret) = refresh_token
+            .split_once('.')
+            .ok_or(SessionError::InvalidToken)?;
+        let session_id = Uuid::parse_str(session_id).map_err(|_| SessionError::InvalidToken)?;
+        let mut tx = self.begin().await?;
+        let mut session = tx
+            .get_for_update(self, &session_id)
+            .await?
+            .ok_or(SessionError::InvalidToken)?;
+
+        let now = Utc::now();
+        if session.revoked_at.is_some() {
+            return Err(SessionError::Revoked);
+        }
+        if now >= session.expires_at {
+            return Err(SessionError::Expired);
+        }
+        if hash_secret(secret) != session.refresh_token_hash {
+            warn!("Refresh token of session {session_id} reused, revoking the session");
+            session.revoked_at = Some(now);
+            tx.update(self, session_id, session).await?;
+            tx.commit().await?;
+            return Err(SessionError::Reused);
+        }
+
+        let secret = new_secret();
+        session.refresh_token_hash = hash_secret(&secret);
+        session.last_used_at = now;
+        tx.update(self, session_id, session.clone()).await?;
+        tx.commit().await?;
+        Ok((
+            session_id,
+            session,
+            self::refresh_token(session_id, &secret),
+        ))
+    }
+
+    async fn is_session_active(&self, session_id: &SessionId) -> Result<bool, DbError> {
+        Ok(self
+            .get(session_id)
+            .await?
+            .is_some_and(|session| session.is_active(Utc::now())))
+    }
+
+    async fn active_sessions(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Vec<(SessionId, Session)>, DbError> {
+        let now = Utc::now();
+        let mut sessions: Vec<_> = self
+            .find_all_by_field("user_id", &user_id.to_string())
+            .await?
+            .into_iter()
+            .filter(|(_, session)| session.is_active(now))
+            .collect();
+        sessions.sort_by_key(|(_, session)| std::cmp::Reverse(session.last_used_at));
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, session_id: &SessionId) -> Result<(), DbError> {
+        // Locked, so that a refresh in progress cannot write the session back unrevoked
+        let mut tx = self.begin().await?;
+        let Some(mut session) = tx.get_for_update(self, session_id).await? else {
+            return Ok(());
+        };
+        if session.revoked_at.is_none() {
+            session.revoked_at = Some(Utc::now());
+            tx.update(self, *session_id, session).await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    async fn revoke_all_sessions(&self, user_id: &UserId) -> Result<usize, DbError> {
+        // Sessions opened while the listed ones are revoked are listed on the next
+        // round, so none is left once a listing comes back empty
+        let mut revoked = 0;
+        loop {
+            let sessions = self.active_sessions(user_id).await?;
+            if sessions.is_empty() {
+                return Ok(revoked);
+            }
+            for (session_id, _) in &sessions {
+                self.revoke_session(session_id).await?;
+            }
+            revoked += sessions.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+
+    const TTL: TimeDelta = TimeDelta::days(30);
+
+    #[tokio::test]
+    async fn test_refresh_rotates_the_token() {
+        let repo = SessionRepo::new(Arc::new(InMemoryStore::new()), "sessions")
+            .await
+            .unwrap();
+        let user_id = Uuid::new_v4();
+        let (session_id, first) = repo.open_session(user_id, None, TTL).await.unwrap();
+        assert!(repo.is_session_active(&session_id).await.unwrap());
+
+        let (refreshed_id, session, second) = repo.refresh_session(&first).await.unwrap();
+        assert_eq!(refreshed_id, session_id);
+        assert_eq!(session.user_id, user_id);
+        assert_ne!(first, second);
+
+        let (_, _, third) = repo.refresh_session(&second).await.unwrap();
+        assert_ne!(second, third);
+        // Only the hash of the secret is stored
+        let stored = serde_json::to_string(&repo.get(&session_id).await.unwrap()).unwrap();
+        assert!(!stored.contains(third.split_once('.').unwrap().1));
+    }
+
+    #[tokio::test]
+    async fn test_reused_token_revokes_the_session() {
+        let repo = SessionRepo::new(Arc::new(InMemoryStore::new()), "sessions")
+            .await
+            .unwrap();
+        let (session_id, first) = repo.open_session(Uuid::new_v4(), None, TTL).await.unwrap();
+        let (_, _, second) = repo.refresh_session(&first).await.unwrap();
+
+        assert!(matches!(
+            repo.refresh_session(&first).await,
+            Err(SessionError::Reused)
+        ));
+        assert!(!repo.is_session_active(&session_id).await.unwrap());
+        assert!(matches!(
+            repo.refresh_session(&second).await,
+            Err(SessionError::Revoked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_with_one_token() {
+        let repo = SessionRepo::new(Arc::new(InMemoryStore::new()), "sessions")
+            .await
+            .unwrap();
+        let (session_id, token) = repo.open_session(Uuid::new_v4(), None, TTL).await.unwrap();
+
+        let (first, second) =
+            tokio::join!(repo.refresh_session(&token), repo.refresh_session(&token));
+        // One rotates the token, the other is a reuse
+        assert!(first.is_ok() != second.is_ok());
+        assert!(matches!(
+            first.err().or(second.err()),
+            Some(SessionError::Reused)
+        ));
+        assert!(!repo.is_session_active(&session_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_malformed_tokens_are_refused() {
+        let repo = SessionRepo::new(Arc::new(InMemoryStore::new()), "sessions")
+            .await
+            .unwrap();
+        let (session_id, token) = repo
+            .open_session(Uuid::new_v4(), None, TimeDelta::seconds(-1))
+            .await
+            .unwrap();
+        assert!(!repo.is_session_active(&session_id).await.unwrap());
+        assert!(matches!(
+            repo.refresh_session(&token).await,
+            Err(SessionError::Expired)
+        ));
+
+        for token in ["", "no-separator", "not-a-uuid.secret"] {
+            assert!(matches!(
+                repo.refresh_session(token).await,
+                Err(SessionError::InvalidToken)
+            ));
+        }
+        let unknown = format!("{}.secret", Uuid::new_v4().simple());
+        assert!(matches!(
+            repo.refresh_session(&unknown).await,
+            Err(SessionError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions_of_a_user() {
+        let repo = SessionRepo::new(Arc::new(InMemoryStore::new()), "sessions")
+            .await
+            .unwrap();
+        let user_id = Uuid::new_v4();
+        let other_user = Uuid::new_v4();
+        let (laptop, _) = repo
+            .open_session(user_id, Some("laptop".to_string()), TTL)
+            .await
+            .unwrap();
+        let (phone, _) = repo
+            .open_session(user_id, Some("phone".to_string()), TTL)
+            .await
+            .unwrap();
+        let (other, _) = repo.open_session(other_user, None, TTL).await.unwrap();
+        assert_eq!(repo.active_sessions(&user_id).await.unwrap().len(), 2);
+
+        repo.revoke_session(&laptop).await.unwrap();
+        let active = repo.active_sessions(&user_id).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].0, phone);
+
+        assert_eq!(repo.revoke_all_sessions(&user_id).await.unwrap(), 1);
+        assert!(repo.active_sessions(&user_id).await.unwrap().is_empty());
+        assert!(repo.is_session_active(&other).await.unwrap());
+    }
+}
@@ -0,0 +1,45 @@
+use std::future::Future;
+use std::time::Duration;
+
+use database_adapter::leader::LeaderLock;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Run `job` every `interval` on exactly one process of the cluster.
+///
+/// Every process runs this loop, but only the one holding the advisory lock named
+/// `name` executes the job. If the leader dies, its lock is released with its
+/// connection and another process takes over on its next attempt.
+pub fn spawn_singleton_job<F, Fut>(name: String, interval: Duration, job: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        let mut leadership: Option<LeaderLock> = None;
+        loop {
+            if let Some(lock) = leadership.as_mut()
+                && !lock.is_held().await
+            {
+                warn!("Lost leadership of {name}");
+                leadership = None;
+            }
+
+            if leadership.is_none() {
+                match LeaderLock::try_acquire(&name).await {
+                    Ok(Some(lock)) => {
+                        info!("Became leader of {name}");
+                        leadership = Some(lock);
+                    }
+                    Ok(None) => debug!("Another instance leads {name}"),
+                    Err(e) => error!("Failed to acquire leadership of {name}: {e}"),
+                }
+            }
+
+            if leadership.is_some() {
+                job().await;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
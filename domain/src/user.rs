@@ -1,17 +1,21 @@
 use color_eyre::Result;
 use database_adapter::db::DbError;
-use database_adapter::db::PostgresRepo;
+use database_adapter::db::JsonRepo;
 use database_adapter::db::Repository;
-use mfa_adapter::MfaError;
 use mfa_adapter::MfaProvider;
+use mfa_adapter::TotpSecretStore;
 use mfa_adapter::mfa::MfaService;
+pub use mfa_adapter::{Locale, MfaError, TotpEnrollment};
+use mfa_adapter::{Recipient, RecipientStore};
+use mfa_adapter::{RecoveryCodeStore, generate_recovery_codes, hash_recovery_code};
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
-use tracing::debug;
+use tracing::{debug, warn};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::password;
 use crate::portfolio::Holding;
 
 #[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
@@ -25,6 +29,83 @@ pub struct User {
     pub is_verified: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub holdings: HashMap<String, Holding>, // Symbol -> Holding
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub totp: Option<TotpAuthenticator>,
+    /// SHA-256 hashes of the recovery codes not used yet
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    /// Second factor asked for first at login, among the enrolled ones
+    #[serde(default)]
+    pub preferred_mfa: Option<MfaMethod>,
+    /// Logins are refused until then, after too many failures
+    #[serde(default)]
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Language of the emails sent to the user
+    #[serde(default)]
+    #[schema(value_type = String, example = "en")]
+    pub locale: Locale,
+    /// Last time step whose TOTP code was used, so that a code cannot be replayed
+    #[serde(default)]
+    pub totp_last_step: Option<u64>,
+}
+
+/// Authenticator app generating the user's TOTP codes
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct TotpAuthenticator {
+    /// Base32 secret shared with the app
+    pub secret: String,
+    /// Set once the user entered a code from the app, until then the secret is not
+    /// used to log in
+    pub confirmed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Second factor asked for at login
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MfaMethod {
+    /// Code sent by email
+    #[default]
+    Email,
+    /// Code from an authenticator app
+    Totp,
+    /// One of the single-use codes saved by the user
+    RecoveryCode,
+}
+
+impl MfaMethod {
+    pub const ALL: [MfaMethod; 3] = [MfaMethod::Email, MfaMethod::Totp, MfaMethod::RecoveryCode];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MfaMethod::Email => "email",
+            MfaMethod::Totp => "totp",
+            MfaMethod::RecoveryCode => "recovery_code",
+        }
+    }
+
+    /// Name shown to users
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            MfaMethod::Email => "Email code",
+            MfaMethod::Totp => "Authenticator app",
+            MfaMethod::RecoveryCode => "Recovery code",
+        }
+    }
+}
+
+/// What a user may do beyond managing their own account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Acts on their own account and orders only
+    #[default]
+    Client,
+    /// Acts on every account and order, and administers reference data
+    Admin,
 }
 
 #[derive(Debug)]
@@ -41,6 +122,17 @@ pub enum AuthError {
     NotVerified(UserId),
     UserRepo(DbError),
     NotEnoughMoneyError,
+    NotEnoughSharesError,
+    /// The user already confirmed an authenticator app
+    TotpAlreadyEnrolled,
+    /// The user has not set up this second factor, or it cannot be preferred
+    MfaMethodUnavailable(MfaMethod),
+    /// The account failed too many logins recently, it may try again after the wait
+    TooManyAttempts(std::time::Duration),
+    /// The client IP failed too many logins recently, it may try again after the wait
+    IpRateLimited(std::time::Duration),
+    /// The account is locked until then
+    AccountLocked(chrono::DateTime<chrono::Utc>),
 }
 
 impl std::fmt::Display for AuthError {
@@ -59,12 +151,46 @@ impl std::fmt::Display for AuthError {
             AuthError::NotEnoughMoneyError => {
                 write!(f, "Not enough money in account")
             }
+            AuthError::NotEnoughSharesError => {
+                write!(f, "Not enough shares in portfolio")
+            }
+            AuthError::TotpAlreadyEnrolled => {
+                write!(f, "An authenticator app is already enrolled")
+            }
+            AuthError::MfaMethodUnavailable(method) => {
+                write!(f, "{} is not available", method.label())
+            }
+            AuthError::TooManyAttempts(wait) => write!(
+                f,
+                "Too many failed attempts, try again in {} seconds",
+                wait.as_secs().max(1)
+            ),
+            AuthError::IpRateLimited(wait) => write!(
+                f,
+                "Too many failed attempts from your network, try again in {} seconds",
+                wait.as_secs().max(1)
+            ),
+            AuthError::AccountLocked(until) => write!(
+                f,
+                "Account locked after too many failed attempts until {}",
+                until.format("%Y-%m-%d %H:%M UTC")
+            ),
         }
     }
 }
 
 impl std::error::Error for AuthError {}
 
+/// Refuse passwords too weak to be set
+/// # Errors
+/// Returns `AuthError::WeakPassword` for passwords shorter than 6 characters
+pub fn validate_password(password: &str) -> Result<(), AuthError> {
+    if password.len() < 6 {
+        return Err(AuthError::WeakPassword);
+    }
+    Ok(())
+}
+
 impl User {
     pub fn new(
         email: String,
@@ -73,39 +199,44 @@ impl User {
         surname: String,
         initial_balance: f64,
     ) -> Result<Self, AuthError> {
-        if password.len() < 6 {
-            return Err(AuthError::WeakPassword);
-        }
+        validate_password(&password)?;
 
         Ok(Self {
             id: None,
             email,
-            password_hash: Self::hash_password(&password),
+            password_hash: password::hash(&password),
             firstname,
             surname,
             balance: initial_balance,
             is_verified: false,
             created_at: chrono::Utc::now(),
             holdings: HashMap::new(),
+            role: Role::Client,
+            totp: None,
+            recovery_codes: Vec::new(),
+            preferred_mfa: None,
+            locked_until: None,
+            locale: Locale::default(),
+            totp_last_step: None,
         })
     }
 
     pub fn verify_password(&self, password: &str) -> bool {
-        // In a real app, use bcrypt or similar
-        // For now, we'll use a simple hash for demonstration
-        self.password_hash == Self::hash_password(password)
+        password::verify(password, &self.password_hash)
     }
+
+    /// Whether the password hash is legacy or uses other cost parameters than the
+    /// configured ones
+    #[must_use]
+    pub fn password_needs_rehash(&self) -> bool {
+        password::needs_rehash(&self.password_hash)
+    }
+
     pub fn update_password(&mut self, password: &str) -> Result<(), AuthError> {
-        if password.len() < 6 {
-            return Err(AuthError::WeakPassword);
-        }
-        self.password_hash = Self::hash_password(password);
+        validate_password(password)?;
+        self.password_hash = password::hash(password);
         Ok(())
     }
-    fn hash_password(password: &str) -> String {
-        // Simple hash for demonstration - use bcrypt in production!
-        format!("hash_{password}")
-    }
 
     /// Deposit money into the user's account
     pub fn deposit(&mut self, amount: f64) {
@@ -132,6 +263,54 @@ impl User {
         self.is_verified = true;
     }
 
+    #[must_use]
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Secret of the user's confirmed authenticator app
+    #[must_use]
+    pub fn totp_secret(&self) -> Option<&str> {
+        self.totp
+            .as_ref()
+            .filter(|totp| totp.confirmed_at.is_some())
+            .map(|totp| totp.secret.as_str())
+    }
+
+    /// End of the lock of the account, if it is locked at `now`
+    #[must_use]
+    pub fn locked_at(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.locked_until.filter(|until| *until > now)
+    }
+
+    /// Second factors the user can log in with. Email codes are always available,
+    /// since the email address was verified at registration.
+    #[must_use]
+    pub fn mfa_methods(&self) -> Vec<MfaMethod> {
+        let mut methods = vec![MfaMethod::Email];
+        if self.totp_secret().is_some() {
+            methods.push(MfaMethod::Totp);
+        }
+        if !self.recovery_codes.is_empty() {
+            methods.push(MfaMethod::RecoveryCode);
+        }
+        methods
+    }
+
+    /// Second factor asked for first at login: the preferred one while it is enrolled,
+    /// otherwise the authenticator app once one is confirmed
+    #[must_use]
+    pub fn mfa_method(&self) -> MfaMethod {
+        match self.preferred_mfa {
+            Some(method) if self.mfa_methods().contains(&method) => method,
+            _ if self.totp_secret().is_some() => MfaMethod::Totp,
+            _ => MfaMethod::Email,
+        }
+    }
+
     /// Update a holding (buy or sell shares)
     pub fn update_holding(&mut self, symbol: &str, quantity_change: i64, price: f64) {
         let symbol = symbol.to_string();
@@ -179,28 +358,42 @@ impl User {
         self.holdings.values().collect()
     }
 
-    /// Get portfolio value (total cost basis for now)
-    pub fn get_portfolio_value(&self) -> f64 {
+    /// Get the cost basis of all holdings
+    pub fn get_total_cost(&self) -> f64 {
         self.holdings
             .values()
             .map(|h| h.average_cost * h.quantity as f64)
             .sum()
     }
 
-    /// Get total gain/loss (currently 0 since we use cost as current price)
-    pub fn get_total_gain_loss(&self) -> f64 {
-        0.0 // Would calculate based on current prices vs cost basis
+    /// Get portfolio value, pricing each holding with `price_of`.
+    /// Holdings without a price are valued at cost.
+    pub fn get_portfolio_value(&self, price_of: impl Fn(&str) -> Option<f64>) -> f64 {
+        self.holdings
+            .values()
+            .map(|h| price_of(&h.symbol).unwrap_or(h.average_cost) * h.quantity as f64)
+            .sum()
+    }
+
+    /// Get total gain/loss against the cost basis
+    pub fn get_total_gain_loss(&self, price_of: impl Fn(&str) -> Option<f64>) -> f64 {
+        self.get_portfolio_value(price_of) - self.get_total_cost()
     }
 
     /// Get gain/loss percentage
-    pub fn get_gain_loss_percentage(&self) -> f64 {
-        0.0 // Would calculate based on current prices vs cost basis
+    pub fn get_gain_loss_percentage(&self, price_of: impl Fn(&str) -> Option<f64>) -> f64 {
+        let total_cost = self.get_total_cost();
+        if total_cost == 0.0 {
+            0.0
+        } else {
+            (self.get_total_gain_loss(price_of) / total_cost) * 100.0
+        }
     }
 }
 
 pub type UserId = Uuid;
 
-pub type UserRepo = PostgresRepo<User, UserId>;
+pub type UserRepo = JsonRepo<User, UserId>;
 
 #[allow(async_fn_in_trait)]
 pub trait UserRepoExt {
@@ -221,7 +414,7 @@ pub trait UserRepoExt {
         mfa_service: &MfaService<P>,
     ) -> Result<String, AuthError>;
 
-    fn complete_mfa_authentication<P: MfaProvider>(
+    async fn complete_mfa_authentication<P: MfaProvider>(
         &self,
         challenge_id: &str,
         code: &str,
@@ -234,12 +427,43 @@ pub trait UserRepoExt {
     async fn email_exists(&self, email: &str) -> Result<bool, AuthError>;
     async fn is_verified(&self, email: &str) -> Result<bool, AuthError>;
 
-    async fn deposit_to_user(&self, user_id: &UserId, amount: f64) -> Result<(), AuthError>;
-    async fn withdraw_from_user(&self, user_id: &UserId, amount: f64) -> Result<(), AuthError>;
+    /// Returns the new balance of the user
+    async fn deposit_to_user(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError>;
+    /// Returns the new balance of the user
+    async fn withdraw_from_user(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError>;
     async fn get_user_balance(&self, user_id: &UserId) -> Result<f64, AuthError>;
 
     async fn verify_user_email(&self, user_id: &UserId) -> Result<(), AuthError>;
     async fn is_user_verified(&self, user_id: &UserId) -> Result<bool, AuthError>;
+    async fn set_role(&self, user_id: &UserId, role: Role) -> Result<(), AuthError>;
+    /// Change the names and email of a user, leaving those that are `None` as they are
+    async fn update_profile(
+        &self,
+        user_id: &UserId,
+        firstname: Option<String>,
+        surname: Option<String>,
+        email: Option<String>,
+    ) -> Result<(), AuthError>;
+    /// Replace the password of a user, refusing weak ones
+    async fn set_password(&self, user_id: &UserId, password: &str) -> Result<(), AuthError>;
+    async fn set_totp(
+        &self,
+        user_id: &UserId,
+        totp: Option<TotpAuthenticator>,
+    ) -> Result<(), AuthError>;
+    /// Replace the recovery codes of a user, returns the new codes to show them once
+    async fn regenerate_recovery_codes(&self, user_id: &UserId) -> Result<Vec<String>, AuthError>;
+    /// Remove the recovery code with this hash, returns whether it was unused
+    async fn burn_recovery_code(&self, email: &str, code_hash: &str) -> Result<bool, AuthError>;
+    async fn set_preferred_mfa(&self, user_id: &UserId, method: MfaMethod)
+    -> Result<(), AuthError>;
+    /// Refuse the logins of a user until `until`, `None` unlocks them
+    async fn set_locked_until(
+        &self,
+        user_id: &UserId,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), AuthError>;
+    async fn set_locale(&self, user_id: &UserId, locale: Locale) -> Result<(), AuthError>;
 }
 
 impl UserRepoExt for UserRepo {
@@ -266,11 +490,28 @@ impl UserRepoExt for UserRepo {
     }
     async fn authenticate_user(&self, email: &str, password: &str) -> Result<bool, AuthError> {
         if let Some(user) = self.get_user_by_email(email).await? {
+            // Checked first, so that the lock and verification of an account are only
+            // told to whoever knows its password
+            if !user.verify_password(password) {
+                return Ok(false);
+            }
+            if let Some(until) = user.locked_at(chrono::Utc::now()) {
+                return Err(AuthError::AccountLocked(until));
+            }
             if !user.is_verified {
                 debug!("User {} not verified", email);
                 return Err(AuthError::NotVerified(user.id.unwrap_or_default()));
             }
-            Ok(user.verify_password(password))
+            // The password is only known now, upgrade its hash while we have it
+            if user.password_needs_rehash()
+                && let Some(user_id) = user.id
+            {
+                let hash = password::hash(password);
+                if let Err(e) = self.set_field(&user_id, "password_hash", hash).await {
+                    warn!("Could not rehash the password of user {}: {}", email, e);
+                }
+            }
+            Ok(true)
         } else {
             Err(AuthError::UserNotFound)
         }
@@ -292,7 +533,7 @@ impl UserRepoExt for UserRepo {
             .map_err(AuthError::MfaFailed)
     }
 
-    fn complete_mfa_authentication<P: MfaProvider>(
+    async fn complete_mfa_authentication<P: MfaProvider>(
         &self,
         challenge_id: &str,
         code: &str,
@@ -300,6 +541,7 @@ impl UserRepoExt for UserRepo {
     ) -> Result<bool, AuthError> {
         mfa_service
             .verify_mfa(challenge_id, code)
+            .await
             .map_err(AuthError::MfaFailed)
     }
 
@@ -326,19 +568,20 @@ impl UserRepoExt for UserRepo {
         Ok(user.is_verified)
     }
 
-    async fn deposit_to_user(&self, user_id: &UserId, amount: f64) -> Result<(), AuthError> {
+    async fn deposit_to_user(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError> {
         let mut user = self
             .get(user_id)
             .await
             .map_err(AuthError::UserRepo)?
             .ok_or(AuthError::UserNotFound)?;
         user.deposit(amount);
+        let balance = user.get_balance();
         self.update(*user_id, user)
             .await
             .map_err(AuthError::UserRepo)?;
-        Ok(())
+        Ok(balance)
     }
-    async fn withdraw_from_user(&self, user_id: &UserId, amount: f64) -> Result<(), AuthError> {
+    async fn withdraw_from_user(&self, user_id: &UserId, amount: f64) -> Result<f64, AuthError> {
         let mut user = self
             .get(user_id)
             .await
@@ -346,10 +589,11 @@ impl UserRepoExt for UserRepo {
             .ok_or(AuthError::UserNotFound)?;
         user.withdraw(amount)
             .map_err(|_e| AuthError::NotEnoughMoneyError)?;
+        let balance = user.get_balance();
         self.update(*user_id, user)
             .await
             .map_err(AuthError::UserRepo)?;
-        Ok(())
+        Ok(balance)
     }
 
     async fn get_user_balance(&self, user_id: &UserId) -> Result<f64, AuthError> {
@@ -362,23 +606,468 @@ impl UserRepoExt for UserRepo {
     }
 
     async fn verify_user_email(&self, user_id: &UserId) -> Result<(), AuthError> {
-        let mut user = self
+        set_user_field(self, user_id, "is_verified", true).await
+    }
+    async fn is_user_verified(&self, user_id: &UserId) -> Result<bool, AuthError> {
+        let user = self
             .get(user_id)
             .await
             .map_err(AuthError::UserRepo)?
             .ok_or(AuthError::UserNotFound)?;
-        user.verify_email();
-        self.update(*user_id, user)
+        Ok(user.is_verified)
+    }
+
+    async fn set_role(&self, user_id: &UserId, role: Role) -> Result<(), AuthError> {
+        set_user_field(self, user_id, "role", role).await
+    }
+
+    async fn update_profile(
+        &self,
+        user_id: &UserId,
+        firstname: Option<String>,
+        surname: Option<String>,
+        email: Option<String>,
+    ) -> Result<(), AuthError> {
+        for (field, value) in [
+            ("firstname", firstname),
+            ("surname", surname),
+            ("email", email),
+        ] {
+            if let Some(value) = value {
+                set_user_field(self, user_id, field, value).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_password(&self, user_id: &UserId, password: &str) -> Result<(), AuthError> {
+        validate_password(password)?;
+        set_user_field(self, user_id, "password_hash", password::hash(password)).await
+    }
+
+    async fn set_totp(
+        &self,
+        user_id: &UserId,
+        totp: Option<TotpAuthenticator>,
+    ) -> Result<(), AuthError> {
+        set_user_field(self, user_id, "totp", totp).await
+    }
+
+    async fn regenerate_recovery_codes(&self, user_id: &UserId) -> Result<Vec<String>, AuthError> {
+        let codes = generate_recovery_codes();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        set_user_field(self, user_id, "recovery_codes", hashes).await?;
+        Ok(codes)
+    }
+
+    async fn burn_recovery_code(&self, email: &str, code_hash: &str) -> Result<bool, AuthError> {
+        let Some(user) = self.get_user_by_email(email).await? else {
+            return Err(AuthError::UserNotFound);
+        };
+        let user_id = user.id.ok_or(AuthError::UserNotFound)?;
+        // Removed only if still there, so a code replayed concurrently is burned once
+        let burned = self
+            .remove_from_array(&user_id, "recovery_codes", code_hash)
             .await
             .map_err(AuthError::UserRepo)?;
-        Ok(())
+        Ok(burned == 1)
     }
-    async fn is_user_verified(&self, user_id: &UserId) -> Result<bool, AuthError> {
+
+    async fn set_preferred_mfa(
+        &self,
+        user_id: &UserId,
+        method: MfaMethod,
+    ) -> Result<(), AuthError> {
         let user = self
             .get(user_id)
             .await
             .map_err(AuthError::UserRepo)?
             .ok_or(AuthError::UserNotFound)?;
-        Ok(user.is_verified)
+        // Recovery codes are a last resort, each login would burn one
+        if method == MfaMethod::RecoveryCode || !user.mfa_methods().contains(&method) {
+            return Err(AuthError::MfaMethodUnavailable(method));
+        }
+        set_user_field(self, user_id, "preferred_mfa", Some(method)).await
+    }
+
+    async fn set_locked_until(
+        &self,
+        user_id: &UserId,
+        until: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), AuthError> {
+        set_user_field(self, user_id, "locked_until", until).await
+    }
+
+    async fn set_locale(&self, user_id: &UserId, locale: Locale) -> Result<(), AuthError> {
+        set_user_field(self, user_id, "locale", locale).await
+    }
+}
+
+/// Set one field of a user, so the writes of concurrent requests to its other fields,
+/// such as fills updating the balance, are kept
+async fn set_user_field<V: Serialize>(
+    repo: &UserRepo,
+    user_id: &UserId,
+    field: &str,
+    value: V,
+) -> Result<(), AuthError> {
+    match repo
+        .set_field(user_id, field, value)
+        .await
+        .map_err(AuthError::UserRepo)?
+    {
+        0 => Err(AuthError::UserNotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Second factor secrets of the users in a `UserRepo`: confirmed TOTP secrets for
+/// the `TotpProvider`, recovery code hashes for the `RecoveryCodeProvider` and the
+/// locale and verification status the `EmailOtpProvider` writes with
+#[derive(Clone)]
+pub struct UserMfaSecrets(pub UserRepo);
+
+impl std::fmt::Debug for UserMfaSecrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("UserMfaSecrets")
+    }
+}
+
+impl TotpSecretStore for UserMfaSecrets {
+    async fn totp_secret(&self, user_email: &str) -> Result<Option<String>, MfaError> {
+        let user = self
+            .0
+            .get_user_by_email(user_email)
+            .await
+            .map_err(|_| MfaError::ServiceUnavailable)?;
+        Ok(user.and_then(|user| user.totp_secret().map(ToOwned::to_owned)))
+    }
+
+    async fn use_step(&self, user_email: &str, step: u64) -> Result<bool, MfaError> {
+        let user_id = self
+            .0
+            .get_user_by_email(user_email)
+            .await
+            .map_err(|_| MfaError::ServiceUnavailable)?
+            .and_then(|user| user.id)
+            .ok_or(MfaError::NotEnrolled)?;
+        let step = i64::try_from(step).map_err(|_| MfaError::ServiceUnavailable)?;
+        let changed = self
+            .0
+            .raise_field(&user_id, "totp_last_step", step)
+            .await
+            .map_err(|_| MfaError::ServiceUnavailable)?;
+        Ok(changed == 1)
+    }
+}
+
+impl RecoveryCodeStore for UserMfaSecrets {
+    async fn recovery_code_hashes(&self, user_email: &str) -> Result<Vec<String>, MfaError> {
+        let user = self
+            .0
+            .get_user_by_email(user_email)
+            .await
+            .map_err(|_| MfaError::ServiceUnavailable)?;
+        Ok(user.map(|user| user.recovery_codes).unwrap_or_default())
+    }
+}
+
+impl RecipientStore for UserMfaSecrets {
+    async fn recipient(&self, user_email: &str) -> Result<Option<Recipient>, MfaError> {
+        let user = self
+            .0
+            .get_user_by_email(user_email)
+            .await
+            .map_err(|_| MfaError::ServiceUnavailable)?;
+        Ok(user.map(|user| Recipient {
+            locale: user.locale,
+            verified: user.is_verified,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_legacy_hash_is_upgraded_on_login() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = repo
+            .create_user(
+                "legacy@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Legacy".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        repo.verify_user_email(&user_id).await.unwrap();
+        let mut user = repo.get(&user_id).await.unwrap().unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$"));
+        user.password_hash = "hash_aaaaaa".to_string();
+        repo.update(user_id, user).await.unwrap();
+
+        // A failed login leaves the hash alone
+        assert!(
+            !repo
+                .authenticate_user("legacy@test.com", "bbbbbb")
+                .await
+                .unwrap()
+        );
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.password_hash, "hash_aaaaaa");
+
+        assert!(
+            repo.authenticate_user("legacy@test.com", "aaaaaa")
+                .await
+                .unwrap()
+        );
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$"));
+        assert!(!user.password_needs_rehash());
+        assert!(
+            repo.authenticate_user("legacy@test.com", "aaaaaa")
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_account_state_is_only_told_with_the_password() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = repo
+            .create_user(
+                "state@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "State".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+
+        assert!(
+            !repo
+                .authenticate_user("state@test.com", "wrong!")
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            repo.authenticate_user("state@test.com", "aaaaaa").await,
+            Err(AuthError::NotVerified(_))
+        ));
+
+        repo.verify_user_email(&user_id).await.unwrap();
+        let until = chrono::Utc::now() + chrono::TimeDelta::hours(1);
+        repo.set_locked_until(&user_id, Some(until)).await.unwrap();
+        assert!(
+            !repo
+                .authenticate_user("state@test.com", "wrong!")
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            repo.authenticate_user("state@test.com", "aaaaaa").await,
+            Err(AuthError::AccountLocked(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_only_confirmed_totp_secret_is_used_to_log_in() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = repo
+            .create_user(
+                "totp@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Totp".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        let secrets = UserMfaSecrets(repo.clone());
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.mfa_method(), MfaMethod::Email);
+
+        // Pending enrollment
+        let mut totp = TotpAuthenticator {
+            secret: "JBSWY3DPEHPK3PXP".to_string(),
+            confirmed_at: None,
+        };
+        repo.set_totp(&user_id, Some(totp.clone())).await.unwrap();
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.mfa_method(), MfaMethod::Email);
+        assert_eq!(secrets.totp_secret("totp@test.com").await.unwrap(), None);
+
+        totp.confirmed_at = Some(chrono::Utc::now());
+        repo.set_totp(&user_id, Some(totp)).await.unwrap();
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.mfa_method(), MfaMethod::Totp);
+        assert_eq!(
+            secrets
+                .totp_secret("totp@test.com")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("JBSWY3DPEHPK3PXP")
+        );
+
+        repo.set_totp(&user_id, None).await.unwrap();
+        assert_eq!(secrets.totp_secret("totp@test.com").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_used_totp_steps_are_shared_by_instances() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        repo.create_user(
+            "totp@test.com".to_string(),
+            "aaaaaa".to_string(),
+            "Totp".to_string(),
+            "User".to_string(),
+            0.0,
+        )
+        .await
+        .unwrap();
+        // Two instances over the same users
+        let first = UserMfaSecrets(repo.clone());
+        let second = UserMfaSecrets(repo);
+
+        assert!(first.use_step("totp@test.com", 10).await.unwrap());
+        assert!(!second.use_step("totp@test.com", 10).await.unwrap());
+        assert!(!second.use_step("totp@test.com", 9).await.unwrap());
+        assert!(second.use_step("totp@test.com", 11).await.unwrap());
+        assert!(matches!(
+            first.use_step("missing@test.com", 12).await,
+            Err(MfaError::NotEnrolled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes_are_hashed_and_burned() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = repo
+            .create_user(
+                "codes@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Codes".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        let codes = repo.regenerate_recovery_codes(&user_id).await.unwrap();
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.recovery_codes.len(), codes.len());
+        assert!(!user.recovery_codes.contains(&codes[0]));
+        assert!(user.mfa_methods().contains(&MfaMethod::RecoveryCode));
+
+        let hash = hash_recovery_code(&codes[0]);
+        assert!(
+            repo.burn_recovery_code("codes@test.com", &hash)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !repo
+                .burn_recovery_code("codes@test.com", &hash)
+                .await
+                .unwrap()
+        );
+
+        // Regenerating invalidates the remaining codes
+        let new_codes = repo.regenerate_recovery_codes(&user_id).await.unwrap();
+        let hash = hash_recovery_code(&codes[1]);
+        assert!(
+            !repo
+                .burn_recovery_code("codes@test.com", &hash)
+                .await
+                .unwrap()
+        );
+        let hash = hash_recovery_code(&new_codes[1]);
+        assert!(
+            repo.burn_recovery_code("codes@test.com", &hash)
+                .await
+                .unwrap()
+        );
+
+        // A code submitted twice at once logs in once
+        let hash = hash_recovery_code(&new_codes[2]);
+        let (first, second) = tokio::join!(
+            repo.burn_recovery_code("codes@test.com", &hash),
+            repo.burn_recovery_code("codes@test.com", &hash)
+        );
+        assert!(first.unwrap() ^ second.unwrap());
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.recovery_codes.len(), new_codes.len() - 2);
+    }
+
+    #[tokio::test]
+    async fn test_preferred_mfa_method() {
+        let repo = UserRepo::new(Arc::new(InMemoryStore::new()), "users")
+            .await
+            .unwrap();
+        let user_id = repo
+            .create_user(
+                "preferred@test.com".to_string(),
+                "aaaaaa".to_string(),
+                "Preferred".to_string(),
+                "User".to_string(),
+                0.0,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            repo.set_preferred_mfa(&user_id, MfaMethod::Totp).await,
+            Err(AuthError::MfaMethodUnavailable(MfaMethod::Totp))
+        ));
+        repo.regenerate_recovery_codes(&user_id).await.unwrap();
+        assert!(matches!(
+            repo.set_preferred_mfa(&user_id, MfaMethod::RecoveryCode)
+                .await,
+            Err(AuthError::MfaMethodUnavailable(MfaMethod::RecoveryCode))
+        ));
+
+        let totp = TotpAuthenticator {
+            secret: "JBSWY3DPEHPK3PXP".to_string(),
+            confirmed_at: Some(chrono::Utc::now()),
+        };
+        repo.set_totp(&user_id, Some(totp)).await.unwrap();
+        repo.set_preferred_mfa(&user_id, MfaMethod::Email)
+            .await
+            .unwrap();
+        let user = repo.get(&user_id).await.unwrap().unwrap();
+        assert_eq!(
+            user.mfa_methods(),
+            vec![MfaMethod::Email, MfaMethod::Totp, MfaMethod::RecoveryCode]
+        );
+        assert_eq!(user.mfa_method(), MfaMethod::Email);
+
+        repo.set_preferred_mfa(&user_id, MfaMethod::Totp)
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.get(&user_id).await.unwrap().unwrap().mfa_method(),
+            MfaMethod::Totp
+        );
+        // Removing the app falls back to email
+        repo.set_totp(&user_id, None).await.unwrap();
+        assert_eq!(
+            repo.get(&user_id).await.unwrap().unwrap().mfa_method(),
+            MfaMethod::Email
+        );
     }
 }
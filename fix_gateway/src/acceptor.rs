@@ -0,0 +1,1638 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use database_adapter::db::Repository;
+use domain::core::BrokerX;
+use domain::events::{DomainEvent, EventEnvelope};
+use domain::order::{CancelError, OrderId, OrderStatus};
+use domain::user::{UserId, UserRepoExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::FixError;
+use crate::message::{Message, msg_type, tag, take_frame};
+use crate::orders::{
+    OrderTicket, cancel_reject, exec_type, execution_report, fill_report, ord_rej_reason,
+    ord_status, ord_status_of, order_rejected, reject_reason, response_to,
+};
+use crate::store::{FixOrder, FixStore, PendingReplace, SessionState};
+
+/// Time a new connection has to send its Logon
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a connection holds its session without renewing, should its instance stop
+const SESSION_LEASE: Duration = Duration::from_secs(30);
+
+/// Acceptor settings
+#[derive(Debug, Clone)]
+pub struct AcceptorConfig {
+    /// `SenderCompID` of the gateway, expected as `TargetCompID` from counterparties
+    pub comp_id: String,
+    pub port: u16,
+}
+
+impl AcceptorConfig {
+    /// Reads `FIX_PORT` and `FIX_COMP_ID` (default `BROKERX`).
+    /// Returns `None` when `FIX_PORT` is unset or invalid, the gateway is then disabled.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let port = std::env::var("FIX_PORT").ok()?;
+        let Ok(port) = port.parse() else {
+            warn!("Invalid FIX_PORT {port:?}, the FIX gateway is disabled");
+            return None;
+        };
+        Some(Self {
+            comp_id: std::env::var("FIX_COMP_ID").unwrap_or_else(|_| "BROKERX".to_string()),
+            port,
+        })
+    }
+}
+
+/// Accepts FIX sessions and routes their orders to BrokerX
+#[derive(Clone)]
+pub struct FixAcceptor {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    broker: Arc<BrokerX>,
+    store: FixStore,
+    config: AcceptorConfig,
+}
+
+impl FixAcceptor {
+    #[must_use]
+    pub fn new(broker: Arc<BrokerX>, store: FixStore, config: AcceptorConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                broker,
+                store,
+                config,
+            }),
+        }
+    }
+
+    /// Accept connections until the listener fails
+    /// # Errors
+    /// Returns `FixError::Io` if a connection cannot be accepted
+    pub async fn serve(self, listener: TcpListener) -> Result<(), FixError> {
+        loop {
+            let (stream, address) = listener.accept().await?;
+            debug!("FIX connection from {address}");
+            let acceptor = self.clone();
+            tokio::spawn(async move {
+                match acceptor.handle_connection(stream).await {
+                    Ok(()) | Err(FixError::LoggedOut(None)) => {
+                        debug!("FIX connection from {address} closed");
+                    }
+                    Err(e) => warn!("FIX connection from {address} ended: {e}"),
+                }
+            });
+        }
+    }
+
+    /// Serve `listener` in the background
+    pub fn spawn(self, listener: TcpListener) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Err(e) = self.serve(listener).await {
+                warn!("FIX acceptor stopped: {e}");
+            }
+        })
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> Result<(), FixError> {
+        let mut connection = Connection {
+            stream,
+            buffer: Vec::new(),
+        };
+        let logon = tokio::time::timeout(LOGON_TIMEOUT, connection.read())
+            .await
+            .map_err(|_| FixError::Timeout)??;
+        if logon.msg_type() != msg_type::LOGON {
+            return Err(FixError::Malformed(format!(
+                "expected a logon, got message type {}",
+                logon.msg_type()
+            )));
+        }
+
+        self.logon(connection, &logon).await?.run().await
+    }
+
+    /// Check a Logon and answer it. The connection is closed with a Logout if it is refused.
+    async fn logon(&self, connection: Connection, logon: &Message) -> Result<Session, FixError> {
+        let comp_id = self.inner.config.comp_id.clone();
+        let target_comp_id = logon.required(tag::SENDER_COMP_ID)?.to_string();
+        let session_id = format!("{comp_id}:{target_comp_id}");
+        let state = self.inner.store.session(&session_id).await?;
+
+        let mut session = Session {
+            acceptor: self.clone(),
+            connection,
+            session_id,
+            comp_id,
+            target_comp_id,
+            user_id: UserId::nil(),
+            heartbeat_interval: Duration::ZERO,
+            state,
+            lease_holder: Uuid::new_v4().to_string(),
+            lease_renewed: Instant::now(),
+            resend_until: None,
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request_sent: false,
+            last_event: 0,
+            logged_on: false,
+        };
+
+        match self.check_logon(&session, logon).await {
+            Ok((user_id, heartbeat_interval)) => {
+                session.user_id = user_id;
+                session.heartbeat_interval = heartbeat_interval;
+            }
+            Err(reason) => return session.refuse(reason).await,
+        }
+        // The stored state belongs to the connection holding the session, at any instance
+        if !self
+            .inner
+            .store
+            .take_lease(&session.session_id, &session.lease_holder, SESSION_LEASE)
+            .await?
+        {
+            return session
+                .refuse("Session already logged on".to_string())
+                .await;
+        }
+        session.logged_on = true;
+        // Read again, the previous holder may have written it since
+        session.state = self.inner.store.session(&session.session_id).await?;
+        if session
+            .state
+            .user_id
+            .is_some_and(|owner| owner != session.user_id)
+        {
+            return session
+                .refuse("SenderCompID belongs to another user".to_string())
+                .await;
+        }
+        session.state.user_id = Some(session.user_id);
+        if logon.flag(tag::RESET_SEQ_NUM_FLAG) {
+            session.state.next_outgoing = 1;
+            session.state.next_incoming = 1;
+        }
+        let seq_num = logon.seq_num();
+        if seq_num < session.state.next_incoming {
+            let reason = format!(
+                "MsgSeqNum too low, expecting {} but received {seq_num}",
+                session.state.next_incoming
+            );
+            return session.refuse(reason).await;
+        }
+
+        let mut reply = Message::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, session.heartbeat_interval.as_secs());
+        if logon.flag(tag::RESET_SEQ_NUM_FLAG) {
+            reply.set(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await?;
+        if seq_num > session.state.next_incoming {
+            session.request_resend(seq_num).await?;
+        } else {
+            session.state.next_incoming = seq_num + 1;
+            session.save_state().await?;
+        }
+        info!(
+            "FIX session {} logged on for user {}",
+            session.session_id, session.user_id
+        );
+        Ok(session)
+    }
+
+    /// User and heartbeat interval of a valid Logon, or the reason it is refused
+    async fn check_logon(
+        &self,
+        session: &Session,
+        logon: &Message,
+    ) -> Result<(UserId, Duration), String> {
+        if logon.get(tag::TARGET_COMP_ID) != Some(session.comp_id.as_str()) {
+            return Err(format!(
+                "Unknown TargetCompID, expected {}",
+                session.comp_id
+            ));
+        }
+        let heartbeat_interval = match logon.parse::<u64>(tag::HEART_BT_INT) {
+            Ok(Some(seconds)) if seconds > 0 => Duration::from_secs(seconds),
+            _ => return Err("Invalid HeartBtInt".to_string()),
+        };
+        let (Some(username), Some(password)) = (logon.get(tag::USERNAME), logon.get(tag::PASSWORD))
+        else {
+            return Err("Username and Password are required".to_string());
+        };
+
+        // Throttled and counted towards lockout like web logins, by account and peer
+        let ip = session
+            .connection
+            .stream
+            .peer_addr()
+            .ok()
+            .map(|peer| peer.ip());
+        match self
+            .inner
+            .broker
+            .authenticate_user(username, password, ip)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return Err("Invalid credentials".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+        let user_repo = self.inner.broker.get_user_repo().await;
+        match user_repo.get_user_by_email(username).await {
+            Ok(Some(user)) => user
+                .id
+                .map(|id| (id, heartbeat_interval))
+                .ok_or_else(|| "Invalid credentials".to_string()),
+            Ok(None) => Err("Invalid credentials".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+fn logout_message(text: &str) -> Message {
+    Message::new(msg_type::LOGOUT).with(tag::TEXT, text)
+}
+
+/// TCP stream with the bytes read but not yet parsed
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    /// Next well-formed message. Garbled messages are dropped, as the counterparty will
+    /// notice the sequence gap.
+    async fn read(&mut self) -> Result<Message, FixError> {
+        loop {
+            if let Some(message) = self.buffered()? {
+                return Ok(message);
+            }
+            self.fill().await?;
+        }
+    }
+
+    fn buffered(&mut self) -> Result<Option<Message>, FixError> {
+        while let Some(frame) = take_frame(&mut self.buffer)? {
+            match Message::decode(&frame) {
+                Ok(message) => return Ok(Some(message)),
+                Err(e) => warn!("Dropping FIX message: {e}"),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read more bytes. Cancel safe.
+    async fn fill(&mut self) -> Result<(), FixError> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err(FixError::LoggedOut(None));
+        }
+        Ok(())
+    }
+}
+
+/// Logged on session
+struct Session {
+    acceptor: FixAcceptor,
+    connection: Connection,
+    session_id: String,
+    comp_id: String,
+    target_comp_id: String,
+    user_id: UserId,
+    heartbeat_interval: Duration,
+    state: SessionState,
+    /// Identifies the connection in the session lease
+    lease_holder: String,
+    lease_renewed: Instant,
+    /// Highest sequence number received while messages are missing
+    resend_until: Option<u64>,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request_sent: bool,
+    /// Last event bus sequence handled
+    last_event: u64,
+    logged_on: bool,
+}
+
+impl Session {
+    fn broker(&self) -> &BrokerX {
+        &self.acceptor.inner.broker
+    }
+
+    fn store(&self) -> &FixStore {
+        &self.acceptor.inner.store
+    }
+
+    async fn run(&mut self) -> Result<(), FixError> {
+        let broker = Arc::clone(&self.acceptor.inner.broker);
+        let events = broker.events();
+        let since = self
+            .state
+            .last_event
+            .unwrap_or_else(|| events.last_sequence());
+        let replay = events.subscribe_after(since);
+        self.last_event = since;
+        for envelope in replay.events {
+            self.on_event(envelope).await?;
+        }
+        let mut receiver = replay.receiver;
+        let mut timer = tokio::time::interval(Duration::from_millis(500));
+
+        loop {
+            while let Some(message) = self.connection.buffered()? {
+                if !self.on_message(message).await? {
+                    return Ok(());
+                }
+            }
+            tokio::select! {
+                read = self.connection.fill() => read?,
+                event = receiver.recv() => match event {
+                    Ok(envelope) => self.on_event(envelope).await?,
+                    Err(RecvError::Lagged(_)) => {
+                        let replay = events.subscribe_after(self.last_event);
+                        if !replay.complete {
+                            warn!("FIX session {} missed order events", self.session_id);
+                        }
+                        receiver = replay.receiver;
+                        for envelope in replay.events {
+                            self.on_event(envelope).await?;
+                        }
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = timer.tick() => {
+                    if !self.check_heartbeats().await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Heartbeats, test requests and the session lease. Returns false when the session
+    /// is over.
+    async fn check_heartbeats(&mut self) -> Result<bool, FixError> {
+        if self.lease_renewed.elapsed() >= SESSION_LEASE / 3 {
+            if !self
+                .store()
+                .take_lease(&self.session_id, &self.lease_holder, SESSION_LEASE)
+                .await?
+            {
+                // The lease expired and another connection holds the stored state now
+                self.logged_on = false;
+                self.logout("Session logged on elsewhere").await?;
+                return Ok(false);
+            }
+            self.lease_renewed = Instant::now();
+        }
+        let silence = self.last_received.elapsed();
+        if silence >= self.heartbeat_interval * 2 {
+            self.logout("Heartbeat timeout").await?;
+            return Ok(false);
+        }
+        if silence >= self.heartbeat_interval.mul_f64(1.2) && !self.test_request_sent {
+            self.send(Message::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, Uuid::new_v4()))
+                .await?;
+            self.test_request_sent = true;
+        }
+        if self.last_sent.elapsed() >= self.heartbeat_interval {
+            self.send(Message::new(msg_type::HEARTBEAT)).await?;
+        }
+        Ok(true)
+    }
+
+    /// Handle a message from the counterparty. Returns false once the session is over.
+    async fn on_message(&mut self, message: Message) -> Result<bool, FixError> {
+        self.last_received = Instant::now();
+        self.test_request_sent = false;
+
+        let seq_num = message.seq_num();
+        let is_reset =
+            message.msg_type() == msg_type::SEQUENCE_RESET && !message.flag(tag::GAP_FILL_FLAG);
+        if !is_reset && message.msg_type() != msg_type::LOGOUT {
+            if seq_num > self.state.next_incoming {
+                if self.resend_until.is_none() {
+                    self.request_resend(seq_num).await?;
+                }
+                self.resend_until = self.resend_until.max(Some(seq_num));
+                return Ok(true);
+            }
+            if seq_num < self.state.next_incoming {
+                if message.flag(tag::POSS_DUP_FLAG) {
+                    return Ok(true);
+                }
+                let reason = format!(
+                    "MsgSeqNum too low, expecting {} but received {seq_num}",
+                    self.state.next_incoming
+                );
+                self.logout(&reason).await?;
+                return Ok(false);
+            }
+        }
+
+        match message.msg_type() {
+            msg_type::SEQUENCE_RESET => {
+                self.on_sequence_reset(&message).await?;
+                return Ok(true);
+            }
+            msg_type::LOGOUT => {
+                if seq_num == self.state.next_incoming {
+                    self.state.next_incoming += 1;
+                    self.save_state().await?;
+                }
+                self.logout("Logout confirmed").await?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        self.state.next_incoming = seq_num + 1;
+        if self
+            .resend_until
+            .is_some_and(|until| self.state.next_incoming > until)
+        {
+            self.resend_until = None;
+        }
+        self.save_state().await?;
+
+        let result = match message.msg_type() {
+            msg_type::HEARTBEAT | msg_type::REJECT => Ok(()),
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = Message::new(msg_type::HEARTBEAT);
+                if let Some(id) = message.get(tag::TEST_REQ_ID) {
+                    heartbeat.set(tag::TEST_REQ_ID, id);
+                }
+                self.send(heartbeat).await
+            }
+            msg_type::RESEND_REQUEST => self.on_resend_request(&message).await,
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order(&message).await,
+            msg_type::ORDER_CANCEL_REQUEST => self.on_cancel_request(&message).await,
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.on_replace_request(&message).await,
+            other => Err(FixError::Malformed(format!(
+                "unsupported message type {other}"
+            ))),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(
+                e @ (FixError::MissingField(_)
+                | FixError::InvalidField { .. }
+                | FixError::Malformed(_)),
+            ) => {
+                self.send(
+                    Message::new(msg_type::REJECT)
+                        .with(tag::REF_SEQ_NUM, seq_num)
+                        .with(tag::TEXT, e),
+                )
+                .await?;
+                Ok(true)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn on_sequence_reset(&mut self, message: &Message) -> Result<(), FixError> {
+        let new_seq_no = message.parse_required::<u64>(tag::NEW_SEQ_NO)?;
+        if new_seq_no < self.state.next_incoming {
+            return self
+                .send(
+                    Message::new(msg_type::REJECT)
+                        .with(tag::REF_SEQ_NUM, message.seq_num())
+                        .with(tag::TEXT, "NewSeqNo cannot decrease the sequence"),
+                )
+                .await;
+        }
+        self.state.next_incoming = new_seq_no;
+        if self
+            .resend_until
+            .is_some_and(|until| self.state.next_incoming > until)
+        {
+            self.resend_until = None;
+        }
+        self.save_state().await
+    }
+
+    /// Ask for the messages from the expected sequence number onwards
+    async fn request_resend(&mut self, received: u64) -> Result<(), FixError> {
+        debug!(
+            "FIX session {} expected {} but received {received}",
+            self.session_id, self.state.next_incoming
+        );
+        self.resend_until = Some(received);
+        self.send(
+            Message::new(msg_type::RESEND_REQUEST)
+                .with(tag::BEGIN_SEQ_NO, self.state.next_incoming)
+                .with(tag::END_SEQ_NO, 0),
+        )
+        .await
+    }
+
+    /// Resend the application messages stored for the range, and skip the admin messages
+    /// with gap fills
+    async fn on_resend_request(&mut self, message: &Message) -> Result<(), FixError> {
+        let begin = message.parse_required::<u64>(tag::BEGIN_SEQ_NO)?.max(1);
+        let last_sent = self.state.next_outgoing - 1;
+        let end = match message.parse_required::<u64>(tag::END_SEQ_NO)? {
+            0 => last_sent,
+            end => end.min(last_sent),
+        };
+        let mut next = begin;
+        for sent in self
+            .store()
+            .sent_between(&self.session_id, begin, end)
+            .await?
+        {
+            if sent.seq_num > next {
+                self.gap_fill(next, sent.seq_num).await?;
+            }
+            let mut resent = Message::decode(sent.raw.as_bytes())?;
+            if let Some(sending_time) = resent.get(tag::SENDING_TIME) {
+                resent.set(tag::ORIG_SENDING_TIME, sending_time.to_string());
+            }
+            resent.set(tag::POSS_DUP_FLAG, "Y");
+            self.send_with_seq(resent, sent.seq_num).await?;
+            next = sent.seq_num + 1;
+        }
+        if next <= end {
+            self.gap_fill(next, end + 1).await?;
+        }
+        Ok(())
+    }
+
+    async fn gap_fill(&mut self, seq_num: u64, new_seq_no: u64) -> Result<(), FixError> {
+        let message = Message::new(msg_type::SEQUENCE_RESET)
+            .with(tag::POSS_DUP_FLAG, "Y")
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, new_seq_no);
+        self.send_with_seq(message, seq_num).await
+    }
+
+    async fn on_new_order(&mut self, message: &Message) -> Result<(), FixError> {
+        let ticket = OrderTicket::parse(message)?;
+        let now = self.broker().clock().now();
+        if self
+            .store()
+            .order_by_cl_ord_id(&self.session_id, &ticket.cl_ord_id)
+            .await?
+            .is_some()
+        {
+            let report = order_rejected(
+                &ticket,
+                reject_reason::DUPLICATE_ORDER,
+                "Duplicate ClOrdID",
+                now,
+            );
+            return self.send(report).await;
+        }
+
+        match self
+            .broker()
+            .create_order(
+                self.user_id,
+                ticket.symbol.clone(),
+                ticket.quantity,
+                ticket.side.clone(),
+                ticket.order_type.clone(),
+            )
+            .await
+        {
+            // The order is reported when its creation event is received
+            Ok(order_id) => {
+                let order = FixOrder {
+                    session_id: self.session_id.clone(),
+                    cl_ord_id: ticket.cl_ord_id,
+                    order_id,
+                    symbol: ticket.symbol,
+                    side: ticket.side,
+                    quantity: ticket.quantity,
+                    order_type: ticket.order_type,
+                    orig_cl_ord_id: None,
+                    pending_cancel: None,
+                    pending_replace: None,
+                };
+                Ok(self.store().save_order(&order).await?)
+            }
+            Err(e) => {
+                let report = order_rejected(&ticket, ord_rej_reason(&e), &e.to_string(), now);
+                self.send(report).await
+            }
+        }
+    }
+
+    async fn on_cancel_request(&mut self, message: &Message) -> Result<(), FixError> {
+        let cl_ord_id = message.required(tag::CL_ORD_ID)?.to_string();
+        let orig_cl_ord_id = message.required(tag::ORIG_CL_ORD_ID)?.to_string();
+        let Some(mut order) = self
+            .requested_order(&cl_ord_id, &orig_cl_ord_id, response_to::CANCEL)
+            .await?
+        else {
+            return Ok(());
+        };
+        if let Some(reason) = self.cancel_order(&order).await? {
+            return self
+                .send(cancel_reject(
+                    &cl_ord_id,
+                    &orig_cl_ord_id,
+                    Some(order.order_id),
+                    reason.0,
+                    response_to::CANCEL,
+                    reason.1,
+                    &reason.2,
+                ))
+                .await;
+        }
+        order.pending_cancel = Some(cl_ord_id);
+        Ok(self.store().save_order(&order).await?)
+    }
+
+    async fn on_replace_request(&mut self, message: &Message) -> Result<(), FixError> {
+        let ticket = OrderTicket::parse(message)?;
+        let orig_cl_ord_id = message.required(tag::ORIG_CL_ORD_ID)?.to_string();
+        let Some(mut order) = self
+            .requested_order(
+                &ticket.cl_ord_id,
+                &orig_cl_ord_id,
+                response_to::CANCEL_REPLACE,
+            )
+            .await?
+        else {
+            return Ok(());
+        };
+        let refusal = if ticket.symbol != order.symbol || ticket.side != order.side {
+            Some((
+                ord_status::NEW,
+                reject_reason::OTHER,
+                "Symbol and Side cannot be replaced".to_string(),
+            ))
+        } else {
+            self.cancel_order(&order).await?
+        };
+        if let Some((status, reason, text)) = refusal {
+            return self
+                .send(cancel_reject(
+                    &ticket.cl_ord_id,
+                    &orig_cl_ord_id,
+                    Some(order.order_id),
+                    status,
+                    response_to::CANCEL_REPLACE,
+                    reason,
+                    &text,
+                ))
+                .await;
+        }
+        order.pending_replace = Some(PendingReplace {
+            cl_ord_id: ticket.cl_ord_id,
+            quantity: ticket.quantity,
+            order_type: ticket.order_type,
+        });
+        Ok(self.store().save_order(&order).await?)
+    }
+
+    /// Order targeted by a cancel or replace request. Unknown orders, orders of other
+    /// users, duplicate identifiers and orders with a request in progress are refused
+    /// here.
+    async fn requested_order(
+        &mut self,
+        cl_ord_id: &str,
+        orig_cl_ord_id: &str,
+        response_to: &str,
+    ) -> Result<Option<FixOrder>, FixError> {
+        let order = match self
+            .store()
+            .order_by_cl_ord_id(&self.session_id, orig_cl_ord_id)
+            .await?
+        {
+            Some(order) if self.owns(&order).await? => Some(order),
+            _ => None,
+        };
+        let refusal = match &order {
+            None => Some((
+                ord_status::REJECTED,
+                reject_reason::UNKNOWN_ORDER,
+                "Unknown order",
+            )),
+            Some(_)
+                if self
+                    .store()
+                    .order_by_cl_ord_id(&self.session_id, cl_ord_id)
+                    .await?
+                    .is_some() =>
+            {
+                Some((
+                    ord_status::NEW,
+                    reject_reason::DUPLICATE_ORDER,
+                    "Duplicate ClOrdID",
+                ))
+            }
+            Some(order) if order.pending_cancel.is_some() || order.pending_replace.is_some() => {
+                Some((
+                    ord_status::PENDING_CANCEL,
+                    reject_reason::ALREADY_PENDING,
+                    "A cancel or replace request is already in progress",
+                ))
+            }
+            Some(_) => None,
+        };
+        match refusal {
+            Some((status, reason, text)) => {
+                self.send(cancel_reject(
+                    cl_ord_id,
+                    orig_cl_ord_id,
+                    order.map(|order| order.order_id),
+                    status,
+                    response_to,
+                    reason,
+                    text,
+                ))
+                .await?;
+                Ok(None)
+            }
+            None => Ok(order),
+        }
+    }
+
+    /// Whether the order was placed by the logged on user
+    async fn owns(&self, order: &FixOrder) -> Result<bool, FixError> {
+        Ok(self
+            .broker()
+            .get_order_repo()
+            .await
+            .get(&order.order_id)
+            .await?
+            .is_some_and(|order| order.client_id == self.user_id))
+    }
+
+    /// Ask BrokerX to cancel an order. Returns the `OrdStatus`, `CxlRejReason` and text of
+    /// the refusal if it cannot be cancelled.
+    async fn cancel_order(
+        &self,
+        order: &FixOrder,
+    ) -> Result<Option<(&'static str, u32, String)>, FixError> {
+        match self.broker().cancel_order(order.order_id).await {
+            Ok(_) => Ok(None),
+            Err(CancelError::NotCancellable) => {
+                let status = self
+                    .broker()
+                    .get_order_repo()
+                    .await
+                    .get(&order.order_id)
+                    .await?
+                    .map_or(ord_status::REJECTED, |order| ord_status_of(&order.status));
+                Ok(Some((
+                    status,
+                    reject_reason::TOO_LATE,
+                    CancelError::NotCancellable.to_string(),
+                )))
+            }
+            Err(CancelError::OrderNotFound) => Ok(Some((
+                ord_status::REJECTED,
+                reject_reason::UNKNOWN_ORDER,
+                CancelError::OrderNotFound.to_string(),
+            ))),
+            Err(CancelError::DbError(e)) => Err(e.into()),
+        }
+    }
+
+    /// Report what happened to one of the session's orders
+    async fn on_event(&mut self, envelope: EventEnvelope) -> Result<(), FixError> {
+        self.last_event = self.last_event.max(envelope.sequence);
+        // Saved with the session state when a report is sent
+        self.state.last_event = Some(self.last_event);
+        let event = envelope.event;
+        if event.user_id() != self.user_id {
+            return Ok(());
+        }
+        let order_id = match &event {
+            DomainEvent::OrderCreated { order_id, .. }
+            | DomainEvent::OrderStatusChanged { order_id, .. }
+            | DomainEvent::OrderFilled { order_id, .. } => *order_id,
+            _ => return Ok(()),
+        };
+        let Some(order) = self.session_order(&order_id).await? else {
+            return Ok(());
+        };
+        let at = envelope.occurred_at;
+
+        match event {
+            DomainEvent::OrderCreated { .. } => {
+                let exec_type = if order.orig_cl_ord_id.is_some() {
+                    exec_type::REPLACED
+                } else {
+                    exec_type::NEW
+                };
+                self.send(execution_report(&order, exec_type, ord_status::NEW, at))
+                    .await
+            }
+            DomainEvent::OrderFilled { price, .. } => self.on_filled(order, price, at).await,
+            DomainEvent::OrderStatusChanged { status, .. } => match status {
+                OrderStatus::PendingCancel => {
+                    let report = match (&order.pending_replace, &order.pending_cancel) {
+                        (Some(replace), _) => execution_report(
+                            &request_view(&order, &replace.cl_ord_id),
+                            exec_type::PENDING_REPLACE,
+                            ord_status::PENDING_REPLACE,
+                            at,
+                        ),
+                        (None, Some(cl_ord_id)) => execution_report(
+                            &request_view(&order, cl_ord_id),
+                            exec_type::PENDING_CANCEL,
+                            ord_status::PENDING_CANCEL,
+                            at,
+                        ),
+                        (None, None) => execution_report(
+                            &order,
+                            exec_type::PENDING_CANCEL,
+                            ord_status::PENDING_CANCEL,
+                            at,
+                        ),
+                    };
+                    self.send(report).await
+                }
+                OrderStatus::Cancelled => self.on_cancelled(order, at).await,
+                OrderStatus::Expired { .. } => {
+                    self.send(execution_report(
+                        &order,
+                        exec_type::EXPIRED,
+                        ord_status::EXPIRED,
+                        at,
+                    ))
+                    .await
+                }
+                OrderStatus::Rejected { .. } => {
+                    self.send(execution_report(
+                        &order,
+                        exec_type::REJECTED,
+                        ord_status::REJECTED,
+                        at,
+                    ))
+                    .await
+                }
+                // Fills are reported from `OrderFilled`, which carries the price
+                OrderStatus::Filled { .. } | OrderStatus::Pending | OrderStatus::Queued => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    async fn session_order(&self, order_id: &OrderId) -> Result<Option<FixOrder>, FixError> {
+        Ok(self
+            .store()
+            .order(order_id)
+            .await?
+            .filter(|order| order.session_id == self.session_id))
+    }
+
+    async fn on_filled(
+        &mut self,
+        mut order: FixOrder,
+        price: f64,
+        at: chrono::DateTime<Utc>,
+    ) -> Result<(), FixError> {
+        self.send(fill_report(&order, price, at)).await?;
+        let pending = match (order.pending_replace.take(), order.pending_cancel.take()) {
+            (Some(replace), _) => Some((replace.cl_ord_id, response_to::CANCEL_REPLACE)),
+            (None, Some(cl_ord_id)) => Some((cl_ord_id, response_to::CANCEL)),
+            (None, None) => None,
+        };
+        if let Some((cl_ord_id, response_to)) = pending {
+            self.store().save_order(&order).await?;
+            self.send(cancel_reject(
+                &cl_ord_id,
+                &order.cl_ord_id,
+                Some(order.order_id),
+                ord_status::FILLED,
+                response_to,
+                reject_reason::TOO_LATE,
+                "Order filled before it could be cancelled",
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Report a cancellation, or enter the replacement of a replaced order. The
+    /// replacement is reported when its creation event is received.
+    async fn on_cancelled(
+        &mut self,
+        mut order: FixOrder,
+        at: chrono::DateTime<Utc>,
+    ) -> Result<(), FixError> {
+        let pending_cancel = order.pending_cancel.take();
+        let Some(replace) = order.pending_replace.take() else {
+            if pending_cancel.is_some() {
+                self.store().save_order(&order).await?;
+            }
+            let view = match &pending_cancel {
+                Some(cl_ord_id) => request_view(&order, cl_ord_id),
+                None => order,
+            };
+            return self
+                .send(execution_report(
+                    &view,
+                    exec_type::CANCELED,
+                    ord_status::CANCELED,
+                    at,
+                ))
+                .await;
+        };
+        self.store().save_order(&order).await?;
+
+        match self
+            .broker()
+            .create_order(
+                self.user_id,
+                order.symbol.clone(),
+                replace.quantity,
+                order.side.clone(),
+                replace.order_type.clone(),
+            )
+            .await
+        {
+            Ok(order_id) => {
+                let replacement = FixOrder {
+                    session_id: self.session_id.clone(),
+                    cl_ord_id: replace.cl_ord_id,
+                    order_id,
+                    symbol: order.symbol,
+                    side: order.side,
+                    quantity: replace.quantity,
+                    order_type: replace.order_type,
+                    orig_cl_ord_id: Some(order.cl_ord_id),
+                    pending_cancel: None,
+                    pending_replace: None,
+                };
+                Ok(self.store().save_order(&replacement).await?)
+            }
+            Err(e) => {
+                let report = execution_report(
+                    &request_view(&order, &replace.cl_ord_id),
+                    exec_type::CANCELED,
+                    ord_status::CANCELED,
+                    at,
+                )
+                .with(tag::TEXT, format!("Replacement rejected: {e}"));
+                self.send(report).await
+            }
+        }
+    }
+
+    /// Send an application or admin message with the next sequence number
+    async fn send(&mut self, message: Message) -> Result<(), FixError> {
+        let encoded = self.sequence(message).await?;
+        self.write(&encoded).await
+    }
+
+    /// Number a message and record it, returning it encoded
+    async fn sequence(&mut self, mut message: Message) -> Result<Vec<u8>, FixError> {
+        let seq_num = self.state.next_outgoing;
+        message.set_header(&self.comp_id, &self.target_comp_id, seq_num, Utc::now());
+        let encoded = message.encode();
+        if !msg_type::is_admin(message.msg_type()) {
+            let raw = String::from_utf8_lossy(&encoded).into_owned();
+            self.store()
+                .record_sent(&self.session_id, seq_num, raw)
+                .await?;
+        }
+        self.state.next_outgoing += 1;
+        self.save_state().await?;
+        Ok(encoded)
+    }
+
+    /// Send a message without touching the stored session, for refused logons
+    async fn send_unrecorded(&mut self, mut message: Message) -> Result<(), FixError> {
+        message.set_header(
+            &self.comp_id,
+            &self.target_comp_id,
+            self.state.next_outgoing,
+            Utc::now(),
+        );
+        self.write(&message.encode()).await
+    }
+
+    /// Send a message again with its original sequence number
+    async fn send_with_seq(&mut self, mut message: Message, seq_num: u64) -> Result<(), FixError> {
+        message.set_header(&self.comp_id, &self.target_comp_id, seq_num, Utc::now());
+        self.write(&message.encode()).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), FixError> {
+        self.connection.stream.write_all(bytes).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    async fn logout(&mut self, text: &str) -> Result<(), FixError> {
+        let message = logout_message(text);
+        if self.logged_on {
+            let encoded = self.sequence(message).await?;
+            // The counterparty may log on again as soon as it reads the Logout
+            self.release().await?;
+            self.write(&encoded).await?;
+        } else {
+            self.send_unrecorded(message).await?;
+        }
+        self.connection.stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// Refuse the logon with a Logout, leaving the stored session as it is
+    async fn refuse(mut self, reason: String) -> Result<Session, FixError> {
+        info!("FIX logon of {} refused: {reason}", self.session_id);
+        self.release().await?;
+        self.logout(&reason).await?;
+        Err(FixError::LoggedOut(Some(reason)))
+    }
+
+    /// Let the session log on from another connection
+    async fn release(&mut self) -> Result<(), FixError> {
+        if self.logged_on {
+            self.logged_on = false;
+            self.store()
+                .release_lease(&self.session_id, &self.lease_holder)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn save_state(&self) -> Result<(), FixError> {
+        Ok(self.store().save_session(&self.state).await?)
+    }
+}
+
+impl Drop for Session {
+    /// Release the lease of a connection that ended without a Logout, rather than
+    /// waiting for it to expire
+    fn drop(&mut self) {
+        if !self.logged_on {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let store = self.store().clone();
+        let session_id = self.session_id.clone();
+        let holder = self.lease_holder.clone();
+        runtime.spawn(async move {
+            if let Err(e) = store.release_lease(&session_id, &holder).await {
+                warn!("Cannot release FIX session {session_id}: {e}");
+            }
+        });
+    }
+}
+
+/// Order as seen by the cancel or replace request `cl_ord_id`
+fn request_view(order: &FixOrder, cl_ord_id: &str) -> FixOrder {
+    FixOrder {
+        cl_ord_id: cl_ord_id.to_string(),
+        orig_cl_ord_id: Some(order.cl_ord_id.clone()),
+        ..order.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use domain::clock::Clock;
+    use domain::market_data::MarketDataConfig;
+    use domain::order::{OrderSide, OrderType};
+
+    use super::*;
+    use crate::initiator::{Initiator, InitiatorConfig};
+    use crate::orders::cancel_request;
+
+    const EMAIL: &str = "fix@test.com";
+    const PASSWORD: &str = "password123";
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn create_test_setup() -> (SocketAddr, Arc<BrokerX>) {
+        let broker = BrokerX::in_memory(Clock::system(), MarketDataConfig::default()).await;
+        create_user(&broker, EMAIL).await;
+        let broker = Arc::new(broker);
+        (listen(&broker).await, broker)
+    }
+
+    async fn create_user(broker: &BrokerX, email: &str) -> UserId {
+        let user_repo = broker.get_user_repo().await;
+        let user_id = user_repo
+            .create_user(
+                email.to_string(),
+                PASSWORD.to_string(),
+                "Fix".to_string(),
+                "User".to_string(),
+                100_000.0,
+            )
+            .await
+            .expect("user creation failed");
+        user_repo.verify_user_email(&user_id).await.unwrap();
+        user_id
+    }
+
+    /// Start an acceptor, as another instance would, over the broker's store
+    async fn listen(broker: &Arc<BrokerX>) -> SocketAddr {
+        let config = AcceptorConfig {
+            comp_id: "BROKERX".to_string(),
+            port: 0,
+        };
+        let store = FixStore::new(broker.store()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        FixAcceptor::new(Arc::clone(broker), store, config).spawn(listener);
+        address
+    }
+
+    fn initiator_config(reset: bool) -> InitiatorConfig {
+        InitiatorConfig {
+            sender_comp_id: "CLIENT".to_string(),
+            target_comp_id: "BROKERX".to_string(),
+            username: EMAIL.to_string(),
+            password: PASSWORD.to_string(),
+            heartbeat_interval: 30,
+            reset,
+        }
+    }
+
+    fn ticket(cl_ord_id: &str, symbol: &str, quantity: u64, order_type: OrderType) -> OrderTicket {
+        OrderTicket {
+            cl_ord_id: cl_ord_id.to_string(),
+            symbol: symbol.to_string(),
+            side: OrderSide::Buy,
+            quantity,
+            order_type,
+        }
+    }
+
+    /// Next message, checking its type and a few of its fields
+    async fn expect(initiator: &mut Initiator, msg_type: &str, fields: &[(u32, &str)]) -> Message {
+        let message = initiator.next_message(WAIT).await.unwrap();
+        assert_eq!(
+            message.msg_type(),
+            msg_type,
+            "unexpected message {message:?}"
+        );
+        for (tag, value) in fields {
+            assert_eq!(message.get(*tag), Some(*value), "tag {tag} of {message:?}");
+        }
+        message
+    }
+
+    #[tokio::test]
+    async fn test_logon_is_refused_without_valid_credentials() {
+        let (address, _broker) = create_test_setup().await;
+
+        let mut config = initiator_config(true);
+        config.password = "wrong".to_string();
+        assert!(matches!(
+            Initiator::connect(address, config, 1).await,
+            Err(FixError::LoggedOut(Some(_)))
+        ));
+
+        let mut config = initiator_config(true);
+        config.target_comp_id = "OTHER".to_string();
+        assert!(matches!(
+            Initiator::connect(address, config, 1).await,
+            Err(FixError::LoggedOut(Some(_)))
+        ));
+
+        let initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+        assert!(matches!(
+            Initiator::connect(address, initiator_config(false), 2).await,
+            Err(FixError::LoggedOut(Some(text))) if text.contains("already")
+        ));
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_held_by_one_connection_across_instances() {
+        let (address, broker) = create_test_setup().await;
+        let other_instance = listen(&broker).await;
+
+        let initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+        assert!(matches!(
+            Initiator::connect(other_instance, initiator_config(false), 2).await,
+            Err(FixError::LoggedOut(Some(text))) if text.contains("already")
+        ));
+        let next_outgoing = initiator.logout().await.unwrap();
+
+        let initiator = Initiator::connect(other_instance, initiator_config(false), next_outgoing)
+            .await
+            .unwrap();
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_belong_to_their_first_user() {
+        let (address, broker) = create_test_setup().await;
+        create_user(&broker, "other@test.com").await;
+        let initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+        initiator.logout().await.unwrap();
+
+        let mut config = initiator_config(true);
+        config.username = "other@test.com".to_string();
+        assert!(matches!(
+            Initiator::connect(address, config.clone(), 1).await,
+            Err(FixError::LoggedOut(Some(text))) if text.contains("another user")
+        ));
+        config.sender_comp_id = "OTHER".to_string();
+        let initiator = Initiator::connect(address, config, 1).await.unwrap();
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_orders_of_other_users_cannot_be_cancelled() {
+        let (address, broker) = create_test_setup().await;
+        let other_id = create_user(&broker, "other@test.com").await;
+        let order_id = broker
+            .create_order(
+                other_id,
+                "AAPL".to_string(),
+                5,
+                OrderSide::Buy,
+                OrderType::Limit(2.0),
+            )
+            .await
+            .unwrap();
+        broker.run_until_idle().await;
+        // Recorded under the session, as if entered through it
+        let store = FixStore::new(broker.store()).await.unwrap();
+        store
+            .save_order(&FixOrder {
+                session_id: "BROKERX:CLIENT".to_string(),
+                cl_ord_id: "THEIRS".to_string(),
+                order_id,
+                symbol: "AAPL".to_string(),
+                side: OrderSide::Buy,
+                quantity: 5,
+                order_type: OrderType::Limit(2.0),
+                orig_cl_ord_id: None,
+                pending_cancel: None,
+                pending_replace: None,
+            })
+            .await
+            .unwrap();
+
+        let mut initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+        initiator
+            .send(cancel_request(
+                "ORD-1",
+                "THEIRS",
+                "AAPL",
+                &OrderSide::Buy,
+                Utc::now(),
+            ))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::ORDER_CANCEL_REJECT,
+            &[(tag::CXL_REJ_REASON, "1")],
+        )
+        .await;
+        let order = broker
+            .get_order_repo()
+            .await
+            .get(&order_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!matches!(
+            order.status,
+            OrderStatus::PendingCancel | OrderStatus::Cancelled
+        ));
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_logons_are_throttled() {
+        let (address, _broker) = create_test_setup().await;
+
+        for _ in 0..3 {
+            let mut config = initiator_config(true);
+            config.password = "wrong".to_string();
+            assert!(matches!(
+                Initiator::connect(address, config, 1).await,
+                Err(FixError::LoggedOut(Some(text))) if text == "Invalid credentials"
+            ));
+        }
+        // Even the right password waits out the backoff
+        assert!(matches!(
+            Initiator::connect(address, initiator_config(true), 1).await,
+            Err(FixError::LoggedOut(Some(text))) if text.starts_with("Too many failed attempts")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_orders_are_acknowledged_filled_and_rejected() {
+        let (address, broker) = create_test_setup().await;
+        let mut initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+
+        let order = ticket("ORD-1", "AAPL", 10, OrderType::Market);
+        initiator
+            .send(order.new_order_single(Utc::now()))
+            .await
+            .unwrap();
+        let new = expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-1"),
+                (tag::EXEC_TYPE, exec_type::NEW),
+                (tag::ORD_STATUS, ord_status::NEW),
+                (tag::LEAVES_QTY, "10"),
+            ],
+        )
+        .await;
+
+        broker.run_until_idle().await;
+        let fill = expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-1"),
+                (tag::EXEC_TYPE, exec_type::TRADE),
+                (tag::ORD_STATUS, ord_status::FILLED),
+                (tag::LAST_QTY, "10"),
+                (tag::CUM_QTY, "10"),
+                (tag::LEAVES_QTY, "0"),
+            ],
+        )
+        .await;
+        assert_eq!(fill.get(tag::ORDER_ID), new.get(tag::ORDER_ID));
+        assert!(fill.parse_required::<f64>(tag::LAST_PX).unwrap() > 0.0);
+
+        initiator
+            .send(order.new_order_single(Utc::now()))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::EXEC_TYPE, exec_type::REJECTED),
+                (tag::ORD_REJ_REASON, "6"),
+            ],
+        )
+        .await;
+
+        let unknown = ticket("ORD-2", "NOPE", 10, OrderType::Market);
+        initiator
+            .send(unknown.new_order_single(Utc::now()))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-2"),
+                (tag::ORDER_ID, "NONE"),
+                (tag::ORD_STATUS, ord_status::REJECTED),
+                (tag::ORD_REJ_REASON, "1"),
+            ],
+        )
+        .await;
+
+        // Missing OrderQty
+        initiator
+            .send(Message::new(msg_type::NEW_ORDER_SINGLE).with(tag::CL_ORD_ID, "ORD-3"))
+            .await
+            .unwrap();
+        expect(&mut initiator, msg_type::REJECT, &[]).await;
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_orders_are_replaced_and_cancelled() {
+        let (address, broker) = create_test_setup().await;
+        let mut initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+
+        // Far below the market, so that it rests
+        let order = ticket("ORD-1", "AAPL", 5, OrderType::Limit(2.0));
+        initiator
+            .send(order.new_order_single(Utc::now()))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[(tag::EXEC_TYPE, exec_type::NEW)],
+        )
+        .await;
+        broker.run_until_idle().await;
+
+        let replacement = ticket("ORD-2", "AAPL", 7, OrderType::Limit(3.0));
+        initiator
+            .send(replacement.cancel_replace_request("ORD-1", Utc::now()))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-2"),
+                (tag::ORIG_CL_ORD_ID, "ORD-1"),
+                (tag::EXEC_TYPE, exec_type::PENDING_REPLACE),
+            ],
+        )
+        .await;
+        broker.run_until_idle().await;
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-2"),
+                (tag::ORIG_CL_ORD_ID, "ORD-1"),
+                (tag::EXEC_TYPE, exec_type::REPLACED),
+                (tag::ORD_STATUS, ord_status::NEW),
+                (tag::ORDER_QTY, "7"),
+                (tag::PRICE, "3"),
+            ],
+        )
+        .await;
+        broker.run_until_idle().await;
+
+        // The replaced order is gone
+        initiator
+            .send(cancel_request(
+                "ORD-3",
+                "ORD-1",
+                "AAPL",
+                &OrderSide::Buy,
+                Utc::now(),
+            ))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::ORDER_CANCEL_REJECT,
+            &[
+                (tag::CL_ORD_ID, "ORD-3"),
+                (tag::CXL_REJ_RESPONSE_TO, response_to::CANCEL),
+            ],
+        )
+        .await;
+
+        initiator
+            .send(cancel_request(
+                "ORD-4",
+                "ORD-2",
+                "AAPL",
+                &OrderSide::Buy,
+                Utc::now(),
+            ))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-4"),
+                (tag::EXEC_TYPE, exec_type::PENDING_CANCEL),
+            ],
+        )
+        .await;
+        broker.run_until_idle().await;
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[
+                (tag::CL_ORD_ID, "ORD-4"),
+                (tag::ORIG_CL_ORD_ID, "ORD-2"),
+                (tag::EXEC_TYPE, exec_type::CANCELED),
+                (tag::LEAVES_QTY, "0"),
+            ],
+        )
+        .await;
+
+        initiator
+            .send(cancel_request(
+                "ORD-5",
+                "UNKNOWN",
+                "AAPL",
+                &OrderSide::Buy,
+                Utc::now(),
+            ))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::ORDER_CANCEL_REJECT,
+            &[(tag::CXL_REJ_REASON, "1")],
+        )
+        .await;
+        initiator.logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_recovers_missed_messages() {
+        let (address, broker) = create_test_setup().await;
+        let mut initiator = Initiator::connect(address, initiator_config(true), 1)
+            .await
+            .unwrap();
+        let order = ticket("ORD-1", "AAPL", 10, OrderType::Market);
+        initiator
+            .send(order.new_order_single(Utc::now()))
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[(tag::EXEC_TYPE, exec_type::NEW)],
+        )
+        .await;
+        let next_outgoing = initiator.logout().await.unwrap();
+
+        // Filled while disconnected: reported on the next logon
+        broker.run_until_idle().await;
+        let mut initiator = Initiator::connect(address, initiator_config(false), next_outgoing)
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::EXECUTION_REPORT,
+            &[(tag::EXEC_TYPE, exec_type::TRADE), (tag::MSG_SEQ_NUM, "5")],
+        )
+        .await;
+
+        // 1 Logon, 2 New, 3 Logout, 4 Logon, 5 Fill
+        initiator
+            .send(
+                Message::new(msg_type::RESEND_REQUEST)
+                    .with(tag::BEGIN_SEQ_NO, 1)
+                    .with(tag::END_SEQ_NO, 0),
+            )
+            .await
+            .unwrap();
+        for (seq_num, msg_type, new_seq_no) in [
+            ("1", msg_type::SEQUENCE_RESET, Some("2")),
+            ("2", msg_type::EXECUTION_REPORT, None),
+            ("3", msg_type::SEQUENCE_RESET, Some("5")),
+            ("5", msg_type::EXECUTION_REPORT, None),
+        ] {
+            let message = expect(
+                &mut initiator,
+                msg_type,
+                &[(tag::MSG_SEQ_NUM, seq_num), (tag::POSS_DUP_FLAG, "Y")],
+            )
+            .await;
+            assert_eq!(message.get(tag::NEW_SEQ_NO), new_seq_no);
+        }
+
+        // A gap in what the gateway receives is asked for again, then filled
+        let expected = initiator.next_outgoing();
+        initiator
+            .send_with_seq(Message::new(msg_type::HEARTBEAT), expected + 2)
+            .await
+            .unwrap();
+        expect(
+            &mut initiator,
+            msg_type::RESEND_REQUEST,
+            &[(tag::BEGIN_SEQ_NO, &expected.to_string())],
+        )
+        .await;
+        initiator
+            .send_with_seq(
+                Message::new(msg_type::SEQUENCE_RESET)
+                    .with(tag::POSS_DUP_FLAG, "Y")
+                    .with(tag::GAP_FILL_FLAG, "Y")
+                    .with(tag::NEW_SEQ_NO, expected + 3),
+                expected,
+            )
+            .await
+            .unwrap();
+        initiator
+            .send_with_seq(
+                Message::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "ping"),
+                expected + 3,
+            )
+            .await
+            .unwrap();
+        let heartbeat = initiator.receive(WAIT).await.unwrap();
+        assert_eq!(heartbeat.msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(heartbeat.get(tag::TEST_REQ_ID), Some("ping"));
+    }
+}
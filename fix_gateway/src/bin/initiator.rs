@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use clap::{Parser, ValueEnum};
+use color_eyre::Result;
+use domain::order::{OrderSide, OrderType};
+use fix_gateway::message::{Message, SOH, tag};
+use fix_gateway::orders::{OrderTicket, cancel_request, exec_type};
+use fix_gateway::{FixError, Initiator, InitiatorConfig};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "fix-initiator")]
+#[command(about = "Log on to the BrokerX FIX gateway, send an order and print the reports")]
+struct Args {
+    /// Address of the gateway
+    #[arg(long, default_value = "127.0.0.1:9878")]
+    address: String,
+
+    /// Our SenderCompID
+    #[arg(long, default_value = "CLIENT")]
+    sender: String,
+
+    /// SenderCompID of the gateway
+    #[arg(long, default_value = "BROKERX")]
+    target: String,
+
+    /// Email of the BrokerX account
+    #[arg(short, long)]
+    username: String,
+
+    #[arg(short, long)]
+    password: String,
+
+    #[arg(long, default_value = "AAPL")]
+    symbol: String,
+
+    #[arg(long, value_enum, default_value = "buy")]
+    side: Side,
+
+    #[arg(short, long, default_value = "10")]
+    quantity: u64,
+
+    /// Limit price, a market order is sent without one
+    #[arg(long)]
+    price: Option<f64>,
+
+    /// Cancel the order once it is acknowledged
+    #[arg(long)]
+    cancel: bool,
+
+    /// Seconds to wait for reports before logging out
+    #[arg(long, default_value = "5")]
+    wait: u64,
+}
+
+fn print(direction: &str, message: &Message) {
+    let text = String::from_utf8_lossy(&message.encode()).replace(SOH as char, "|");
+    println!("{direction} {text}");
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+
+    let config = InitiatorConfig {
+        sender_comp_id: args.sender,
+        target_comp_id: args.target,
+        username: args.username,
+        password: args.password,
+        heartbeat_interval: 30,
+        reset: true,
+    };
+    let mut initiator = Initiator::connect(&args.address, config, 1).await?;
+    println!("Logged on to {}", args.address);
+
+    let ticket = OrderTicket {
+        cl_ord_id: format!("ORD-{}", Utc::now().timestamp_millis()),
+        symbol: args.symbol,
+        side: match args.side {
+            Side::Buy => OrderSide::Buy,
+            Side::Sell => OrderSide::Sell,
+        },
+        quantity: args.quantity,
+        order_type: args.price.map_or(OrderType::Market, OrderType::Limit),
+    };
+    let order = ticket.new_order_single(Utc::now());
+    print("->", &order);
+    initiator.send(order).await?;
+
+    let wait = Duration::from_secs(args.wait);
+    loop {
+        let message = match initiator.next_message(wait).await {
+            Ok(message) => message,
+            Err(FixError::Timeout) => break,
+            Err(e) => return Err(e.into()),
+        };
+        print("<-", &message);
+
+        if args.cancel && message.get(tag::EXEC_TYPE) == Some(exec_type::NEW) {
+            let cancel = cancel_request(
+                &format!("{}-CXL", ticket.cl_ord_id),
+                &ticket.cl_ord_id,
+                &ticket.symbol,
+                &ticket.side,
+                Utc::now(),
+            );
+            print("->", &cancel);
+            initiator.send(cancel).await?;
+        }
+    }
+
+    initiator.logout().await?;
+    println!("Logged out");
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::FixError;
+use crate::message::{Message, msg_type, tag, take_frame};
+
+/// Logon parameters of an initiator
+#[derive(Debug, Clone)]
+pub struct InitiatorConfig {
+    pub sender_comp_id: String,
+    pub target_comp_id: String,
+    /// Email of the BrokerX account
+    pub username: String,
+    pub password: String,
+    /// `HeartBtInt`, in seconds
+    pub heartbeat_interval: u64,
+    /// Ask the acceptor to restart both sequences at 1
+    pub reset: bool,
+}
+
+/// Minimal FIX counterparty: it logs on, sends messages and reads what the acceptor sends
+/// back. It does not check incoming sequence numbers.
+#[derive(Debug)]
+pub struct Initiator {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    config: InitiatorConfig,
+    next_outgoing: u64,
+}
+
+impl Initiator {
+    /// Connect and log on, numbering messages from `next_outgoing`
+    /// # Errors
+    /// Returns `FixError::LoggedOut` if the logon is refused, or another `FixError` if
+    /// the connection fails
+    pub async fn connect(
+        address: impl ToSocketAddrs,
+        config: InitiatorConfig,
+        next_outgoing: u64,
+    ) -> Result<Self, FixError> {
+        let mut logon = Message::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, config.heartbeat_interval)
+            .with(tag::USERNAME, &config.username)
+            .with(tag::PASSWORD, &config.password);
+        if config.reset {
+            logon.set(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+
+        let mut initiator = Self {
+            stream: TcpStream::connect(address).await?,
+            buffer: Vec::new(),
+            next_outgoing: if config.reset { 1 } else { next_outgoing },
+            config,
+        };
+        initiator.send(logon).await?;
+        let reply = initiator.receive(Duration::from_secs(5)).await?;
+        match reply.msg_type() {
+            msg_type::LOGON => Ok(initiator),
+            msg_type::LOGOUT => Err(FixError::LoggedOut(
+                reply.get(tag::TEXT).map(str::to_string),
+            )),
+            other => Err(FixError::Malformed(format!(
+                "expected a logon reply, got message type {other}"
+            ))),
+        }
+    }
+
+    /// `MsgSeqNum` of the next message sent
+    #[must_use]
+    pub fn next_outgoing(&self) -> u64 {
+        self.next_outgoing
+    }
+
+    /// Send a message with the next sequence number, returned
+    /// # Errors
+    /// Returns `FixError::Io` if the message cannot be written
+    pub async fn send(&mut self, message: Message) -> Result<u64, FixError> {
+        let seq_num = self.next_outgoing;
+        self.send_with_seq(message, seq_num).await?;
+        self.next_outgoing += 1;
+        Ok(seq_num)
+    }
+
+    /// Send a message with an arbitrary sequence number, leaving the sequence unchanged
+    /// # Errors
+    /// Returns `FixError::Io` if the message cannot be written
+    pub async fn send_with_seq(
+        &mut self,
+        mut message: Message,
+        seq_num: u64,
+    ) -> Result<(), FixError> {
+        message.set_header(
+            &self.config.sender_comp_id,
+            &self.config.target_comp_id,
+            seq_num,
+            Utc::now(),
+        );
+        self.stream.write_all(&message.encode()).await?;
+        Ok(())
+    }
+
+    /// Next message from the acceptor, whatever its type
+    /// # Errors
+    /// Returns `FixError::Timeout` if nothing arrives within `timeout`,
+    /// `FixError::LoggedOut` if the connection is closed
+    pub async fn receive(&mut self, timeout: Duration) -> Result<Message, FixError> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(frame) = take_frame(&mut self.buffer)? {
+                    return Message::decode(&frame);
+                }
+                if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                    return Err(FixError::LoggedOut(None));
+                }
+            }
+        })
+        .await
+        .map_err(|_| FixError::Timeout)?
+    }
+
+    /// Next message other than a heartbeat, answering test requests on the way
+    /// # Errors
+    /// Returns `FixError::LoggedOut` on a logout, or another `FixError` as `receive`
+    pub async fn next_message(&mut self, timeout: Duration) -> Result<Message, FixError> {
+        loop {
+            let message = self.receive(timeout).await?;
+            match message.msg_type() {
+                msg_type::HEARTBEAT => {}
+                msg_type::TEST_REQUEST => {
+                    let mut heartbeat = Message::new(msg_type::HEARTBEAT);
+                    if let Some(id) = message.get(tag::TEST_REQ_ID) {
+                        heartbeat.set(tag::TEST_REQ_ID, id);
+                    }
+                    self.send(heartbeat).await?;
+                }
+                msg_type::LOGOUT => {
+                    return Err(FixError::LoggedOut(
+                        message.get(tag::TEXT).map(str::to_string),
+                    ));
+                }
+                _ => return Ok(message),
+            }
+        }
+    }
+
+    /// Log out and wait for the acceptor to confirm. Returns the next outgoing sequence
+    /// number, to reconnect with.
+    /// # Errors
+    /// Returns `FixError` if the logout is not confirmed
+    pub async fn logout(mut self) -> Result<u64, FixError> {
+        self.send(Message::new(msg_type::LOGOUT)).await?;
+        loop {
+            match self.next_message(Duration::from_secs(5)).await {
+                Err(FixError::LoggedOut(_)) => return Ok(self.next_outgoing),
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! FIX 4.4 order entry for BrokerX.
+//!
+//! The acceptor authenticates counterparties on Logon with the `Username` and
+//! `Password` of a BrokerX account, maps `NewOrderSingle`, `OrderCancelRequest` and
+//! `OrderCancelReplaceRequest` onto `BrokerX::create_order` and `BrokerX::cancel_order`,
+//! and reports the order lifecycle published on the event bus as `ExecutionReport`s.
+//!
+//! Session sequence numbers and the application messages sent are persisted, so a
+//! counterparty can reconnect and ask for what it missed with a `ResendRequest`.
+//! The `fix-initiator` binary is a minimal counterparty to exercise the gateway.
+
+use database_adapter::db::DbError;
+
+mod acceptor;
+mod initiator;
+pub mod message;
+pub mod orders;
+pub mod store;
+
+pub use acceptor::{AcceptorConfig, FixAcceptor};
+pub use initiator::{Initiator, InitiatorConfig};
+pub use message::Message;
+pub use store::FixStore;
+
+/// FIX gateway errors
+#[derive(Debug)]
+pub enum FixError {
+    Io(std::io::Error),
+    Malformed(String),
+    InvalidChecksum {
+        expected: String,
+        actual: u32,
+    },
+    MissingField(u32),
+    InvalidField {
+        tag: u32,
+        value: String,
+    },
+    /// The counterparty ended the session or refused the logon
+    LoggedOut(Option<String>),
+    Timeout,
+    DbError(DbError),
+}
+
+impl std::fmt::Display for FixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixError::Io(e) => write!(f, "Connection error: {e}"),
+            FixError::Malformed(msg) => write!(f, "Malformed message: {msg}"),
+            FixError::InvalidChecksum { expected, actual } => {
+                write!(
+                    f,
+                    "Invalid checksum: expected {expected}, computed {actual:03}"
+                )
+            }
+            FixError::MissingField(tag) => write!(f, "Required tag {tag} missing"),
+            FixError::InvalidField { tag, value } => {
+                write!(f, "Incorrect value {value:?} for tag {tag}")
+            }
+            FixError::LoggedOut(Some(text)) => write!(f, "Logged out: {text}"),
+            FixError::LoggedOut(None) => write!(f, "Logged out"),
+            FixError::Timeout => write!(f, "Timed out waiting for the counterparty"),
+            FixError::DbError(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+impl From<std::io::Error> for FixError {
+    fn from(e: std::io::Error) -> Self {
+        FixError::Io(e)
+    }
+}
+
+impl From<DbError> for FixError {
+    fn from(e: DbError) -> Self {
+        FixError::DbError(e)
+    }
+}
@@ -0,0 +1,389 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use crate::FixError;
+
+/// Version spoken by the gateway
+pub const BEGIN_STRING: &str = "FIX.4.4";
+/// Field delimiter
+pub const SOH: u8 = 0x01;
+
+/// Tags of the fields used by the gateway
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECKSUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const STOP_PX: u32 = 99;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+}
+
+/// Message types handled or sent by the gateway
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+
+    /// Session level messages, which are never resent
+    #[must_use]
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(
+            msg_type,
+            HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON
+        )
+    }
+}
+
+/// Format of `SendingTime`, `TransactTime` and `OrigSendingTime`
+#[must_use]
+pub fn format_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+/// A FIX message: its type and its fields in order, without the `BeginString`,
+/// `BodyLength` and `CheckSum` framing fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    msg_type: String,
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    #[must_use]
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            msg_type: msg_type.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Set a field, builder style
+    #[must_use]
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    /// Set a field, replacing its previous value
+    pub fn set(&mut self, tag: u32, value: impl ToString) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(t, _)| *t == tag) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value)),
+        }
+    }
+
+    /// Set the standard header fields, ahead of the body
+    pub fn set_header(
+        &mut self,
+        sender_comp_id: &str,
+        target_comp_id: &str,
+        seq_num: u64,
+        sending_time: DateTime<Utc>,
+    ) {
+        let header = [
+            (tag::SENDER_COMP_ID, sender_comp_id.to_string()),
+            (tag::TARGET_COMP_ID, target_comp_id.to_string()),
+            (tag::MSG_SEQ_NUM, seq_num.to_string()),
+            (tag::SENDING_TIME, format_timestamp(sending_time)),
+        ];
+        self.fields
+            .retain(|(t, _)| !header.iter().any(|(header_tag, _)| header_tag == t));
+        self.fields.splice(0..0, header);
+    }
+
+    pub fn remove(&mut self, tag: u32) {
+        self.fields.retain(|(t, _)| *t != tag);
+    }
+
+    #[must_use]
+    pub fn msg_type(&self) -> &str {
+        &self.msg_type
+    }
+
+    #[must_use]
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value of a required field
+    /// # Errors
+    /// Returns `FixError::MissingField` if the field is absent
+    pub fn required(&self, tag: u32) -> Result<&str, FixError> {
+        self.get(tag).ok_or(FixError::MissingField(tag))
+    }
+
+    /// Parsed value of a field, `None` if it is absent
+    /// # Errors
+    /// Returns `FixError::InvalidField` if the value cannot be parsed
+    pub fn parse<T: FromStr>(&self, tag: u32) -> Result<Option<T>, FixError> {
+        self.get(tag)
+            .map(|value| {
+                value.parse().map_err(|_| FixError::InvalidField {
+                    tag,
+                    value: value.to_string(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Parsed value of a required field
+    /// # Errors
+    /// Returns `FixError` if the field is absent or cannot be parsed
+    pub fn parse_required<T: FromStr>(&self, tag: u32) -> Result<T, FixError> {
+        self.parse(tag)?.ok_or(FixError::MissingField(tag))
+    }
+
+    /// Whether a `Y`/`N` flag is set
+    #[must_use]
+    pub fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    /// `MsgSeqNum` of the message, 0 if it has none
+    #[must_use]
+    pub fn seq_num(&self) -> u64 {
+        self.parse(tag::MSG_SEQ_NUM).ok().flatten().unwrap_or(0)
+    }
+
+    /// Serialize with the framing fields
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_field(&mut body, tag::MSG_TYPE, &self.msg_type);
+        for (tag, value) in &self.fields {
+            push_field(&mut body, *tag, value);
+        }
+
+        let mut message = Vec::with_capacity(body.len() + 32);
+        push_field(&mut message, tag::BEGIN_STRING, BEGIN_STRING);
+        push_field(&mut message, tag::BODY_LENGTH, &body.len().to_string());
+        message.extend_from_slice(&body);
+        let checksum = checksum(&message);
+        push_field(&mut message, tag::CHECKSUM, &format!("{checksum:03}"));
+        message
+    }
+
+    /// Parse a complete message, checking its framing
+    /// # Errors
+    /// Returns `FixError` if the message is malformed or its checksum is wrong
+    pub fn decode(frame: &[u8]) -> Result<Self, FixError> {
+        let text = std::str::from_utf8(frame)
+            .map_err(|_| FixError::Malformed("message is not UTF-8".to_string()))?;
+        let mut fields = Vec::new();
+        for field in text.split(SOH as char).filter(|field| !field.is_empty()) {
+            let (tag, value) = field
+                .split_once('=')
+                .ok_or_else(|| FixError::Malformed(format!("field without a tag: {field}")))?;
+            let tag = tag
+                .parse::<u32>()
+                .map_err(|_| FixError::Malformed(format!("invalid tag: {tag}")))?;
+            fields.push((tag, value.to_string()));
+        }
+
+        match fields.as_slice() {
+            [
+                (tag::BEGIN_STRING, version),
+                (tag::BODY_LENGTH, _),
+                (tag::MSG_TYPE, _),
+                ..,
+            ] if version == BEGIN_STRING => {}
+            [(tag::BEGIN_STRING, version), ..] => {
+                return Err(FixError::Malformed(format!(
+                    "unsupported version {version}"
+                )));
+            }
+            _ => return Err(FixError::Malformed("invalid header".to_string())),
+        }
+        let Some((tag::CHECKSUM, expected)) = fields.pop() else {
+            return Err(FixError::Malformed("missing checksum".to_string()));
+        };
+        // The checksum covers every byte before the `10=` field
+        let checksum_start = frame.len() - (expected.len() + 4);
+        let actual = checksum(&frame[..checksum_start]);
+        if expected.parse::<u32>().ok() != Some(actual) {
+            return Err(FixError::InvalidChecksum { expected, actual });
+        }
+
+        let mut fields = fields.into_iter().skip(2);
+        let (_, msg_type) = fields.next().unwrap_or_default();
+        Ok(Self {
+            msg_type,
+            fields: fields.collect(),
+        })
+    }
+}
+
+fn push_field(buffer: &mut Vec<u8>, tag: u32, value: &str) {
+    buffer.extend_from_slice(tag.to_string().as_bytes());
+    buffer.push(b'=');
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(SOH);
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|byte| u32::from(*byte)).sum::<u32>() % 256
+}
+
+/// Take the first complete message out of `buffer`, if it holds one.
+/// Bytes before the next `8=` are discarded.
+/// # Errors
+/// Returns `FixError::Malformed` if the `BodyLength` field is invalid
+pub fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, FixError> {
+    let start = buffer
+        .windows(2)
+        .position(|window| window == b"8=")
+        .unwrap_or(buffer.len().saturating_sub(1));
+    buffer.drain(..start);
+
+    // 8=FIX.4.4<SOH>9=<length><SOH>
+    let Some(begin_end) = buffer.iter().position(|byte| *byte == SOH) else {
+        return Ok(None);
+    };
+    let Some(length_end) = buffer[begin_end + 1..]
+        .iter()
+        .position(|byte| *byte == SOH)
+        .map(|position| begin_end + 1 + position)
+    else {
+        return Ok(None);
+    };
+    let length = std::str::from_utf8(&buffer[begin_end + 1..length_end])
+        .ok()
+        .and_then(|field| field.strip_prefix("9="))
+        .and_then(|length| length.parse::<usize>().ok())
+        .ok_or_else(|| {
+            // Skip the broken header so that the next message can be read
+            buffer.drain(..length_end);
+            FixError::Malformed("invalid body length".to_string())
+        })?;
+
+    // The trailer is `10=` followed by three digits and a delimiter
+    let end = length_end + 1 + length + 7;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    Ok(Some(buffer.drain(..end).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readable(bytes: &[u8]) -> String {
+        String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .replace('\x01', "|")
+    }
+
+    #[test]
+    fn test_encoding_computes_length_and_checksum() {
+        let message = Message::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "BROKERX")
+            .with(tag::MSG_SEQ_NUM, 2);
+        let encoded = message.encode();
+        assert_eq!(
+            readable(&encoded),
+            "8=FIX.4.4|9=31|35=0|49=CLIENT|56=BROKERX|34=2|10=217|"
+        );
+
+        let decoded = Message::decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.seq_num(), 2);
+    }
+
+    #[test]
+    fn test_corrupted_messages_are_refused() {
+        let mut encoded = Message::new(msg_type::HEARTBEAT)
+            .with(tag::MSG_SEQ_NUM, 1)
+            .encode();
+        let position = encoded.iter().position(|byte| *byte == b'1').unwrap();
+        encoded[position] = b'7';
+        assert!(matches!(
+            Message::decode(&encoded),
+            Err(FixError::InvalidChecksum { .. })
+        ));
+
+        let fix42 = b"8=FIX.4.2\x019=5\x0135=0\x0110=000\x01";
+        assert!(matches!(
+            Message::decode(fix42),
+            Err(FixError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_frames_are_split_from_a_stream() {
+        let first = Message::new(msg_type::HEARTBEAT)
+            .with(tag::MSG_SEQ_NUM, 1)
+            .encode();
+        let second = Message::new(msg_type::TEST_REQUEST)
+            .with(tag::MSG_SEQ_NUM, 2)
+            .with(tag::TEST_REQ_ID, "ping")
+            .encode();
+
+        let mut buffer = b"noise".to_vec();
+        buffer.extend_from_slice(&first);
+        buffer.extend_from_slice(&second[..10]);
+        assert_eq!(take_frame(&mut buffer).unwrap(), Some(first));
+        assert_eq!(take_frame(&mut buffer).unwrap(), None);
+
+        buffer.extend_from_slice(&second[10..]);
+        let frame = take_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!(
+            Message::decode(&frame).unwrap().get(tag::TEST_REQ_ID),
+            Some("ping")
+        );
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+//! Mapping between FIX order messages and BrokerX orders
+
+use chrono::{DateTime, Utc};
+use domain::PreTradeError;
+use domain::order::{OrderId, OrderSide, OrderStatus, OrderType};
+use uuid::Uuid;
+
+use crate::FixError;
+use crate::message::{Message, format_timestamp, msg_type, tag};
+use crate::store::FixOrder;
+
+/// `ExecType` values
+pub mod exec_type {
+    pub const NEW: &str = "0";
+    pub const CANCELED: &str = "4";
+    pub const REPLACED: &str = "5";
+    pub const PENDING_CANCEL: &str = "6";
+    pub const REJECTED: &str = "8";
+    pub const EXPIRED: &str = "C";
+    pub const PENDING_REPLACE: &str = "E";
+    pub const TRADE: &str = "F";
+}
+
+/// `OrdStatus` values
+pub mod ord_status {
+    pub const NEW: &str = "0";
+    pub const FILLED: &str = "2";
+    pub const CANCELED: &str = "4";
+    pub const PENDING_CANCEL: &str = "6";
+    pub const REJECTED: &str = "8";
+    pub const EXPIRED: &str = "C";
+    pub const PENDING_REPLACE: &str = "E";
+}
+
+/// `OrdRejReason` and `CxlRejReason` values
+pub mod reject_reason {
+    pub const TOO_LATE: u32 = 0;
+    pub const UNKNOWN_SYMBOL: u32 = 1;
+    pub const UNKNOWN_ORDER: u32 = 1;
+    pub const EXCHANGE_CLOSED: u32 = 2;
+    pub const ORDER_EXCEEDS_LIMIT: u32 = 3;
+    pub const ALREADY_PENDING: u32 = 3;
+    pub const DUPLICATE_ORDER: u32 = 6;
+    pub const OTHER: u32 = 99;
+}
+
+/// `CxlRejResponseTo` values
+pub mod response_to {
+    pub const CANCEL: &str = "1";
+    pub const CANCEL_REPLACE: &str = "2";
+}
+
+/// Order fields of a `NewOrderSingle` or `OrderCancelReplaceRequest`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderTicket {
+    pub cl_ord_id: String,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub order_type: OrderType,
+}
+
+impl OrderTicket {
+    /// # Errors
+    /// Returns `FixError` if a field is missing or invalid
+    pub fn parse(message: &Message) -> Result<Self, FixError> {
+        let quantity = message.parse_required(tag::ORDER_QTY)?;
+        if quantity == 0 {
+            return Err(FixError::InvalidField {
+                tag: tag::ORDER_QTY,
+                value: "0".to_string(),
+            });
+        }
+        Ok(Self {
+            cl_ord_id: message.required(tag::CL_ORD_ID)?.to_string(),
+            symbol: message.required(tag::SYMBOL)?.to_string(),
+            side: parse_side(message.required(tag::SIDE)?)?,
+            quantity,
+            order_type: parse_order_type(message)?,
+        })
+    }
+
+    fn write(&self, message: &mut Message) {
+        message.set(tag::CL_ORD_ID, &self.cl_ord_id);
+        message.set(tag::SYMBOL, &self.symbol);
+        message.set(tag::SIDE, side_code(&self.side));
+        message.set(tag::ORDER_QTY, self.quantity);
+        write_order_type(message, &self.order_type);
+    }
+
+    /// `NewOrderSingle` entering this order
+    #[must_use]
+    pub fn new_order_single(&self, transact_time: DateTime<Utc>) -> Message {
+        let mut message = Message::new(msg_type::NEW_ORDER_SINGLE);
+        self.write(&mut message);
+        message.set(tag::TRANSACT_TIME, format_timestamp(transact_time));
+        message
+    }
+
+    /// `OrderCancelReplaceRequest` replacing the order `orig_cl_ord_id` with this one
+    #[must_use]
+    pub fn cancel_replace_request(
+        &self,
+        orig_cl_ord_id: &str,
+        transact_time: DateTime<Utc>,
+    ) -> Message {
+        let mut message = Message::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        self.write(&mut message);
+        message.set(tag::TRANSACT_TIME, format_timestamp(transact_time));
+        message
+    }
+}
+
+/// `OrderCancelRequest` for the order `orig_cl_ord_id`
+#[must_use]
+pub fn cancel_request(
+    cl_ord_id: &str,
+    orig_cl_ord_id: &str,
+    symbol: &str,
+    side: &OrderSide,
+    transact_time: DateTime<Utc>,
+) -> Message {
+    Message::new(msg_type::ORDER_CANCEL_REQUEST)
+        .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::SYMBOL, symbol)
+        .with(tag::SIDE, side_code(side))
+        .with(tag::TRANSACT_TIME, format_timestamp(transact_time))
+}
+
+/// # Errors
+/// Returns `FixError::InvalidField` for sides other than buy and sell
+pub fn parse_side(value: &str) -> Result<OrderSide, FixError> {
+    match value {
+        "1" => Ok(OrderSide::Buy),
+        "2" => Ok(OrderSide::Sell),
+        _ => Err(FixError::InvalidField {
+            tag: tag::SIDE,
+            value: value.to_string(),
+        }),
+    }
+}
+
+#[must_use]
+pub fn side_code(side: &OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "1",
+        OrderSide::Sell => "2",
+    }
+}
+
+/// Order type from `OrdType`, with its `Price` or `StopPx`
+/// # Errors
+/// Returns `FixError` for unsupported order types or a missing price
+pub fn parse_order_type(message: &Message) -> Result<OrderType, FixError> {
+    match message.required(tag::ORD_TYPE)? {
+        "1" => Ok(OrderType::Market),
+        "2" => Ok(OrderType::Limit(message.parse_required(tag::PRICE)?)),
+        "3" => Ok(OrderType::Stop(message.parse_required(tag::STOP_PX)?)),
+        other => Err(FixError::InvalidField {
+            tag: tag::ORD_TYPE,
+            value: other.to_string(),
+        }),
+    }
+}
+
+fn write_order_type(message: &mut Message, order_type: &OrderType) {
+    match order_type {
+        OrderType::Market => message.set(tag::ORD_TYPE, "1"),
+        OrderType::Limit(price) => {
+            message.set(tag::ORD_TYPE, "2");
+            message.set(tag::PRICE, price);
+        }
+        OrderType::Stop(price) => {
+            message.set(tag::ORD_TYPE, "3");
+            message.set(tag::STOP_PX, price);
+        }
+    }
+}
+
+/// `OrdStatus` of a BrokerX order
+#[must_use]
+pub fn ord_status_of(status: &OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::Queued | OrderStatus::Pending => ord_status::NEW,
+        OrderStatus::PendingCancel => ord_status::PENDING_CANCEL,
+        OrderStatus::Filled { .. } => ord_status::FILLED,
+        OrderStatus::Cancelled => ord_status::CANCELED,
+        OrderStatus::Expired { .. } => ord_status::EXPIRED,
+        OrderStatus::Rejected { .. } => ord_status::REJECTED,
+    }
+}
+
+/// `OrdRejReason` of an order refused by pre-trade checks
+#[must_use]
+pub fn ord_rej_reason(error: &PreTradeError) -> u32 {
+    match error {
+        PreTradeError::UnknownInstrument { .. } => reject_reason::UNKNOWN_SYMBOL,
+        PreTradeError::MarketClosed { .. } => reject_reason::EXCHANGE_CLOSED,
+        PreTradeError::ExceedsPositionLimit { .. } | PreTradeError::ExceedsNotionalLimit { .. } => {
+            reject_reason::ORDER_EXCEEDS_LIMIT
+        }
+        _ => reject_reason::OTHER,
+    }
+}
+
+/// `ExecutionReport` of an order entered through the gateway. Terminal statuses leave
+/// nothing open; `Price` and `StopPx` are those of the order.
+#[must_use]
+pub fn execution_report(
+    order: &FixOrder,
+    exec_type: &str,
+    ord_status: &str,
+    transact_time: DateTime<Utc>,
+) -> Message {
+    let open = matches!(
+        ord_status,
+        ord_status::NEW | ord_status::PENDING_CANCEL | ord_status::PENDING_REPLACE
+    );
+    let mut message = Message::new(msg_type::EXECUTION_REPORT)
+        .with(tag::ORDER_ID, order.order_id)
+        .with(tag::CL_ORD_ID, &order.cl_ord_id)
+        .with(tag::EXEC_ID, Uuid::new_v4())
+        .with(tag::EXEC_TYPE, exec_type)
+        .with(tag::ORD_STATUS, ord_status)
+        .with(tag::SYMBOL, &order.symbol)
+        .with(tag::SIDE, side_code(&order.side))
+        .with(tag::ORDER_QTY, order.quantity);
+    write_order_type(&mut message, &order.order_type);
+    if let Some(orig_cl_ord_id) = &order.orig_cl_ord_id {
+        message.set(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+    }
+    message
+        .with(tag::LEAVES_QTY, if open { order.quantity } else { 0 })
+        .with(tag::CUM_QTY, 0)
+        .with(tag::AVG_PX, 0)
+        .with(tag::TRANSACT_TIME, format_timestamp(transact_time))
+}
+
+/// `ExecutionReport` of an order fully executed at `price`
+#[must_use]
+pub fn fill_report(order: &FixOrder, price: f64, transact_time: DateTime<Utc>) -> Message {
+    execution_report(order, exec_type::TRADE, ord_status::FILLED, transact_time)
+        .with(tag::LAST_QTY, order.quantity)
+        .with(tag::LAST_PX, price)
+        .with(tag::CUM_QTY, order.quantity)
+        .with(tag::AVG_PX, price)
+}
+
+/// `ExecutionReport` of an order refused before it reached BrokerX
+#[must_use]
+pub fn order_rejected(
+    ticket: &OrderTicket,
+    reason: u32,
+    text: &str,
+    transact_time: DateTime<Utc>,
+) -> Message {
+    let order = FixOrder {
+        session_id: String::new(),
+        cl_ord_id: ticket.cl_ord_id.clone(),
+        order_id: OrderId::nil(),
+        symbol: ticket.symbol.clone(),
+        side: ticket.side.clone(),
+        quantity: ticket.quantity,
+        order_type: ticket.order_type.clone(),
+        orig_cl_ord_id: None,
+        pending_cancel: None,
+        pending_replace: None,
+    };
+    execution_report(
+        &order,
+        exec_type::REJECTED,
+        ord_status::REJECTED,
+        transact_time,
+    )
+    .with(tag::ORDER_ID, "NONE")
+    .with(tag::ORD_REJ_REASON, reason)
+    .with(tag::TEXT, text)
+}
+
+/// `OrderCancelReject` answering the request `cl_ord_id`
+#[must_use]
+pub fn cancel_reject(
+    cl_ord_id: &str,
+    orig_cl_ord_id: &str,
+    order_id: Option<OrderId>,
+    ord_status: &str,
+    response_to: &str,
+    reason: u32,
+    text: &str,
+) -> Message {
+    Message::new(msg_type::ORDER_CANCEL_REJECT)
+        .with(
+            tag::ORDER_ID,
+            order_id.map_or_else(|| "NONE".to_string(), |id| id.to_string()),
+        )
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+        .with(tag::ORD_STATUS, ord_status)
+        .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+        .with(tag::CXL_REJ_REASON, reason)
+        .with(tag::TEXT, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_tickets_round_trip() {
+        let ticket = OrderTicket {
+            cl_ord_id: "ORD-1".to_string(),
+            symbol: "AAPL".to_string(),
+            side: OrderSide::Sell,
+            quantity: 25,
+            order_type: OrderType::Limit(151.25),
+        };
+        let message = Message::decode(&ticket.new_order_single(Utc::now()).encode()).unwrap();
+        assert_eq!(message.get(tag::ORD_TYPE), Some("2"));
+        assert_eq!(OrderTicket::parse(&message).unwrap(), ticket);
+
+        let stop = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, "ORD-2")
+            .with(tag::SYMBOL, "AAPL")
+            .with(tag::SIDE, "1")
+            .with(tag::ORDER_QTY, 10)
+            .with(tag::ORD_TYPE, "3");
+        assert!(matches!(
+            OrderTicket::parse(&stop),
+            Err(FixError::MissingField(tag::STOP_PX))
+        ));
+        let stop = stop.with(tag::STOP_PX, 160);
+        assert_eq!(
+            OrderTicket::parse(&stop).unwrap().order_type,
+            OrderType::Stop(160.0)
+        );
+        assert!(matches!(
+            OrderTicket::parse(&stop.with(tag::SIDE, "5")),
+            Err(FixError::InvalidField { tag: tag::SIDE, .. })
+        ));
+    }
+}
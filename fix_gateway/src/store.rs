@@ -0,0 +1,326 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use database_adapter::db::{DbError, JsonRepo, Repository, Store};
+use domain::order::{OrderId, OrderSide, OrderType};
+use domain::user::UserId;
+use serde::{Deserialize, Serialize};
+
+/// Sequence numbers of a session, kept across connections
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionState {
+    pub session_id: String,
+    /// User who first logged on to the session, the only one who can log on again
+    #[serde(default)]
+    pub user_id: Option<UserId>,
+    /// `MsgSeqNum` of the next message sent by the gateway
+    pub next_outgoing: u64,
+    /// `MsgSeqNum` expected on the next message from the counterparty
+    pub next_incoming: u64,
+    /// Last event feed sequence handled, to report what happened to the session's
+    /// orders while it was disconnected, whichever instance it reconnects to
+    #[serde(default)]
+    pub last_event: Option<u64>,
+}
+
+impl SessionState {
+    fn new(session_id: &str) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            user_id: None,
+            next_outgoing: 1,
+            next_incoming: 1,
+            last_event: None,
+        }
+    }
+}
+
+/// Application message sent on a session, kept to answer resend requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentMessage {
+    pub session_id: String,
+    /// Sequence number padded so that ranges can be queried as text
+    pub seq_key: String,
+    pub seq_num: u64,
+    /// Encoded message
+    pub raw: String,
+}
+
+/// Replacement of an order, sent once the order it replaces is cancelled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingReplace {
+    pub cl_ord_id: String,
+    pub quantity: u64,
+    pub order_type: OrderType,
+}
+
+/// Order entered through the gateway, with the client's identifiers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixOrder {
+    pub session_id: String,
+    pub cl_ord_id: String,
+    pub order_id: OrderId,
+    pub symbol: String,
+    pub side: OrderSide,
+    pub quantity: u64,
+    pub order_type: OrderType,
+    /// `ClOrdID` of the order this one replaced
+    pub orig_cl_ord_id: Option<String>,
+    /// `ClOrdID` of the cancel request being processed
+    pub pending_cancel: Option<String>,
+    pub pending_replace: Option<PendingReplace>,
+}
+
+/// Connection holding a session, whichever instance it is connected to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionLease {
+    pub holder: String,
+    /// Milliseconds since the epoch
+    pub until: i64,
+}
+
+pub type SessionRepo = JsonRepo<SessionState, String>;
+pub type SessionLeaseRepo = JsonRepo<SessionLease, String>;
+pub type SentMessageRepo = JsonRepo<SentMessage, String>;
+pub type FixOrderRepo = JsonRepo<FixOrder, OrderId>;
+
+/// Persistent state of the gateway
+#[derive(Debug, Clone)]
+pub struct FixStore {
+    sessions: SessionRepo,
+    leases: SessionLeaseRepo,
+    messages: SentMessageRepo,
+    orders: FixOrderRepo,
+}
+
+fn seq_key(seq_num: u64) -> String {
+    format!("{seq_num:020}")
+}
+
+impl FixStore {
+    /// Store backed by the `fix_sessions`, `fix_session_leases`, `fix_messages` and
+    /// `fix_orders` tables of `store`
+    /// # Errors
+    /// Returns `DbError` if a table cannot be created
+    pub async fn new(store: Arc<dyn Store>) -> Result<Self, DbError> {
+        Ok(Self {
+            sessions: SessionRepo::new(Arc::clone(&store), "fix_sessions").await?,
+            leases: SessionLeaseRepo::new(Arc::clone(&store), "fix_session_leases").await?,
+            messages: SentMessageRepo::new(Arc::clone(&store), "fix_messages").await?,
+            orders: FixOrderRepo::new(store, "fix_orders").await?,
+        })
+    }
+
+    /// Sequence numbers of a session, starting at 1 for a new session
+    /// # Errors
+    /// Returns `DbError` if the session cannot be read
+    pub async fn session(&self, session_id: &str) -> Result<SessionState, DbError> {
+        Ok(self
+            .sessions
+            .get(&session_id.to_string())
+            .await?
+            .unwrap_or_else(|| SessionState::new(session_id)))
+    }
+
+    /// # Errors
+    /// Returns `DbError` if the session cannot be written
+    pub async fn save_session(&self, state: &SessionState) -> Result<(), DbError> {
+        let id = state.session_id.clone();
+        match self.sessions.get(&id).await? {
+            Some(_) => self.sessions.update(id, state.clone()).await,
+            None => self.sessions.insert(id, state.clone()).await,
+        }
+    }
+
+    /// Hold a session for `lease` on behalf of the connection `holder`, unless another
+    /// connection holds it. Holders renew their lease with the same call.
+    /// Returns whether `holder` holds the session.
+    /// # Errors
+    /// Returns `DbError` if the lease cannot be written
+    pub async fn take_lease(
+        &self,
+        session_id: &str,
+        holder: &str,
+        lease: Duration,
+    ) -> Result<bool, DbError> {
+        let now = Utc::now().timestamp_millis();
+        let lease = i64::try_from(lease.as_millis()).unwrap_or(i64::MAX);
+        let taken = self
+            .leases
+            .take_lease(
+                &session_id.to_string(),
+                holder,
+                now.saturating_add(lease),
+                now,
+            )
+            .await?;
+        Ok(taken == 1)
+    }
+
+    /// Let another connection hold the session at once
+    /// # Errors
+    /// Returns `DbError` if the lease cannot be written
+    pub async fn release_lease(&self, session_id: &str, holder: &str) -> Result<(), DbError> {
+        let now = Utc::now().timestamp_millis();
+        self.leases
+            .take_lease(&session_id.to_string(), holder, 0, now)
+            .await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns `DbError` if the message cannot be written
+    pub async fn record_sent(
+        &self,
+        session_id: &str,
+        seq_num: u64,
+        raw: String,
+    ) -> Result<(), DbError> {
+        let id = format!("{session_id}:{}", seq_key(seq_num));
+        let message = SentMessage {
+            session_id: session_id.to_string(),
+            seq_key: seq_key(seq_num),
+            seq_num,
+            raw,
+        };
+        // A reset session reuses sequence numbers
+        match self.messages.get(&id).await? {
+            Some(_) => self.messages.update(id, message).await,
+            None => self.messages.insert(id, message).await,
+        }
+    }
+
+    /// Application messages sent with a sequence number in `[from, to]`, in order
+    /// # Errors
+    /// Returns `DbError` if the messages cannot be read
+    pub async fn sent_between(
+        &self,
+        session_id: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<SentMessage>, DbError> {
+        Ok(self
+            .messages
+            .find_range_by_field(
+                "session_id",
+                session_id,
+                "seq_key",
+                &seq_key(from),
+                &seq_key(to.saturating_add(1)),
+            )
+            .await?
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect())
+    }
+
+    /// # Errors
+    /// Returns `DbError` if the order cannot be read
+    pub async fn order(&self, order_id: &OrderId) -> Result<Option<FixOrder>, DbError> {
+        self.orders.get(order_id).await
+    }
+
+    /// Order of a session by the client's `ClOrdID`
+    /// # Errors
+    /// Returns `DbError` if the orders cannot be read
+    pub async fn order_by_cl_ord_id(
+        &self,
+        session_id: &str,
+        cl_ord_id: &str,
+    ) -> Result<Option<FixOrder>, DbError> {
+        Ok(self
+            .orders
+            .find_all_by_field("cl_ord_id", cl_ord_id)
+            .await?
+            .into_iter()
+            .map(|(_, order)| order)
+            .find(|order| order.session_id == session_id))
+    }
+
+    /// # Errors
+    /// Returns `DbError` if the order cannot be written
+    pub async fn save_order(&self, order: &FixOrder) -> Result<(), DbError> {
+        match self.orders.get(&order.order_id).await? {
+            Some(_) => self.orders.update(order.order_id, order.clone()).await,
+            None => self.orders.insert(order.order_id, order.clone()).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use in_memory_adapter::InMemoryStore;
+
+    #[tokio::test]
+    async fn test_sent_messages_are_read_by_range() {
+        let store = FixStore::new(Arc::new(InMemoryStore::new())).await.unwrap();
+        for seq_num in [1, 2, 9, 10, 11] {
+            store
+                .record_sent("BROKERX:CLIENT", seq_num, format!("message {seq_num}"))
+                .await
+                .unwrap();
+        }
+        store
+            .record_sent("BROKERX:OTHER", 10, "other".to_string())
+            .await
+            .unwrap();
+
+        let sent: Vec<u64> = store
+            .sent_between("BROKERX:CLIENT", 2, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|message| message.seq_num)
+            .collect();
+        assert_eq!(sent, vec![2, 9, 10]);
+
+        let mut state = store.session("BROKERX:CLIENT").await.unwrap();
+        assert_eq!((state.next_outgoing, state.next_incoming), (1, 1));
+        state.next_outgoing = 12;
+        store.save_session(&state).await.unwrap();
+        assert_eq!(store.session("BROKERX:CLIENT").await.unwrap(), state);
+    }
+
+    #[tokio::test]
+    async fn test_one_connection_holds_a_session() {
+        let store = FixStore::new(Arc::new(InMemoryStore::new())).await.unwrap();
+        let lease = Duration::from_secs(30);
+        assert!(
+            store
+                .take_lease("BROKERX:CLIENT", "a", lease)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .take_lease("BROKERX:CLIENT", "b", lease)
+                .await
+                .unwrap()
+        );
+        assert!(store.take_lease("BROKERX:OTHER", "b", lease).await.unwrap());
+        // Renewed by its holder
+        assert!(
+            store
+                .take_lease("BROKERX:CLIENT", "a", lease)
+                .await
+                .unwrap()
+        );
+
+        store.release_lease("BROKERX:CLIENT", "b").await.unwrap();
+        assert!(
+            !store
+                .take_lease("BROKERX:CLIENT", "b", lease)
+                .await
+                .unwrap()
+        );
+        store.release_lease("BROKERX:CLIENT", "a").await.unwrap();
+        assert!(
+            store
+                .take_lease("BROKERX:CLIENT", "b", lease)
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use database_adapter::db::DbFuture;
+use database_adapter::queue::{JobQueue, QueueJob, QueueListener};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// Current time as seen by an in-memory queue
+pub type TimeSource = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+/// Queue held in process memory, following the semantics of `PostgresQueue`.
+///
+/// It reads the time from a `TimeSource`, so delays and leases follow a simulated
+/// clock if one is given.
+#[derive(Clone)]
+pub struct InMemoryQueue {
+    inner: Arc<MemoryQueue>,
+}
+
+impl std::fmt::Debug for InMemoryQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InMemoryQueue").finish_non_exhaustive()
+    }
+}
+
+impl InMemoryQueue {
+    /// Create a queue reading the time from `now`
+    #[must_use]
+    pub fn new(now: TimeSource) -> Self {
+        Self {
+            inner: Arc::new(MemoryQueue {
+                state: Mutex::default(),
+                ready: Arc::new(Notify::new()),
+                now,
+            }),
+        }
+    }
+}
+
+impl JobQueue for InMemoryQueue {
+    fn push(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()> {
+        self.inner.push(job_id, group_id, priority, true);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn push_if_absent(&self, job_id: Uuid, group_id: Uuid, priority: i16) -> DbFuture<'_, ()> {
+        self.inner.push(job_id, group_id, priority, false);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn claim<'a>(&'a self, worker: &'a str, lease: Duration) -> DbFuture<'a, Option<QueueJob>> {
+        let job = self.inner.claim(worker, lease);
+        Box::pin(async { Ok(job) })
+    }
+
+    fn complete<'a>(&'a self, job_id: Uuid, worker: &'a str) -> DbFuture<'a, bool> {
+        let completed = self.inner.complete(job_id, worker);
+        Box::pin(async move { Ok(completed) })
+    }
+
+    fn release<'a>(
+        &'a self,
+        job_id: Uuid,
+        worker: &'a str,
+        priority: i16,
+        delay: Duration,
+    ) -> DbFuture<'a, ()> {
+        self.inner.release(job_id, worker, priority, delay);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn remove(&self, job_id: Uuid) -> DbFuture<'_, ()> {
+        self.inner.state().jobs.remove(&job_id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn set_group_weight(&self, group_id: Uuid, weight: u32) -> DbFuture<'_, ()> {
+        let weight = i32::try_from(weight.max(1)).unwrap_or(i32::MAX);
+        self.inner
+            .state()
+            .groups
+            .entry(group_id)
+            .or_default()
+            .weight = weight;
+        Box::pin(async { Ok(()) })
+    }
+
+    fn len(&self) -> DbFuture<'_, usize> {
+        let len = self.inner.state().jobs.len();
+        Box::pin(async move { Ok(len) })
+    }
+
+    fn listen(&self) -> DbFuture<'_, Box<dyn QueueListener>> {
+        let listener = InMemoryQueueListener(Arc::clone(&self.inner.ready));
+        Box::pin(async { Ok(Box::new(listener) as Box<dyn QueueListener>) })
+    }
+}
+
+/// Wakes up when a job is pushed to an `InMemoryQueue`
+struct InMemoryQueueListener(Arc<Notify>);
+
+impl QueueListener for InMemoryQueueListener {
+    fn wait(&mut self) -> DbFuture<'_, ()> {
+        Box::pin(async move {
+            self.0.notified().await;
+            Ok(())
+        })
+    }
+}
+
+/// Queued job of an in-memory queue, with the columns of the Postgres table
+#[derive(Debug)]
+struct MemoryJob {
+    group_id: Uuid,
+    priority: i16,
+    /// Insertion order, standing in for `enqueued_at`
+    enqueued: u64,
+    available_at: DateTime<Utc>,
+    lease_owner: Option<String>,
+    lease_expires_at: Option<DateTime<Utc>>,
+    requeued: bool,
+    attempts: i32,
+}
+
+#[derive(Debug)]
+struct MemoryGroup {
+    weight: i32,
+    served: i32,
+    /// Order in which the group's last turn ended, standing in for `turn_ended_at`
+    turn_ended: Option<u64>,
+}
+
+impl Default for MemoryGroup {
+    fn default() -> Self {
+        Self {
+            weight: 1,
+            served: 0,
+            turn_ended: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryQueueState {
+    jobs: HashMap<Uuid, MemoryJob>,
+    groups: HashMap<Uuid, MemoryGroup>,
+    /// Source of the insertion and turn orders
+    counter: u64,
+}
+
+impl MemoryQueueState {
+    fn next(&mut self) -> u64 {
+        self.counter += 1;
+        self.counter
+    }
+}
+
+/// In-memory counterpart of the queue tables, following the same SQL semantics
+struct MemoryQueue {
+    state: Mutex<MemoryQueueState>,
+    ready: Arc<Notify>,
+    now: TimeSource,
+}
+
+impl MemoryQueue {
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryQueueState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn push(&self, job_id: Uuid, group_id: Uuid, priority: i16, update_existing: bool) {
+        let now = (self.now)();
+        {
+            let mut state = self.state();
+            let enqueued = state.next();
+            match state.jobs.get_mut(&job_id) {
+                Some(job) if update_existing => {
+                    job.priority = job.priority.min(priority);
+                    job.available_at = now;
+                    job.requeued = job.lease_owner.is_some();
+                }
+                Some(_) => {}
+                None => {
+                    state.jobs.insert(
+                        job_id,
+                        MemoryJob {
+                            group_id,
+                            priority,
+                            enqueued,
+                            available_at: now,
+                            lease_owner: None,
+                            lease_expires_at: None,
+                            requeued: false,
+                            attempts: 0,
+                        },
+                    );
+                }
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    fn claim(&self, worker: &str, lease: Duration) -> Option<QueueJob> {
+        let now = (self.now)();
+        let mut state = self.state();
+        let state = &mut *state;
+
+        let job_id = state
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.available_at <= now)
+            .filter(|(_, job)| job.lease_expires_at.is_none_or(|expires| expires < now))
+            .min_by_key(|(_, job)| {
+                let turn_ended = state.groups.get(&job.group_id).and_then(|g| g.turn_ended);
+                // NULLS FIRST
+                (job.priority, turn_ended.is_some(), turn_ended, job.enqueued)
+            })
+            .map(|(job_id, _)| *job_id)?;
+
+        let turn = state.next();
+        let job = state.jobs.get_mut(&job_id)?;
+        job.lease_owner = Some(worker.to_string());
+        job.lease_expires_at = Some(now + lease);
+        job.requeued = false;
+        job.attempts += 1;
+        let claimed = QueueJob {
+            job_id,
+            group_id: job.group_id,
+            priority: job.priority,
+            attempts: job.attempts,
+        };
+
+        match state.groups.get_mut(&claimed.group_id) {
+            Some(group) => {
+                if group.served + 1 >= group.weight {
+                    group.turn_ended = Some(turn);
+                }
+                group.served = (group.served + 1) % group.weight;
+            }
+            None => {
+                state.groups.insert(
+                    claimed.group_id,
+                    MemoryGroup {
+                        turn_ended: Some(turn),
+                        ..MemoryGroup::default()
+                    },
+                );
+            }
+        }
+        Some(claimed)
+    }
+
+    fn complete(&self, job_id: Uuid, worker: &str) -> bool {
+        let mut state = self.state();
+        let enqueued = state.next();
+        let Some(job) = state.jobs.get_mut(&job_id) else {
+            return false;
+        };
+        if job.lease_owner.as_deref() != Some(worker) {
+            return false;
+        }
+        if !job.requeued {
+            state.jobs.remove(&job_id);
+            return true;
+        }
+        job.lease_owner = None;
+        job.lease_expires_at = None;
+        job.requeued = false;
+        job.enqueued = enqueued;
+        false
+    }
+
+    fn release(&self, job_id: Uuid, worker: &str, priority: i16, delay: Duration) {
+        let now = (self.now)();
+        let mut state = self.state();
+        let enqueued = state.next();
+        if let Some(job) = state.jobs.get_mut(&job_id)
+            && job.lease_owner.as_deref() == Some(worker)
+        {
+            job.lease_owner = None;
+            job.lease_expires_at = None;
+            job.requeued = false;
+            job.priority = job.priority.min(priority);
+            job.enqueued = enqueued;
+            job.available_at = now + delay;
+        }
+    }
+}
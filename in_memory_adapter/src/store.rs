@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use database_adapter::db::{DbError, DbFuture, Selection, Store, StoreTransaction};
+use database_adapter::outbox::OutboxMessage;
+use serde_json::{Value, json};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Rows of a table, by ID
+type Table = BTreeMap<String, Value>;
+
+/// Tables held in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStore {
+    tables: Arc<RwLock<HashMap<String, Table>>>,
+    /// Held by the open transaction, so transactions run one after the other
+    writer: Arc<Mutex<()>>,
+}
+
+impl InMemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Table>> {
+        self.tables.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, Table>> {
+        self.tables.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Text of a JSON field, as returned by the `->>` operator
+    fn field_text(data: &Value, field: &str) -> Option<String> {
+        match data.get(field)? {
+            Value::Null => None,
+            Value::String(text) => Some(text.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+
+    fn matches(data: &Value, field: &str, value: &str) -> bool {
+        Self::field_text(data, field).as_deref() == Some(value)
+    }
+
+    fn select_rows(table: &Table, selection: Selection<'_>) -> Vec<(String, Value)> {
+        let rows = table.iter().map(|(id, data)| (id.clone(), data.clone()));
+        match selection {
+            Selection::All => rows.collect(),
+            Selection::Field { field, value } => {
+                let mut rows: Vec<_> = rows
+                    .filter(|(_, data)| Self::matches(data, field, value))
+                    .collect();
+                rows.sort_by_key(|(_, data)| std::cmp::Reverse(Self::field_text(data, "date")));
+                rows
+            }
+            Selection::Range {
+                field,
+                value,
+                range_field,
+                from,
+                to,
+            } => {
+                let mut rows: Vec<_> = rows
+                    .filter(|(_, data)| Self::matches(data, field, value))
+                    .filter_map(|(id, data)| {
+                        let key = Self::field_text(&data, range_field)?;
+                        (key.as_str() >= from && key.as_str() < to).then_some((key, id, data))
+                    })
+                    .collect();
+                rows.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+                rows.into_iter().map(|(_, id, data)| (id, data)).collect()
+            }
+        }
+    }
+}
+
+impl Store for InMemoryStore {
+    fn create_table<'a>(&'a self, table: &'a str) -> DbFuture<'a, ()> {
+        self.write().entry(table.to_string()).or_default();
+        Box::pin(async { Ok(()) })
+    }
+
+    fn insert<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        let result = insert_row(&mut self.write(), table, id, data);
+        Box::pin(async { result })
+    }
+
+    fn update<'a>(&'a self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        update_row(&mut self.write(), table, &id, data);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn set_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64> {
+        let mut tables = self.write();
+        let row = tables.get_mut(table).and_then(|rows| rows.get_mut(&id));
+        let changed = match row.and_then(Value::as_object_mut) {
+            Some(data) => {
+                data.insert(field.to_string(), value);
+                1
+            }
+            None => 0,
+        };
+        Box::pin(async move { Ok(changed) })
+    }
+
+    fn set_field_if_null<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: Value,
+    ) -> DbFuture<'a, u64> {
+        let mut tables = self.write();
+        let row = tables.get_mut(table).and_then(|rows| rows.get_mut(&id));
+        let changed = match row.and_then(Value::as_object_mut) {
+            Some(data) if data.get(field).is_none_or(Value::is_null) => {
+                data.insert(field.to_string(), value);
+                1
+            }
+            _ => 0,
+        };
+        Box::pin(async move { Ok(changed) })
+    }
+
+    fn raise_field<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        value: i64,
+    ) -> DbFuture<'a, u64> {
+        let mut tables = self.write();
+        let row = tables.get_mut(table).and_then(|rows| rows.get_mut(&id));
+        let changed = match row.and_then(Value::as_object_mut) {
+            Some(data)
+                if data
+                    .get(field)
+                    .and_then(Value::as_i64)
+                    .is_none_or(|v| v < value) =>
+            {
+                data.insert(field.to_string(), json!(value));
+                1
+            }
+            _ => 0,
+        };
+        Box::pin(async move { Ok(changed) })
+    }
+
+    fn take_lease<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        holder: &'a str,
+        until: i64,
+        now: i64,
+    ) -> DbFuture<'a, u64> {
+        let mut tables = self.write();
+        let rows = tables.entry(table.to_string()).or_default();
+        let free = rows.get(&id).is_none_or(|lease| {
+            Self::matches(lease, "holder", holder)
+                || lease["until"].as_i64().is_none_or(|expiry| expiry < now)
+        });
+        if free {
+            rows.insert(id, json!({ "holder": holder, "until": until }));
+        }
+        Box::pin(async move { Ok(u64::from(free)) })
+    }
+
+    fn remove_from_array<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        field: &'a str,
+        element: &'a str,
+    ) -> DbFuture<'a, u64> {
+        let mut tables = self.write();
+        let array = tables
+            .get_mut(table)
+            .and_then(|rows| rows.get_mut(&id))
+            .and_then(|data| data.get_mut(field))
+            .and_then(Value::as_array_mut);
+        let changed = match array {
+            Some(array) if array.iter().any(|value| value == element) => {
+                array.retain(|value| value != element);
+                1
+            }
+            _ => 0,
+        };
+        Box::pin(async move { Ok(changed) })
+    }
+
+    fn increment_below<'a>(
+        &'a self,
+        table: &'a str,
+        id: String,
+        path: &'a [&'a str],
+        cap: i64,
+    ) -> DbFuture<'a, Option<i64>> {
+        let mut tables = self.write();
+        let pointer: String = path.iter().map(|key| format!("/{key}")).collect();
+        let value = tables
+            .get_mut(table)
+            .and_then(|rows| rows.get_mut(&id))
+            .and_then(|data| data.pointer_mut(&pointer));
+        let incremented = match value {
+            Some(value) => match value.as_i64() {
+                Some(count) if count < cap => {
+                    *value = json!(count + 1);
+                    Some(count + 1)
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        Box::pin(async move { Ok(incremented) })
+    }
+
+    fn remove<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, ()> {
+        if let Some(rows) = self.write().get_mut(table) {
+            rows.remove(&id);
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn get<'a>(&'a self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        let row = self
+            .read()
+            .get(table)
+            .and_then(|rows| rows.get(&id))
+            .cloned();
+        Box::pin(async { Ok(row) })
+    }
+
+    fn count<'a>(&'a self, table: &'a str) -> DbFuture<'a, usize> {
+        let count = self.read().get(table).map_or(0, BTreeMap::len);
+        Box::pin(async move { Ok(count) })
+    }
+
+    fn select<'a>(
+        &'a self,
+        table: &'a str,
+        selection: Selection<'a>,
+        limit: Option<usize>,
+    ) -> DbFuture<'a, Vec<(String, Value)>> {
+        let mut rows = self
+            .read()
+            .get(table)
+            .map(|rows| Self::select_rows(rows, selection))
+            .unwrap_or_default();
+        rows.truncate(limit.unwrap_or(usize::MAX));
+        Box::pin(async { Ok(rows) })
+    }
+
+    fn begin(&self) -> DbFuture<'_, Box<dyn StoreTransaction>> {
+        Box::pin(async move {
+            let writer = Arc::clone(&self.writer).lock_owned().await;
+            Ok(Box::new(InMemoryTransaction {
+                store: self.clone(),
+                _writer: writer,
+                writes: Vec::new(),
+            }) as Box<dyn StoreTransaction>)
+        })
+    }
+}
+
+fn insert_row(
+    tables: &mut HashMap<String, Table>,
+    table: &str,
+    id: String,
+    data: Value,
+) -> Result<(), DbError> {
+    let rows = tables.entry(table.to_string()).or_default();
+    if rows.contains_key(&id) {
+        return Err(DbError::DuplicateKey(id));
+    }
+    rows.insert(id, data);
+    Ok(())
+}
+
+fn update_row(tables: &mut HashMap<String, Table>, table: &str, id: &str, data: Value) {
+    // Like an UPDATE, missing rows are left alone
+    if let Some(row) = tables.get_mut(table).and_then(|rows| rows.get_mut(id)) {
+        *row = data;
+    }
+}
+
+#[derive(Debug)]
+enum Write {
+    Insert {
+        table: String,
+        id: String,
+        data: Value,
+    },
+    Update {
+        table: String,
+        id: String,
+        data: Value,
+    },
+}
+
+/// Writes buffered until commit, while holding the store's writer lock
+struct InMemoryTransaction {
+    store: InMemoryStore,
+    _writer: OwnedMutexGuard<()>,
+    writes: Vec<Write>,
+}
+
+impl InMemoryTransaction {
+    fn is_taken(&self, table: &str, id: &str) -> bool {
+        let inserted = self.writes.iter().any(
+            |write| matches!(write, Write::Insert { table: t, id: i, .. } if t == table && i == id),
+        );
+        inserted
+            || self
+                .store
+                .read()
+                .get(table)
+                .is_some_and(|rows| rows.contains_key(id))
+    }
+
+    /// Row as seen by the transaction: its own latest write, else the stored row
+    fn row(&self, table: &str, id: &str) -> Option<Value> {
+        let written = self.writes.iter().rev().find_map(|write| match write {
+            Write::Insert {
+                table: t,
+                id: i,
+                data,
+            }
+            | Write::Update {
+                table: t,
+                id: i,
+                data,
+            } if t == table && i == id => Some(data.clone()),
+            _ => None,
+        });
+        written.or_else(|| {
+            self.store
+                .read()
+                .get(table)
+                .and_then(|rows| rows.get(id))
+                .cloned()
+        })
+    }
+}
+
+impl StoreTransaction for InMemoryTransaction {
+    fn get<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        let row = self.row(table, &id);
+        Box::pin(async { Ok(row) })
+    }
+
+    fn get_for_update<'a>(&'a mut self, table: &'a str, id: String) -> DbFuture<'a, Option<Value>> {
+        // Transactions already run one at a time
+        self.get(table, id)
+    }
+
+    fn insert<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        // Fail at the statement like Postgres, rather than on commit
+        let result = if self.is_taken(table, &id) {
+            Err(DbError::DuplicateKey(id))
+        } else {
+            self.writes.push(Write::Insert {
+                table: table.to_string(),
+                id,
+                data,
+            });
+            Ok(())
+        };
+        Box::pin(async { result })
+    }
+
+    fn update<'a>(&'a mut self, table: &'a str, id: String, data: Value) -> DbFuture<'a, ()> {
+        self.writes.push(Write::Update {
+            table: table.to_string(),
+            id,
+            data,
+        });
+        Box::pin(async { Ok(()) })
+    }
+
+    fn append_outbox<'a>(
+        &'a mut self,
+        table: &'a str,
+        messages: &'a [OutboxMessage],
+    ) -> DbFuture<'a, ()> {
+        // Kept as rows of the outbox table, which nothing relays without a database
+        for message in messages {
+            self.writes.push(Write::Insert {
+                table: table.to_string(),
+                id: message.id.to_string(),
+                data: json!({ "topic": message.topic, "payload": message.payload }),
+            });
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn commit(self: Box<Self>) -> DbFuture<'static, ()> {
+        let Self {
+            store,
+            _writer: writer,
+            writes,
+        } = *self;
+        let mut tables = store.write();
+        // Rows inserted outside of transactions since the statement ran
+        let conflict = writes.iter().find_map(|write| match write {
+            Write::Insert { table, id, .. }
+                if tables.get(table).is_some_and(|rows| rows.contains_key(id)) =>
+            {
+                Some(id.clone())
+            }
+            _ => None,
+        });
+        if let Some(id) = conflict {
+            return Box::pin(async { Err(DbError::DuplicateKey(id)) });
+        }
+
+        for write in writes {
+            match write {
+                Write::Insert { table, id, data } => {
+                    tables.entry(table).or_default().insert(id, data);
+                }
+                Write::Update { table, id, data } => update_row(&mut tables, &table, &id, data),
+            }
+        }
+        drop(tables);
+        drop(writer);
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use database_adapter::db::{DbError, JsonRepo, PostgresStore, Repository, Store};
+use serde::{Deserialize, Serialize};
+
+use crate::InMemoryStore;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct User {
+    name: String,
+    email: String,
+}
+
+#[tokio::test]
+async fn test_in_memory_repo_behaves_like_postgres() -> anyhow::Result<()> {
+    let repo = JsonRepo::<User, String>::new(Arc::new(InMemoryStore::new()), "users").await?;
+
+    let alice = User {
+        name: "Alice".into(),
+        email: "alice@example.com".into(),
+    };
+    let bob = User {
+        name: "Bob".into(),
+        email: "bob@example.com".into(),
+    };
+    repo.insert("2".to_string(), bob.clone()).await?;
+    repo.insert("1".to_string(), alice.clone()).await?;
+    assert!(matches!(
+        repo.insert("1".to_string(), bob.clone()).await,
+        Err(DbError::DuplicateKey(_))
+    ));
+
+    assert_eq!(
+        repo.find_by_field("email", "bob@example.com").await?,
+        Some(bob.clone())
+    );
+    assert_eq!(repo.find_all_by_field("name", "Alice").await?.len(), 1);
+    assert_eq!(
+        repo.all().await?,
+        vec![("1".to_string(), alice), ("2".to_string(), bob.clone())]
+    );
+
+    // Updating a missing row does not create it
+    repo.update("3".to_string(), bob).await?;
+    assert_eq!(repo.len().await?, 2);
+    repo.remove("1".to_string()).await?;
+    assert_eq!(repo.len().await?, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_range_queries_match_between_backends() -> anyhow::Result<()> {
+    use serde_json::{Value, json};
+    let suffix = uuid::Uuid::new_v4().to_string().replace('-', "");
+
+    let postgres: Arc<dyn Store> = Arc::new(PostgresStore::connect().await?);
+    let memory: Arc<dyn Store> = Arc::new(InMemoryStore::new());
+    for store in [postgres, memory] {
+        let repo = JsonRepo::<Value, String>::new(store, &format!("bars_test_{suffix}")).await?;
+        for (id, symbol, start) in [
+            ("a", "AAPL", "2024-01-02T14:32:00Z"),
+            ("b", "AAPL", "2024-01-02T14:30:00Z"),
+            ("c", "AAPL", "2024-01-02T14:31:00Z"),
+            ("d", "MSFT", "2024-01-02T14:31:00Z"),
+            ("e", "AAPL", "2024-01-02T14:33:00Z"),
+        ] {
+            repo.insert(id.to_string(), json!({ "symbol": symbol, "start": start }))
+                .await?;
+        }
+
+        let ids: Vec<String> = repo
+            .find_range_by_field(
+                "symbol",
+                "AAPL",
+                "start",
+                "2024-01-02T14:30:00Z",
+                "2024-01-02T14:33:00Z",
+            )
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec!["b", "c", "a"]);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_field_updates_match_between_backends() -> anyhow::Result<()> {
+    use serde_json::{Value, json};
+    let suffix = uuid::Uuid::new_v4().to_string().replace('-', "");
+
+    let postgres: Arc<dyn Store> = Arc::new(PostgresStore::connect().await?);
+    let memory: Arc<dyn Store> = Arc::new(InMemoryStore::new());
+    for store in [postgres, memory] {
+        let repo = JsonRepo::<Value, String>::new(store, &format!("fields_test_{suffix}")).await?;
+        let id = "1".to_string();
+        repo.insert(id.clone(), json!({ "name": "Alice", "codes": ["a", "b"] }))
+            .await?;
+
+        assert_eq!(repo.set_field(&id, "locked", true).await?, 1);
+        assert_eq!(repo.set_field(&"2".to_string(), "locked", true).await?, 0);
+        assert_eq!(repo.set_field_if_null(&id, "used", 1).await?, 1);
+        assert_eq!(repo.set_field_if_null(&id, "used", 2).await?, 0);
+        repo.set_field(&id, "used", Value::Null).await?;
+        assert_eq!(repo.set_field_if_null(&id, "used", 3).await?, 1);
+        assert_eq!(repo.raise_field(&id, "step", 5).await?, 1);
+        assert_eq!(repo.raise_field(&id, "step", 5).await?, 0);
+        assert_eq!(repo.raise_field(&id, "step", 4).await?, 0);
+        assert_eq!(repo.raise_field(&id, "step", 6).await?, 1);
+
+        // Of two removals of the same code, one changes the row
+        let (first, second) = tokio::join!(
+            repo.remove_from_array(&id, "codes", "a"),
+            repo.remove_from_array(&id, "codes", "a")
+        );
+        assert_eq!(first? + second?, 1);
+        assert_eq!(repo.remove_from_array(&id, "missing", "b").await?, 0);
+        assert_eq!(
+            repo.get(&id).await?,
+            Some(json!({ "name": "Alice", "codes": ["b"], "locked": true, "used": 3, "step": 6 }))
+        );
+
+        // Concurrent increments stop at the cap
+        repo.set_field(&id, "retries", json!({ "count": 0 }))
+            .await?;
+        let path = ["retries", "count"];
+        let increment = || repo.increment_below(&id, &path, 3);
+        let counts = tokio::join!(
+            increment(),
+            increment(),
+            increment(),
+            increment(),
+            increment()
+        );
+        let mut counts = vec![counts.0?, counts.1?, counts.2?, counts.3?, counts.4?];
+        counts.sort_unstable();
+        assert_eq!(counts, vec![None, None, Some(1), Some(2), Some(3)]);
+        assert_eq!(
+            repo.increment_below(&"2".to_string(), &path, 3).await?,
+            None
+        );
+
+        // A running lease is only renewed by its holder, an expired one is taken
+        let lease = "lease".to_string();
+        assert_eq!(repo.take_lease(&lease, "a", 100, 0).await?, 1);
+        assert_eq!(repo.take_lease(&lease, "b", 150, 50).await?, 0);
+        assert_eq!(repo.take_lease(&lease, "a", 200, 50).await?, 1);
+        assert_eq!(repo.take_lease(&lease, "b", 300, 201).await?, 1);
+        assert_eq!(
+            repo.get(&lease).await?,
+            Some(json!({ "holder": "b", "until": 300 }))
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transaction_writes_all_or_nothing() -> anyhow::Result<()> {
+    let store: Arc<dyn Store> = Arc::new(InMemoryStore::new());
+    let users = JsonRepo::<User, String>::new(Arc::clone(&store), "users").await?;
+    let archive = JsonRepo::<User, String>::new(store, "archive").await?;
+    let alice = User {
+        name: "Alice".into(),
+        email: "alice@example.com".into(),
+    };
+    users.insert("1".to_string(), alice.clone()).await?;
+
+    // Dropped without commit
+    let mut tx = users.begin().await?;
+    tx.insert(&archive, "1".to_string(), alice.clone()).await?;
+    drop(tx);
+    assert!(archive.is_empty().await?);
+
+    let mut tx = users.begin().await?;
+    tx.insert(&archive, "1".to_string(), alice.clone()).await?;
+    assert!(matches!(
+        tx.insert(&users, "1".to_string(), alice.clone()).await,
+        Err(DbError::DuplicateKey(_))
+    ));
+    tx.commit().await?;
+    assert_eq!(archive.get(&"1".to_string()).await?, Some(alice));
+
+    Ok(())
+}
+
+mod queue {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use database_adapter::queue::JobQueue;
+    use uuid::Uuid;
+
+    use crate::InMemoryQueue;
+
+    const LEASE: Duration = Duration::from_secs(30);
+
+    /// Claim and complete the next job
+    async fn pop(queue: &InMemoryQueue) -> Option<Uuid> {
+        let job = queue.claim("worker", LEASE).await.unwrap()?;
+        assert!(queue.complete(job.job_id, "worker").await.unwrap());
+        Some(job.job_id)
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_queue_follows_simulated_time() {
+        let now = Arc::new(Mutex::new(chrono::Utc::now()));
+        let clock = Arc::clone(&now);
+        let queue = InMemoryQueue::new(Arc::new(move || *clock.lock().unwrap()));
+        let mut listener = queue.listen().await.unwrap();
+
+        let flooder = Uuid::new_v4();
+        let flood: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for job_id in &flood {
+            queue.push(*job_id, flooder, 2).await.unwrap();
+        }
+        let other_job = Uuid::new_v4();
+        queue.push(other_job, Uuid::new_v4(), 2).await.unwrap();
+        let liquidation = Uuid::new_v4();
+        queue.push(liquidation, flooder, 0).await.unwrap();
+        listener.wait().await.unwrap();
+
+        // Same lanes and turns as the Postgres queue
+        assert_eq!(pop(&queue).await, Some(liquidation));
+        assert_eq!(pop(&queue).await, Some(other_job));
+        assert_eq!(pop(&queue).await, Some(flood[0]));
+
+        // Delays only elapse when the time source moves
+        let job = queue.claim("worker", LEASE).await.unwrap().unwrap();
+        queue
+            .release(job.job_id, "worker", 2, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(pop(&queue).await, Some(flood[2]));
+        assert_eq!(pop(&queue).await, None);
+        *now.lock().unwrap() += chrono::TimeDelta::seconds(60);
+        assert_eq!(pop(&queue).await, Some(flood[1]));
+        assert!(queue.is_empty().await.unwrap());
+    }
+}
@@ -0,0 +1,223 @@
+//! Where providers keep the challenges they opened until they are answered.
+//!
+//! Challenges only hold hashes of their codes. A store shared by several instances,
+//! such as a database table, lets any of them verify a challenge opened by another.
+//! Expired challenges are removed when they are looked up, the others must be swept
+//! with `ChallengeStore::purge_expired`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use tracing::warn;
+
+use crate::{MfaError, OtpChallenge, MAX_ATTEMPTS};
+
+pub trait ChallengeStore: Send + Sync {
+    fn insert(
+        &self,
+        challenge: OtpChallenge,
+    ) -> impl std::future::Future<Output = Result<(), MfaError>> + Send;
+    fn get(
+        &self,
+        challenge_id: &str,
+    ) -> impl std::future::Future<Output = Result<Option<OtpChallenge>, MfaError>> + Send;
+    /// Replace a stored challenge, a challenge that is gone is left alone
+    fn update(
+        &self,
+        challenge: OtpChallenge,
+    ) -> impl std::future::Future<Output = Result<(), MfaError>> + Send;
+    fn remove(
+        &self,
+        challenge_id: &str,
+    ) -> impl std::future::Future<Output = Result<(), MfaError>> + Send;
+    /// Count a wrong code against a challenge unless it already saw `cap`, in one step
+    /// so concurrent guesses cannot go past it. Returns the wrong codes counted, `None`
+    /// if the challenge is gone or already saw `cap`.
+    fn count_wrong_code(
+        &self,
+        challenge_id: &str,
+        cap: u32,
+    ) -> impl std::future::Future<Output = Result<Option<u32>, MfaError>> + Send;
+    /// Remove the challenges expired at `now`, returns how many were removed
+    fn purge_expired(
+        &self,
+        now: SystemTime,
+    ) -> impl std::future::Future<Output = Result<usize, MfaError>> + Send;
+}
+
+/// Challenges held by this process only, lost on restart
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryChallengeStore {
+    challenges: Arc<Mutex<HashMap<String, OtpChallenge>>>,
+}
+
+impl InMemoryChallengeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChallengeStore for InMemoryChallengeStore {
+    async fn insert(&self, challenge: OtpChallenge) -> Result<(), MfaError> {
+        self.challenges
+            .lock()
+            .unwrap()
+            .insert(challenge.id.clone(), challenge);
+        Ok(())
+    }
+
+    async fn get(&self, challenge_id: &str) -> Result<Option<OtpChallenge>, MfaError> {
+        Ok(self.challenges.lock().unwrap().get(challenge_id).cloned())
+    }
+
+    async fn update(&self, challenge: OtpChallenge) -> Result<(), MfaError> {
+        if let Some(stored) = self.challenges.lock().unwrap().get_mut(&challenge.id) {
+            *stored = challenge;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, challenge_id: &str) -> Result<(), MfaError> {
+        self.challenges.lock().unwrap().remove(challenge_id);
+        Ok(())
+    }
+
+    async fn count_wrong_code(
+        &self,
+        challenge_id: &str,
+        cap: u32,
+    ) -> Result<Option<u32>, MfaError> {
+        let mut challenges = self.challenges.lock().unwrap();
+        Ok(challenges
+            .get_mut(challenge_id)
+            .filter(|challenge| challenge.attempts < cap)
+            .map(|challenge| {
+                challenge.attempts += 1;
+                challenge.attempts
+            }))
+    }
+
+    async fn purge_expired(&self, now: SystemTime) -> Result<usize, MfaError> {
+        let mut challenges = self.challenges.lock().unwrap();
+        let before = challenges.len();
+        challenges.retain(|_, challenge| !challenge.is_expired(now));
+        Ok(before - challenges.len())
+    }
+}
+
+/// The challenge `challenge_id` while it can be answered. An expired one is removed.
+pub(crate) async fn live_challenge<C: ChallengeStore>(
+    store: &C,
+    challenge_id: &str,
+) -> Result<OtpChallenge, MfaError> {
+    let challenge = store
+        .get(challenge_id)
+        .await?
+        .ok_or(MfaError::ChallengeNotFound)?;
+    if challenge.is_expired(SystemTime::now()) {
+        store.remove(challenge_id).await?;
+        return Err(MfaError::ChallengeExpired);
+    }
+    Ok(challenge)
+}
+
+/// Count a wrong code against the challenge and return the error to report. The
+/// challenge is discarded once it has seen `MAX_ATTEMPTS`.
+pub(crate) async fn reject_code<C: ChallengeStore>(store: &C, challenge_id: &str) -> MfaError {
+    match store.count_wrong_code(challenge_id, MAX_ATTEMPTS).await {
+        Ok(Some(attempts)) if attempts < MAX_ATTEMPTS => MfaError::InvalidCode,
+        // The last attempt, or one past it made concurrently
+        Ok(_) => {
+            warn!("Too many wrong codes for challenge {}", challenge_id);
+            match store.remove(challenge_id).await {
+                Ok(()) => MfaError::TooManyAttempts,
+                Err(e) => e,
+            }
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn challenge(id: &str, expires_at: SystemTime) -> OtpChallenge {
+        OtpChallenge {
+            id: id.to_string(),
+            user_email: "test@example.com".to_string(),
+            code_hash: String::new(),
+            verified: false,
+            attempts: 0,
+            created_at: expires_at - Duration::from_secs(300),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purge_removes_only_expired_challenges() {
+        let store = InMemoryChallengeStore::new();
+        let now = SystemTime::now();
+        store
+            .insert(challenge("old", now - Duration::from_secs(1)))
+            .await
+            .unwrap();
+        store
+            .insert(challenge("new", now + Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        assert_eq!(store.purge_expired(now).await.unwrap(), 1);
+        assert!(store.get("old").await.unwrap().is_none());
+        assert!(store.get("new").await.unwrap().is_some());
+        assert_eq!(store.purge_expired(now).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_wrong_codes_are_capped() {
+        let store = InMemoryChallengeStore::new();
+        store
+            .insert(challenge("a", SystemTime::now() + Duration::from_secs(60)))
+            .await
+            .unwrap();
+        let guess = || reject_code(&store, "a");
+        let errors = tokio::join!(
+            guess(),
+            guess(),
+            guess(),
+            guess(),
+            guess(),
+            guess(),
+            guess()
+        );
+        let errors = [
+            errors.0, errors.1, errors.2, errors.3, errors.4, errors.5, errors.6,
+        ];
+
+        let invalid = errors
+            .iter()
+            .filter(|e| matches!(e, MfaError::InvalidCode))
+            .count();
+        assert_eq!(invalid, MAX_ATTEMPTS as usize - 1);
+        assert!(store.get("a").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_challenge_is_removed_on_lookup() {
+        let store = InMemoryChallengeStore::new();
+        store
+            .insert(challenge("old", SystemTime::now() - Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            live_challenge(&store, "old").await,
+            Err(MfaError::ChallengeExpired)
+        ));
+        assert!(matches!(
+            live_challenge(&store, "old").await,
+            Err(MfaError::ChallengeNotFound)
+        ));
+    }
+}
@@ -0,0 +1,486 @@
+//! Where outgoing emails go.
+//!
+//! Notifications are rendered with the templates of the recipient's locale, see
+//! `templates`, and handed to an `EmailTransport`: an SMTP relay, a
+//! directory that receives one `.eml` file per email, or an in-memory list that tests
+//! and local development can read back. `EMAIL_TRANSPORT` selects one of them.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use lettre::message::MultiPart;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{FileTransport, Message, SmtpTransport, Transport};
+use tracing::{info, warn};
+
+use crate::templates::{Branding, Locale, Notification};
+use crate::MfaError;
+
+/// An email ready to be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    /// Sender mailbox, as `Name <address>`
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    /// Same content as `html_body`, for clients that do not show HTML
+    pub text_body: String,
+}
+
+impl Email {
+    fn to_message(&self) -> Result<Message, MfaError> {
+        Message::builder()
+            .from(
+                self.from
+                    .parse()
+                    .map_err(|e| MfaError::SendingFailed(format!("Invalid from address: {e}")))?,
+            )
+            .to(self
+                .to
+                .parse()
+                .map_err(|e| MfaError::SendingFailed(format!("Invalid to address: {e}")))?)
+            .subject(&self.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                self.text_body.clone(),
+                self.html_body.clone(),
+            ))
+            .map_err(|e| MfaError::SendingFailed(format!("Failed to build email: {e}")))
+    }
+}
+
+/// Destination of outgoing emails
+pub trait EmailTransport: Send + Sync {
+    fn name(&self) -> &str;
+    fn send(&self, email: &Email)
+        -> impl std::future::Future<Output = Result<(), MfaError>> + Send;
+}
+
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub server: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for SmtpConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmtpConfig")
+            .field("server", &self.server)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SmtpConfig {
+    /// Read the relay from the environment
+    /// Required environment variables:
+    /// - SMTP_USERNAME: SMTP username for authentication
+    /// - SMTP_PASSWORD: SMTP password for authentication
+    ///
+    /// Optional environment variables:
+    /// - SMTP_SERVER: SMTP server hostname (default: smtp.gmail.com)
+    /// - SMTP_PORT: SMTP server port (default: 587)
+    pub fn from_env() -> Result<Self, String> {
+        let server = std::env::var("SMTP_SERVER").unwrap_or_else(|_| "smtp.gmail.com".to_string());
+
+        let port = std::env::var("SMTP_PORT")
+            .unwrap_or_else(|_| "587".to_string())
+            .parse()
+            .map_err(|_| "Invalid SMTP_PORT: must be a valid port number".to_string())?;
+
+        let username = std::env::var("SMTP_USERNAME")
+            .map_err(|_| "SMTP_USERNAME environment variable must be set".to_string())?;
+
+        let password = std::env::var("SMTP_PASSWORD")
+            .map_err(|_| "SMTP_PASSWORD environment variable must be set".to_string())?;
+
+        Ok(Self {
+            server,
+            port,
+            username,
+            password,
+        })
+    }
+}
+
+/// Sends emails through an SMTP relay
+#[derive(Debug, Clone)]
+pub struct SmtpEmailTransport {
+    config: SmtpConfig,
+}
+
+impl SmtpEmailTransport {
+    pub fn new(config: SmtpConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl EmailTransport for SmtpEmailTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), MfaError> {
+        let message = email.to_message()?;
+        let creds = Credentials::new(self.config.username.clone(), self.config.password.clone());
+        let mailer = SmtpTransport::relay(&self.config.server)
+            .map_err(|e| MfaError::SendingFailed(format!("SMTP relay error: {e}")))?
+            .port(self.config.port)
+            .credentials(creds)
+            .build();
+
+        // The relay is synchronous, keep it off the runtime threads
+        tokio::task::spawn_blocking(move || mailer.send(&message))
+            .await
+            .map_err(|e| MfaError::SendingFailed(format!("SMTP task failed: {e}")))?
+            .map_err(|e| MfaError::SendingFailed(format!("Failed to send email: {e}")))?;
+        Ok(())
+    }
+}
+
+/// Drops each email in a directory as an `.eml` file, which mail clients can open
+#[derive(Debug, Clone)]
+pub struct FileEmailTransport {
+    dir: PathBuf,
+}
+
+impl FileEmailTransport {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+}
+
+impl EmailTransport for FileEmailTransport {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), MfaError> {
+        let message = email.to_message()?;
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir)
+                .map_err(|e| MfaError::SendingFailed(format!("{}: {e}", dir.display())))?;
+            FileTransport::new(&dir)
+                .send(&message)
+                .map_err(|e| MfaError::SendingFailed(format!("{}: {e}", dir.display())))
+        })
+        .await
+        .map_err(|e| MfaError::SendingFailed(format!("File transport task failed: {e}")))??;
+        Ok(())
+    }
+}
+
+/// Keeps sent emails in memory. Clones share the same list.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryEmailTransport {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl InMemoryEmailTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emails sent so far, oldest first
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Last email sent to `to`
+    pub fn last_sent_to(&self, to: &str) -> Option<Email> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|email| email.to == to)
+            .cloned()
+    }
+}
+
+impl EmailTransport for InMemoryEmailTransport {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), MfaError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+/// Transport selected from the configuration
+#[derive(Debug, Clone)]
+pub enum ConfiguredTransport {
+    Smtp(SmtpEmailTransport),
+    File(FileEmailTransport),
+    Memory(InMemoryEmailTransport),
+}
+
+impl ConfiguredTransport {
+    /// Parse a transport from `smtp`, `file:<directory>` or `memory`. The SMTP relay
+    /// is read from the environment.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.split_once(':') {
+            None if value == "smtp" => {
+                Ok(Self::Smtp(SmtpEmailTransport::new(SmtpConfig::from_env()?)))
+            }
+            None if value == "memory" => Ok(Self::Memory(InMemoryEmailTransport::new())),
+            Some(("file", dir)) if !dir.is_empty() => {
+                Ok(Self::File(FileEmailTransport::new(PathBuf::from(dir))))
+            }
+            _ => Err(format!("Invalid EMAIL_TRANSPORT value: {value}")),
+        }
+    }
+
+    /// Read the transport from `EMAIL_TRANSPORT`. When it is unset, emails go through
+    /// SMTP if the relay is configured, and are dropped in a temporary directory
+    /// otherwise.
+    pub fn from_env() -> Result<Self, String> {
+        if let Ok(value) = std::env::var("EMAIL_TRANSPORT") {
+            return Self::parse(&value);
+        }
+        match SmtpConfig::from_env() {
+            Ok(config) => Ok(Self::Smtp(SmtpEmailTransport::new(config))),
+            Err(e) => {
+                let dir = std::env::temp_dir().join("brokerx-mail");
+                warn!(
+                    "SMTP is not configured ({e}), emails are written to {}",
+                    dir.display()
+                );
+                Ok(Self::File(FileEmailTransport::new(dir)))
+            }
+        }
+    }
+
+    /// The emails kept by the in-memory transport, `None` for the others
+    pub fn captured(&self) -> Option<&InMemoryEmailTransport> {
+        match self {
+            ConfiguredTransport::Memory(transport) => Some(transport),
+            _ => None,
+        }
+    }
+}
+
+impl EmailTransport for ConfiguredTransport {
+    fn name(&self) -> &str {
+        match self {
+            ConfiguredTransport::Smtp(transport) => transport.name(),
+            ConfiguredTransport::File(transport) => transport.name(),
+            ConfiguredTransport::Memory(transport) => transport.name(),
+        }
+    }
+
+    async fn send(&self, email: &Email) -> Result<(), MfaError> {
+        match self {
+            ConfiguredTransport::Smtp(transport) => transport.send(email).await,
+            ConfiguredTransport::File(transport) => transport.send(email).await,
+            ConfiguredTransport::Memory(transport) => transport.send(email).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub transport: ConfiguredTransport,
+    pub from_email: String,
+    pub from_name: String,
+    /// Product name used in subjects and headers
+    pub brand: String,
+}
+
+impl EmailConfig {
+    /// Create EmailConfig from environment variables, see `ConfiguredTransport::from_env`
+    ///
+    /// Optional environment variables:
+    /// - SMTP_FROM_EMAIL: Email address to send from, required with SMTP
+    /// - SMTP_FROM_NAME: Display name for sender (default: BrokerX Security)
+    /// - EMAIL_BRAND: Product name in subjects and headers (default: BrokerX)
+    pub fn from_env() -> Result<Self, String> {
+        let _ = dotenvy::dotenv();
+
+        let transport = ConfiguredTransport::from_env()?;
+
+        let from_email = match std::env::var("SMTP_FROM_EMAIL") {
+            Ok(from_email) => from_email,
+            Err(_) if matches!(transport, ConfiguredTransport::Smtp(_)) => {
+                return Err("SMTP_FROM_EMAIL environment variable must be set".to_string());
+            }
+            Err(_) => "security@brokerx.local".to_string(),
+        };
+
+        let from_name =
+            std::env::var("SMTP_FROM_NAME").unwrap_or_else(|_| "BrokerX Security".to_string());
+
+        let brand = std::env::var("EMAIL_BRAND").unwrap_or_else(|_| "BrokerX".to_string());
+
+        info!("Sending emails through the {} transport", transport.name());
+        Ok(Self {
+            transport,
+            from_email,
+            from_name,
+            brand,
+        })
+    }
+
+    pub fn branding(&self) -> Branding {
+        Branding {
+            name: self.brand.clone(),
+            from_name: self.from_name.clone(),
+        }
+    }
+
+    /// Create a test configuration that keeps emails in memory
+    pub fn new_test_config() -> Self {
+        Self {
+            transport: ConfiguredTransport::Memory(InMemoryEmailTransport::new()),
+            from_email: "test@example.com".to_string(),
+            from_name: "Test Sender".to_string(),
+            brand: "BrokerX".to_string(),
+        }
+    }
+}
+
+/// Sends emails on behalf of BrokerX. Clones share the same transport.
+#[derive(Debug, Clone)]
+pub struct Mailer<T = ConfiguredTransport> {
+    from: String,
+    branding: Branding,
+    transport: T,
+}
+
+impl Mailer {
+    pub fn new(config: &EmailConfig) -> Self {
+        Self::with_transport(config, config.transport.clone())
+    }
+}
+
+impl<T: EmailTransport> Mailer<T> {
+    /// Send with `transport` instead of the transport of `config`
+    pub fn with_transport(config: &EmailConfig, transport: T) -> Self {
+        Self {
+            from: format!("{} <{}>", config.from_name, config.from_email),
+            branding: config.branding(),
+            transport,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Render `notification` in `locale` and send it to `to`
+    pub async fn send(
+        &self,
+        to: &str,
+        locale: Locale,
+        notification: &Notification,
+    ) -> Result<(), MfaError> {
+        let rendered = notification.render(locale, &self.branding)?;
+        let email = Email {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: rendered.subject,
+            html_body: rendered.html_body,
+            text_body: rendered.text_body,
+        };
+        self.transport.send(&email).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::{CodeEmail, DepositEmail};
+
+    fn code(code: &str) -> Notification {
+        Notification::LoginCode(CodeEmail {
+            code: code.to_string(),
+            valid_minutes: 5,
+        })
+    }
+
+    #[test]
+    fn test_parse_transport() {
+        assert!(matches!(
+            ConfiguredTransport::parse("memory"),
+            Ok(ConfiguredTransport::Memory(_))
+        ));
+        assert!(matches!(
+            ConfiguredTransport::parse("file:/tmp/mail"),
+            Ok(ConfiguredTransport::File(transport)) if transport.dir() == &PathBuf::from("/tmp/mail")
+        ));
+        assert!(ConfiguredTransport::parse("file:").is_err());
+        assert!(ConfiguredTransport::parse("carrier-pigeon").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_transport_captures_emails() {
+        let mailer = Mailer::new(&EmailConfig::new_test_config());
+        mailer
+            .send("first@test.com", Locale::En, &code("111111"))
+            .await
+            .unwrap();
+        mailer
+            .send("second@test.com", Locale::Fr, &code("222222"))
+            .await
+            .unwrap();
+
+        // Clones share the captured emails
+        let captured = mailer.clone().transport().captured().unwrap().clone();
+        assert_eq!(captured.sent().len(), 2);
+        let email = captured.last_sent_to("first@test.com").unwrap();
+        assert_eq!(email.from, "Test Sender <test@example.com>");
+        assert_eq!(email.subject, "BrokerX - Your verification code");
+        assert!(email.html_body.contains("111111"));
+        assert!(email.text_body.contains("111111"));
+    }
+
+    #[tokio::test]
+    async fn test_file_transport_writes_eml_files() {
+        let dir = std::env::temp_dir().join(format!("brokerx-mail-{}", uuid::Uuid::new_v4()));
+        let config = EmailConfig::new_test_config();
+        let mailer = Mailer::with_transport(&config, FileEmailTransport::new(dir.clone()));
+        let deposit = Notification::Deposit(DepositEmail {
+            amount: 250.0,
+            balance: 1250.0,
+        });
+        mailer
+            .send("user@test.com", Locale::En, &deposit)
+            .await
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("To: user@test.com"));
+        assert!(content.contains("multipart/alternative"));
+        assert!(content.contains("1,250.00"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_recipient_is_rejected() {
+        let config = EmailConfig::new_test_config();
+        let dir = std::env::temp_dir().join(format!("brokerx-mail-{}", uuid::Uuid::new_v4()));
+        let mailer = Mailer::with_transport(&config, FileEmailTransport::new(dir));
+        assert!(matches!(
+            mailer
+                .send("not an address", Locale::En, &code("123456"))
+                .await,
+            Err(MfaError::SendingFailed(_))
+        ));
+    }
+}
@@ -1,15 +1,32 @@
 use color_eyre::Result;
-use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
 use rand::Rng;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::time::{Duration, SystemTime};
-use tracing::{debug, error};
+use tracing::debug;
 use uuid::Uuid;
 
+pub mod challenge_store;
+pub mod email;
 pub mod mfa;
+pub mod recovery;
+pub mod templates;
+pub mod totp;
+
+use challenge_store::{live_challenge, reject_code};
+pub use challenge_store::{ChallengeStore, InMemoryChallengeStore};
+pub use email::{
+    ConfiguredTransport, Email, EmailConfig, EmailTransport, FileEmailTransport,
+    InMemoryEmailTransport, Mailer, SmtpConfig, SmtpEmailTransport,
+};
+pub use recovery::{
+    generate_recovery_codes, hash_recovery_code, RecoveryCodeProvider, RecoveryCodeStore,
+};
+pub use templates::{
+    Branding, CodeEmail, DepositEmail, Locale, Notification, OrderFilledEmail, PasswordResetEmail,
+    SecurityAlertEmail, SecurityEvent, TradeSide,
+};
+pub use totp::{TotpConfig, TotpEnrollment, TotpProvider, TotpSecretStore};
 
 // MFA Error types
 #[derive(Debug, Clone)]
@@ -18,7 +35,12 @@ pub enum MfaError {
     ChallengeNotFound,
     ChallengeExpired,
     InvalidCode,
+    /// Too many wrong codes, the challenge was discarded
+    TooManyAttempts,
     ServiceUnavailable,
+    /// The user has not set up this second factor
+    NotEnrolled,
+    InvalidSecret(String),
 }
 
 impl std::fmt::Display for MfaError {
@@ -28,7 +50,12 @@ impl std::fmt::Display for MfaError {
             MfaError::ChallengeNotFound => write!(f, "Challenge not found"),
             MfaError::ChallengeExpired => write!(f, "Challenge has expired"),
             MfaError::InvalidCode => write!(f, "Invalid verification code"),
+            MfaError::TooManyAttempts => {
+                write!(f, "Too many wrong codes, please log in again")
+            }
             MfaError::ServiceUnavailable => write!(f, "MFA service is temporarily unavailable"),
+            MfaError::NotEnrolled => write!(f, "This verification method is not set up"),
+            MfaError::InvalidSecret(msg) => write!(f, "Invalid TOTP secret: {msg}"),
         }
     }
 }
@@ -36,137 +63,101 @@ impl std::fmt::Display for MfaError {
 impl std::error::Error for MfaError {}
 
 // OTP Challenge structure
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OtpChallenge {
     pub id: String,
     pub user_email: String,
-    pub code: String,
+    /// Hash of the expected code, see `hash_otp_code`. Empty when the code is not
+    /// known in advance, as with authenticator apps.
+    pub code_hash: String,
     pub verified: bool,
+    /// Wrong codes entered so far
+    pub attempts: u32,
     pub created_at: SystemTime,
     pub expires_at: SystemTime,
 }
 
+/// Wrong codes a challenge accepts before it is discarded
+pub const MAX_ATTEMPTS: u32 = 5;
+
+impl OtpChallenge {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        now > self.expires_at
+    }
+}
+
+/// SHA-256 hash of a code, hex encoded. Challenges are short-lived, so the hash only
+/// keeps codes out of the store rather than making them hard to guess.
+pub fn hash_otp_code(code: &str) -> String {
+    Sha256::digest(code.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
 // MFA Provider trait
 pub trait MfaProvider: Send + Sync {
     fn send_otp(
         &self,
         user_email: &str,
     ) -> impl std::future::Future<Output = Result<String, MfaError>> + Send;
-    fn verify_otp(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError>;
-    fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError>;
+    fn verify_otp(
+        &self,
+        challenge_id: &str,
+        code: &str,
+    ) -> impl std::future::Future<Output = Result<bool, MfaError>> + Send;
+    fn get_challenge(
+        &self,
+        challenge_id: &str,
+    ) -> impl std::future::Future<Output = Result<OtpChallenge, MfaError>> + Send;
 }
 
-#[derive(Debug, Clone)]
-pub struct EmailConfig {
-    pub smtp_server: String,
-    pub smtp_port: u16,
-    pub username: String,
-    pub password: String,
-    pub from_email: String,
-    pub from_name: String,
+/// Who an emailed code is for
+#[derive(Debug, Clone, Copy)]
+pub struct Recipient {
+    /// Language the email is written in
+    pub locale: Locale,
+    /// False while the address is not confirmed, the code then confirms a registration
+    pub verified: bool,
 }
 
-impl EmailConfig {
-    fn new() -> Result<Self, MfaError> {
-        if let Err(e) = dotenvy::dotenv() {
-            error!("Warning: Could not load .env file: {}", e);
-            return Err(MfaError::ServiceUnavailable);
-        }
-
-        Ok(Self {
-            smtp_server: std::env::var("SMTP_SERVER")
-                .unwrap_or_else(|_| "smtp.gmail.com".to_string()),
-            smtp_port: std::env::var("SMTP_PORT")
-                .unwrap_or_else(|_| "587".to_string())
-                .parse()
-                .unwrap_or(587),
-            username: std::env::var("SMTP_USERNAME")
-                .expect("SMTP_USERNAME environment variable must be set"),
-            password: std::env::var("SMTP_PASSWORD")
-                .expect("SMTP_PASSWORD environment variable must be set"),
-            from_email: std::env::var("SMTP_FROM_EMAIL")
-                .expect("SMTP_FROM_EMAIL environment variable must be set"),
-            from_name: std::env::var("SMTP_FROM_NAME")
-                .unwrap_or_else(|_| "BrokerX Security".to_string()),
-        })
-    }
-
-    /// Create a test configuration that doesn't require environment variables
-    pub fn new_test_config() -> Self {
-        Self {
-            smtp_server: "test.smtp.com".to_string(),
-            smtp_port: 587,
-            username: "test_user".to_string(),
-            password: "test_password".to_string(),
-            from_email: "test@example.com".to_string(),
-            from_name: "Test Sender".to_string(),
-        }
-    }
+/// Where the provider finds who it is writing to
+pub trait RecipientStore: Send + Sync {
+    fn recipient(
+        &self,
+        user_email: &str,
+    ) -> impl std::future::Future<Output = Result<Option<Recipient>, MfaError>> + Send;
 }
 
-impl EmailConfig {
-    /// Create EmailConfig from environment variables
-    /// Required environment variables:
-    /// - SMTP_USERNAME: SMTP username for authentication
-    /// - SMTP_PASSWORD: SMTP password for authentication  
-    /// - SMTP_FROM_EMAIL: Email address to send from
-    ///
-    /// Optional environment variables:
-    /// - SMTP_SERVER: SMTP server hostname (default: smtp.gmail.com)
-    /// - SMTP_PORT: SMTP server port (default: 587)
-    /// - SMTP_FROM_NAME: Display name for sender (default: BrokerX Security)
-    pub fn from_env() -> Result<Self, String> {
-        let _ = dotenvy::dotenv();
-
-        let smtp_server =
-            std::env::var("SMTP_SERVER").unwrap_or_else(|_| "smtp.gmail.com".to_string());
-
-        let smtp_port = std::env::var("SMTP_PORT")
-            .unwrap_or_else(|_| "587".to_string())
-            .parse()
-            .map_err(|_| "Invalid SMTP_PORT: must be a valid port number".to_string())?;
-
-        let username = std::env::var("SMTP_USERNAME")
-            .map_err(|_| "SMTP_USERNAME environment variable must be set".to_string())?;
-
-        let password = std::env::var("SMTP_PASSWORD")
-            .map_err(|_| "SMTP_PASSWORD environment variable must be set".to_string())?;
-
-        let from_email = std::env::var("SMTP_FROM_EMAIL")
-            .map_err(|_| "SMTP_FROM_EMAIL environment variable must be set".to_string())?;
-
-        let from_name =
-            std::env::var("SMTP_FROM_NAME").unwrap_or_else(|_| "BrokerX Security".to_string());
-
-        Ok(Self {
-            smtp_server,
-            smtp_port,
-            username,
-            password,
-            from_email,
-            from_name,
-        })
+/// Knows nobody: codes are sent as English login codes
+#[derive(Debug, Clone, Default)]
+pub struct UnknownRecipients;
+
+impl RecipientStore for UnknownRecipients {
+    async fn recipient(&self, _user_email: &str) -> Result<Option<Recipient>, MfaError> {
+        Ok(None)
     }
 }
 
 #[derive(Debug)]
-pub struct EmailOtpProvider {
-    config: EmailConfig,
-    challenges: Arc<Mutex<HashMap<String, OtpChallenge>>>,
+pub struct EmailOtpProvider<
+    C = InMemoryChallengeStore,
+    T = ConfiguredTransport,
+    R = UnknownRecipients,
+> {
+    mailer: Mailer<T>,
+    challenges: C,
+    recipients: R,
     challenge_duration: Duration,
 }
 
 impl EmailOtpProvider {
     pub fn new(config: EmailConfig) -> Self {
-        Self {
-            config,
-            challenges: Arc::new(Mutex::new(HashMap::new())),
-            challenge_duration: Duration::from_secs(300), // 5 minutes
-        }
+        Self::with_challenge_store(config, InMemoryChallengeStore::new())
     }
 
     pub fn new_with_default_config() -> Self {
-        Self::new(EmailConfig::new().expect("Failed to load email config from environment"))
+        Self::new_from_env().expect("Failed to load email config from environment")
     }
 
     /// Create EmailOtpProvider with test configuration for testing purposes
@@ -187,78 +178,69 @@ impl EmailOtpProvider {
         let config = EmailConfig::from_env()?;
         Ok(Self::new(config))
     }
+}
+
+impl<C: ChallengeStore> EmailOtpProvider<C> {
+    /// Create EmailOtpProvider keeping its challenges in `challenges`, which other
+    /// instances can share
+    pub fn with_challenge_store(config: EmailConfig, challenges: C) -> Self {
+        Self::with_mailer(Mailer::new(&config), challenges)
+    }
+}
+
+impl<C: ChallengeStore, T: EmailTransport> EmailOtpProvider<C, T> {
+    /// Create EmailOtpProvider sending its codes with `mailer`
+    pub fn with_mailer(mailer: Mailer<T>, challenges: C) -> Self {
+        Self {
+            mailer,
+            challenges,
+            recipients: UnknownRecipients,
+            challenge_duration: Duration::from_secs(300), // 5 minutes
+        }
+    }
+}
+
+impl<C: ChallengeStore, T: EmailTransport, R: RecipientStore> EmailOtpProvider<C, T, R> {
+    /// Write to users in their language, and tell registrations from logins, with
+    /// what `recipients` knows about them
+    pub fn with_recipients<S: RecipientStore>(self, recipients: S) -> EmailOtpProvider<C, T, S> {
+        EmailOtpProvider {
+            mailer: self.mailer,
+            challenges: self.challenges,
+            recipients,
+            challenge_duration: self.challenge_duration,
+        }
+    }
+
+    pub fn mailer(&self) -> &Mailer<T> {
+        &self.mailer
+    }
 
     fn generate_otp_code(&self) -> String {
         let mut rng = rand::thread_rng();
         format!("{:06}", rng.gen_range(100_000..999_999))
     }
 
-    fn send_email(&self, to_email: &str, code: &str) -> Result<(), MfaError> {
-        let email_body = format!(
-            r#"
-<!DOCTYPE html>
-<html>
-<head>
-    <style>
-        body {{ font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; }}
-        .header {{ background-color: #f8f9fa; padding: 20px; text-align: center; border-radius: 8px; }}
-        .code {{ font-size: 32px; font-weight: bold; color: #007bff; text-align: center; margin: 20px 0; }}
-        .footer {{ color: #666; font-size: 12px; text-align: center; margin-top: 20px; }}
-    </style>
-</head>
-<body>
-    <div class="header">
-        <h1>BrokerX Security Verification</h1>
-    </div>
-    
-    <p>Hello,</p>
-    
-    <p>You have requested to sign in to your BrokerX account. Please use the verification code below:</p>
-    
-    <div class="code">{code}</div>
-    
-    <p>This code will expire in 5 minutes for security reasons.</p>
-    
-    <p>If you did not request this code, please ignore this email.</p>
-    
-    <div class="footer">
-        <p>This is an automated message from BrokerX. Please do not reply to this email.</p>
-    </div>
-</body>
-</html>
-            "#
-        );
-
-        let email = Message::builder()
-            .from(
-                format!("{} <{}>", self.config.from_name, self.config.from_email)
-                    .parse()
-                    .map_err(|e| MfaError::SendingFailed(format!("Invalid from address: {e}")))?,
-            )
-            .to(to_email
-                .parse()
-                .map_err(|e| MfaError::SendingFailed(format!("Invalid to address: {e}")))?)
-            .subject("BrokerX - Your Verification Code")
-            .header(ContentType::TEXT_HTML)
-            .body(email_body)
-            .map_err(|e| MfaError::SendingFailed(format!("Failed to build email: {e}")))?;
-
-        let creds = Credentials::new(self.config.username.clone(), self.config.password.clone());
-
-        let mailer = SmtpTransport::relay(&self.config.smtp_server)
-            .map_err(|e| MfaError::SendingFailed(format!("SMTP relay error: {e}")))?
-            .credentials(creds)
-            .build();
-
-        mailer
-            .send(&email)
-            .map_err(|e| MfaError::SendingFailed(format!("Failed to send email: {e}")))?;
-
-        Ok(())
+    async fn send_email(&self, to_email: &str, code: &str) -> Result<(), MfaError> {
+        let recipient = self.recipients.recipient(to_email).await?;
+        let code = CodeEmail {
+            code: code.to_string(),
+            valid_minutes: self.challenge_duration.as_secs().div_ceil(60),
+        };
+        let (locale, notification) = match recipient {
+            Some(recipient) if !recipient.verified => {
+                (recipient.locale, Notification::RegistrationCode(code))
+            }
+            Some(recipient) => (recipient.locale, Notification::LoginCode(code)),
+            None => (Locale::default(), Notification::LoginCode(code)),
+        };
+        self.mailer.send(to_email, locale, &notification).await
     }
 }
 
-impl MfaProvider for EmailOtpProvider {
+impl<C: ChallengeStore, T: EmailTransport, R: RecipientStore> MfaProvider
+    for EmailOtpProvider<C, T, R>
+{
     async fn send_otp(&self, user_email: &str) -> Result<String, MfaError> {
         let challenge_id = Uuid::new_v4().to_string();
         let code = if user_email == "test@test.com" {
@@ -274,36 +256,25 @@ impl MfaProvider for EmailOtpProvider {
         let expires_at = now + self.challenge_duration;
 
         // Send the email to the target address
-        self.send_email(user_email, &code)?;
+        self.send_email(user_email, &code).await?;
 
         // Store the challenge with the user's actual email for identification
         let challenge = OtpChallenge {
             id: challenge_id.clone(),
             user_email: user_email.to_string(),
-            code,
+            code_hash: hash_otp_code(&code),
             verified: false,
+            attempts: 0,
             created_at: now,
             expires_at,
         };
-
-        let mut challenges = self.challenges.lock().unwrap();
-        challenges.insert(challenge_id.clone(), challenge);
+        self.challenges.insert(challenge).await?;
 
         Ok(challenge_id)
     }
 
-    fn verify_otp(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
-        let mut challenges = self.challenges.lock().unwrap();
-
-        let challenge = challenges
-            .get_mut(challenge_id)
-            .ok_or(MfaError::ChallengeNotFound)?;
-
-        // Check if challenge has expired
-        if SystemTime::now() > challenge.expires_at {
-            challenges.remove(challenge_id);
-            return Err(MfaError::ChallengeExpired);
-        }
+    async fn verify_otp(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
+        let mut challenge = live_challenge(&self.challenges, challenge_id).await?;
 
         // Check if already verified
         if challenge.verified {
@@ -311,27 +282,16 @@ impl MfaProvider for EmailOtpProvider {
         }
 
         // Verify the code
-        if challenge.code == code {
-            challenge.verified = true;
-            Ok(true)
-        } else {
-            Err(MfaError::InvalidCode)
+        if challenge.code_hash != hash_otp_code(code) {
+            return Err(reject_code(&self.challenges, &challenge.id).await);
         }
+        challenge.verified = true;
+        self.challenges.update(challenge).await?;
+        Ok(true)
     }
 
-    fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
-        let challenges = self.challenges.lock().unwrap();
-
-        let challenge = challenges
-            .get(challenge_id)
-            .ok_or(MfaError::ChallengeNotFound)?;
-
-        // Check if challenge has expired
-        if SystemTime::now() > challenge.expires_at {
-            return Err(MfaError::ChallengeExpired);
-        }
-
-        Ok(challenge.clone())
+    async fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
+        live_challenge(&self.challenges, challenge_id).await
     }
 }
 
@@ -351,8 +311,28 @@ mod tests {
         assert!((100_000..1_000_000).contains(&code_num));
     }
 
-    #[test]
-    fn test_challenge_expiry() {
+    #[tokio::test]
+    async fn test_send_otp_emails_the_code() {
+        let provider = EmailOtpProvider::new_for_testing();
+        let challenge_id = provider.send_otp("user@example.com").await.unwrap();
+
+        let email = provider
+            .mailer()
+            .transport()
+            .captured()
+            .unwrap()
+            .last_sent_to("user@example.com")
+            .unwrap();
+        let code = email
+            .html_body
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|word| word.len() == 6)
+            .unwrap();
+        assert!(provider.verify_otp(&challenge_id, code).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_expiry() {
         // Create a provider with very short expiry duration for testing
         let provider = EmailOtpProvider::new_for_testing_with_duration(Duration::from_millis(10));
 
@@ -362,23 +342,87 @@ mod tests {
         let challenge = OtpChallenge {
             id: challenge_id.clone(),
             user_email: "test@example.com".to_string(),
-            code: "123456".to_string(),
+            code_hash: hash_otp_code("123456"),
             verified: false,
+            attempts: 0,
             created_at: now,
             expires_at: now + Duration::from_millis(10),
         };
 
         // Manually insert the challenge
-        {
-            let mut challenges = provider.challenges.lock().unwrap();
-            challenges.insert(challenge_id.clone(), challenge);
-        }
+        provider.challenges.insert(challenge).await.unwrap();
 
         // Wait for the challenge to expire
-        std::thread::sleep(Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(20)).await;
 
         // Try to get the expired challenge
-        let result = provider.get_challenge(&challenge_id);
+        let result = provider.get_challenge(&challenge_id).await;
         assert!(matches!(result, Err(MfaError::ChallengeExpired)));
     }
+
+    #[tokio::test]
+    async fn test_challenge_is_discarded_after_too_many_wrong_codes() {
+        let provider = EmailOtpProvider::new_for_testing();
+        let challenge_id = Uuid::new_v4().to_string();
+        let now = SystemTime::now();
+        let challenge = OtpChallenge {
+            id: challenge_id.clone(),
+            user_email: "test@example.com".to_string(),
+            code_hash: hash_otp_code("123456"),
+            verified: false,
+            attempts: 0,
+            created_at: now,
+            expires_at: now + Duration::from_secs(300),
+        };
+        provider.challenges.insert(challenge).await.unwrap();
+        // Only the hash of the code is stored
+        let stored = provider.get_challenge(&challenge_id).await.unwrap();
+        assert_ne!(stored.code_hash, "123456");
+
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(matches!(
+                provider.verify_otp(&challenge_id, "000000").await,
+                Err(MfaError::InvalidCode)
+            ));
+        }
+        assert!(matches!(
+            provider.verify_otp(&challenge_id, "000000").await,
+            Err(MfaError::TooManyAttempts)
+        ));
+        // Even the right code is refused now
+        assert!(matches!(
+            provider.verify_otp(&challenge_id, "123456").await,
+            Err(MfaError::ChallengeNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_instances_sharing_a_store_verify_each_others_challenges() {
+        let store = InMemoryChallengeStore::new();
+        let first =
+            EmailOtpProvider::with_challenge_store(EmailConfig::new_test_config(), store.clone());
+        let second = EmailOtpProvider::with_challenge_store(EmailConfig::new_test_config(), store);
+        let now = SystemTime::now();
+        let challenge = OtpChallenge {
+            id: Uuid::new_v4().to_string(),
+            user_email: "test@example.com".to_string(),
+            code_hash: hash_otp_code("123456"),
+            verified: false,
+            attempts: 0,
+            created_at: now,
+            expires_at: now + Duration::from_secs(300),
+        };
+        first.challenges.insert(challenge.clone()).await.unwrap();
+
+        assert!(matches!(
+            second.verify_otp(&challenge.id, "654321").await,
+            Err(MfaError::InvalidCode)
+        ));
+        assert_eq!(
+            first.get_challenge(&challenge.id).await.unwrap().attempts,
+            1
+        );
+        assert!(second.verify_otp(&challenge.id, "123456").await.unwrap());
+        assert!(first.get_challenge(&challenge.id).await.unwrap().verified);
+    }
 }
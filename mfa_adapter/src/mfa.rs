@@ -21,12 +21,17 @@ impl<P: MfaProvider> MfaService<P> {
     }
 
     /// Verifies MFA challenge
-    pub fn verify_mfa(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
-        self.provider.verify_otp(challenge_id, code)
+    pub async fn verify_mfa(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
+        self.provider.verify_otp(challenge_id, code).await
+    }
+
+    /// Provider the challenges are delegated to
+    pub fn provider(&self) -> &P {
+        &self.provider
     }
 
     /// Gets challenge information
-    pub fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
-        self.provider.get_challenge(challenge_id)
+    pub async fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
+        self.provider.get_challenge(challenge_id).await
     }
 }
@@ -0,0 +1,212 @@
+//! One-time recovery codes, for users who lost access to their other second factors.
+//!
+//! Users are shown their codes once and only SHA-256 hashes are kept. A challenge is
+//! checked against the user's unused codes when it is answered, and then keeps the
+//! hash of the code that answered it; whoever stores the codes must burn that one,
+//! see `RecoveryCodeProvider::used_code`.
+
+use std::time::{Duration, SystemTime};
+
+use rand::Rng;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::challenge_store::{live_challenge, reject_code};
+use crate::{
+    hash_otp_code, ChallengeStore, InMemoryChallengeStore, MfaError, MfaProvider, OtpChallenge,
+};
+
+/// Codes handed out at once
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Characters of recovery codes, without the ones easily mistaken for another
+const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Where the provider finds the hashes of the unused recovery codes of a user
+pub trait RecoveryCodeStore: Send + Sync {
+    fn recovery_code_hashes(
+        &self,
+        user_email: &str,
+    ) -> impl std::future::Future<Output = Result<Vec<String>, MfaError>> + Send;
+}
+
+#[derive(Debug)]
+pub struct RecoveryCodeProvider<S, C = InMemoryChallengeStore> {
+    store: S,
+    challenge_duration: Duration,
+    challenges: C,
+}
+
+impl<S: RecoveryCodeStore> RecoveryCodeProvider<S> {
+    pub fn new(store: S) -> Self {
+        Self::with_challenge_store(store, InMemoryChallengeStore::new())
+    }
+}
+
+impl<S: RecoveryCodeStore, C: ChallengeStore> RecoveryCodeProvider<S, C> {
+    /// Create a provider keeping its challenges in `challenges`, which other
+    /// instances can share
+    pub fn with_challenge_store(store: S, challenges: C) -> Self {
+        Self {
+            store,
+            challenge_duration: Duration::from_secs(300),
+            challenges,
+        }
+    }
+
+    /// Hash of the code that answered a verified challenge
+    pub async fn used_code(&self, challenge_id: &str) -> Result<String, MfaError> {
+        let challenge = live_challenge(&self.challenges, challenge_id).await?;
+        if !challenge.verified {
+            return Err(MfaError::InvalidCode);
+        }
+        Ok(challenge.code_hash)
+    }
+}
+
+/// New set of recovery codes, formatted as `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| char::from(ALPHABET[rng.gen_range(0..ALPHABET.len())]))
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Hash a recovery code is kept as. Case, spaces and dashes are ignored.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_otp_code(&normalized)
+}
+
+impl<S: RecoveryCodeStore, C: ChallengeStore> MfaProvider for RecoveryCodeProvider<S, C> {
+    async fn send_otp(&self, user_email: &str) -> Result<String, MfaError> {
+        if self
+            .store
+            .recovery_code_hashes(user_email)
+            .await?
+            .is_empty()
+        {
+            return Err(MfaError::NotEnrolled);
+        }
+        let challenge_id = Uuid::new_v4().to_string();
+        debug!(
+            "Opened recovery code challenge {} for {}",
+            challenge_id, user_email
+        );
+        let now = SystemTime::now();
+        let challenge = OtpChallenge {
+            id: challenge_id.clone(),
+            user_email: user_email.to_string(),
+            // Set to the hash of the code that answers it
+            code_hash: String::new(),
+            verified: false,
+            attempts: 0,
+            created_at: now,
+            expires_at: now + self.challenge_duration,
+        };
+        self.challenges.insert(challenge).await?;
+        Ok(challenge_id)
+    }
+
+    async fn verify_otp(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
+        let mut challenge = live_challenge(&self.challenges, challenge_id).await?;
+        if challenge.verified {
+            return Ok(true);
+        }
+
+        let hash = hash_recovery_code(code);
+        let hashes = self
+            .store
+            .recovery_code_hashes(&challenge.user_email)
+            .await?;
+        if !hashes.contains(&hash) {
+            return Err(reject_code(&self.challenges, &challenge.id).await);
+        }
+        challenge.verified = true;
+        challenge.code_hash = hash;
+        self.challenges.update(challenge).await?;
+        Ok(true)
+    }
+
+    async fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
+        live_challenge(&self.challenges, challenge_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Hashes(Vec<String>);
+
+    impl RecoveryCodeStore for Hashes {
+        async fn recovery_code_hashes(&self, user_email: &str) -> Result<Vec<String>, MfaError> {
+            Ok(if user_email == "codes@test.com" {
+                self.0.clone()
+            } else {
+                Vec::new()
+            })
+        }
+    }
+
+    #[test]
+    fn test_generated_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(code.as_bytes()[5], b'-');
+        }
+        let hashes: std::collections::HashSet<_> =
+            codes.iter().map(|code| hash_recovery_code(code)).collect();
+        assert_eq!(hashes.len(), RECOVERY_CODE_COUNT);
+    }
+
+    #[test]
+    fn test_hash_ignores_formatting() {
+        assert_eq!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code(" ABCDE FGHJK ")
+        );
+        assert_ne!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code("abcde-fghjm")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_challenge() {
+        let codes = generate_recovery_codes();
+        let provider = RecoveryCodeProvider::new(Hashes(
+            codes.iter().map(|c| hash_recovery_code(c)).collect(),
+        ));
+        assert!(matches!(
+            provider.send_otp("none@test.com").await,
+            Err(MfaError::NotEnrolled)
+        ));
+
+        let challenge_id = provider.send_otp("codes@test.com").await.unwrap();
+        assert!(matches!(
+            provider.used_code(&challenge_id).await,
+            Err(MfaError::InvalidCode)
+        ));
+        assert!(matches!(
+            provider.verify_otp(&challenge_id, "aaaaa-aaaaa").await,
+            Err(MfaError::InvalidCode)
+        ));
+        assert!(provider.verify_otp(&challenge_id, &codes[3]).await.unwrap());
+        assert_eq!(
+            provider.used_code(&challenge_id).await.unwrap(),
+            hash_recovery_code(&codes[3])
+        );
+    }
+}
@@ -0,0 +1,517 @@
+//! Emails sent to users, rendered from the templates in `templates/email`.
+//!
+//! Every notification has an HTML and a plain-text template per locale, under
+//! `templates/email/<locale>/`. Amounts and dates are formatted the way readers of
+//! that locale expect them.
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::MfaError;
+
+/// Language emails are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Fr];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+        }
+    }
+
+    /// Name of the language, in that language
+    pub fn label(self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::Fr => "Français",
+        }
+    }
+}
+
+impl std::str::FromStr for Locale {
+    type Err = String;
+
+    /// Parse a language tag such as `fr` or `fr-CA`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let language = value.split(['-', '_']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Ok(Locale::En),
+            "fr" => Ok(Locale::Fr),
+            _ => Err(format!("Unsupported locale: {value}")),
+        }
+    }
+}
+
+/// How emails present the company, from the email configuration
+#[derive(Debug, Clone)]
+pub struct Branding {
+    /// Product name used in subjects and headers
+    pub name: String,
+    /// Display name of the sender, used to sign emails
+    pub from_name: String,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            name: "BrokerX".to_string(),
+            from_name: "BrokerX Security".to_string(),
+        }
+    }
+}
+
+/// A one-time code sent by email
+#[derive(Debug, Clone)]
+pub struct CodeEmail {
+    pub code: String,
+    /// How long the code can be entered
+    pub valid_minutes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderFilledEmail {
+    pub order_id: String,
+    pub symbol: String,
+    pub side: TradeSide,
+    pub quantity: u64,
+    pub price: f64,
+}
+
+impl OrderFilledEmail {
+    pub fn total(&self) -> f64 {
+        self.price * self.quantity as f64
+    }
+}
+
+/// A single-use link to choose a new password
+#[derive(Debug, Clone)]
+pub struct PasswordResetEmail {
+    pub link: String,
+    /// How long the link can be followed
+    pub valid_minutes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DepositEmail {
+    pub amount: f64,
+    pub balance: f64,
+}
+
+/// Change to an account its owner should know about, in case it was not them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityEvent {
+    PasswordChanged,
+    TotpEnabled,
+    TotpDisabled,
+    RecoveryCodesRegenerated,
+    /// Too many failed logins, logins are refused until `until`
+    AccountLocked {
+        until: DateTime<Utc>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityAlertEmail {
+    pub event: SecurityEvent,
+}
+
+/// Email BrokerX sends to its users
+#[derive(Debug, Clone)]
+pub enum Notification {
+    /// Second factor of a login
+    LoginCode(CodeEmail),
+    /// Confirms the address of a new account
+    RegistrationCode(CodeEmail),
+    PasswordReset(PasswordResetEmail),
+    OrderFilled(OrderFilledEmail),
+    Deposit(DepositEmail),
+    SecurityAlert(SecurityAlertEmail),
+}
+
+/// Subject and bodies of a notification
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+mod filters {
+    use std::borrow::Borrow;
+
+    /// Amount with two decimals, `1,234.50` in English and `1 234,50` in French
+    fn money(amount: f64, thousands: char, decimal: char) -> String {
+        let formatted = format!("{:.2}", amount.abs());
+        let (units, cents) = formatted.split_once('.').unwrap_or((&formatted, "00"));
+        let mut grouped = String::new();
+        for (i, digit) in units.chars().enumerate() {
+            if i > 0 && (units.len() - i) % 3 == 0 {
+                grouped.push(thousands);
+            }
+            grouped.push(digit);
+        }
+        let sign = if amount < 0.0 { "-" } else { "" };
+        format!("{sign}{grouped}{decimal}{cents}")
+    }
+
+    pub fn money_en(amount: impl Borrow<f64>) -> askama::Result<String> {
+        Ok(money(*amount.borrow(), ',', '.'))
+    }
+
+    pub fn money_fr(amount: impl Borrow<f64>) -> askama::Result<String> {
+        // Narrow no-break space, as French typography groups digits
+        Ok(money(*amount.borrow(), '\u{202f}', ','))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "email/en/login_code.html")]
+struct LoginCodeEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/login_code.txt")]
+struct LoginCodeEnText<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/login_code.html")]
+struct LoginCodeFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/login_code.txt")]
+struct LoginCodeFrText<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/registration_code.html")]
+struct RegistrationCodeEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/registration_code.txt")]
+struct RegistrationCodeEnText<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/registration_code.html")]
+struct RegistrationCodeFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/registration_code.txt")]
+struct RegistrationCodeFrText<'a> {
+    brand: &'a Branding,
+    n: &'a CodeEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/password_reset.html")]
+struct PasswordResetEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a PasswordResetEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/password_reset.txt")]
+struct PasswordResetEnText<'a> {
+    brand: &'a Branding,
+    n: &'a PasswordResetEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/password_reset.html")]
+struct PasswordResetFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a PasswordResetEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/password_reset.txt")]
+struct PasswordResetFrText<'a> {
+    brand: &'a Branding,
+    n: &'a PasswordResetEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/order_filled.html")]
+struct OrderFilledEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a OrderFilledEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/order_filled.txt")]
+struct OrderFilledEnText<'a> {
+    brand: &'a Branding,
+    n: &'a OrderFilledEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/order_filled.html")]
+struct OrderFilledFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a OrderFilledEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/order_filled.txt")]
+struct OrderFilledFrText<'a> {
+    brand: &'a Branding,
+    n: &'a OrderFilledEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/deposit.html")]
+struct DepositEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a DepositEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/deposit.txt")]
+struct DepositEnText<'a> {
+    brand: &'a Branding,
+    n: &'a DepositEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/deposit.html")]
+struct DepositFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a DepositEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/deposit.txt")]
+struct DepositFrText<'a> {
+    brand: &'a Branding,
+    n: &'a DepositEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/security_alert.html")]
+struct SecurityAlertEnHtml<'a> {
+    brand: &'a Branding,
+    n: &'a SecurityAlertEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/en/security_alert.txt")]
+struct SecurityAlertEnText<'a> {
+    brand: &'a Branding,
+    n: &'a SecurityAlertEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/security_alert.html")]
+struct SecurityAlertFrHtml<'a> {
+    brand: &'a Branding,
+    n: &'a SecurityAlertEmail,
+}
+
+#[derive(Template)]
+#[template(path = "email/fr/security_alert.txt")]
+struct SecurityAlertFrText<'a> {
+    brand: &'a Branding,
+    n: &'a SecurityAlertEmail,
+}
+
+fn render(template: &impl Template) -> Result<String, MfaError> {
+    template
+        .render()
+        .map_err(|e| MfaError::SendingFailed(format!("Failed to render email: {e}")))
+}
+
+impl Notification {
+    /// Subject line, before the product name
+    fn subject(&self, locale: Locale) -> &'static str {
+        match (self, locale) {
+            (Notification::LoginCode(_), Locale::En) => "Your verification code",
+            (Notification::LoginCode(_), Locale::Fr) => "Votre code de vérification",
+            (Notification::RegistrationCode(_), Locale::En) => "Confirm your email address",
+            (Notification::RegistrationCode(_), Locale::Fr) => "Confirmez votre adresse email",
+            (Notification::PasswordReset(_), Locale::En) => "Reset your password",
+            (Notification::PasswordReset(_), Locale::Fr) => "Réinitialisez votre mot de passe",
+            (Notification::OrderFilled(_), Locale::En) => "Your order was filled",
+            (Notification::OrderFilled(_), Locale::Fr) => "Votre ordre a été exécuté",
+            (Notification::Deposit(_), Locale::En) => "Deposit received",
+            (Notification::Deposit(_), Locale::Fr) => "Dépôt reçu",
+            (Notification::SecurityAlert(_), Locale::En) => "Security alert",
+            (Notification::SecurityAlert(_), Locale::Fr) => "Alerte de sécurité",
+        }
+    }
+
+    pub fn render(&self, locale: Locale, brand: &Branding) -> Result<RenderedEmail, MfaError> {
+        let (html_body, text_body) = match (self, locale) {
+            (Notification::LoginCode(n), Locale::En) => (
+                render(&LoginCodeEnHtml { brand, n })?,
+                render(&LoginCodeEnText { brand, n })?,
+            ),
+            (Notification::LoginCode(n), Locale::Fr) => (
+                render(&LoginCodeFrHtml { brand, n })?,
+                render(&LoginCodeFrText { brand, n })?,
+            ),
+            (Notification::RegistrationCode(n), Locale::En) => (
+                render(&RegistrationCodeEnHtml { brand, n })?,
+                render(&RegistrationCodeEnText { brand, n })?,
+            ),
+            (Notification::RegistrationCode(n), Locale::Fr) => (
+                render(&RegistrationCodeFrHtml { brand, n })?,
+                render(&RegistrationCodeFrText { brand, n })?,
+            ),
+            (Notification::PasswordReset(n), Locale::En) => (
+                render(&PasswordResetEnHtml { brand, n })?,
+                render(&PasswordResetEnText { brand, n })?,
+            ),
+            (Notification::PasswordReset(n), Locale::Fr) => (
+                render(&PasswordResetFrHtml { brand, n })?,
+                render(&PasswordResetFrText { brand, n })?,
+            ),
+            (Notification::OrderFilled(n), Locale::En) => (
+                render(&OrderFilledEnHtml { brand, n })?,
+                render(&OrderFilledEnText { brand, n })?,
+            ),
+            (Notification::OrderFilled(n), Locale::Fr) => (
+                render(&OrderFilledFrHtml { brand, n })?,
+                render(&OrderFilledFrText { brand, n })?,
+            ),
+            (Notification::Deposit(n), Locale::En) => (
+                render(&DepositEnHtml { brand, n })?,
+                render(&DepositEnText { brand, n })?,
+            ),
+            (Notification::Deposit(n), Locale::Fr) => (
+                render(&DepositFrHtml { brand, n })?,
+                render(&DepositFrText { brand, n })?,
+            ),
+            (Notification::SecurityAlert(n), Locale::En) => (
+                render(&SecurityAlertEnHtml { brand, n })?,
+                render(&SecurityAlertEnText { brand, n })?,
+            ),
+            (Notification::SecurityAlert(n), Locale::Fr) => (
+                render(&SecurityAlertFrHtml { brand, n })?,
+                render(&SecurityAlertFrText { brand, n })?,
+            ),
+        };
+        Ok(RenderedEmail {
+            subject: format!("{} - {}", brand.name, self.subject(locale)),
+            html_body,
+            text_body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code() -> Notification {
+        Notification::LoginCode(CodeEmail {
+            code: "123456".to_string(),
+            valid_minutes: 5,
+        })
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!("fr".parse::<Locale>(), Ok(Locale::Fr));
+        assert_eq!("fr-CA".parse::<Locale>(), Ok(Locale::Fr));
+        assert_eq!("EN_us".parse::<Locale>(), Ok(Locale::En));
+        assert!("de".parse::<Locale>().is_err());
+    }
+
+    #[test]
+    fn test_code_is_in_both_bodies_of_every_locale() {
+        let brand = Branding {
+            name: "Acme Trading".to_string(),
+            from_name: "Acme Security".to_string(),
+        };
+        for locale in Locale::ALL {
+            let email = code().render(locale, &brand).unwrap();
+            assert!(email.subject.starts_with("Acme Trading - "));
+            assert!(email.html_body.contains("123456"));
+            assert!(email.text_body.contains("123456"));
+            assert!(email.text_body.contains("Acme Security"));
+            assert!(!email.text_body.contains('<'));
+        }
+        let french = code().render(Locale::Fr, &brand).unwrap();
+        assert_eq!(french.subject, "Acme Trading - Votre code de vérification");
+        assert!(french.html_body.contains("5 minutes"));
+    }
+
+    #[test]
+    fn test_amounts_follow_the_locale() {
+        let deposit = Notification::Deposit(DepositEmail {
+            amount: 1234.5,
+            balance: 2000.0,
+        });
+        let brand = Branding::default();
+        let english = deposit.render(Locale::En, &brand).unwrap();
+        assert!(english.text_body.contains("1,234.50"));
+        assert!(english.text_body.contains("2,000.00"));
+        let french = deposit.render(Locale::Fr, &brand).unwrap();
+        assert!(french.text_body.contains("1\u{202f}234,50"));
+    }
+
+    #[test]
+    fn test_reset_link_is_in_both_bodies_of_every_locale() {
+        let reset = Notification::PasswordReset(PasswordResetEmail {
+            link: "https://brokerx.test/reset-password?token=abc.def".to_string(),
+            valid_minutes: 30,
+        });
+        for locale in Locale::ALL {
+            let email = reset.render(locale, &Branding::default()).unwrap();
+            assert!(email.html_body.contains("href="));
+            assert!(email.html_body.contains("token=abc.def"));
+            assert!(email
+                .text_body
+                .contains("https://brokerx.test/reset-password?token=abc.def"));
+            assert!(email.text_body.contains("30 minutes"));
+        }
+    }
+
+    #[test]
+    fn test_html_bodies_escape_their_values() {
+        let fill = Notification::OrderFilled(OrderFilledEmail {
+            order_id: "1".to_string(),
+            symbol: "<b>AAPL</b>".to_string(),
+            side: TradeSide::Buy,
+            quantity: 10,
+            price: 150.0,
+        });
+        let email = fill.render(Locale::En, &Branding::default()).unwrap();
+        assert!(!email.html_body.contains("<b>AAPL</b>"));
+        assert!(email.text_body.contains("<b>AAPL</b>"));
+    }
+}
@@ -57,6 +57,7 @@ impl MfaProvider for MockMfaProvider {
             user_email: user_email.to_string(),
             code,
             verified: false,
+            attempts: 0,
             created_at: now,
             expires_at: now + Duration::from_secs(300),
         };
@@ -208,6 +209,7 @@ mod tests {
             user_email: "test@example.com".to_string(),
             code: "123456".to_string(),
             verified: false,
+            attempts: 0,
             created_at: past_time,
             expires_at: past_time + Duration::from_secs(300),
         };
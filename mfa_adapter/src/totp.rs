@@ -0,0 +1,379 @@
+//! Time-based one-time passwords (RFC 6238) from authenticator apps.
+//!
+//! Nothing is sent: a challenge only remembers whose code is expected, the secret is
+//! looked up when it is answered. Codes are accepted from `skew` steps before or
+//! after the current one, to tolerate clocks that drift, and each step's code is
+//! only accepted once per user: the secret store keeps the last step used, so that
+//! every instance refuses a replayed code.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use qrcode::render::svg;
+use qrcode::QrCode;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::challenge_store::{live_challenge, reject_code};
+use crate::{ChallengeStore, InMemoryChallengeStore, MfaError, MfaProvider, OtpChallenge};
+
+#[derive(Debug, Clone)]
+pub struct TotpConfig {
+    /// Name shown next to the account in authenticator apps
+    pub issuer: String,
+    pub digits: usize,
+    /// Length of a time step, in seconds
+    pub step: u64,
+    /// Steps accepted before and after the current one
+    pub skew: u8,
+    /// How long a login challenge can be answered
+    pub challenge_duration: Duration,
+}
+
+impl Default for TotpConfig {
+    /// Parameters every authenticator app supports: 6 digits every 30 seconds
+    fn default() -> Self {
+        Self {
+            issuer: "BrokerX".to_string(),
+            digits: 6,
+            step: 30,
+            skew: 1,
+            challenge_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+impl TotpConfig {
+    /// Read `TOTP_ISSUER` and `TOTP_SKEW`, falling back to the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(issuer) = std::env::var("TOTP_ISSUER") {
+            if issuer.contains(':') {
+                warn!("TOTP_ISSUER cannot contain ':', using {}", config.issuer);
+            } else {
+                config.issuer = issuer;
+            }
+        }
+        if let Ok(skew) = std::env::var("TOTP_SKEW") {
+            match skew.parse() {
+                Ok(skew) => config.skew = skew,
+                Err(_) => warn!("Invalid TOTP_SKEW {skew}, using {}", config.skew),
+            }
+        }
+        config
+    }
+}
+
+/// Where the provider finds the confirmed TOTP secret of a user
+pub trait TotpSecretStore: Send + Sync {
+    fn totp_secret(
+        &self,
+        user_email: &str,
+    ) -> impl std::future::Future<Output = Result<Option<String>, MfaError>> + Send;
+
+    /// Record that the code of `step` was used, unless a code of this step or a
+    /// later one already was. Returns whether it was recorded.
+    fn use_step(
+        &self,
+        user_email: &str,
+        step: u64,
+    ) -> impl std::future::Future<Output = Result<bool, MfaError>> + Send;
+}
+
+/// What a user needs to add an account to their authenticator app
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    /// Base32 secret, for apps that cannot scan the QR code
+    pub secret: String,
+    /// `otpauth://` URI holding the secret and parameters
+    pub uri: String,
+    /// The URI as a QR code, in SVG
+    pub qr_code_svg: String,
+}
+
+#[derive(Debug)]
+pub struct TotpProvider<S, C = InMemoryChallengeStore> {
+    store: S,
+    config: TotpConfig,
+    challenges: C,
+}
+
+impl<S: TotpSecretStore> TotpProvider<S> {
+    pub fn new(store: S, config: TotpConfig) -> Self {
+        Self::with_challenge_store(store, config, InMemoryChallengeStore::new())
+    }
+
+    /// New random 160-bit secret, base32 encoded
+    pub fn generate_secret() -> String {
+        Secret::generate_secret().to_encoded().to_string()
+    }
+}
+
+impl<S: TotpSecretStore, C: ChallengeStore> TotpProvider<S, C> {
+    /// Create a provider keeping its challenges in `challenges`, which other
+    /// instances can share
+    pub fn with_challenge_store(store: S, config: TotpConfig, challenges: C) -> Self {
+        Self {
+            store,
+            config,
+            challenges,
+        }
+    }
+
+    fn totp(&self, user_email: &str, secret: &str) -> Result<TOTP, MfaError> {
+        let secret = Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .map_err(|e| MfaError::InvalidSecret(format!("{e:?}")))?;
+        TOTP::new(
+            Algorithm::SHA1,
+            self.config.digits,
+            0,
+            self.config.step,
+            secret,
+            Some(self.config.issuer.clone()),
+            user_email.replace(':', "_"),
+        )
+        .map_err(|e| MfaError::InvalidSecret(e.to_string()))
+    }
+
+    /// URI and QR code to enroll `secret` in an authenticator app
+    pub fn enrollment(&self, user_email: &str, secret: &str) -> Result<TotpEnrollment, MfaError> {
+        let uri = self.totp(user_email, secret)?.get_url();
+        let qr_code_svg = QrCode::new(uri.as_bytes())
+            .map_err(|e| MfaError::InvalidSecret(e.to_string()))?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+        Ok(TotpEnrollment {
+            secret: secret.to_string(),
+            uri,
+            qr_code_svg,
+        })
+    }
+
+    /// Whether `code` is the current code of `secret`, give or take the skew
+    pub async fn verify_code(
+        &self,
+        user_email: &str,
+        secret: &str,
+        code: &str,
+    ) -> Result<bool, MfaError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| MfaError::ServiceUnavailable)?
+            .as_secs();
+        self.verify_code_at(user_email, secret, code, now).await
+    }
+
+    async fn verify_code_at(
+        &self,
+        user_email: &str,
+        secret: &str,
+        code: &str,
+        time: u64,
+    ) -> Result<bool, MfaError> {
+        let totp = self.totp(user_email, secret)?;
+        let current = time / self.config.step;
+        let skew = u64::from(self.config.skew);
+        let Some(step) = (current.saturating_sub(skew)..=current + skew)
+            .find(|step| totp.generate(step * self.config.step) == code)
+        else {
+            return Ok(false);
+        };
+
+        if !self.store.use_step(user_email, step).await? {
+            warn!("Replayed TOTP code for {}", user_email);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+impl<S: TotpSecretStore, C: ChallengeStore> MfaProvider for TotpProvider<S, C> {
+    async fn send_otp(&self, user_email: &str) -> Result<String, MfaError> {
+        if self.store.totp_secret(user_email).await?.is_none() {
+            return Err(MfaError::NotEnrolled);
+        }
+        let challenge_id = Uuid::new_v4().to_string();
+        debug!("Opened TOTP challenge {} for {}", challenge_id, user_email);
+        let now = SystemTime::now();
+        let challenge = OtpChallenge {
+            id: challenge_id.clone(),
+            user_email: user_email.to_string(),
+            // The code comes from the user's app
+            code_hash: String::new(),
+            verified: false,
+            attempts: 0,
+            created_at: now,
+            expires_at: now + self.config.challenge_duration,
+        };
+        self.challenges.insert(challenge).await?;
+        Ok(challenge_id)
+    }
+
+    async fn verify_otp(&self, challenge_id: &str, code: &str) -> Result<bool, MfaError> {
+        let mut challenge = live_challenge(&self.challenges, challenge_id).await?;
+        if challenge.verified {
+            return Ok(true);
+        }
+        // The app may have been removed since the challenge was opened
+        let secret = self
+            .store
+            .totp_secret(&challenge.user_email)
+            .await?
+            .ok_or(MfaError::NotEnrolled)?;
+
+        if !self
+            .verify_code(&challenge.user_email, &secret, code)
+            .await?
+        {
+            return Err(reject_code(&self.challenges, &challenge.id).await);
+        }
+        challenge.verified = true;
+        self.challenges.update(challenge).await?;
+        Ok(true)
+    }
+
+    async fn get_challenge(&self, challenge_id: &str) -> Result<OtpChallenge, MfaError> {
+        live_challenge(&self.challenges, challenge_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, PoisonError};
+
+    use super::*;
+
+    /// Secret of the RFC 6238 SHA-1 test vectors, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    struct Secrets {
+        secrets: HashMap<String, String>,
+        last_steps: Mutex<HashMap<String, u64>>,
+    }
+
+    impl TotpSecretStore for Secrets {
+        async fn totp_secret(&self, user_email: &str) -> Result<Option<String>, MfaError> {
+            Ok(self.secrets.get(user_email).cloned())
+        }
+
+        async fn use_step(&self, user_email: &str, step: u64) -> Result<bool, MfaError> {
+            let mut last_steps = self
+                .last_steps
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if last_steps.get(user_email).is_some_and(|last| step <= *last) {
+                return Ok(false);
+            }
+            last_steps.insert(user_email.to_string(), step);
+            Ok(true)
+        }
+    }
+
+    fn provider(digits: usize) -> TotpProvider<Secrets> {
+        let secrets = HashMap::from([("app@test.com".to_string(), RFC_SECRET.to_string())]);
+        TotpProvider::new(
+            Secrets {
+                secrets,
+                last_steps: Mutex::default(),
+            },
+            TotpConfig {
+                digits,
+                ..TotpConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_rfc_6238_vectors() {
+        for (time, code) in [
+            (59, "94287082"),
+            (1_111_111_109, "07081804"),
+            (1_234_567_890, "89005924"),
+            (20_000_000_000, "65353130"),
+        ] {
+            let provider = provider(8);
+            assert!(provider
+                .verify_code_at("app@test.com", RFC_SECRET, code, time)
+                .await
+                .unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drift_window() {
+        let provider = provider(8);
+        // The code of step 37037036, at 1111111109, is accepted one step later
+        assert!(provider
+            .verify_code_at("a@test.com", RFC_SECRET, "07081804", 1_111_111_109 + 30)
+            .await
+            .unwrap());
+        // but not two steps later
+        assert!(!provider
+            .verify_code_at("b@test.com", RFC_SECRET, "07081804", 1_111_111_109 + 60)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_codes_cannot_be_replayed() {
+        let provider = provider(8);
+        assert!(provider
+            .verify_code_at("app@test.com", RFC_SECRET, "07081804", 1_111_111_109)
+            .await
+            .unwrap());
+        assert!(!provider
+            .verify_code_at("app@test.com", RFC_SECRET, "07081804", 1_111_111_109)
+            .await
+            .unwrap());
+        // Other users are not affected
+        assert!(provider
+            .verify_code_at("other@test.com", RFC_SECRET, "07081804", 1_111_111_109)
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn test_enrollment_uri() {
+        let provider = provider(6);
+        let secret = TotpProvider::<Secrets>::generate_secret();
+        let enrollment = provider.enrollment("app@test.com", &secret).unwrap();
+        assert!(enrollment
+            .uri
+            .starts_with("otpauth://totp/BrokerX:app%40test.com?"));
+        assert!(enrollment.uri.contains(&format!("secret={secret}")));
+        assert!(enrollment.uri.contains("issuer=BrokerX"));
+        assert!(enrollment.qr_code_svg.contains("<svg"));
+
+        assert!(matches!(
+            provider.enrollment("app@test.com", "not base32!"),
+            Err(MfaError::InvalidSecret(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_challenge() {
+        let provider = provider(6);
+        assert!(matches!(
+            provider.send_otp("unenrolled@test.com").await,
+            Err(MfaError::NotEnrolled)
+        ));
+
+        let challenge_id = provider.send_otp("app@test.com").await.unwrap();
+        let challenge = provider.get_challenge(&challenge_id).await.unwrap();
+        assert_eq!(challenge.user_email, "app@test.com");
+        assert!(matches!(
+            provider.verify_otp(&challenge_id, "000000").await,
+            Err(MfaError::InvalidCode)
+        ));
+
+        let code = provider
+            .totp("app@test.com", RFC_SECRET)
+            .unwrap()
+            .generate_current()
+            .unwrap();
+        assert!(provider.verify_otp(&challenge_id, &code).await.unwrap());
+    }
+}